@@ -0,0 +1,202 @@
+//! Persistent embedding cache.
+//!
+//! This module stores the embeddings generated by Ollama in a local SQLite
+//! database, keyed by `(model, md5(text))`. It is consulted by `OllamaClient`
+//! before each embedding request, so that both the indexer (re-indexing a
+//! slightly modified file, boilerplate shared between files) and the retriever
+//! (repeated questions) only pay an Ollama call for text never embedded before.
+//!
+//! SQLite is used so that the indexer and the RAG proxy can share the same
+//! cache file concurrently. The database is opened once per process and the
+//! cache handle is cloned where needed; since rusqlite is synchronous, lookups
+//! and inserts run on the blocking thread pool (`spawn_blocking`) so that they
+//! never stall the tokio workers.
+
+use md5::{Digest, Md5};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{AppError, Config};
+
+/// How long a writer waits for a concurrent process to release the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Embedding cache backed by a SQLite database, shared by its clones
+#[derive(Clone)]
+pub struct EmbeddingCache {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl EmbeddingCache {
+    /// Opens (and creates if needed) the embedding cache database
+    ///
+    /// # Arguments
+    /// * `path` - Path to the SQLite database file
+    ///
+    /// # Returns
+    /// * `Result<EmbeddingCache, AppError>` - The opened cache if successful, error otherwise
+    pub fn open(path: &str) -> Result<Self, AppError> {
+        let connection = Connection::open(path).map_err(cache_error)?;
+        connection.busy_timeout(BUSY_TIMEOUT).map_err(cache_error)?;
+        connection
+            .execute_batch(
+                "PRAGMA journal_mode = WAL;
+                 CREATE TABLE IF NOT EXISTS embeddings (
+                     model TEXT NOT NULL,
+                     text_hash TEXT NOT NULL,
+                     vector BLOB NOT NULL,
+                     PRIMARY KEY (model, text_hash)
+                 );",
+            )
+            .map_err(cache_error)?;
+        Ok(EmbeddingCache {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Opens the embedding cache configured in `embeddings.cache_path`, if any
+    ///
+    /// A cache that cannot be opened is logged and disabled.
+    ///
+    /// # Arguments
+    /// * `config` - Application configuration
+    ///
+    /// # Returns
+    /// * `Option<EmbeddingCache>` - The opened cache, None if not configured or not available
+    pub fn open_configured(config: &Config) -> Option<Self> {
+        config.embeddings.cache_path.as_deref().and_then(|path| {
+            EmbeddingCache::open(path)
+                .map_err(|e| tracing::warn!("Embedding cache disabled: {}", e))
+                .ok()
+        })
+    }
+
+    /// Looks up the cached embedding of a text
+    ///
+    /// # Arguments
+    /// * `model` - Embedding model name
+    /// * `text` - Embedded text
+    ///
+    /// # Returns
+    /// * `Result<Option<Vec<f32>>, AppError>` - The cached embedding if present
+    pub async fn get(&self, model: &str, text: &str) -> Result<Option<Vec<f32>>, AppError> {
+        let (model, text_hash) = (model.to_string(), text_hash(text));
+        self.run(move |connection| {
+            connection
+                .query_row(
+                    "SELECT vector FROM embeddings WHERE model = ?1 AND text_hash = ?2",
+                    params![model, text_hash],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .optional()
+                .map(|blob| blob.map(|bytes| decode_vector(&bytes)))
+        })
+        .await
+    }
+
+    /// Stores the embedding of a text
+    ///
+    /// # Arguments
+    /// * `model` - Embedding model name
+    /// * `text` - Embedded text
+    /// * `vector` - Embedding of the text
+    ///
+    /// # Returns
+    /// * `Result<(), AppError>` - Ok if successful, error otherwise
+    pub async fn put(&self, model: &str, text: &str, vector: &[f32]) -> Result<(), AppError> {
+        let (model, text_hash, blob) = (model.to_string(), text_hash(text), encode_vector(vector));
+        self.run(move |connection| {
+            connection
+                .execute(
+                    "INSERT OR REPLACE INTO embeddings (model, text_hash, vector) VALUES (?1, ?2, ?3)",
+                    params![model, text_hash, blob],
+                )
+                .map(|_| ())
+        })
+        .await
+    }
+
+    /// Runs a query on the blocking thread pool, holding the connection lock
+    async fn run<T, F>(&self, query: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = connection
+                .lock()
+                .map_err(|_| AppError::Cache("Embedding cache lock poisoned".to_string()))?;
+            query(&connection).map_err(cache_error)
+        })
+        .await
+        .map_err(|e| AppError::Cache(format!("Embedding cache task failed: {}", e)))?
+    }
+}
+
+/// Computes the hash used as cache key for a text
+pub fn text_hash(text: &str) -> String {
+    format!("{:x}", Md5::digest(text.as_bytes()))
+}
+
+/// Serializes a vector as little-endian f32 bytes
+fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+/// Deserializes a vector from little-endian f32 bytes
+fn decode_vector(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+fn cache_error(e: rusqlite::Error) -> AppError {
+    AppError::Cache(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(dir: &tempfile::TempDir) -> EmbeddingCache {
+        EmbeddingCache::open(dir.path().join("embeddings.db").to_str().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn stored_embeddings_are_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = open(&dir);
+        cache.put("nomic", "some text", &[0.5, -1.25, 3.0]).await.unwrap();
+        assert_eq!(cache.get("nomic", "some text").await.unwrap(), Some(vec![0.5, -1.25, 3.0]));
+    }
+
+    #[tokio::test]
+    async fn embeddings_are_keyed_by_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = open(&dir);
+        cache.put("nomic", "some text", &[1.0]).await.unwrap();
+        cache.put("bge", "some text", &[2.0]).await.unwrap();
+        assert_eq!(cache.get("nomic", "some text").await.unwrap(), Some(vec![1.0]));
+        assert_eq!(cache.get("bge", "some text").await.unwrap(), Some(vec![2.0]));
+        assert_eq!(cache.get("other", "some text").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn changed_text_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = open(&dir);
+        cache.put("nomic", "some text", &[1.0]).await.unwrap();
+        assert_eq!(cache.get("nomic", "some text!").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn embeddings_survive_reopening_the_database() {
+        let dir = tempfile::tempdir().unwrap();
+        open(&dir).put("nomic", "some text", &[1.0, 2.0]).await.unwrap();
+        let reopened = open(&dir);
+        assert_eq!(reopened.get("nomic", "some text").await.unwrap(), Some(vec![1.0, 2.0]));
+    }
+}
@@ -1,2 +1,3 @@
+pub mod embedding_cache;
 pub mod ollama;
 pub mod llm;
@@ -1,6 +1,7 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use crate::{Config, AppError};
+use crate::clients::embedding_cache::EmbeddingCache;
 
 #[derive(Serialize)]
 struct EmbeddingRequest {
@@ -17,18 +18,53 @@ pub struct OllamaClient {
     client: Client,
     base_url: String,
     model: String,
+    cache: Option<EmbeddingCache>,
 }
 
 impl OllamaClient {
+    /// Creates a new Ollama client
+    ///
+    /// If `embeddings.cache_path` is configured, the persistent embedding cache
+    /// is opened; a cache that cannot be opened is logged and disabled. The
+    /// client is meant to be created once and shared.
     pub fn new(config: &Config) -> Self {
         Self {
             client: Client::new(),
             base_url: config.embeddings.endpoint.clone(),
             model: config.embeddings.model.clone(),
+            cache: EmbeddingCache::open_configured(config),
         }
     }
 
+    /// Generates the embedding of a text, using the persistent cache when available
+    ///
+    /// # Arguments
+    /// * `prompt` - Text to embed
+    ///
+    /// # Returns
+    /// * `Result<Vec<f32>, AppError>` - The embedding if successful, error otherwise
     pub async fn generate_embedding(&self, prompt: &str) -> Result<Vec<f32>, AppError> {
+        if let Some(cache) = &self.cache {
+            match cache.get(&self.model, prompt).await {
+                Ok(Some(embedding)) => return Ok(embedding),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to read embedding cache: {}", e),
+            }
+        }
+
+        let embedding = self.request_embedding(prompt).await?;
+
+        if let Some(cache) = &self.cache
+            && let Err(e) = cache.put(&self.model, prompt, &embedding).await
+        {
+            tracing::warn!("Failed to write embedding cache: {}", e);
+        }
+
+        Ok(embedding)
+    }
+
+    /// Requests the embedding of a text from the Ollama API
+    async fn request_embedding(&self, prompt: &str) -> Result<Vec<f32>, AppError> {
         let url = format!("{}/api/embeddings", self.base_url);
         let request = EmbeddingRequest {
             model: self.model.clone(),
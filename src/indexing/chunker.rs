@@ -3,6 +3,28 @@
 //! This module provides functionality to split text content into chunks
 //! of a specified size, which is useful for processing large documents
 //! in smaller, manageable pieces for indexing and embedding generation.
+//!
+//! Markdown text (Markdown files, and HTML converted by the loader) is chunked
+//! section by section, so that a chunk never spans two sections and carries
+//! the path of headings it belongs to. Loaded documents are chunked section
+//! by section too (slides, sheets, chapters, pages), each chunk keeping the
+//! location of its section for citations.
+//!
+//! Source code is chunked at symbol boundaries (functions, impls, classes)
+//! by the `code` submodule, each chunk recording its symbol and line range.
+//!
+//! Consecutive chunks can be grouped into parent sections (`[indexing.parents]`),
+//! appended after the chunks: the chunks are matched with the question, and
+//! their parent section is given to the LLM for a wider context.
+
+mod code;
+
+use serde_json::{Map, Value};
+use std::ops::Range;
+
+use crate::indexing::loader::LoadedDocument;
+use crate::qdrant_custom_client::SparseVector;
+use crate::{ParentConfig, ParentMode};
 
 /// Splits text content into chunks of a specified size
 /// 
@@ -37,4 +59,334 @@ pub fn chunk_text(text: &str, chunk_size: usize) -> Vec<String> {
     }
     
     chunks
-}
\ No newline at end of file
+}
+
+/// A chunk of a document, with its location in the document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentChunk {
+    /// Text of the chunk
+    pub text: String,
+    /// Location of the chunk (page, slide, sheet, chapter, section...), for citations
+    pub location: Map<String, Value>,
+    /// True if the text was recognized with OCR
+    pub ocr: bool,
+    /// ISO 639-1 code of the language of the chunk, set by the language detector
+    pub lang: Option<&'static str>,
+    /// Position in the chunk list of the parent section of the chunk, set by `attach_parents`
+    pub parent: Option<usize>,
+    /// True for a parent section, stored as context for its chunks but never matched
+    pub is_parent: bool,
+    /// Sparse (BM25) vector of the chunk terms, set when the collection stores sparse vectors
+    pub sparse: Option<SparseVector>,
+}
+
+/// Splits a loaded document into chunks
+///
+/// Each section of the document is chunked on its own, and its chunks get
+/// its location. Markdown documents are split at headings (outside fenced
+/// code blocks) before being chunked with `chunk_text`. Every chunk of a
+/// Markdown document starts with the path of headings it belongs to (e.g.
+/// `## Installation > Linux`), so that it keeps its context once embedded on
+/// its own, and the path is added to its location as `section`. Headings
+/// carry over from a section to the next one, since a page break does not
+/// end a chapter. Source code documents are chunked at symbol boundaries.
+///
+/// # Arguments
+/// * `document` - The loaded document
+/// * `chunk_size` - Maximum size of each chunk in characters
+///
+/// # Returns
+/// * `Vec<DocumentChunk>` - A vector of chunks with their location
+pub fn chunk_document(document: &LoadedDocument, chunk_size: usize) -> Vec<DocumentChunk> {
+    let mut chunks = Vec::new();
+    let mut markdown = MarkdownChunker {
+        chunk_size,
+        headings: Vec::new(),
+    };
+
+    for section in &document.sections {
+        if let Some(language) = document.code {
+            chunks.extend(code::chunk_code(&section.text, language, chunk_size).into_iter().map(|mut chunk| {
+                chunk.location.extend(section.location.clone());
+                chunk.ocr = section.ocr;
+                chunk
+            }));
+        } else if document.markdown {
+            for (path, text) in markdown.chunk(&section.text) {
+                let mut location = section.location.clone();
+                if let Some(path) = path {
+                    location.insert("section".to_string(), path.into());
+                }
+                chunks.push(DocumentChunk {
+                    text,
+                    location,
+                    ocr: section.ocr,
+                    lang: None,
+                    parent: None,
+                    is_parent: false,
+                    sparse: None,
+                });
+            }
+        } else {
+            chunks.extend(
+                chunk_text(&section.text, chunk_size)
+                    .into_iter()
+                    .filter(|text| !text.is_empty())
+                    .map(|text| DocumentChunk {
+                        text,
+                        location: section.location.clone(),
+                        ocr: section.ocr,
+                        lang: None,
+                        parent: None,
+                        is_parent: false,
+                        sparse: None,
+                    }),
+            );
+        }
+    }
+
+    chunks
+}
+
+/// Groups consecutive chunks into parent sections, appended after the chunks
+///
+/// In `section` mode, the chunks of a parent section share the same location
+/// (heading path, page, slide...); in `fixed` mode, consecutive chunks are
+/// grouped whatever their location. A parent section never exceeds
+/// `max_size` characters, and a chunk alone in its group gets no parent
+/// section, since it would only repeat the chunk.
+///
+/// # Arguments
+/// * `chunks` - Chunks of the document, which get the position of their parent section
+/// * `config` - Parent sections configuration
+pub fn attach_parents(chunks: &mut Vec<DocumentChunk>, config: &ParentConfig) {
+    let mut groups: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+    let mut size = 0;
+    for (index, chunk) in chunks.iter().enumerate() {
+        let new_section = config.mode == ParentMode::Section && chunk.location != chunks[start].location;
+        if index > start && (new_section || size + chunk.text.len() + 1 > config.max_size) {
+            groups.push(start..index);
+            start = index;
+            size = 0;
+        }
+        size += chunk.text.len() + 1;
+    }
+    groups.push(start..chunks.len());
+
+    for group in groups.into_iter().filter(|group| group.len() > 1) {
+        let parent = DocumentChunk {
+            text: parent_text(&chunks[group.clone()]),
+            location: chunks[group.start].location.clone(),
+            ocr: chunks[group.clone()].iter().any(|chunk| chunk.ocr),
+            lang: None,
+            parent: None,
+            is_parent: true,
+            sparse: None,
+        };
+        let position = chunks.len();
+        for chunk in &mut chunks[group] {
+            chunk.parent = Some(position);
+        }
+        chunks.push(parent);
+    }
+}
+
+/// Joins the texts of the chunks of a parent section
+///
+/// The heading path starting every chunk of a Markdown section is kept once.
+fn parent_text(chunks: &[DocumentChunk]) -> String {
+    let mut text = String::new();
+    let mut previous: Option<&DocumentChunk> = None;
+    for chunk in chunks {
+        let mut body = chunk.text.as_str();
+        if let Some(previous) = previous
+            && previous.location.contains_key("section")
+            && previous.location.get("section") == chunk.location.get("section")
+            && let Some((heading, rest)) = body.split_once('\n')
+            && heading.starts_with('#')
+        {
+            body = rest;
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(body);
+        previous = Some(chunk);
+    }
+    text
+}
+
+/// Chunks Markdown text section by section, keeping track of the headings
+struct MarkdownChunker {
+    chunk_size: usize,
+    /// Current headings: (level, title)
+    headings: Vec<(usize, String)>,
+}
+
+impl MarkdownChunker {
+    /// Chunks Markdown text, returning each chunk with its heading path
+    fn chunk(&mut self, text: &str) -> Vec<(Option<String>, String)> {
+        let mut chunks = Vec::new();
+        let mut section = String::new();
+        let mut in_code_block = false;
+
+        for line in text.lines() {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_code_block = !in_code_block;
+            }
+
+            match parse_heading(line).filter(|_| !in_code_block) {
+                Some((level, title)) => {
+                    self.push_section(&mut chunks, &section);
+                    section.clear();
+                    self.headings.retain(|(parent_level, _)| *parent_level < level);
+                    self.headings.push((level, title));
+                }
+                None => {
+                    section.push_str(line);
+                    section.push('\n');
+                }
+            }
+        }
+        self.push_section(&mut chunks, &section);
+
+        chunks
+    }
+
+    /// Chunks the body of a section, prefixing each chunk with its heading path
+    fn push_section(&self, chunks: &mut Vec<(Option<String>, String)>, body: &str) {
+        if body.trim().is_empty() {
+            return;
+        }
+
+        let Some((level, _)) = self.headings.last() else {
+            chunks.extend(
+                chunk_text(body, self.chunk_size)
+                    .into_iter()
+                    .filter(|chunk| !chunk.is_empty())
+                    .map(|chunk| (None, chunk)),
+            );
+            return;
+        };
+        let path = self
+            .headings
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .collect::<Vec<_>>()
+            .join(" > ");
+        let context = format!("{} {}", "#".repeat(*level), path);
+
+        // Keep room for the heading path, but never less than half of the chunk size
+        let body_size = self.chunk_size.saturating_sub(context.len() + 1).max(self.chunk_size / 2);
+        for chunk in chunk_text(body, body_size) {
+            if !chunk.is_empty() {
+                chunks.push((Some(path.clone()), format!("{}\n{}", context, chunk)));
+            }
+        }
+    }
+}
+
+/// Parses an ATX heading line (`## Title`) into its level and title
+fn parse_heading(line: &str) -> Option<(usize, String)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    let title = rest.trim().trim_end_matches('#').trim();
+    (!title.is_empty()).then(|| (level, title.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(text: &str, section: &str) -> DocumentChunk {
+        let mut location = Map::new();
+        location.insert("section".to_string(), Value::from(section));
+        DocumentChunk {
+            text: text.to_string(),
+            location,
+            ..DocumentChunk::default()
+        }
+    }
+
+    fn config(mode: ParentMode, max_size: usize) -> ParentConfig {
+        ParentConfig {
+            enabled: true,
+            mode,
+            max_size,
+        }
+    }
+
+    fn parents(chunks: &[DocumentChunk]) -> Vec<Option<usize>> {
+        chunks.iter().map(|chunk| chunk.parent).collect()
+    }
+
+    #[test]
+    fn section_mode_groups_the_chunks_of_each_section() {
+        let mut chunks = vec![
+            chunk("a", "Intro"),
+            chunk("b", "Intro"),
+            chunk("c", "Usage"),
+            chunk("d", "Notes"),
+            chunk("e", "Notes"),
+        ];
+        attach_parents(&mut chunks, &config(ParentMode::Section, 2048));
+
+        // The single chunk of `Usage` gets no parent section
+        assert_eq!(chunks.len(), 7);
+        assert_eq!(parents(&chunks[..5]), [Some(5), Some(5), None, Some(6), Some(6)]);
+        assert!(chunks[5].is_parent && chunks[6].is_parent);
+        assert_eq!((chunks[5].text.as_str(), chunks[6].text.as_str()), ("a\nb", "d\ne"));
+        assert_eq!(chunks[6].location, chunks[3].location);
+        assert_eq!(chunks[5].parent, None);
+    }
+
+    #[test]
+    fn fixed_mode_groups_consecutive_chunks_up_to_the_maximum_size() {
+        // Each chunk counts for its length plus a separator: three of them fit in 12 characters
+        let mut chunks: Vec<DocumentChunk> = ["aaa", "bbb", "ccc", "ddd", "eee"]
+            .iter()
+            .zip(["A", "B", "C", "D", "E"])
+            .map(|(text, section)| chunk(text, section))
+            .collect();
+        attach_parents(&mut chunks, &config(ParentMode::Fixed, 12));
+
+        assert_eq!(parents(&chunks), [Some(5), Some(5), Some(5), Some(6), Some(6), None, None]);
+        assert_eq!(chunks[5].text, "aaa\nbbb\nccc");
+        assert_eq!(chunks[6].text, "ddd\neee");
+        assert_eq!(chunks[5].location.get("section"), Some(&Value::from("A")));
+    }
+
+    #[test]
+    fn oversized_chunks_stay_alone() {
+        let mut chunks = vec![chunk("a very long chunk", "Intro"), chunk("b", "Intro")];
+        attach_parents(&mut chunks, &config(ParentMode::Section, 4));
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(parents(&chunks), [None, None]);
+
+        let mut chunks = Vec::new();
+        attach_parents(&mut chunks, &config(ParentMode::Fixed, 4));
+        assert!(chunks.is_empty());
+    }
+
+    #[test]
+    fn parent_sections_keep_the_heading_path_once_and_the_ocr_flag() {
+        let mut chunks = vec![
+            chunk("# Guide > Install\nDownload the archive.", "Guide > Install"),
+            DocumentChunk {
+                ocr: true,
+                ..chunk("# Guide > Install\nRun the installer.", "Guide > Install")
+            },
+        ];
+        attach_parents(&mut chunks, &config(ParentMode::Section, 2048));
+        assert_eq!(chunks[2].text, "# Guide > Install\nDownload the archive.\nRun the installer.");
+        assert!(chunks[2].ocr);
+    }
+}
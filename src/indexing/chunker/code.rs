@@ -0,0 +1,499 @@
+//! Source code chunking at symbol boundaries.
+//!
+//! Source files are parsed with tree-sitter, and each top-level definition
+//! (function, struct, impl, class...) becomes a chunk of its own, with the
+//! comments and attributes written right above it. A definition larger than
+//! the chunk size is split at its members when it has some (the methods of an
+//! impl or a class), and at line boundaries otherwise. The code between
+//! definitions (imports, constants, statements) is grouped into chunks at line
+//! boundaries.
+//!
+//! Each chunk records its line range (`lines`) and, for definitions, the name
+//! of the symbol (`symbol`, qualified with its impl, class or module) and its
+//! kind (`kind`) in its location.
+
+use serde_json::Map;
+use tracing::warn;
+use tree_sitter::{Node, Parser};
+
+use crate::indexing::chunker::DocumentChunk;
+use crate::indexing::loader::CodeLanguage;
+
+/// Closing delimiters left alone at the end of a split definition, which are not worth a chunk
+const CLOSING_DELIMITERS: [char; 4] = ['}', ')', ']', ';'];
+
+/// A definition of the source code: function, method, struct, impl, class...
+#[derive(Clone)]
+struct Symbol {
+    /// Name of the symbol, qualified with its enclosing impl, class or module
+    name: String,
+    /// Kind of the symbol (`function`, `method`, `struct`, `impl`, `class`...)
+    kind: &'static str,
+}
+
+/// Splits a source file into chunks at symbol boundaries
+///
+/// Files that cannot be parsed are split at line boundaries, with their line
+/// ranges but without symbols.
+///
+/// # Arguments
+/// * `source` - Source code of the file
+/// * `language` - Programming language of the file
+/// * `chunk_size` - Maximum size of each chunk in characters
+///
+/// # Returns
+/// * `Vec<DocumentChunk>` - Chunks with their symbol and line range
+pub fn chunk_code(source: &str, language: CodeLanguage, chunk_size: usize) -> Vec<DocumentChunk> {
+    let mut chunker = CodeChunker {
+        language,
+        lines: source.lines().collect(),
+        chunk_size,
+        chunks: Vec::new(),
+    };
+    if chunker.lines.is_empty() {
+        return Vec::new();
+    }
+
+    let mut parser = Parser::new();
+    let tree = parser
+        .set_language(&language.grammar())
+        .ok()
+        .and_then(|_| parser.parse(source, None));
+    match tree {
+        Some(tree) => {
+            let root = tree.root_node();
+            let items: Vec<Node> = root.named_children(&mut root.walk()).collect();
+            chunker.chunk_items(&items, None, None, chunker.lines.len() - 1);
+        }
+        None => {
+            warn!("Failed to parse {} source code, chunking it by lines", language.name());
+            chunker.push_lines(0, chunker.lines.len() - 1, None);
+        }
+    }
+    chunker.chunks
+}
+
+/// Chunks the definitions of a source file
+struct CodeChunker<'a> {
+    language: CodeLanguage,
+    /// Lines of the source code
+    lines: Vec<&'a str>,
+    chunk_size: usize,
+    chunks: Vec<DocumentChunk>,
+}
+
+impl CodeChunker<'_> {
+    /// Chunks a sequence of sibling nodes (the items of a file, or the members of a definition)
+    ///
+    /// # Arguments
+    /// * `items` - The nodes, in source order
+    /// * `scope` - The definition holding the nodes, None at the top level
+    /// * `header` - Rows of the definition before its members (signature, opening brace)
+    /// * `last_row` - Last row of the definition or file, for the code after the last node
+    fn chunk_items(&mut self, items: &[Node], scope: Option<&Symbol>, header: Option<(usize, usize)>, last_row: usize) {
+        // Rows of the code waiting to be chunked without a symbol of its own
+        let mut loose = None;
+        // Rows of the comments and attributes waiting for the definition they document,
+        // starting with the header, which goes with the first member
+        let mut preamble = header;
+        let mut previous_end = header.map(|(_, end)| end);
+
+        for node in items {
+            let mut start = node.start_position().row;
+            let end = node.end_position().row;
+            // A node sharing a line with the previous one stays with it
+            if let Some(previous_end) = previous_end
+                && start <= previous_end
+            {
+                if end <= previous_end {
+                    continue;
+                }
+                start = previous_end + 1;
+            }
+            previous_end = Some(end);
+
+            if self.is_preamble(node) {
+                preamble = match preamble {
+                    Some((first, last)) if start <= last + 1 => Some((first, end)),
+                    Some(rows) => {
+                        extend_rows(&mut loose, rows);
+                        Some((start, end))
+                    }
+                    None => Some((start, end)),
+                };
+                continue;
+            }
+
+            match self.symbol(node, scope) {
+                Some(symbol) => {
+                    let start = match preamble.take() {
+                        Some((first, last)) if start <= last + 1 => first,
+                        Some(rows) => {
+                            extend_rows(&mut loose, rows);
+                            start
+                        }
+                        None => start,
+                    };
+                    if let Some((first, last)) = loose.take() {
+                        self.push_lines(first, last, scope);
+                    }
+                    self.push_definition(*node, start, end, symbol);
+                }
+                None => {
+                    if let Some(rows) = preamble.take() {
+                        extend_rows(&mut loose, rows);
+                    }
+                    extend_rows(&mut loose, (start, end));
+                }
+            }
+        }
+
+        if let Some(rows) = preamble {
+            extend_rows(&mut loose, rows);
+        }
+        let tail_start = previous_end.map_or(0, |end| end + 1);
+        if tail_start <= last_row && !self.is_closing(tail_start, last_row) {
+            extend_rows(&mut loose, (tail_start, last_row));
+        }
+        if let Some((first, last)) = loose {
+            self.push_lines(first, last, scope);
+        }
+    }
+
+    /// Chunks a definition, split at its members or lines if it is too large
+    fn push_definition(&mut self, node: Node, start: usize, end: usize, symbol: Symbol) {
+        if self.size(start, end) <= self.chunk_size {
+            self.push_chunk(start, end, Some(&symbol));
+            return;
+        }
+
+        let Some(body) = self.container_body(node) else {
+            self.push_lines(start, end, Some(&symbol));
+            return;
+        };
+        let members: Vec<Node> = body.named_children(&mut body.walk()).collect();
+        let header_end = members
+            .first()
+            .map_or(end, |member| member.start_position().row.saturating_sub(1).max(start));
+        self.chunk_items(&members, Some(&symbol), Some((start, header_end)), end);
+    }
+
+    /// Splits rows into chunks of at most `chunk_size` characters at line boundaries
+    ///
+    /// The chunks have about the same size, so that the last one is not a few
+    /// stray lines.
+    fn push_lines(&mut self, start: usize, end: usize, symbol: Option<&Symbol>) {
+        let total = self.size(start, end);
+        let target = total.div_ceil(total.div_ceil(self.chunk_size.max(1)).max(1));
+        let mut first = start;
+        let mut size = 0;
+        for row in start..=end {
+            let line_size = self.lines[row].len() + 1;
+            if size > 0 && (size >= target || size + line_size > self.chunk_size) {
+                self.push_chunk(first, row - 1, symbol);
+                first = row;
+                size = 0;
+            }
+            size += line_size;
+        }
+        self.push_chunk(first, end, symbol);
+    }
+
+    /// Adds a chunk made of rows, without their surrounding blank lines
+    fn push_chunk(&mut self, start: usize, end: usize, symbol: Option<&Symbol>) {
+        let Some(first) = (start..=end).find(|row| !self.lines[*row].trim().is_empty()) else {
+            return;
+        };
+        let last = (first..=end)
+            .rev()
+            .find(|row| !self.lines[*row].trim().is_empty())
+            .unwrap_or(first);
+
+        let mut location = Map::new();
+        if let Some(symbol) = symbol {
+            location.insert("symbol".to_string(), symbol.name.clone().into());
+            location.insert("kind".to_string(), symbol.kind.into());
+        }
+        location.insert("lines".to_string(), format!("{}-{}", first + 1, last + 1).into());
+        self.chunks.push(DocumentChunk {
+            text: self.lines[first..=last].join("\n"),
+            location,
+            ocr: false,
+            lang: None,
+            parent: None,
+            is_parent: false,
+            sparse: None,
+        });
+    }
+
+    /// Size of rows in characters, line breaks included
+    fn size(&self, start: usize, end: usize) -> usize {
+        self.lines[start..=end].iter().map(|line| line.len() + 1).sum()
+    }
+
+    /// Checks if rows only hold closing delimiters
+    fn is_closing(&self, start: usize, end: usize) -> bool {
+        self.lines[start..=end]
+            .iter()
+            .all(|line| line.chars().all(|c| c.is_whitespace() || CLOSING_DELIMITERS.contains(&c)))
+    }
+
+    /// Checks if a node documents or annotates the definition following it
+    fn is_preamble(&self, node: &Node) -> bool {
+        match self.language {
+            CodeLanguage::Rust => matches!(node.kind(), "line_comment" | "block_comment" | "attribute_item"),
+            _ => matches!(node.kind(), "comment" | "decorator"),
+        }
+    }
+
+    /// Gets the symbol defined by a node, None for code that is not a definition
+    fn symbol(&self, node: &Node, scope: Option<&Symbol>) -> Option<Symbol> {
+        let node = definition(*node);
+        let in_type = scope.is_some_and(|scope| matches!(scope.kind, "impl" | "trait" | "class" | "interface"));
+        let function = if in_type { "method" } else { "function" };
+
+        let (name, kind) = match self.language {
+            CodeLanguage::Rust => match node.kind() {
+                "function_item" | "function_signature_item" => (self.field_text(node, "name")?, function),
+                "impl_item" => (self.type_name(node.child_by_field_name("type")?), "impl"),
+                "struct_item" => (self.field_text(node, "name")?, "struct"),
+                "enum_item" => (self.field_text(node, "name")?, "enum"),
+                "union_item" => (self.field_text(node, "name")?, "union"),
+                "trait_item" => (self.field_text(node, "name")?, "trait"),
+                "mod_item" if node.child_by_field_name("body").is_some() => (self.field_text(node, "name")?, "module"),
+                "macro_definition" => (self.field_text(node, "name")?, "macro"),
+                _ => return None,
+            },
+            CodeLanguage::Python => match node.kind() {
+                "function_definition" => (self.field_text(node, "name")?, function),
+                "class_definition" => (self.field_text(node, "name")?, "class"),
+                _ => return None,
+            },
+            CodeLanguage::TypeScript | CodeLanguage::Tsx | CodeLanguage::JavaScript => match node.kind() {
+                "function_declaration" | "generator_function_declaration" | "function_signature" => {
+                    (self.field_text(node, "name")?, "function")
+                }
+                "method_definition" | "method_signature" | "abstract_method_signature" => {
+                    (self.field_text(node, "name")?, "method")
+                }
+                "class_declaration" | "abstract_class_declaration" => (self.field_text(node, "name")?, "class"),
+                "interface_declaration" => (self.field_text(node, "name")?, "interface"),
+                "enum_declaration" => (self.field_text(node, "name")?, "enum"),
+                "type_alias_declaration" => (self.field_text(node, "name")?, "type"),
+                "internal_module" | "module" => (self.field_text(node, "name")?, "module"),
+                "lexical_declaration" | "variable_declaration" => self.function_variable(node)?,
+                _ => return None,
+            },
+            CodeLanguage::Go => match node.kind() {
+                "function_declaration" => (self.field_text(node, "name")?, "function"),
+                "method_declaration" => {
+                    let receiver = node
+                        .child_by_field_name("receiver")
+                        .and_then(|receiver| receiver.named_child(0))
+                        .and_then(|parameter| parameter.child_by_field_name("type"))
+                        .map(|receiver_type| self.type_name(receiver_type));
+                    let name = self.field_text(node, "name")?;
+                    match receiver {
+                        Some(receiver) => (format!("{}.{}", receiver, name), "method"),
+                        None => (name, "method"),
+                    }
+                }
+                "type_declaration" if node.named_child_count() == 1 => {
+                    let spec = node.named_child(0)?;
+                    let kind = match spec.child_by_field_name("type").map(|spec_type| spec_type.kind()) {
+                        Some("struct_type") => "struct",
+                        Some("interface_type") => "interface",
+                        _ => "type",
+                    };
+                    (self.field_text(spec, "name")?, kind)
+                }
+                _ => return None,
+            },
+        };
+
+        let name = match scope {
+            Some(scope) => format!("{}{}{}", scope.name, self.separator(), name),
+            None => name,
+        };
+        Some(Symbol { name, kind })
+    }
+
+    /// Gets the function or class assigned to a variable (`const handler = () => {...}`)
+    fn function_variable(&self, node: Node) -> Option<(String, &'static str)> {
+        if node.named_child_count() != 1 {
+            return None;
+        }
+        let declarator = node.named_child(0).filter(|child| child.kind() == "variable_declarator")?;
+        let kind = match declarator.child_by_field_name("value")?.kind() {
+            "arrow_function" | "function_expression" | "function" | "generator_function" => "function",
+            "class" => "class",
+            _ => return None,
+        };
+        Some((self.field_text(declarator, "name")?, kind))
+    }
+
+    /// Gets the body holding the members of a definition that can be split at its members
+    fn container_body<'tree>(&self, node: Node<'tree>) -> Option<Node<'tree>> {
+        let node = definition(node);
+        let container = match self.language {
+            CodeLanguage::Rust => matches!(node.kind(), "impl_item" | "trait_item" | "mod_item"),
+            CodeLanguage::Python => node.kind() == "class_definition",
+            CodeLanguage::TypeScript | CodeLanguage::Tsx | CodeLanguage::JavaScript => matches!(
+                node.kind(),
+                "class_declaration" | "abstract_class_declaration" | "interface_declaration" | "internal_module" | "module"
+            ),
+            CodeLanguage::Go => false,
+        };
+        if container { node.child_by_field_name("body") } else { None }
+    }
+
+    /// Gets the text of a field of a node
+    fn field_text(&self, node: Node, field: &str) -> Option<String> {
+        let child = node.child_by_field_name(field)?;
+        Some(self.text(child).to_string())
+    }
+
+    /// Gets the name of a type without its generic parameters or pointer (`Cache<K, V>` -> `Cache`)
+    fn type_name(&self, node: Node) -> String {
+        let text = self.text(node).trim_start_matches(['*', '&']);
+        text.split(['<', '[']).next().unwrap_or(text).trim().to_string()
+    }
+
+    /// Gets the source text of a node, limited to its first line
+    fn text(&self, node: Node) -> &str {
+        let position = node.start_position();
+        let line = self.lines.get(position.row).copied().unwrap_or("");
+        let start = position.column.min(line.len());
+        let end = if node.end_position().row == position.row { node.end_position().column } else { line.len() };
+        line.get(start..end.clamp(start, line.len())).unwrap_or("")
+    }
+
+    /// Separator between the name of a definition and the names of its members
+    fn separator(&self) -> &'static str {
+        match self.language {
+            CodeLanguage::Rust => "::",
+            _ => ".",
+        }
+    }
+}
+
+/// Gets the definition wrapped by a node (decorators, `export`, namespace statement)
+fn definition(node: Node) -> Node {
+    let wrapped = match node.kind() {
+        "decorated_definition" => node.child_by_field_name("definition"),
+        "export_statement" => node.child_by_field_name("declaration"),
+        "expression_statement" => node.named_child(0).filter(|child| child.kind() == "internal_module"),
+        _ => None,
+    };
+    wrapped.unwrap_or(node)
+}
+
+/// Extends a run of rows to the end of other rows following it
+fn extend_rows(run: &mut Option<(usize, usize)>, rows: (usize, usize)) {
+    *run = Some(match *run {
+        Some((first, _)) => (first, rows.1),
+        None => rows,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gets the symbol, kind and line range of each chunk
+    fn outline(chunks: &[DocumentChunk]) -> Vec<(String, String, String)> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                let field = |key: &str| chunk.location.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+                (field("symbol"), field("kind"), field("lines"))
+            })
+            .collect()
+    }
+
+    const RUST: &str = "use std::fmt;
+
+/// A cache entry
+#[derive(Debug)]
+pub struct Entry {
+    key: String,
+}
+
+impl Entry {
+    /// Creates an entry
+    pub fn new(key: &str) -> Self {
+        Entry { key: key.to_string() }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+const LIMIT: usize = 10;
+";
+
+    fn expected(outline: &[(&str, &str, &str)]) -> Vec<(String, String, String)> {
+        outline
+            .iter()
+            .map(|(symbol, kind, lines)| (symbol.to_string(), kind.to_string(), lines.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rust_definitions_are_chunked_with_their_doc_comments() {
+        let chunks = chunk_code(RUST, CodeLanguage::Rust, 1000);
+        assert_eq!(
+            outline(&chunks),
+            expected(&[("", "", "1-1"), ("Entry", "struct", "3-7"), ("Entry", "impl", "9-18"), ("", "", "20-20")])
+        );
+        assert!(chunks[1].text.starts_with("/// A cache entry\n#[derive(Debug)]\npub struct Entry {"));
+        assert_eq!(chunks[3].text, "const LIMIT: usize = 10;");
+    }
+
+    #[test]
+    fn large_definitions_are_split_at_their_members() {
+        let chunks = chunk_code(RUST, CodeLanguage::Rust, 120);
+        assert_eq!(
+            outline(&chunks),
+            expected(&[
+                ("", "", "1-1"),
+                ("Entry", "struct", "3-7"),
+                ("Entry::new", "method", "9-13"),
+                ("Entry::key", "method", "15-17"),
+                ("", "", "20-20"),
+            ])
+        );
+        // The header of the impl goes with its first member, its closing brace is dropped
+        assert!(chunks[2].text.starts_with("impl Entry {\n    /// Creates an entry\n    pub fn new"));
+        assert!(chunks[3].text.ends_with("&self.key\n    }"));
+    }
+
+    #[test]
+    fn python_methods_are_qualified_with_their_class() {
+        let source = "import os\n\n\n@dataclass\nclass Config:\n    path: str\n\n    def load(self):\n        \
+                      return os.path.exists(self.path)\n\n\ndef main():\n    Config('a').load()\n";
+        let chunks = chunk_code(source, CodeLanguage::Python, 80);
+        assert_eq!(
+            outline(&chunks),
+            expected(&[
+                ("", "", "1-1"),
+                ("Config", "class", "4-6"),
+                ("Config.load", "method", "8-9"),
+                ("main", "function", "12-13"),
+            ])
+        );
+        assert_eq!(chunks[1].text, "@dataclass\nclass Config:\n    path: str");
+    }
+
+    #[test]
+    fn typescript_exported_and_assigned_functions_are_symbols() {
+        let source = "export function parse(input: string): number {\n  return Number(input);\n}\n\n\
+                      const handler = async () => {\n  await parse('1');\n};\n";
+        let chunks = chunk_code(source, CodeLanguage::TypeScript, 1000);
+        assert_eq!(
+            outline(&chunks),
+            expected(&[("parse", "function", "1-3"), ("handler", "function", "5-7")])
+        );
+    }
+}
@@ -0,0 +1,419 @@
+//! Data sources module for listing and fetching the files to index.
+//!
+//! A data source lists its entries, each with an identifier and a version
+//! that changes whenever its content changes, and fetches the content of an
+//! entry as bytes. The implementations are:
+//! - `fs`: the files of a local directory tree (version: modification time and size)
+//! - `archive`: the files of a ZIP or tar archive (version: CRC or modification time, and size)
+//! - `s3`: the objects of an S3-compatible bucket (version: ETag)
+//! - `git`: the files of a commit of a local git repository (version: blob SHA,
+//!   changes: `git diff` from the last indexed commit)
+//!
+//! Several named sources can be configured with `[[data_sources.sources]]`
+//! and are indexed in the same run. Their entries are tracked, and stored in
+//! the `source` payload field, as `<source id>:<entry id>`. Without named
+//! sources, the `[indexing] path` directory (or the `[data_sources.git]`
+//! repository) is the only source, with an empty identifier, and its entries
+//! are tracked by their bare name as before.
+//!
+//! Archives listed by any source (`.zip`, `.tar`, `.tar.gz`, `.tgz`) are
+//! replaced by their members, tracked as `<archive>!/<path inside>` (e.g.
+//! `docs.zip!/guide/intro.pdf`) and dispatched to the loader of their own
+//! format. An archive that cannot be expanded (corrupted, or over the size
+//! limit) is reported as failed, and its members indexed before are kept.
+//!
+//! Sources are blocking: they are listed and fetched outside of the async
+//! runtime (on the blocking thread pool for the pipeline).
+
+mod archive;
+mod fs;
+mod git;
+mod s3;
+
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::AppError;
+use crate::indexing::file_tracker::FileTracker;
+use crate::{Config, SourceKind};
+
+pub use archive::{MEMBER_SEPARATOR, ArchiveLimits, ArchiveMembers, ArchiveSource, UnreadableArchive};
+pub use fs::FsSource;
+pub use git::GitSource;
+pub use s3::S3Source;
+
+/// An entry of a data source: a file, an archive member, an object...
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceEntry {
+    /// Identifier of the entry in its source (file name, path, object key)
+    pub id: String,
+    /// Version of the entry, which changes whenever its content changes
+    pub version: String,
+}
+
+/// Trait for the sources of the files to index
+pub trait DataSource: Send + Sync {
+    /// Gets the identifier of the source, empty for the default source
+    fn id(&self) -> &str;
+
+    /// Lists the entries of the source
+    ///
+    /// # Returns
+    /// * `Result<Vec<SourceEntry>, AppError>` - The entries with their version, error if the source cannot be read
+    fn list(&self) -> Result<Vec<SourceEntry>, AppError>;
+
+    /// Fetches the content of an entry
+    ///
+    /// # Arguments
+    /// * `entry_id` - Identifier of the entry
+    ///
+    /// # Returns
+    /// * `Result<Vec<u8>, AppError>` - The content of the entry, error if it cannot be read
+    fn fetch(&self, entry_id: &str) -> Result<Vec<u8>, AppError>;
+
+    /// Gets the path of an entry on the local filesystem, so that it is loaded in place
+    fn local_path(&self, _entry_id: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Gets the local directory holding the entries, watched in watch mode
+    fn local_root(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Gets the metadata recorded on the points of every entry (e.g. the indexed commit)
+    fn metadata(&self) -> Map<String, Value> {
+        Map::new()
+    }
+
+    /// Gets the state of the source at its last listing (e.g. the listed commit)
+    ///
+    /// The tracker records it once every entry listed is indexed (see
+    /// `FileTracker::record_checkpoints`), and gives it back to
+    /// `changed_since` on the next run.
+    fn checkpoint(&self) -> Option<String> {
+        None
+    }
+
+    /// Lists the entries changed between a checkpoint and the last listing
+    ///
+    /// # Arguments
+    /// * `checkpoint` - Checkpoint recorded after a previous run
+    ///
+    /// # Returns
+    /// * `Option<HashSet<String>>` - Identifiers of the entries added, modified or deleted since, None if unknown
+    fn changed_since(&self, _checkpoint: &str) -> Option<HashSet<String>> {
+        None
+    }
+}
+
+/// An entry of a data source to index
+#[derive(Clone)]
+pub struct SourceFile {
+    /// Name of the file in the tracker and in the `source` payload field
+    pub key: String,
+    /// Source of the entry
+    pub source: Arc<dyn DataSource>,
+    /// Entry in its source
+    pub entry: SourceEntry,
+    /// Version of the archive holding the entry, for archive members
+    pub archive_version: Option<String>,
+}
+
+impl SourceFile {
+    /// Creates a file to index from an entry of a source
+    pub fn new(source: &Arc<dyn DataSource>, entry: SourceEntry) -> Self {
+        SourceFile {
+            key: entry_key(source.id(), &entry.id),
+            source: source.clone(),
+            entry,
+            archive_version: None,
+        }
+    }
+
+    /// Creates a member of an archive listed by a source
+    fn member(members: &Arc<dyn DataSource>, entry: SourceEntry, archive: &SourceFile) -> Self {
+        SourceFile {
+            archive_version: Some(archive.entry.version.clone()),
+            ..SourceFile::new(members, entry)
+        }
+    }
+
+    /// Fetches the content of the file from its source
+    pub fn fetch(&self) -> Result<Vec<u8>, AppError> {
+        self.source.fetch(&self.entry.id)
+    }
+}
+
+/// Gets the name under which an entry is tracked and stored
+///
+/// # Arguments
+/// * `source_id` - Identifier of the source, empty for the default source
+/// * `entry_id` - Identifier of the entry in its source
+///
+/// # Returns
+/// * `String` - `<source id>:<entry id>`, or the entry identifier alone for the default source
+pub fn entry_key(source_id: &str, entry_id: &str) -> String {
+    if source_id.is_empty() {
+        entry_id.to_string()
+    } else {
+        format!("{}:{}", source_id, entry_id)
+    }
+}
+
+/// Creates the data sources of the configuration
+///
+/// # Arguments
+/// * `config` - Configuration object
+///
+/// # Returns
+/// * `Result<Vec<Arc<dyn DataSource>>, AppError>` - The sources, error if their configuration is invalid
+pub fn open_sources(config: &Config) -> Result<Vec<Arc<dyn DataSource>>, AppError> {
+    let data_sources = &config.data_sources;
+    if data_sources.sources.is_empty() {
+        let source: Arc<dyn DataSource> = match &data_sources.git {
+            Some(git) => Arc::new(GitSource::new("", git)),
+            None => Arc::new(FsSource::new("", &config.indexing.path)),
+        };
+        return Ok(vec![source]);
+    }
+
+    let limits = ArchiveLimits::from_config(&config.indexing);
+    let mut ids = HashSet::new();
+    let mut sources: Vec<Arc<dyn DataSource>> = Vec::new();
+    for source in &data_sources.sources {
+        if source.id.is_empty() || source.id.contains(':') {
+            return Err(AppError::Config(format!(
+                "Invalid data source id '{}': it must be non-empty and without ':'",
+                source.id
+            )));
+        }
+        if !ids.insert(source.id.as_str()) {
+            return Err(AppError::Config(format!("Duplicate data source id '{}'", source.id)));
+        }
+        let id = source.id.as_str();
+        sources.push(match &source.kind {
+            SourceKind::Fs { path } => Arc::new(FsSource::new(id, path)),
+            SourceKind::Archive { path } => Arc::new(ArchiveSource::new(id, path, limits)),
+            SourceKind::S3(s3) => Arc::new(S3Source::new(id, s3)?),
+            SourceKind::Git(git) => Arc::new(GitSource::new(id, git)),
+        });
+    }
+    Ok(sources)
+}
+
+/// Lists the entries of all the sources
+///
+/// A source that cannot be listed fails the whole listing: its entries
+/// would otherwise be considered as deleted and removed from the index.
+///
+/// # Arguments
+/// * `sources` - Data sources to list
+///
+/// # Returns
+/// * `Result<Vec<SourceFile>, AppError>` - The entries of every source
+pub fn list_sources(sources: &[Arc<dyn DataSource>]) -> Result<Vec<SourceFile>, AppError> {
+    let mut files = Vec::new();
+    for source in sources {
+        let entries = source.list()?;
+        if !source.id().is_empty() {
+            info!("Data source '{}': {} entries", source.id(), entries.len());
+        }
+        files.extend(entries.into_iter().map(|entry| SourceFile::new(source, entry)));
+    }
+    Ok(files)
+}
+
+/// Gets the tracked files that are no longer listed by the data sources
+///
+/// The tracked files of a source that listed no entry at all are kept: an
+/// empty listing of a source that held indexed files is far more likely an
+/// unmounted or misconfigured source than the deletion of all its files, and
+/// sweeping it would wipe its whole index. A warning is logged instead. The
+/// tracked files of no configured source (a source removed from the
+/// configuration) are returned.
+///
+/// # Arguments
+/// * `sources` - Data sources of the configuration
+/// * `files` - Files listed by the sources, with the archives expanded
+/// * `tracker` - File tracker
+///
+/// # Returns
+/// * `Vec<String>` - The tracked files to remove from the index
+pub fn deleted_files(sources: &[Arc<dyn DataSource>], files: &[SourceFile], tracker: &FileTracker) -> Vec<String> {
+    let listed: HashSet<&str> = files.iter().map(|file| file.key.as_str()).collect();
+    let prefixes: Vec<(&str, String)> = sources
+        .iter()
+        .map(|source| (source.id(), entry_key(source.id(), "")))
+        .collect();
+    let empty: HashSet<&str> = prefixes
+        .iter()
+        .filter(|(_, prefix)| !files.iter().any(|file| file.key.starts_with(prefix)))
+        .map(|(id, _)| *id)
+        .collect();
+
+    let mut kept: Vec<(&str, usize)> = Vec::new();
+    let mut deleted = Vec::new();
+    for file_name in tracker.tracked_files() {
+        if listed.contains(file_name.as_str()) {
+            continue;
+        }
+        let owner = prefixes
+            .iter()
+            .find(|(_, prefix)| file_name.starts_with(prefix))
+            .map(|(id, _)| *id);
+        match owner {
+            Some(id) if empty.contains(id) => match kept.iter_mut().find(|(kept_id, _)| *kept_id == id) {
+                Some((_, count)) => *count += 1,
+                None => kept.push((id, 1)),
+            },
+            _ => deleted.push(file_name),
+        }
+    }
+    for (id, count) in kept {
+        warn!(
+            "Data source '{}' listed no entries while {} of its files are tracked, not removing them from the index",
+            if id.is_empty() { "default" } else { id },
+            count
+        );
+    }
+    deleted
+}
+
+/// Replaces the archives among the listed files by their members
+///
+/// An archive whose version did not change since all its members were
+/// tracked (see `FileTracker::record_archives`) is not opened again: its
+/// tracked members are returned with their tracked version, and only fetched
+/// if they need to be indexed again. An archive that cannot be expanded is
+/// kept as a file whose fetch fails with the reason, so that it is reported
+/// as failed, along with the members of the archive already tracked, so that
+/// they are not removed from the index.
+///
+/// # Arguments
+/// * `files` - Listed files
+/// * `limits` - Limits on the nesting depth and the uncompressed size of the archives
+/// * `tracker` - File tracker, holding the members indexed before
+///
+/// # Returns
+/// * `Vec<SourceFile>` - The files, with the archives expanded
+pub fn expand_archives(files: Vec<SourceFile>, limits: ArchiveLimits, tracker: &FileTracker) -> Vec<SourceFile> {
+    if limits.max_depth == 0 {
+        return files;
+    }
+
+    let mut expanded = Vec::with_capacity(files.len());
+    for file in files {
+        if !archive::is_archive(&file.entry.id) {
+            expanded.push(file);
+            continue;
+        }
+        let members: Arc<dyn DataSource> = Arc::new(ArchiveMembers::new(file.source.clone(), &file.entry.id, limits));
+        if tracker.is_archive_unchanged(&file) {
+            expanded.extend(
+                tracked_members(tracker, &file)
+                    .into_iter()
+                    .map(|entry| SourceFile::member(&members, entry, &file)),
+            );
+            continue;
+        }
+        match members.list() {
+            Ok(entries) => {
+                info!("Archive {}: {} members", file.key, entries.len());
+                expanded.extend(entries.into_iter().map(|entry| SourceFile::member(&members, entry, &file)));
+            }
+            Err(e) => {
+                warn!("Failed to expand archive {}: {}", file.key, e);
+                let unreadable: Arc<dyn DataSource> = Arc::new(UnreadableArchive::new(
+                    file.source.clone(),
+                    match e {
+                        AppError::Document(message) => message,
+                        e => e.to_string(),
+                    },
+                ));
+                expanded.extend(
+                    tracked_members(tracker, &file)
+                        .into_iter()
+                        .map(|entry| SourceFile::new(&unreadable, entry)),
+                );
+                expanded.push(SourceFile::new(&unreadable, file.entry));
+            }
+        }
+    }
+    expanded
+}
+
+/// Lists the tracked members of an archive, with their tracked version
+///
+/// # Arguments
+/// * `tracker` - File tracker, holding the members indexed before
+/// * `archive` - Archive listed by a source
+///
+/// # Returns
+/// * `Vec<SourceEntry>` - The members of the archive in the tracker
+fn tracked_members(tracker: &FileTracker, archive: &SourceFile) -> Vec<SourceEntry> {
+    let prefix = format!("{}{}", archive.entry.id, archive::MEMBER_SEPARATOR);
+    let mut entries = Vec::new();
+    for key in tracker.tracked_files() {
+        let entry_id = match archive.source.id() {
+            "" => key.as_str(),
+            source_id => key.strip_prefix(source_id).and_then(|id| id.strip_prefix(':')).unwrap_or(""),
+        };
+        if entry_id.starts_with(&prefix) {
+            let version = tracker.get_file(&key).and_then(|tracked| tracked.version.clone());
+            entries.push(SourceEntry {
+                id: entry_id.to_string(),
+                version: version.unwrap_or_default(),
+            });
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(tracker: &mut FileTracker, key: &str) {
+        tracker.set_file(key.to_string(), "md5".to_string(), Vec::new());
+    }
+
+    #[test]
+    fn missing_directory_fails_the_listing_and_removes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("unmounted");
+        let sources: Vec<Arc<dyn DataSource>> = vec![Arc::new(FsSource::new("", missing.to_str().unwrap()))];
+        let mut tracker = FileTracker::new();
+        track(&mut tracker, "a.md");
+        track(&mut tracker, "sub/b.md");
+
+        assert!(list_sources(&sources).is_err());
+        assert!(deleted_files(&sources, &[], &tracker).is_empty());
+    }
+
+    #[test]
+    fn files_of_a_source_listing_nothing_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (docs, notes) = (dir.path().join("docs"), dir.path().join("notes"));
+        std::fs::create_dir(&docs).unwrap();
+        std::fs::create_dir(&notes).unwrap();
+        std::fs::write(docs.join("kept.md"), "kept").unwrap();
+        let sources: Vec<Arc<dyn DataSource>> = vec![
+            Arc::new(FsSource::new("docs", docs.to_str().unwrap())),
+            Arc::new(FsSource::new("notes", notes.to_str().unwrap())),
+        ];
+        let mut tracker = FileTracker::new();
+        for key in ["docs:kept.md", "docs:gone.md", "notes:a.md", "removed:b.md"] {
+            track(&mut tracker, key);
+        }
+
+        let files = list_sources(&sources).unwrap();
+        assert_eq!(files.len(), 1);
+        let mut deleted = deleted_files(&sources, &files, &tracker);
+        deleted.sort();
+        assert_eq!(deleted, vec!["docs:gone.md", "removed:b.md"]);
+    }
+}
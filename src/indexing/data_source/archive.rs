@@ -0,0 +1,768 @@
+//! Archive data sources.
+//!
+//! `ArchiveSource` lists the regular files of a local ZIP or tar archive
+//! (plain or gzipped, `.tar.gz` / `.tgz`), identified by their path in the
+//! archive. Their version is made of the CRC-32 (ZIP) or the modification
+//! time (tar) of the member and its size. Directories, links and members
+//! whose path leaves the archive root are left out.
+//!
+//! `ArchiveMembers` exposes the members of an archive found in another
+//! source, identified as `<archive>!/<path inside>` (`docs.zip!/guide/intro.pdf`).
+//! Archives nested in the archive are expanded in turn
+//! (`docs.zip!/old.tar.gz!/notes.md`), up to `archive_max_depth` levels.
+//! Members are extracted in memory when they are fetched: a local archive is
+//! read in place, and a remote one is downloaded once and kept for the run.
+//! A tar archive can only be read sequentially, so all its members are
+//! extracted in a single pass the first time one of them is fetched, and
+//! handed out as they are fetched; nested archives are extracted once and
+//! kept for the fetches of their members.
+//!
+//! Against zip bombs, the uncompressed size of an archive, nested archives
+//! included, is limited to `archive_max_size_mb`: the declared sizes of the
+//! members are checked when listing, nested archives are read within the
+//! size left by the enclosing archive, and reads stop at the limit whatever
+//! the headers declare.
+
+use flate2::read::GzDecoder;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use zip::ZipArchive;
+
+use super::{DataSource, SourceEntry};
+use crate::{AppError, IndexingConfig};
+
+/// Separator between an archive and the path of a member inside it
+pub const MEMBER_SEPARATOR: &str = "!/";
+
+/// Limits applied when expanding archives
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveLimits {
+    /// Maximum nesting depth of the expanded archives, 0 to leave archives unexpanded
+    pub max_depth: usize,
+    /// Maximum uncompressed size of an archive, nested archives included (bytes)
+    pub max_size: u64,
+}
+
+impl ArchiveLimits {
+    /// Gets the archive limits of the indexing configuration
+    pub fn from_config(config: &IndexingConfig) -> Self {
+        ArchiveLimits {
+            max_depth: config.archive_max_depth,
+            max_size: config.archive_max_size_mb.saturating_mul(1024 * 1024),
+        }
+    }
+}
+
+/// Format of an archive, from its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks if an entry is an archive whose members can be indexed (from its extension)
+pub fn is_archive(entry_id: &str) -> bool {
+    ArchiveFormat::from_name(entry_id).is_some()
+}
+
+/// Readers an archive can be read from
+trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Content of an archive shared between the reads of its members
+struct SharedContent(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for SharedContent {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Members of the tar archives of a source, extracted in a single pass
+#[derive(Default)]
+struct TarMembers(Mutex<HashMap<String, HashMap<String, Vec<u8>>>>);
+
+impl TarMembers {
+    /// Takes the content of a member of a tar archive, extracting all the members of the archive if needed
+    ///
+    /// # Arguments
+    /// * `archive_id` - Identifier of the archive
+    /// * `name` - Identifier of the member in the archive
+    /// * `extract` - Extracts the members of the archive
+    fn take(
+        &self,
+        archive_id: &str,
+        name: &str,
+        extract: impl FnOnce() -> Result<HashMap<String, Vec<u8>>, AppError>,
+    ) -> Result<Vec<u8>, AppError> {
+        let mut archives = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(content) = archives.get_mut(archive_id).and_then(|members| members.remove(name)) {
+            return Ok(content);
+        }
+        // Not extracted yet, or fetched before: extract the archive again
+        let mut members = extract()?;
+        let content = members.remove(name).ok_or_else(|| not_found(name))?;
+        archives.insert(archive_id.to_string(), members);
+        Ok(content)
+    }
+}
+
+/// Files of a local ZIP or tar archive
+pub struct ArchiveSource {
+    id: String,
+    path: PathBuf,
+    limits: ArchiveLimits,
+    tar_members: TarMembers,
+}
+
+impl ArchiveSource {
+    /// Creates an archive data source
+    ///
+    /// # Arguments
+    /// * `id` - Identifier of the source
+    /// * `path` - Path of the archive (`.zip`, `.tar`, `.tar.gz` or `.tgz`)
+    /// * `limits` - Limits on the uncompressed size of the archive
+    pub fn new(id: &str, path: &str, limits: ArchiveLimits) -> Self {
+        ArchiveSource {
+            id: id.to_string(),
+            path: PathBuf::from(path),
+            limits,
+            tar_members: TarMembers::default(),
+        }
+    }
+
+    fn format(&self) -> Result<ArchiveFormat, AppError> {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(ArchiveFormat::from_name)
+            .ok_or_else(|| {
+                AppError::Config(format!(
+                    "Unknown archive format for '{}' (expected .zip, .tar, .tar.gz or .tgz)",
+                    self.path.display()
+                ))
+            })
+    }
+
+    fn open(&self) -> Result<BufReader<File>, AppError> {
+        Ok(BufReader::new(File::open(&self.path)?))
+    }
+}
+
+impl DataSource for ArchiveSource {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn list(&self) -> Result<Vec<SourceEntry>, AppError> {
+        let mut budget = self.limits.max_size;
+        let members = list_members(self.format()?, self.open()?, &mut budget)
+            .map_err(|e| archive_error(&self.path.display().to_string(), e))?;
+        Ok(members.into_iter().map(|(entry, _)| entry).collect())
+    }
+
+    fn fetch(&self, entry_id: &str) -> Result<Vec<u8>, AppError> {
+        let format = self.format()?;
+        let content = match format {
+            ArchiveFormat::Zip => read_member(format, self.open()?, entry_id, self.limits.max_size),
+            ArchiveFormat::Tar | ArchiveFormat::TarGz => self.tar_members.take("", entry_id, || {
+                extract_tar(format, self.open()?, self.limits.max_size)
+            }),
+        };
+        content.map_err(|e| archive_error(&self.path.display().to_string(), e))
+    }
+}
+
+/// Members of an archive found in another data source
+pub struct ArchiveMembers {
+    parent: Arc<dyn DataSource>,
+    /// Identifier of the archive in the parent source
+    archive_id: String,
+    limits: ArchiveLimits,
+    /// Content of the archive, kept once fetched when it is not a local file
+    content: Mutex<Option<Arc<Vec<u8>>>>,
+    /// Content of the nested archives extracted so far, by identifier
+    nested: Mutex<HashMap<String, Arc<Vec<u8>>>>,
+    tar_members: TarMembers,
+}
+
+impl ArchiveMembers {
+    /// Creates the source of the members of an archive
+    ///
+    /// # Arguments
+    /// * `parent` - Source holding the archive
+    /// * `archive_id` - Identifier of the archive in the parent source
+    /// * `limits` - Limits on the nesting depth and the uncompressed size of the archive
+    pub fn new(parent: Arc<dyn DataSource>, archive_id: &str, limits: ArchiveLimits) -> Self {
+        ArchiveMembers {
+            parent,
+            archive_id: archive_id.to_string(),
+            limits,
+            content: Mutex::new(None),
+            nested: Mutex::new(HashMap::new()),
+            tar_members: TarMembers::default(),
+        }
+    }
+
+    /// Opens the archive, in place when it is a local file
+    fn open(&self) -> Result<Box<dyn ReadSeek>, AppError> {
+        if let Some(path) = self.parent.local_path(&self.archive_id) {
+            return Ok(Box::new(BufReader::new(File::open(path)?)));
+        }
+        let mut content = self.content.lock().unwrap_or_else(|e| e.into_inner());
+        let content = match &*content {
+            Some(content) => content.clone(),
+            None => content.insert(Arc::new(self.parent.fetch(&self.archive_id)?)).clone(),
+        };
+        Ok(Box::new(Cursor::new(SharedContent(content))))
+    }
+
+    /// Opens the archive of the parent source or a nested archive already extracted
+    ///
+    /// # Arguments
+    /// * `archive_id` - Identifier of the archive (`docs.zip`, `docs.zip!/old.tar.gz`)
+    fn open_archive(&self, archive_id: &str) -> Result<Box<dyn ReadSeek>, AppError> {
+        if archive_id == self.archive_id {
+            return self.open();
+        }
+        let nested = self.nested.lock().unwrap_or_else(|e| e.into_inner());
+        let content = nested
+            .get(archive_id)
+            .cloned()
+            .ok_or_else(|| AppError::Document(format!("'{}' was not extracted", archive_id)))?;
+        Ok(Box::new(Cursor::new(SharedContent(content))))
+    }
+
+    /// Reads a member of the archive of the parent source or of a nested archive
+    ///
+    /// # Arguments
+    /// * `archive_id` - Identifier of the archive holding the member
+    /// * `format` - Format of the archive
+    /// * `name` - Identifier of the member in the archive
+    fn read_archive_member(&self, archive_id: &str, format: ArchiveFormat, name: &str) -> Result<Vec<u8>, AppError> {
+        match format {
+            ArchiveFormat::Zip => read_member(format, self.open_archive(archive_id)?, name, self.limits.max_size),
+            ArchiveFormat::Tar | ArchiveFormat::TarGz => self.tar_members.take(archive_id, name, || {
+                extract_tar(format, self.open_archive(archive_id)?, self.limits.max_size)
+            }),
+        }
+    }
+
+    /// Extracts a nested archive, unless it was already extracted
+    ///
+    /// # Arguments
+    /// * `archive_id` - Identifier of the archive holding the nested archive
+    /// * `format` - Format of that archive
+    /// * `name` - Identifier of the nested archive in it
+    ///
+    /// # Returns
+    /// * `Result<String, AppError>` - Identifier of the nested archive if successful, error otherwise
+    fn extract_nested(&self, archive_id: &str, format: ArchiveFormat, name: &str) -> Result<String, AppError> {
+        let nested_id = format!("{}{}{}", archive_id, MEMBER_SEPARATOR, name);
+        let extracted = self.nested.lock().unwrap_or_else(|e| e.into_inner()).contains_key(&nested_id);
+        if !extracted {
+            let content = self.read_archive_member(archive_id, format, name)?;
+            self.nested
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(nested_id.clone(), Arc::new(content));
+        }
+        Ok(nested_id)
+    }
+
+    /// Lists the members of an archive, expanding the nested archives
+    ///
+    /// # Arguments
+    /// * `open` - Opens the archive
+    /// * `format` - Format of the archive
+    /// * `prefix` - Identifier of the archive, prepended to the paths of its members
+    /// * `depth` - Nesting depth of the archive, 1 for the archive of the parent source
+    /// * `budget` - Uncompressed size still allowed, reduced by the members and the content of the nested archives
+    /// * `entries` - Receives the members
+    fn list_nested(
+        &self,
+        open: &dyn Fn() -> Result<Box<dyn ReadSeek>, AppError>,
+        format: ArchiveFormat,
+        prefix: &str,
+        depth: usize,
+        budget: &mut u64,
+        entries: &mut Vec<SourceEntry>,
+    ) -> Result<(), AppError> {
+        for (member, size) in list_members(format, open()?, budget)? {
+            let id = format!("{}{}{}", prefix, MEMBER_SEPARATOR, member.id);
+            match ArchiveFormat::from_name(&member.id) {
+                Some(nested) if depth < self.limits.max_depth => {
+                    // The declared size of the nested archive was counted: read it within that size and
+                    // the size left, and count what was actually read instead
+                    let allowed = budget.saturating_add(size);
+                    let content = Arc::new(read_member(format, open()?, &member.id, allowed)?);
+                    *budget = allowed - content.len() as u64;
+                    let open_nested =
+                        || -> Result<Box<dyn ReadSeek>, AppError> { Ok(Box::new(Cursor::new(SharedContent(content.clone())))) };
+                    self.list_nested(&open_nested, nested, &id, depth + 1, budget, entries)?;
+                }
+                _ => entries.push(SourceEntry { id, version: member.version }),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DataSource for ArchiveMembers {
+    fn id(&self) -> &str {
+        self.parent.id()
+    }
+
+    fn list(&self) -> Result<Vec<SourceEntry>, AppError> {
+        let format = ArchiveFormat::from_name(&self.archive_id)
+            .ok_or_else(|| AppError::Document(format!("'{}' is not an archive", self.archive_id)))?;
+        let mut budget = self.limits.max_size;
+        let mut entries = Vec::new();
+        self.list_nested(&|| self.open(), format, &self.archive_id, 1, &mut budget, &mut entries)
+            .map_err(|e| archive_error(&self.archive_id, e))?;
+        Ok(entries)
+    }
+
+    fn fetch(&self, entry_id: &str) -> Result<Vec<u8>, AppError> {
+        let path = entry_id
+            .strip_prefix(&self.archive_id)
+            .and_then(|path| path.strip_prefix(MEMBER_SEPARATOR))
+            .ok_or_else(|| AppError::Document(format!("'{}' is not in '{}'", entry_id, self.archive_id)))?;
+        let not_archive = |name: &str| AppError::Document(format!("'{}' is not an archive", name));
+
+        // Extract the nested archives down to the member
+        let mut format = ArchiveFormat::from_name(&self.archive_id).ok_or_else(|| not_archive(&self.archive_id))?;
+        let mut archive_id = self.archive_id.clone();
+        let mut names = path.split(MEMBER_SEPARATOR).peekable();
+        while let Some(name) = names.next() {
+            if names.peek().is_none() {
+                return self
+                    .read_archive_member(&archive_id, format, name)
+                    .map_err(|e| archive_error(&self.archive_id, e));
+            }
+            archive_id = self
+                .extract_nested(&archive_id, format, name)
+                .map_err(|e| archive_error(&self.archive_id, e))?;
+            format = ArchiveFormat::from_name(name).ok_or_else(|| not_archive(name))?;
+        }
+        Err(AppError::Document(format!("Empty member path in '{}'", entry_id)))
+    }
+
+    fn metadata(&self) -> Map<String, Value> {
+        let mut metadata = self.parent.metadata();
+        metadata.insert("archive".to_string(), self.archive_id.clone().into());
+        metadata
+    }
+}
+
+/// An archive that could not be expanded: fetching its entries reports why
+pub struct UnreadableArchive {
+    parent: Arc<dyn DataSource>,
+    error: String,
+}
+
+impl UnreadableArchive {
+    /// Creates the source of an archive that could not be expanded
+    ///
+    /// # Arguments
+    /// * `parent` - Source holding the archive
+    /// * `error` - Why the archive could not be expanded
+    pub fn new(parent: Arc<dyn DataSource>, error: String) -> Self {
+        UnreadableArchive { parent, error }
+    }
+}
+
+impl DataSource for UnreadableArchive {
+    fn id(&self) -> &str {
+        self.parent.id()
+    }
+
+    fn list(&self) -> Result<Vec<SourceEntry>, AppError> {
+        Err(AppError::Document(self.error.clone()))
+    }
+
+    fn fetch(&self, _entry_id: &str) -> Result<Vec<u8>, AppError> {
+        Err(AppError::Document(self.error.clone()))
+    }
+}
+
+/// Lists the regular files of an archive
+///
+/// # Arguments
+/// * `format` - Format of the archive
+/// * `reader` - Content of the archive
+/// * `budget` - Uncompressed size still allowed, reduced by the declared size of the members
+///
+/// # Returns
+/// * `Result<Vec<(SourceEntry, u64)>, AppError>` - The members with their declared size
+fn list_members(
+    format: ArchiveFormat,
+    reader: impl Read + Seek,
+    budget: &mut u64,
+) -> Result<Vec<(SourceEntry, u64)>, AppError> {
+    match format {
+        ArchiveFormat::Zip => list_zip(reader, budget),
+        ArchiveFormat::Tar => list_tar(reader, budget),
+        ArchiveFormat::TarGz => list_tar(GzDecoder::new(reader), budget),
+    }
+}
+
+/// Reads a member of an archive
+///
+/// # Arguments
+/// * `format` - Format of the archive
+/// * `reader` - Content of the archive
+/// * `name` - Identifier of the member
+/// * `max_size` - Maximum uncompressed size of the member
+fn read_member(format: ArchiveFormat, reader: impl Read + Seek, name: &str, max_size: u64) -> Result<Vec<u8>, AppError> {
+    match format {
+        ArchiveFormat::Zip => read_zip(reader, name, max_size),
+        ArchiveFormat::Tar => read_tar(reader, name, max_size),
+        ArchiveFormat::TarGz => read_tar(GzDecoder::new(reader), name, max_size),
+    }
+}
+
+/// Lists the regular files of a ZIP archive
+fn list_zip(reader: impl Read + Seek, budget: &mut u64) -> Result<Vec<(SourceEntry, u64)>, AppError> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| AppError::Document(e.to_string()))?;
+    let mut entries = Vec::new();
+    for index in 0..archive.len() {
+        let member = archive.by_index_raw(index).map_err(|e| AppError::Document(e.to_string()))?;
+        if member.is_file()
+            && let Some(id) = member_id(member.name())
+        {
+            consume(budget, member.size())?;
+            let version = format!("{:08x}-{}", member.crc32(), member.size());
+            entries.push((SourceEntry { id, version }, member.size()));
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads a member of a ZIP archive
+fn read_zip(reader: impl Read + Seek, name: &str, max_size: u64) -> Result<Vec<u8>, AppError> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| AppError::Document(e.to_string()))?;
+    let index = (0..archive.len())
+        .find(|index| {
+            archive
+                .name_for_index(*index)
+                .is_some_and(|member| member_id(member).as_deref() == Some(name))
+        })
+        .ok_or_else(|| not_found(name))?;
+    let member = archive.by_index(index).map_err(|e| AppError::Document(e.to_string()))?;
+    read_limited(member, name, max_size)
+}
+
+/// Lists the regular files of a tar archive
+fn list_tar(reader: impl Read, budget: &mut u64) -> Result<Vec<(SourceEntry, u64)>, AppError> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+    for member in archive.entries()? {
+        let member = member?;
+        let header = member.header();
+        if !header.entry_type().is_file() {
+            continue;
+        }
+        if let Some(id) = member_id(&member.path()?.to_string_lossy()) {
+            consume(budget, member.size())?;
+            let version = format!("{}-{}", header.mtime().unwrap_or(0), header.size().unwrap_or(0));
+            entries.push((SourceEntry { id, version }, member.size()));
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads a member of a tar archive, which is scanned up to the member
+fn read_tar(reader: impl Read, name: &str, max_size: u64) -> Result<Vec<u8>, AppError> {
+    let mut archive = tar::Archive::new(reader);
+    for member in archive.entries()? {
+        let member = member?;
+        if member.header().entry_type().is_file()
+            && member_id(&member.path()?.to_string_lossy()).as_deref() == Some(name)
+        {
+            return read_limited(member, name, max_size);
+        }
+    }
+    Err(not_found(name))
+}
+
+/// Extracts all the regular files of a tar archive in a single pass
+///
+/// # Arguments
+/// * `format` - Format of the archive, `Tar` or `TarGz`
+/// * `reader` - Content of the archive
+/// * `max_size` - Maximum uncompressed size of all the members
+///
+/// # Returns
+/// * `Result<HashMap<String, Vec<u8>>, AppError>` - Content of each member, by identifier
+fn extract_tar(format: ArchiveFormat, reader: impl Read, max_size: u64) -> Result<HashMap<String, Vec<u8>>, AppError> {
+    let reader: Box<dyn Read> = match format {
+        ArchiveFormat::TarGz => Box::new(GzDecoder::new(reader)),
+        _ => Box::new(reader),
+    };
+    let mut archive = tar::Archive::new(reader);
+    let mut budget = max_size;
+    let mut members = HashMap::new();
+    for member in archive.entries()? {
+        let member = member?;
+        if !member.header().entry_type().is_file() {
+            continue;
+        }
+        if let Some(id) = member_id(&member.path()?.to_string_lossy()) {
+            let content = read_limited(member, "the archive", budget)?;
+            budget -= content.len() as u64;
+            members.insert(id, content);
+        }
+    }
+    Ok(members)
+}
+
+/// Reads a member up to the size limit, whatever size its header declares
+fn read_limited(member: impl Read, name: &str, max_size: u64) -> Result<Vec<u8>, AppError> {
+    let mut content = Vec::new();
+    member.take(max_size.saturating_add(1)).read_to_end(&mut content)?;
+    if content.len() as u64 > max_size {
+        return Err(size_error(name));
+    }
+    Ok(content)
+}
+
+/// Reduces the uncompressed size still allowed by the size of a member
+fn consume(budget: &mut u64, size: u64) -> Result<(), AppError> {
+    *budget = budget.checked_sub(size).ok_or_else(|| size_error("the archive"))?;
+    Ok(())
+}
+
+fn not_found(name: &str) -> AppError {
+    AppError::Document(format!("'{}' not found in the archive", name))
+}
+
+fn size_error(name: &str) -> AppError {
+    AppError::Document(format!(
+        "Uncompressed size of {} exceeds the limit (archive_max_size_mb)",
+        name
+    ))
+}
+
+/// Gets the identifier of a member from its path (`./docs/a.md` is `docs/a.md`)
+///
+/// # Returns
+/// * `Option<String>` - The identifier, None if the path leaves the archive root
+fn member_id(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+fn archive_error(archive: &str, error: AppError) -> AppError {
+    match error {
+        AppError::Document(message) => AppError::Document(format!("Archive '{}': {}", archive, message)),
+        error => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::data_source::fs::FsSource;
+    use crate::indexing::loader::xml::tests::write_zip;
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    const LIMITS: ArchiveLimits = ArchiveLimits {
+        max_depth: 3,
+        max_size: 1024 * 1024,
+    };
+
+    /// Writes a gzipped tar archive holding the given members, names written as is
+    fn write_tar_gz(path: &Path, members: &[(&str, &[u8])]) {
+        let mut builder = tar::Builder::new(GzEncoder::new(File::create(path).unwrap(), Compression::default()));
+        for (name, content) in members {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    /// Source of the members of an archive written in a directory
+    fn members(dir: &Path, archive_id: &str, limits: ArchiveLimits) -> ArchiveMembers {
+        let parent: Arc<dyn DataSource> = Arc::new(FsSource::new("docs", &dir.display().to_string()));
+        ArchiveMembers::new(parent, archive_id, limits)
+    }
+
+    /// Declares an uncompressed size in the local and the central headers of the first member of a ZIP archive
+    fn declare_size(path: &Path, size: u32) {
+        let mut content = std::fs::read(path).unwrap();
+        for (signature, offset) in [(b"PK\x03\x04", 22), (b"PK\x01\x02", 24)] {
+            let start = content.windows(4).position(|window| window == signature).unwrap() + offset;
+            content[start..start + 4].copy_from_slice(&size.to_le_bytes());
+        }
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn ids(entries: Vec<SourceEntry>) -> Vec<String> {
+        let mut ids: Vec<String> = entries.into_iter().map(|entry| entry.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn nested_archives_are_expanded_down_to_the_maximum_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        write_zip(&dir.path().join("inner.zip"), &[("deep.md", "deep")]);
+        let inner = std::fs::read(dir.path().join("inner.zip")).unwrap();
+        write_tar_gz(&dir.path().join("middle.tgz"), &[("inner.zip", &inner), ("notes.md", b"notes")]);
+        let middle = std::fs::read(dir.path().join("middle.tgz")).unwrap();
+        let mut writer = ZipWriter::new(File::create(dir.path().join("docs.zip")).unwrap());
+        writer.start_file("middle.tgz", SimpleFileOptions::default()).unwrap();
+        writer.write_all(&middle).unwrap();
+        writer.finish().unwrap();
+
+        let source = members(dir.path(), "docs.zip", LIMITS);
+        assert_eq!(
+            ids(source.list().unwrap()),
+            ["docs.zip!/middle.tgz!/inner.zip!/deep.md", "docs.zip!/middle.tgz!/notes.md"]
+        );
+        assert_eq!(source.fetch("docs.zip!/middle.tgz!/inner.zip!/deep.md").unwrap(), b"deep");
+        assert_eq!(source.fetch("docs.zip!/middle.tgz!/notes.md").unwrap(), b"notes");
+
+        // Too deep: the innermost archive is listed as a member
+        let source = members(dir.path(), "docs.zip", ArchiveLimits { max_depth: 2, ..LIMITS });
+        assert_eq!(
+            ids(source.list().unwrap()),
+            ["docs.zip!/middle.tgz!/inner.zip", "docs.zip!/middle.tgz!/notes.md"]
+        );
+        assert_eq!(source.fetch("docs.zip!/middle.tgz!/inner.zip").unwrap(), inner);
+    }
+
+    #[test]
+    fn nested_archives_are_read_within_the_size_left() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "x".repeat(1000);
+        write_zip(&dir.path().join("inner.zip"), &[("a.md", &content)]);
+        let inner = std::fs::read(dir.path().join("inner.zip")).unwrap();
+        let path = dir.path().join("docs.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer.start_file("inner.zip", SimpleFileOptions::default()).unwrap();
+        writer.write_all(&inner).unwrap();
+        writer.finish().unwrap();
+
+        // The nested archive and its members fit exactly
+        let size = (inner.len() + content.len()) as u64;
+        let source = members(dir.path(), "docs.zip", ArchiveLimits { max_size: size, ..LIMITS });
+        assert_eq!(ids(source.list().unwrap()), ["docs.zip!/inner.zip!/a.md"]);
+
+        let source = members(dir.path(), "docs.zip", ArchiveLimits { max_size: size - 1, ..LIMITS });
+        let error = source.list().unwrap_err().to_string();
+        assert!(error.contains("exceeds the limit"), "{}", error);
+
+        // A nested archive declared smaller than it is cannot be read beyond the size left
+        write_zip(&dir.path().join("inner.zip"), &[("a.md", "a")]);
+        let inner = std::fs::read(dir.path().join("inner.zip")).unwrap();
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer.start_file("inner.zip", SimpleFileOptions::default()).unwrap();
+        writer.write_all(&inner).unwrap();
+        writer.start_file("filler.md", SimpleFileOptions::default()).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+        writer.finish().unwrap();
+        declare_size(&path, 10);
+        let size = (10 + content.len() + 10) as u64;
+        assert!(inner.len() > 20);
+        let source = members(dir.path(), "docs.zip", ArchiveLimits { max_size: size, ..LIMITS });
+        let error = source.list().unwrap_err().to_string();
+        assert!(error.contains("exceeds the limit"), "{}", error);
+    }
+
+    #[test]
+    fn members_exceeding_the_size_limit_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("docs.zip");
+        write_zip(&path, &[("small.md", "small"), ("big.md", &"x".repeat(2000))]);
+
+        let limits = ArchiveLimits { max_size: 1000, ..LIMITS };
+        let source = ArchiveSource::new("docs", &path.display().to_string(), limits);
+        let error = source.list().unwrap_err().to_string();
+        assert!(error.contains("exceeds the limit"), "{}", error);
+        assert!(source.fetch("big.md").is_err());
+        assert_eq!(source.fetch("small.md").unwrap(), b"small");
+    }
+
+    #[test]
+    fn reads_stop_at_the_limit_whatever_the_declared_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("docs.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.start_file("bomb.md", options).unwrap();
+        writer.write_all(&[b'x'; 10_000]).unwrap();
+        writer.finish().unwrap();
+
+        declare_size(&path, 10);
+
+        let limits = ArchiveLimits { max_size: 100, ..LIMITS };
+        let source = ArchiveSource::new("docs", &path.display().to_string(), limits);
+        assert_eq!(ids(source.list().unwrap()), ["bomb.md"]);
+        let error = source.fetch("bomb.md").unwrap_err().to_string();
+        assert!(error.contains("exceeds the limit"), "{}", error);
+    }
+
+    #[test]
+    fn members_leaving_the_archive_root_are_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("docs.tar.gz");
+        write_tar_gz(&path, &[("../evil.md", b"evil"), ("./docs/a.md", b"a"), ("b.md", b"b")]);
+
+        let source = ArchiveSource::new("docs", &path.display().to_string(), LIMITS);
+        assert_eq!(ids(source.list().unwrap()), ["b.md", "docs/a.md"]);
+        assert!(source.fetch("../evil.md").is_err());
+        assert_eq!(member_id("../evil.md"), None);
+        assert_eq!(member_id("/etc/passwd"), None);
+        assert_eq!(member_id("docs/../../evil.md"), None);
+    }
+
+    #[test]
+    fn tar_members_are_extracted_once_and_can_be_fetched_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("docs.tgz");
+        write_tar_gz(&path, &[("a.md", b"a"), ("b.md", b"b")]);
+
+        let source = ArchiveSource::new("docs", &path.display().to_string(), LIMITS);
+        assert_eq!(source.fetch("b.md").unwrap(), b"b");
+        assert_eq!(source.fetch("a.md").unwrap(), b"a");
+        assert_eq!(source.fetch("b.md").unwrap(), b"b");
+        let error = source.fetch("c.md").unwrap_err().to_string();
+        assert!(error.contains("'c.md' not found"), "{}", error);
+    }
+}
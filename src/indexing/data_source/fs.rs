@@ -0,0 +1,98 @@
+//! Local directory data source.
+//!
+//! The entries are the files of the directory and of its subdirectories,
+//! identified by their path relative to the directory (with `/` separators).
+//! Their version is made of their modification time and size. Symbolic links
+//! to directories are not followed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::{DataSource, SourceEntry};
+use crate::AppError;
+
+/// Files of a local directory tree
+pub struct FsSource {
+    id: String,
+    root: PathBuf,
+}
+
+impl FsSource {
+    /// Creates a directory data source
+    ///
+    /// # Arguments
+    /// * `id` - Identifier of the source
+    /// * `path` - Path of the directory
+    pub fn new(id: &str, path: &str) -> Self {
+        FsSource {
+            id: id.to_string(),
+            root: PathBuf::from(path),
+        }
+    }
+}
+
+impl DataSource for FsSource {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Lists the files of the directory tree, error if the directory does not exist
+    ///
+    /// A missing (or unmounted) directory must not be listed as empty: its
+    /// tracked files would be considered as deleted and removed from the index.
+    fn list(&self) -> Result<Vec<SourceEntry>, AppError> {
+        if !self.root.is_dir() {
+            return Err(AppError::Config(format!(
+                "Data directory '{}' does not exist or is not a directory",
+                self.root.display()
+            )));
+        }
+        let mut entries = Vec::new();
+        list_directory(&self.root, "", &mut entries)?;
+        Ok(entries)
+    }
+
+    fn fetch(&self, entry_id: &str) -> Result<Vec<u8>, AppError> {
+        Ok(fs::read(self.root.join(entry_id))?)
+    }
+
+    fn local_path(&self, entry_id: &str) -> Option<PathBuf> {
+        Some(self.root.join(entry_id))
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+/// Adds the files of a directory and of its subdirectories to the entries
+///
+/// # Arguments
+/// * `directory` - Directory to list
+/// * `prefix` - Path of the directory relative to the root, empty or ending with `/`
+/// * `entries` - Entries listed so far
+fn list_directory(directory: &Path, prefix: &str, entries: &mut Vec<SourceEntry>) -> Result<(), AppError> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let Some(file_name) = entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            list_directory(&path, &format!("{}{}/", prefix, file_name), entries)?;
+        } else if path.is_file() {
+            let metadata = fs::metadata(&path)?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_nanos());
+            entries.push(SourceEntry {
+                id: format!("{}{}", prefix, file_name),
+                version: format!("{}-{}", modified, metadata.len()),
+            });
+        }
+    }
+    Ok(())
+}
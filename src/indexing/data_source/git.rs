@@ -0,0 +1,387 @@
+//! Git repository data source.
+//!
+//! The entries are the files of a commit of a local git repository (bare or
+//! with a working tree), identified by their path in the repository. The
+//! configured ref is resolved to a commit each time the source is listed,
+//! and only the files of the commit are listed, so files ignored by
+//! `.gitignore` are never indexed.
+//!
+//! The tracker records the last indexed commit once every file of the commit
+//! is indexed (see `FileTracker::record_checkpoints`). On the next run, the
+//! changed files are the ones `git diff --name-only <last indexed
+//! commit> <ref>` reports: the other tracked files are neither read nor
+//! hashed. The version of a file is the SHA of its blob, so a reported file
+//! that was already indexed at its current blob (by an interrupted run) is
+//! not indexed again. Without a recorded commit (first run, tracker rebuilt
+//! from the collection) or when it is no longer in the repository (rewritten
+//! history), every file is compared with the blob it was last indexed at.
+//! Listing the tree only reads tree objects, never the blobs, and is needed
+//! anyway to find the deleted files. The indexed commit is recorded in the
+//! metadata of the points (`commit`).
+//!
+//! The git command line is used, so `git` must be installed.
+
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use super::{DataSource, SourceEntry};
+use crate::AppError;
+use crate::GitSourceConfig;
+
+/// A local git repository indexed at a given ref
+pub struct GitSource {
+    id: String,
+    repository: PathBuf,
+    reference: String,
+    /// Commit the ref resolved to when the source was last listed
+    commit: Mutex<Option<String>>,
+}
+
+impl GitSource {
+    /// Creates a git data source
+    ///
+    /// # Arguments
+    /// * `id` - Identifier of the source
+    /// * `config` - Repository and ref to index
+    pub fn new(id: &str, config: &GitSourceConfig) -> Self {
+        GitSource {
+            id: id.to_string(),
+            repository: PathBuf::from(&config.repository),
+            reference: config.reference.clone(),
+            commit: Mutex::new(None),
+        }
+    }
+
+    /// Gets the commit resolved by the last listing, resolving the ref if needed
+    fn commit(&self) -> Result<String, AppError> {
+        let mut commit = self.commit.lock().unwrap_or_else(|e| e.into_inner());
+        match &*commit {
+            Some(commit) => Ok(commit.clone()),
+            None => {
+                let resolved = self.resolve(&self.reference)?;
+                *commit = Some(resolved.clone());
+                Ok(resolved)
+            }
+        }
+    }
+
+    /// Resolves a ref to the SHA of its commit
+    fn resolve(&self, reference: &str) -> Result<String, AppError> {
+        let output = self.git(&["rev-parse", "--verify", &format!("{}^{{commit}}", reference)])?;
+        let commit = String::from_utf8_lossy(&output).trim().to_string();
+        if commit.is_empty() {
+            return Err(AppError::Git(format!("Unknown ref '{}'", reference)));
+        }
+        Ok(commit)
+    }
+
+    /// Runs a git command in the repository and returns its output
+    fn git(&self, args: &[&str]) -> Result<Vec<u8>, AppError> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repository)
+            .args(args)
+            .output()
+            .map_err(|e| AppError::Git(format!("Failed to run git: {}", e)))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(AppError::Git(format!(
+                "git {} failed in {}: {}",
+                args.first().unwrap_or(&""),
+                self.repository.display(),
+                stderr.trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl DataSource for GitSource {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Lists the regular files of the commit of the ref (symbolic links and submodules are left out)
+    fn list(&self) -> Result<Vec<SourceEntry>, AppError> {
+        let commit = self.resolve(&self.reference)?;
+        *self.commit.lock().unwrap_or_else(|e| e.into_inner()) = Some(commit.clone());
+
+        let output = self.git(&["ls-tree", "-r", "-z", &commit])?;
+        let entries: Vec<SourceEntry> = output
+            .split(|byte| *byte == 0)
+            .filter_map(|entry| {
+                // <mode> SP <type> SP <object> TAB <path>
+                let entry = String::from_utf8_lossy(entry);
+                let (info, path) = entry.split_once('\t')?;
+                let mut fields = info.split(' ');
+                let mode = fields.next()?;
+                let object_type = fields.next()?;
+                let object = fields.next()?;
+                (object_type == "blob" && mode != "120000" && is_safe_path(path)).then(|| SourceEntry {
+                    id: path.to_string(),
+                    version: object.to_string(),
+                })
+            })
+            .collect();
+        info!(
+            "{} files in {} at {} ({})",
+            entries.len(),
+            self.repository.display(),
+            self.reference,
+            commit
+        );
+        Ok(entries)
+    }
+
+    fn fetch(&self, entry_id: &str) -> Result<Vec<u8>, AppError> {
+        let commit = self.commit()?;
+        self.git(&["cat-file", "blob", &format!("{}:{}", commit, entry_id)])
+    }
+
+    fn metadata(&self) -> Map<String, Value> {
+        let mut metadata = Map::new();
+        if let Ok(commit) = self.commit() {
+            metadata.insert("commit".to_string(), commit.into());
+        }
+        metadata
+    }
+
+    /// Gets the commit of the last listing
+    fn checkpoint(&self) -> Option<String> {
+        self.commit.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Lists the files `git diff` reports between a previously indexed commit and the listed one
+    ///
+    /// Renames are reported as a deletion and an addition. None if the
+    /// previous commit is no longer in the repository.
+    fn changed_since(&self, checkpoint: &str) -> Option<HashSet<String>> {
+        let commit = self.checkpoint()?;
+        match self.git(&["diff", "--name-only", "--no-renames", "-z", checkpoint, &commit]) {
+            Ok(output) => {
+                let changed: HashSet<String> = output
+                    .split(|byte| *byte == 0)
+                    .filter(|path| !path.is_empty())
+                    .map(|path| String::from_utf8_lossy(path).into_owned())
+                    .collect();
+                info!("{} files changed in {} since {}", changed.len(), self.repository.display(), checkpoint);
+                Some(changed)
+            }
+            Err(e) => {
+                warn!("Cannot diff from the last indexed commit {}, comparing every file: {}", checkpoint, e);
+                None
+            }
+        }
+    }
+}
+
+/// Checks that a path of the repository is relative and stays inside the repository
+fn is_safe_path(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::data_source::{SourceFile, list_sources};
+    use crate::indexing::file_tracker::FileTracker;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::Arc;
+
+    /// Runs git in a directory, with an identity so that commits work anywhere
+    fn run_git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .expect("git is installed");
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// Commits the working tree and pushes it to the bare repository
+    fn commit_and_push(work: &Path, message: &str) -> String {
+        run_git(work, &["add", "-A"]);
+        run_git(work, &["commit", "-q", "-m", message]);
+        run_git(work, &["push", "-q", "origin", "HEAD:refs/heads/main"]);
+        run_git(work, &["rev-parse", "HEAD"])
+    }
+
+    /// Creates a bare repository and a clone to commit into it
+    fn bare_repository() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let bare = dir.path().join("repo.git");
+        let work = dir.path().join("work");
+        run_git(dir.path(), &["init", "-q", "--bare", "repo.git"]);
+        run_git(dir.path(), &["clone", "-q", bare.to_str().unwrap(), "work"]);
+        (dir, bare, work)
+    }
+
+    fn source(bare: &Path) -> GitSource {
+        GitSource::new(
+            "repo",
+            &GitSourceConfig {
+                repository: bare.to_string_lossy().to_string(),
+                reference: "main".to_string(),
+            },
+        )
+    }
+
+    fn versions(source: &GitSource) -> HashMap<String, String> {
+        source.list().unwrap().into_iter().map(|entry| (entry.id, entry.version)).collect()
+    }
+
+    #[test]
+    fn lists_and_fetches_the_files_of_the_ref() {
+        let (_dir, bare, work) = bare_repository();
+        fs::create_dir(work.join("docs")).unwrap();
+        fs::write(work.join("readme.md"), "# Readme").unwrap();
+        fs::write(work.join("docs/guide.txt"), "Guide").unwrap();
+        fs::write(work.join(".gitignore"), "ignored.txt").unwrap();
+        fs::write(work.join("ignored.txt"), "not committed").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("readme.md", work.join("link.md")).unwrap();
+        let commit = commit_and_push(&work, "first");
+
+        let source = source(&bare);
+        let versions = versions(&source);
+        let mut ids: Vec<&str> = versions.keys().map(String::as_str).collect();
+        ids.sort();
+        assert_eq!(ids, [".gitignore", "docs/guide.txt", "readme.md"]);
+        assert_eq!(versions["readme.md"], run_git(&work, &["rev-parse", "HEAD:readme.md"]));
+        assert_eq!(source.fetch("docs/guide.txt").unwrap(), b"Guide");
+        assert_eq!(source.metadata()["commit"], commit.as_str());
+    }
+
+    #[test]
+    fn changed_versions_match_git_diff() {
+        let (_dir, bare, work) = bare_repository();
+        for name in ["kept.txt", "edited.txt", "deleted.txt"] {
+            fs::write(work.join(name), name).unwrap();
+        }
+        let first = commit_and_push(&work, "first");
+        let source = source(&bare);
+        let before = versions(&source);
+
+        fs::write(work.join("edited.txt"), "edited").unwrap();
+        fs::remove_file(work.join("deleted.txt")).unwrap();
+        fs::write(work.join("added.txt"), "added").unwrap();
+        let second = commit_and_push(&work, "second");
+        let after = versions(&source);
+
+        let mut changed: Vec<String> = before
+            .keys()
+            .chain(after.keys())
+            .filter(|id| before.get(*id) != after.get(*id))
+            .cloned()
+            .collect();
+        changed.sort();
+        changed.dedup();
+        let diff = run_git(&bare, &["diff", "--name-only", &format!("{}..{}", first, second)]);
+        let mut expected: Vec<&str> = diff.lines().collect();
+        expected.sort();
+        assert_eq!(changed, expected);
+        assert_eq!(before["kept.txt"], after["kept.txt"]);
+        assert_eq!(source.fetch("edited.txt").unwrap(), b"edited");
+    }
+
+    /// Tracks every listed file as indexed with its listed version
+    fn index_all(tracker: &mut FileTracker, files: &[SourceFile]) {
+        for file in files {
+            tracker.set_file(file.key.clone(), String::new(), Vec::new());
+            tracker.set_file_version(&file.key, Some(file.entry.version.clone()));
+        }
+    }
+
+    fn keys(files: &[SourceFile]) -> Vec<&str> {
+        let mut keys: Vec<&str> = files.iter().map(|file| file.key.as_str()).collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn changes_are_diffed_from_the_last_indexed_commit() {
+        let (_dir, bare, work) = bare_repository();
+        for name in ["kept.txt", "edited.txt"] {
+            fs::write(work.join(name), name).unwrap();
+        }
+        commit_and_push(&work, "first");
+        let sources: Vec<Arc<dyn DataSource>> = vec![Arc::new(source(&bare))];
+        let mut tracker = FileTracker::new();
+
+        let files = list_sources(&sources).unwrap();
+        assert_eq!(keys(&tracker.get_changed_files(&files, &sources)), ["repo:edited.txt", "repo:kept.txt"]);
+        index_all(&mut tracker, &files);
+        tracker.record_checkpoints(&sources, &files);
+
+        fs::write(work.join("edited.txt"), "edited").unwrap();
+        fs::write(work.join("added.txt"), "added").unwrap();
+        commit_and_push(&work, "second");
+        let files = list_sources(&sources).unwrap();
+        // A stale version of a file outside the diff is not compared
+        tracker.set_file_version("repo:kept.txt", Some("stale".to_string()));
+        assert_eq!(keys(&tracker.get_changed_files(&files, &sources)), ["repo:added.txt", "repo:edited.txt"]);
+    }
+
+    #[test]
+    fn checkpoint_waits_for_every_file_and_falls_back_without_commit() {
+        let (dir, bare, work) = bare_repository();
+        for name in ["a.txt", "b.txt"] {
+            fs::write(work.join(name), name).unwrap();
+        }
+        commit_and_push(&work, "first");
+        let sources: Vec<Arc<dyn DataSource>> = vec![Arc::new(source(&bare))];
+        let files = list_sources(&sources).unwrap();
+
+        // b.txt is not indexed yet: no checkpoint, every file is compared
+        let mut tracker = FileTracker::new();
+        index_all(&mut tracker, &files[..1]);
+        tracker.record_checkpoints(&sources, &files);
+        tracker.set_file_version(&files[0].key, Some("stale".to_string()));
+        assert_eq!(tracker.get_changed_files(&files, &sources).len(), 2);
+
+        // A checkpoint missing from the repository falls back to the versions
+        let tracker_path = dir.path().join("tracker.json");
+        fs::write(
+            &tracker_path,
+            r#"{"files": {}, "checkpoints": {"repo": "0000000000000000000000000000000000000000"}}"#,
+        )
+        .unwrap();
+        let mut tracker = FileTracker::new();
+        tracker.load_from_file(tracker_path.to_str().unwrap()).unwrap();
+        index_all(&mut tracker, &files);
+        tracker.set_file_version(&files[0].key, Some("stale".to_string()));
+        assert_eq!(keys(&tracker.get_changed_files(&files, &sources)), [files[0].key.as_str()]);
+    }
+
+    #[test]
+    fn unknown_ref_is_an_error() {
+        let (_dir, bare, work) = bare_repository();
+        fs::write(work.join("file.txt"), "content").unwrap();
+        commit_and_push(&work, "first");
+        let source = GitSource::new(
+            "repo",
+            &GitSourceConfig {
+                repository: bare.to_string_lossy().to_string(),
+                reference: "missing".to_string(),
+            },
+        );
+        assert!(matches!(source.list(), Err(AppError::Git(_))));
+    }
+
+    #[test]
+    fn paths_leaving_the_repository_are_rejected() {
+        assert!(is_safe_path("docs/guide.md"));
+        assert!(!is_safe_path("../outside"));
+        assert!(!is_safe_path("/etc/passwd"));
+    }
+}
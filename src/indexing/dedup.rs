@@ -0,0 +1,754 @@
+//! Deduplication module for identical and near-identical chunks.
+//!
+//! The same document often exists under several names or versions in the
+//! data sources. Instead of storing each copy of a passage, a chunk whose
+//! text is identical (same hash) or, when `near_duplicate_distance` is set,
+//! nearly identical (SimHash within that many bits) to a chunk already
+//! stored, including by the current run once its upsert succeeded, shares
+//! the point of that chunk, and is retrieved with its text: the file is
+//! added to the `sources` payload of the point and its chunk is tracked as
+//! `shared`. Files with the same content as an indexed file therefore share
+//! all its points.
+//!
+//! A point is deleted only when no file references it anymore: when its
+//! owner drops it, one of the files sharing it becomes its owner (`source`
+//! payload). The `sources` of the points whose references changed, with the
+//! MD5 of each of these files (`source_hashes` payload), are synchronized at
+//! the end of each run with `sync_shared_points`.
+
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use tracing::{error, info, warn};
+
+use crate::AppError;
+use crate::DedupConfig;
+use crate::indexing::file_tracker::{FileTracker, PendingChunk, TrackedChunk};
+use crate::qdrant_custom_client::QdrantClient;
+
+/// Number of words of the shingles hashed into the SimHash
+const SHINGLE_WORDS: usize = 3;
+
+/// Minimum number of words of a chunk compared by SimHash, shorter chunks are only deduplicated when identical
+const MIN_SIMHASH_WORDS: usize = 12;
+
+/// Computes the 64-bit SimHash of a text
+///
+/// The text is normalized (lowercase words, without punctuation) and split
+/// into overlapping shingles of three words, so that whitespace, case or
+/// punctuation differences do not change the hash and small edits only
+/// change a few bits.
+///
+/// # Arguments
+/// * `text` - The text
+///
+/// # Returns
+/// * `Option<u64>` - The SimHash, None if the text is too short to be compared
+pub fn simhash(text: &str) -> Option<u64> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect();
+    if words.len() < MIN_SIMHASH_WORDS {
+        return None;
+    }
+
+    let mut weights = [0i32; 64];
+    for shingle in words.windows(SHINGLE_WORDS) {
+        let hash = fnv1a(&shingle.join(" "));
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash >> bit & 1 == 1 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0u64, |hash, (bit, _)| hash | 1 << bit),
+    )
+}
+
+/// 64-bit FNV-1a hash, stable across runs and Rust versions (unlike the standard hasher)
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Formats a SimHash as stored in the tracker and the payloads
+pub fn format_simhash(simhash: u64) -> String {
+    format!("{:016x}", simhash)
+}
+
+/// A point the chunks of other files can share
+#[derive(Debug, Clone)]
+struct IndexedPoint {
+    point_id: String,
+    /// File referencing the point (its owner, or a file sharing it)
+    file: String,
+    simhash: Option<u64>,
+    removed: bool,
+    /// False while the upsert of the point by the current run has not succeeded
+    stored: bool,
+}
+
+/// A stored point matching a new chunk
+#[derive(Debug, Clone)]
+pub struct DuplicateMatch {
+    /// ID of the point holding the passage
+    pub point_id: String,
+    /// File referencing the point
+    pub file: String,
+    /// True if the texts are identical, false for a near-duplicate
+    pub exact: bool,
+}
+
+/// Index of the stored chunks, by hash and by SimHash blocks
+///
+/// Two SimHashes within `d` bits of each other have at least one of `d + 1`
+/// blocks of bits in common, so only the points sharing a block with a chunk
+/// are compared with it.
+pub struct DedupIndex {
+    points: Vec<IndexedPoint>,
+    by_hash: HashMap<String, Vec<usize>>,
+    by_block: HashMap<(usize, u64), Vec<usize>>,
+    by_point_id: HashMap<String, Vec<usize>>,
+    /// Maximum SimHash distance of near-duplicates, None to only match identical chunks
+    max_distance: Option<u32>,
+}
+
+impl DedupIndex {
+    /// Builds the index of the chunks of the tracker
+    ///
+    /// # Arguments
+    /// * `tracker` - File tracker
+    /// * `config` - Deduplication configuration
+    pub fn from_tracker(tracker: &FileTracker, config: &DedupConfig) -> Self {
+        let mut index = DedupIndex {
+            points: Vec::new(),
+            by_hash: HashMap::new(),
+            by_block: HashMap::new(),
+            by_point_id: HashMap::new(),
+            max_distance: config.near_duplicate_distance.map(|distance| distance.min(63)),
+        };
+        for filename in tracker.tracked_files() {
+            let Some(entry) = tracker.get_file(&filename) else {
+                continue;
+            };
+            for chunk in &entry.chunks {
+                let simhash = chunk
+                    .simhash
+                    .as_deref()
+                    .and_then(|simhash| u64::from_str_radix(simhash, 16).ok());
+                index.insert(&chunk.hash, simhash, &chunk.point_id, &filename);
+            }
+        }
+        index
+    }
+
+    /// Ranges of bits of the SimHash blocks
+    fn blocks(max_distance: u32) -> impl Iterator<Item = (usize, u32, u32)> {
+        let count = max_distance + 1;
+        (0..count).map(move |block| (block as usize, block * 64 / count, (block + 1) * 64 / count))
+    }
+
+    /// Gets the value of the bits `start..end` of a SimHash
+    fn block_value(simhash: u64, start: u32, end: u32) -> u64 {
+        let width = end - start;
+        let mask = if width >= 64 { u64::MAX } else { (1u64 << width) - 1 };
+        simhash >> start & mask
+    }
+
+    /// Adds a chunk stored in a point
+    ///
+    /// # Arguments
+    /// * `hash` - Hash of the chunk
+    /// * `simhash` - SimHash of the chunk text, if it can be compared
+    /// * `point_id` - ID of the point holding the chunk
+    /// * `file` - File referencing the point
+    pub fn insert(&mut self, hash: &str, simhash: Option<u64>, point_id: &str, file: &str) {
+        self.add(hash, simhash, point_id, file, true);
+    }
+
+    /// Adds a chunk about to be stored by the current run
+    ///
+    /// Its point is only shared once `mark_stored` confirms the upsert, so
+    /// that no file references a point whose upsert fails, even if the run
+    /// is interrupted before the shared points are synchronized.
+    ///
+    /// # Arguments
+    /// * `hash` - Hash of the chunk
+    /// * `simhash` - SimHash of the chunk text, if it can be compared
+    /// * `point_id` - ID of the point that will hold the chunk
+    /// * `file` - File owning the point
+    pub fn insert_pending(&mut self, hash: &str, simhash: Option<u64>, point_id: &str, file: &str) {
+        self.add(hash, simhash, point_id, file, false);
+    }
+
+    /// Allows the chunks of other files to share a point whose upsert succeeded
+    ///
+    /// # Arguments
+    /// * `point_id` - ID of the stored point
+    pub fn mark_stored(&mut self, point_id: &str) {
+        for position in self.by_point_id.get(point_id).into_iter().flatten() {
+            self.points[*position].stored = true;
+        }
+    }
+
+    fn add(&mut self, hash: &str, simhash: Option<u64>, point_id: &str, file: &str, stored: bool) {
+        let position = self.points.len();
+        self.points.push(IndexedPoint {
+            point_id: point_id.to_string(),
+            file: file.to_string(),
+            simhash,
+            removed: false,
+            stored,
+        });
+        self.by_hash.entry(hash.to_string()).or_default().push(position);
+        self.by_point_id.entry(point_id.to_string()).or_default().push(position);
+        if let (Some(simhash), Some(max_distance)) = (simhash, self.max_distance) {
+            for (block, start, end) in Self::blocks(max_distance) {
+                self.by_block
+                    .entry((block, Self::block_value(simhash, start, end)))
+                    .or_default()
+                    .push(position);
+            }
+        }
+    }
+
+    /// Removes a deleted point from the index
+    ///
+    /// # Arguments
+    /// * `point_id` - ID of the deleted point
+    pub fn remove_point(&mut self, point_id: &str) {
+        for position in self.by_point_id.remove(point_id).unwrap_or_default() {
+            self.points[position].removed = true;
+        }
+    }
+
+    /// Finds a point holding the same passage as a chunk, in another file
+    ///
+    /// # Arguments
+    /// * `chunk` - The new chunk, with its hash and SimHash
+    /// * `filename` - File of the chunk, whose own points are not matched
+    ///
+    /// # Returns
+    /// * `Option<DuplicateMatch>` - The matching point, None if the chunk is new
+    pub fn find(&self, chunk: &PendingChunk, filename: &str) -> Option<DuplicateMatch> {
+        let usable = |point: &&IndexedPoint| !point.removed && point.stored && point.file != filename;
+
+        if let Some(point) = self
+            .by_hash
+            .get(&chunk.hash)
+            .into_iter()
+            .flatten()
+            .map(|position| &self.points[*position])
+            .find(usable)
+        {
+            return Some(DuplicateMatch {
+                point_id: point.point_id.clone(),
+                file: point.file.clone(),
+                exact: true,
+            });
+        }
+
+        let (simhash, max_distance) = (chunk.simhash?, self.max_distance?);
+        Self::blocks(max_distance)
+            .filter_map(|(block, start, end)| self.by_block.get(&(block, Self::block_value(simhash, start, end))))
+            .flatten()
+            .map(|position| &self.points[*position])
+            .filter(usable)
+            .filter_map(|point| Some((point, (point.simhash? ^ simhash).count_ones())))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(point, distance)| DuplicateMatch {
+                point_id: point.point_id.clone(),
+                file: point.file.clone(),
+                exact: distance == 0,
+            })
+    }
+}
+
+/// Points whose references changed during a run, to be synchronized with `sync_shared_points`
+#[derive(Debug, Default)]
+pub struct SharedPoints {
+    /// Points shared or unshared by a file
+    pub touched: HashSet<String>,
+    /// Points dropped by their owner while other files referenced them, kept until synchronized
+    pub released: HashSet<String>,
+}
+
+impl SharedPoints {
+    /// Returns true if no point needs to be synchronized
+    pub fn is_empty(&self) -> bool {
+        self.touched.is_empty() && self.released.is_empty()
+    }
+
+    /// Keeps the vanished points of a file that other files still reference
+    ///
+    /// The points referenced by other files, in the tracker or by the current
+    /// run, are moved from `to_delete` to the released points; the shared
+    /// points the file no longer references are touched.
+    ///
+    /// # Arguments
+    /// * `tracker` - File tracker
+    /// * `filename` - Name of the file
+    /// * `to_delete` - Points of the vanished chunks owned by the file, filtered in place
+    /// * `to_unshare` - Points of the vanished chunks shared by the file
+    pub fn release(&mut self, tracker: &FileTracker, filename: &str, to_delete: &mut Vec<String>, to_unshare: &[String]) {
+        self.touched.extend(to_unshare.iter().cloned());
+        if to_delete.is_empty() {
+            return;
+        }
+        let referenced = tracker.referenced_elsewhere(filename, to_delete);
+        to_delete.retain(|point_id| {
+            if referenced.contains(point_id) || self.touched.contains(point_id) {
+                self.released.insert(point_id.clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Synchronizes the payload of the points whose references changed
+///
+/// For each point, the files referencing it are read from the tracker:
+/// - a point with an owner gets the files sharing it as `sources`, and their
+///   MD5 as `source_hashes`;
+/// - a released point gets the first file sharing it as owner (`source`,
+///   `file_hash`, `chunk_hash`), or is deleted if no file references it;
+/// - a shared point that no file owns and that was not released was never
+///   stored: the files sharing it drop it and are recorded as failed, so
+///   that the chunk is indexed again on the next run. Points are only shared
+///   once stored (see `DedupIndex::insert_pending`), so this only guards
+///   against an inconsistent tracker.
+///
+/// # Arguments
+/// * `qdrant_client` - Qdrant client
+/// * `collection_name` - Name of the collection
+/// * `tracker` - File tracker, updated with the new owners and the dropped references
+/// * `points` - Points whose references changed during the run
+///
+/// # Returns
+/// * `Vec<(String, String)>` - Files recorded as failed because of a missing point, with the error
+pub async fn sync_shared_points(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    tracker: &mut FileTracker,
+    points: SharedPoints,
+) -> Vec<(String, String)> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+    let mut point_ids: HashSet<String> = points.touched;
+    point_ids.extend(points.released.iter().cloned());
+    let references = tracker.point_references(&point_ids);
+
+    let mut payloads: HashMap<String, (Value, Vec<String>)> = HashMap::new();
+    let mut to_delete: Vec<String> = Vec::new();
+    let mut failed_files: Vec<(String, String)> = Vec::new();
+
+    for point_id in point_ids {
+        let files = references.get(&point_id).cloned().unwrap_or_default();
+        let owner = files.iter().find(|(_, shared)| !shared).map(|(file, _)| file.clone());
+        let sharing: Vec<String> = files
+            .iter()
+            .filter(|(_, shared)| *shared)
+            .map(|(file, _)| file.clone())
+            .collect();
+
+        let payload = match owner {
+            Some(_) => json!({ "sources": sharing, "source_hashes": source_hashes(tracker, &sharing) }),
+            None if points.released.contains(&point_id) => {
+                let Some((new_owner, others)) = sharing.split_first() else {
+                    to_delete.push(point_id);
+                    continue;
+                };
+                let md5 = tracker.get_file_md5(new_owner).cloned().unwrap_or_default();
+                let hashes = source_hashes(tracker, others);
+                let Some(chunk) = tracker.take_point_ownership(new_owner, &point_id) else {
+                    continue;
+                };
+                info!("Point {} is now owned by {}", point_id, new_owner);
+                let mut payload = json!({
+                    "source": new_owner,
+                    "file_hash": md5,
+                    "chunk_hash": chunk.hash,
+                    "sources": others,
+                    "source_hashes": hashes,
+                });
+                if let Some(index) = chunk.index {
+                    payload["chunk_index"] = index.into();
+                }
+                payload
+            }
+            None => {
+                for file in sharing {
+                    let error = format!("Shared chunk point {} could not be stored", point_id);
+                    warn!("{}: {}", file, error);
+                    tracker.drop_missing_point(&file, &point_id, error.clone());
+                    failed_files.push((file, error));
+                }
+                continue;
+            }
+        };
+        payloads
+            .entry(payload.to_string())
+            .or_insert_with(|| (payload, Vec::new()))
+            .1
+            .push(point_id);
+    }
+
+    for (payload, point_ids) in payloads.into_values() {
+        match qdrant_client.set_payload(collection_name, payload, &point_ids).await {
+            Ok(true) => {}
+            Ok(false) => error!("Failed to update the sources of {} shared points", point_ids.len()),
+            Err(e) => error!("Failed to update the sources of {} shared points: {}", point_ids.len(), e),
+        }
+    }
+    if !to_delete.is_empty() {
+        info!("Deleting {} points no longer referenced by any file", to_delete.len());
+        if let Err(e) = delete_points(qdrant_client, collection_name, &to_delete).await {
+            error!("{}", e);
+        }
+    }
+    if let Err(e) = tracker.save() {
+        error!("{}", e);
+    }
+
+    failed_files
+}
+
+/// Gets the MD5 of the files sharing a point, by file name
+fn source_hashes(tracker: &FileTracker, files: &[String]) -> Value {
+    let hashes: serde_json::Map<String, Value> = files
+        .iter()
+        .map(|file| (file.clone(), tracker.get_file_md5(file).cloned().unwrap_or_default().into()))
+        .collect();
+    Value::Object(hashes)
+}
+
+/// Deletes points, reporting a failure as an error
+async fn delete_points(qdrant_client: &QdrantClient, collection_name: &str, point_ids: &[String]) -> Result<(), AppError> {
+    match qdrant_client.delete_points(collection_name, point_ids).await? {
+        true => Ok(()),
+        false => Err(AppError::Qdrant(format!("Failed to delete {} unreferenced points", point_ids.len()))),
+    }
+}
+
+/// Converts a new chunk into a reference to the point of its duplicate
+///
+/// # Arguments
+/// * `chunk` - The new chunk
+/// * `duplicate` - The point holding the same passage
+///
+/// # Returns
+/// * `TrackedChunk` - The chunk, tracked as shared
+pub fn shared_chunk(chunk: &PendingChunk, duplicate: &DuplicateMatch) -> TrackedChunk {
+    TrackedChunk {
+        hash: chunk.hash.clone(),
+        point_id: duplicate.point_id.clone(),
+        index: Some(chunk.index),
+        parent: None,
+        simhash: None,
+        shared: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Map;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    const TEXT: &str = "Le proxy recherche les fragments les plus proches de la question \
+                        et les ajoute au message système avant d'appeler le modèle";
+
+    fn pending(hash: &str, simhash: Option<u64>) -> PendingChunk {
+        PendingChunk {
+            index: 0,
+            text: String::new(),
+            location: Map::new(),
+            ocr: false,
+            lang: None,
+            hash: hash.to_string(),
+            point_id: format!("point-{}", hash),
+            simhash,
+            parent_id: None,
+            is_parent: false,
+            sparse: None,
+        }
+    }
+
+    fn tracked(hash: &str, point_id: &str, simhash: Option<u64>, shared: bool) -> TrackedChunk {
+        TrackedChunk {
+            hash: hash.to_string(),
+            point_id: point_id.to_string(),
+            index: Some(0),
+            parent: None,
+            simhash: simhash.map(format_simhash),
+            shared,
+        }
+    }
+
+    fn index(near_duplicate_distance: Option<u32>) -> DedupIndex {
+        DedupIndex::from_tracker(
+            &FileTracker::new(),
+            &DedupConfig {
+                enabled: true,
+                near_duplicate_distance,
+            },
+        )
+    }
+
+    #[test]
+    fn simhash_ignores_case_and_punctuation_and_skips_short_texts() {
+        let simhash = simhash(TEXT).unwrap();
+        let shouted = TEXT.to_uppercase().replace(' ', " , ");
+        assert_eq!(super::simhash(&shouted), Some(simhash));
+
+        let edited = super::simhash(&TEXT.replace("proches", "voisins")).unwrap();
+        assert!((simhash ^ edited).count_ones() <= 16);
+        assert_eq!(super::simhash("trop court pour être comparé"), None);
+        assert_eq!(format_simhash(0xff), "00000000000000ff");
+    }
+
+    #[test]
+    fn near_duplicates_match_up_to_the_distance() {
+        let mut index = index(Some(3));
+        index.insert("stored", Some(0), "point-stored", "a.md");
+
+        // Three bits apart, each in a different block: the last block is still shared
+        let near = index.find(&pending("new", Some(1 | 1 << 20 | 1 << 40)), "b.md").unwrap();
+        assert_eq!((near.point_id.as_str(), near.file.as_str(), near.exact), ("point-stored", "a.md", false));
+        // Four bits apart, one in each block: beyond the threshold and no block in common
+        assert!(index.find(&pending("new", Some(1 | 1 << 20 | 1 << 40 | 1 << 60)), "b.md").is_none());
+        assert!(index.find(&pending("new", Some(0b1111)), "b.md").is_none());
+        // The same SimHash with another hash is a near-duplicate of distance 0
+        assert!(index.find(&pending("new", Some(0)), "b.md").unwrap().exact);
+        // Chunks too short to be compared only match identical chunks
+        assert!(index.find(&pending("new", None), "b.md").is_none());
+    }
+
+    #[test]
+    fn identical_chunks_match_other_files_and_live_points_only() {
+        let mut index = index(None);
+        index.insert("stored", None, "point-stored", "a.md");
+
+        let exact = index.find(&pending("stored", None), "b.md").unwrap();
+        assert_eq!((exact.point_id.as_str(), exact.exact), ("point-stored", true));
+        assert!(index.find(&pending("stored", None), "a.md").is_none());
+
+        index.remove_point("point-stored");
+        assert!(index.find(&pending("stored", None), "b.md").is_none());
+    }
+
+    #[test]
+    fn points_of_the_run_are_shared_once_stored() {
+        let mut index = index(Some(3));
+        index.insert_pending("stored", Some(0), "point-stored", "a.md");
+        assert!(index.find(&pending("stored", Some(0)), "b.md").is_none());
+        assert!(index.find(&pending("new", Some(1)), "b.md").is_none());
+
+        // Another file stores the same chunk in the meantime: its point is shared once stored
+        index.insert_pending("stored", Some(0), "point-c", "c.md");
+        index.mark_stored("point-c");
+        assert_eq!(index.find(&pending("stored", Some(0)), "b.md").unwrap().point_id, "point-c");
+
+        index.mark_stored("point-stored");
+        assert_eq!(index.find(&pending("stored", Some(0)), "b.md").unwrap().point_id, "point-stored");
+        assert!(index.find(&pending("new", Some(1)), "b.md").is_some());
+    }
+
+    #[test]
+    fn default_config_only_matches_identical_chunks() {
+        let config = DedupConfig::default();
+        assert!(config.enabled);
+        assert_eq!(config.near_duplicate_distance, None);
+
+        let mut tracker = FileTracker::new();
+        tracker.set_file(
+            "a.md".to_string(),
+            "md5-a".to_string(),
+            vec![tracked("stored", "point-stored", Some(0), false)],
+        );
+        let index = DedupIndex::from_tracker(&tracker, &config);
+        assert!(index.find(&pending("new", Some(1)), "b.md").is_none());
+        assert!(index.find(&pending("stored", Some(0)), "b.md").is_some());
+
+        // The SimHash stored in the tracker is used once a distance is set
+        let config = DedupConfig {
+            enabled: true,
+            near_duplicate_distance: Some(1),
+        };
+        let index = DedupIndex::from_tracker(&tracker, &config);
+        assert_eq!(index.find(&pending("new", Some(1)), "b.md").unwrap().point_id, "point-stored");
+    }
+
+    #[test]
+    fn shared_chunks_reference_the_point_of_their_duplicate() {
+        let duplicate = DuplicateMatch {
+            point_id: "point-stored".to_string(),
+            file: "a.md".to_string(),
+            exact: true,
+        };
+        let chunk = shared_chunk(&PendingChunk { index: 4, ..pending("new", Some(0)) }, &duplicate);
+        assert_eq!(chunk.point_id, "point-stored");
+        assert_eq!(chunk.hash, "new");
+        assert_eq!(chunk.index, Some(4));
+        assert!(chunk.shared && chunk.simhash.is_none());
+    }
+
+    #[test]
+    fn release_keeps_the_points_shared_by_other_files() {
+        let mut tracker = FileTracker::new();
+        tracker.set_file(
+            "a.md".to_string(),
+            "md5-a".to_string(),
+            vec![tracked("one", "p1", None, false), tracked("two", "p2", None, false)],
+        );
+        tracker.set_file("b.md".to_string(), "md5-b".to_string(), vec![tracked("one", "p1", None, true)]);
+
+        // The owner drops both points: only the unshared one is deleted
+        let mut points = SharedPoints::default();
+        let mut to_delete = vec!["p1".to_string(), "p2".to_string()];
+        points.release(&tracker, "a.md", &mut to_delete, &[]);
+        assert_eq!(to_delete, ["p2"]);
+        assert_eq!(points.released, HashSet::from(["p1".to_string()]));
+        assert!(points.touched.is_empty());
+
+        // A file dropping a shared chunk touches the point without deleting it
+        let mut points = SharedPoints::default();
+        let mut to_delete = Vec::new();
+        points.release(&tracker, "b.md", &mut to_delete, &["p1".to_string()]);
+        assert!(to_delete.is_empty() && points.released.is_empty());
+        assert_eq!(points.touched, HashSet::from(["p1".to_string()]));
+
+        // A point unshared during the run is kept until synchronized
+        let mut to_delete = vec!["p3".to_string()];
+        points.touched.insert("p3".to_string());
+        points.release(&tracker, "c.md", &mut to_delete, &[]);
+        assert!(to_delete.is_empty());
+        assert!(points.released.contains("p3"));
+    }
+
+    /// Requests received by the stand-in Qdrant server: request path and JSON body
+    type Received = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// Starts a minimal Qdrant HTTP server accepting every request
+    ///
+    /// # Returns
+    /// * `(QdrantClient, Received)` - A client of the server and the requests received
+    fn start_qdrant() -> (QdrantClient, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received: Received = Arc::default();
+        let log = received.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                let body_start = loop {
+                    if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break request.len(),
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..body_start]).to_string();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .and_then(|length| length.trim().parse().ok())
+                    .unwrap_or(0);
+                while request.len() < body_start + length {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+                let body = serde_json::from_slice(&request[body_start..]).unwrap_or(Value::Null);
+                log.lock().unwrap().push((path, body));
+
+                let body = r#"{"result":{"operation_id":0,"status":"completed"},"status":"ok","time":0}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        let client = QdrantClient::new("127.0.0.1".to_string(), port, String::new(), 4, "Cosine".to_string(), 10, 0.5, false);
+        (client, received)
+    }
+
+    #[tokio::test]
+    async fn sync_hands_released_points_over_and_updates_sources() {
+        let (client, received) = start_qdrant();
+        let mut tracker = FileTracker::new();
+        // a.md owned p1 and p2 and was deleted: b.md and c.md share p1, nobody shares p2
+        tracker.set_file("b.md".to_string(), "md5-b".to_string(), vec![tracked("one", "p1", None, true)]);
+        tracker.set_file("c.md".to_string(), "md5-c".to_string(), vec![tracked("one", "p1", None, true)]);
+        // d.md owns p3, still shared by e.md after f.md unshared it
+        tracker.set_file("d.md".to_string(), "md5-d".to_string(), vec![tracked("three", "p3", None, false)]);
+        tracker.set_file("e.md".to_string(), "md5-e".to_string(), vec![tracked("three", "p3", None, true)]);
+
+        let points = SharedPoints {
+            touched: HashSet::from(["p3".to_string()]),
+            released: HashSet::from(["p1".to_string(), "p2".to_string()]),
+        };
+        let failed = sync_shared_points(&client, "docs", &mut tracker, points).await;
+        assert!(failed.is_empty());
+
+        let mut received = received.lock().unwrap().clone();
+        received.sort_by_key(|(_, body)| body.to_string());
+        let payload_path = "/collections/docs/points/payload?wait=true";
+        assert_eq!(
+            received,
+            [
+                (
+                    payload_path.to_string(),
+                    json!({
+                        "payload": {
+                            "source": "b.md",
+                            "file_hash": "md5-b",
+                            "chunk_hash": "one",
+                            "sources": ["c.md"],
+                            "source_hashes": { "c.md": "md5-c" },
+                            "chunk_index": 0,
+                        },
+                        "points": ["p1"],
+                    }),
+                ),
+                (
+                    payload_path.to_string(),
+                    json!({
+                        "payload": { "sources": ["e.md"], "source_hashes": { "e.md": "md5-e" } },
+                        "points": ["p3"],
+                    }),
+                ),
+                (
+                    "/collections/docs/points/delete?wait=true".to_string(),
+                    json!({ "points": ["p2"] }),
+                ),
+            ]
+        );
+        // b.md now owns p1, c.md still shares it
+        assert!(!tracker.get_file("b.md").unwrap().chunks[0].shared);
+        assert!(tracker.get_file("c.md").unwrap().chunks[0].shared);
+    }
+}
@@ -1,148 +1,1178 @@
-//! File tracker module for tracking indexed files and their MD5 checksums.
+//! File tracker module for tracking indexed files and their chunks.
 //!
 //! This module provides functionality to track which files have been indexed,
 //! and to determine if files have changed since their last indexing.
 //! It uses a JSON file to persist the tracking information between runs.
 //! This allows the indexing process to skip files that haven't changed,
 //! significantly improving performance when re-running the indexing process.
+//!
+//! For each file, the tracker records the MD5 of the whole file and the hash
+//! and Qdrant point ID of each of its chunks. When a file changes, only the
+//! chunks that did not exist before need to be embedded and upserted, and the
+//! points of the chunks that vanished can be deleted from Qdrant.
+//!
+//! Each file also has an indexing status: files that could not be indexed
+//! (`failed`) or whose chunks were only partly embedded (`partial`) are kept
+//! with their error and attempt count, and retried on the next runs.
+//!
+//! Files are keyed by data source and entry (`<source id>:<entry id>`, or the
+//! bare entry id for the default source), and record the version listed by
+//! their source when they were indexed: entries whose version did not change
+//! are skipped without being fetched.
+//!
+//! A chunk may be stored in a point owned by another file, when the two
+//! files hold the same (or a near-identical) passage: the chunk is then
+//! tracked as `shared` and the file is listed in the `sources` payload of the
+//! point, which is only deleted once no file references it anymore.
+//!
+//! The tracker format is versioned. Version 1 (`{"files": {"name": "md5"}}`)
+//! is migrated on load; migrated entries are flagged as `legacy` since their
+//! point IDs are unknown.
+//!
+//! A tracker opened with `FileTracker::open` holds an advisory lock on
+//! `<tracker>.lock` for as long as it lives, so two indexers cannot run on the
+//! same tracker, and is saved with `save` by the pipeline after each indexed
+//! file (or every `tracker_save_files` files). Saving writes a temporary file
+//! and renames it over the tracker, so a crash during the write never leaves a
+//! truncated tracker behind.
 
 use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use crate::AppError;
 use crate::Config;
+use crate::indexing::chunker::DocumentChunk;
+use crate::indexing::data_source::{DataSource, MEMBER_SEPARATOR, SourceFile};
+use crate::indexing::dedup;
+use crate::qdrant_custom_client::SparseVector;
+
+/// Current version of the tracker file format
+pub const TRACKER_FORMAT_VERSION: u32 = 2;
+
+/// A chunk of a tracked file, stored in Qdrant as one point
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackedChunk {
+    /// MD5 of the chunk text
+    pub hash: String,
+    /// ID of the Qdrant point holding the chunk
+    pub point_id: String,
+    /// Position of the chunk in the file (`chunk_index` payload), None for chunks tracked without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    /// ID of the point of the parent section of the chunk (`parent_id` payload)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// SimHash of the chunk text (hexadecimal), for near-duplicate detection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simhash: Option<String>,
+    /// True if the point is owned by another file, which holds the same passage
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shared: bool,
+}
+
+/// Indexing status of a tracked file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// All the chunks of the file are stored in Qdrant
+    #[default]
+    Ok,
+    /// The file could not be indexed (loading, deletion or upsert error, or no chunk embedded)
+    Failed,
+    /// The file was indexed, but the embedding of some of its chunks failed
+    Partial,
+    /// The type of the file cannot be indexed, it is skipped until it changes
+    Unsupported,
+}
+
+impl FileStatus {
+    /// Returns true if the file is fully indexed
+    pub fn is_ok(&self) -> bool {
+        *self == FileStatus::Ok
+    }
+
+    /// Returns true if the indexing of the file failed or was partial
+    pub fn is_failed(&self) -> bool {
+        matches!(self, FileStatus::Failed | FileStatus::Partial)
+    }
+}
+
+/// Tracking information of a single file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackedFile {
+    /// MD5 of the whole file content
+    pub md5: String,
+    /// Chunks of the file currently stored in Qdrant
+    #[serde(default)]
+    pub chunks: Vec<TrackedChunk>,
+    /// True for entries migrated from format version 1, whose chunks are unknown
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub legacy: bool,
+    /// Indexing status of the file
+    #[serde(default, skip_serializing_if = "FileStatus::is_ok")]
+    pub status: FileStatus,
+    /// Error of the last failed or partial indexing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Number of consecutive failed or partial indexing attempts
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub attempts: u32,
+    /// Version of the entry in its data source when it was indexed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// A chunk that needs to be embedded and upserted
+#[derive(Debug, Clone)]
+pub struct PendingChunk {
+    /// Position of the chunk in the file
+    pub index: usize,
+    /// Text of the chunk
+    pub text: String,
+    /// Location of the chunk in the document, empty for flat documents
+    pub location: Map<String, Value>,
+    /// True if the text was recognized with OCR
+    pub ocr: bool,
+    /// ISO 639-1 code of the language of the chunk
+    pub lang: Option<&'static str>,
+    /// MD5 of the chunk text and location
+    pub hash: String,
+    /// ID of the Qdrant point that will hold the chunk
+    pub point_id: String,
+    /// SimHash of the chunk text, None for chunks too short to be compared and parent sections
+    pub simhash: Option<u64>,
+    /// ID of the point of the parent section of the chunk
+    pub parent_id: Option<String>,
+    /// True for a parent section, excluded from the search
+    pub is_parent: bool,
+    /// Sparse (BM25) vector of the chunk terms, if the collection stores sparse vectors
+    pub sparse: Option<SparseVector>,
+}
+
+/// Differences between the chunks stored for a file and its current chunks
+#[derive(Debug, Default)]
+pub struct ChunkDiff {
+    /// Chunks that are new and must be embedded and upserted
+    pub to_index: Vec<PendingChunk>,
+    /// Chunks already stored in Qdrant that can be kept, with their new position in the file
+    pub unchanged: Vec<TrackedChunk>,
+    /// Point IDs of the chunks that vanished and must be deleted from Qdrant
+    pub to_delete: Vec<String>,
+    /// Point IDs of the vanished chunks that were stored in points of other files
+    pub to_unshare: Vec<String>,
+    /// True if the previous points of the file are unknown (legacy entry)
+    /// and must be deleted by source before upserting
+    pub delete_by_source: bool,
+}
+
+/// Exclusive advisory lock on a tracker file, released when dropped
+#[derive(Debug)]
+pub struct TrackerLock {
+    _file: fs::File,
+}
+
+impl TrackerLock {
+    /// Acquires the lock of a tracker file, without waiting
+    ///
+    /// # Arguments
+    /// * `tracker_path` - Path to the tracker file; the lock is taken on `<tracker_path>.lock`
+    ///
+    /// # Returns
+    /// * `Result<TrackerLock, AppError>` - The lock, or an error if another process holds it
+    pub fn acquire(tracker_path: &str) -> Result<Self, AppError> {
+        let lock_path = format!("{}.lock", tracker_path);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(TrackerLock { _file: file }),
+            Err(fs::TryLockError::WouldBlock) => Err(AppError::Config(format!(
+                "Tracker '{}' is locked by another indexing process ({})",
+                tracker_path, lock_path
+            ))),
+            Err(fs::TryLockError::Error(e)) => Err(AppError::Io(e)),
+        }
+    }
+}
+
+/// Tracker file opened for the duration of an indexing run
+#[derive(Debug)]
+struct TrackerStore {
+    path: String,
+    _lock: TrackerLock,
+}
 
 /// File tracker structure for managing file indexing status
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileTracker {
-    files: HashMap<String, String>, // filename -> md5
+    version: u32,
+    files: HashMap<String, TrackedFile>,
+    /// Version of each archive whose members were all tracked after it was expanded
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    archives: HashMap<String, String>,
+    /// Checkpoint of each data source whose listed entries were all tracked (e.g. the indexed commit)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    checkpoints: HashMap<String, String>,
+    #[serde(skip)]
+    store: Option<TrackerStore>,
+}
+
+/// Tracker file format version 1: filename -> md5
+#[derive(Deserialize)]
+struct FileTrackerV1 {
+    #[serde(default)]
+    files: HashMap<String, String>,
+}
+
+impl Default for FileTracker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FileTracker {
     /// Creates a new empty file tracker
     pub fn new() -> Self {
         FileTracker {
+            version: TRACKER_FORMAT_VERSION,
             files: HashMap::new(),
+            archives: HashMap::new(),
+            checkpoints: HashMap::new(),
+            store: None,
+        }
+    }
+
+    /// Locks and loads a tracker file, so that it can be saved with `save`
+    ///
+    /// # Arguments
+    /// * `file_path` - Path to the JSON file containing tracking information
+    ///
+    /// # Returns
+    /// * `Result<FileTracker, AppError>` - The loaded tracker, or an error if it is locked or invalid
+    pub fn open(file_path: &str) -> Result<Self, AppError> {
+        let lock = TrackerLock::acquire(file_path)?;
+        let mut tracker = FileTracker::new();
+        tracker
+            .load_from_file(file_path)
+            .map_err(|e| AppError::Config(format!("Failed to load tracker '{}': {}", file_path, e)))?;
+        tracker.store = Some(TrackerStore {
+            path: file_path.to_string(),
+            _lock: lock,
+        });
+        Ok(tracker)
+    }
+
+    /// Saves the tracker to the file it was opened from
+    ///
+    /// Does nothing for a tracker that was not created with `open`.
+    ///
+    /// # Returns
+    /// * `Result<(), AppError>` - Ok if successful, error otherwise
+    pub fn save(&self) -> Result<(), AppError> {
+        match &self.store {
+            Some(store) => self
+                .save_to_file(&store.path)
+                .map_err(|e| AppError::Unknown(format!("Failed to save tracker '{}': {}", store.path, e))),
+            None => Ok(()),
         }
     }
 
     /// Loads tracking information from a JSON file
-    /// 
+    ///
+    /// Files written in format version 1 are migrated to the current format.
+    ///
     /// # Arguments
     /// * `file_path` - Path to the JSON file containing tracking information
-    /// 
+    ///
     /// # Returns
     /// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, error otherwise
     pub fn load_from_file(&mut self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
         if Path::new(file_path).exists() {
             let content = fs::read_to_string(file_path)?;
             if !content.trim().is_empty() {
-                let tracker: FileTracker = serde_json::from_str(&content)?;
+                let tracker = Self::parse(&content)?;
                 self.files = tracker.files;
+                self.archives = tracker.archives;
+                self.checkpoints = tracker.checkpoints;
             }
         }
         Ok(())
     }
 
+    /// Parses the content of a tracker file, migrating older formats
+    fn parse(content: &str) -> Result<FileTracker, Box<dyn std::error::Error>> {
+        let value: serde_json::Value = serde_json::from_str(content)?;
+        match value.get("version").and_then(|v| v.as_u64()) {
+            Some(version) if version == TRACKER_FORMAT_VERSION as u64 => Ok(serde_json::from_value(value)?),
+            Some(version) => Err(format!("Unsupported tracker format version: {}", version).into()),
+            None => {
+                let tracker: FileTrackerV1 = serde_json::from_value(value)?;
+                if !tracker.files.is_empty() {
+                    tracing::info!(
+                        "Migrating {} tracked files to tracker format version {}",
+                        tracker.files.len(),
+                        TRACKER_FORMAT_VERSION
+                    );
+                }
+                let files = tracker
+                    .files
+                    .into_iter()
+                    .map(|(filename, md5)| {
+                        let entry = TrackedFile {
+                            md5,
+                            legacy: true,
+                            ..TrackedFile::default()
+                        };
+                        (filename, entry)
+                    })
+                    .collect();
+                Ok(FileTracker {
+                    files,
+                    ..FileTracker::new()
+                })
+            }
+        }
+    }
+
     /// Saves tracking information to a JSON file
-    /// 
+    ///
+    /// The content is written to `<file_path>.tmp`, flushed to disk and renamed
+    /// over the tracker file, so the tracker is either the old or the new one.
+    ///
     /// # Arguments
     /// * `file_path` - Path to the JSON file where tracking information will be saved
-    /// 
+    ///
     /// # Returns
     /// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, error otherwise
     pub fn save_to_file(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let json = serde_json::to_string_pretty(self)?;
-        let mut file = fs::File::create(file_path)?;
+
+        // Write a temporary file next to the tracker, then atomically replace it
+        let temp_path = PathBuf::from(format!("{}.tmp", file_path));
+        let mut file = fs::File::create(&temp_path)?;
         file.write_all(json.as_bytes())?;
         file.write_all(b"\n")?;
+        file.sync_all()?;
+        fs::rename(&temp_path, file_path)?;
         Ok(())
     }
 
     /// Gets the MD5 checksum for a specific file
-    /// 
+    ///
     /// # Arguments
     /// * `filename` - Name of the file to look up
-    /// 
+    ///
     /// # Returns
     /// * `Option<&String>` - The MD5 checksum if the file is tracked, None otherwise
     pub fn get_file_md5(&self, filename: &str) -> Option<&String> {
+        self.files.get(filename).map(|entry| &entry.md5)
+    }
+
+    /// Gets the tracking information of a specific file
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file to look up
+    ///
+    /// # Returns
+    /// * `Option<&TrackedFile>` - The tracking information if the file is tracked, None otherwise
+    pub fn get_file(&self, filename: &str) -> Option<&TrackedFile> {
         self.files.get(filename)
     }
 
-    /// Sets the MD5 checksum for a specific file
-    /// 
+    /// Sets the MD5 checksum and the stored chunks of a fully indexed file
+    ///
     /// # Arguments
     /// * `filename` - Name of the file to set
     /// * `md5` - MD5 checksum to associate with the file
-    pub fn set_file_md5(&mut self, filename: String, md5: String) {
-        self.files.insert(filename, md5);
+    /// * `chunks` - Chunks of the file stored in Qdrant
+    pub fn set_file(&mut self, filename: String, md5: String, chunks: Vec<TrackedChunk>) {
+        self.files.insert(
+            filename,
+            TrackedFile {
+                md5,
+                chunks,
+                ..TrackedFile::default()
+            },
+        );
+    }
+
+    /// Records a file whose indexing failed or was partial
+    ///
+    /// The chunks must be the ones actually stored in Qdrant after the attempt,
+    /// so that the next attempt only indexes the missing ones.
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file
+    /// * `md5` - MD5 checksum of the file content that was indexed
+    /// * `chunks` - Chunks of the file stored in Qdrant
+    /// * `status` - `Failed` or `Partial`
+    /// * `error` - Error of the attempt
+    pub fn set_file_failed(
+        &mut self,
+        filename: String,
+        md5: String,
+        chunks: Vec<TrackedChunk>,
+        status: FileStatus,
+        error: String,
+    ) {
+        let attempts = self.files.get(&filename).map_or(0, |entry| entry.attempts) + 1;
+        self.files.insert(
+            filename,
+            TrackedFile {
+                md5,
+                chunks,
+                status,
+                error: Some(error),
+                attempts,
+                ..TrackedFile::default()
+            },
+        );
+    }
+
+    /// Records a failed attempt for a file whose stored chunks did not change
+    ///
+    /// Used when the file could not be loaded or its previous points could not
+    /// be deleted: its previous chunks are kept. The MD5 of the current content
+    /// is recorded, so that the file is retried on the next runs according to
+    /// its attempt count rather than as a changed file.
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file
+    /// * `md5` - MD5 checksum of the current file content, if it could be read
+    /// * `error` - Error of the attempt
+    pub fn mark_failed(&mut self, filename: &str, md5: Option<String>, error: String) {
+        let entry = self.files.entry(filename.to_string()).or_default();
+        if let Some(md5) = md5 {
+            entry.md5 = md5;
+        }
+        entry.status = FileStatus::Failed;
+        entry.error = Some(error);
+        entry.attempts += 1;
+    }
+
+    /// Records a file whose type cannot be indexed
+    ///
+    /// The file has no chunks and is not retried: it is skipped until its
+    /// content changes.
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file
+    /// * `md5` - MD5 checksum of the file content
+    /// * `reason` - Why the file cannot be indexed
+    pub fn set_file_unsupported(&mut self, filename: String, md5: String, reason: String) {
+        self.files.insert(
+            filename,
+            TrackedFile {
+                md5,
+                status: FileStatus::Unsupported,
+                error: Some(reason),
+                ..TrackedFile::default()
+            },
+        );
+    }
+
+    /// Replaces the tracking information of a specific file
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file
+    /// * `entry` - Tracking information of the file
+    pub fn insert_file(&mut self, filename: String, entry: TrackedFile) {
+        self.files.insert(filename, entry);
     }
 
     /// Removes a file from tracking
-    /// 
+    ///
     /// # Arguments
     /// * `filename` - Name of the file to remove from tracking
-    pub fn remove_file(&mut self, filename: &str) {
-        self.files.remove(filename);
+    ///
+    /// # Returns
+    /// * `Option<TrackedFile>` - The removed tracking information, if the file was tracked
+    pub fn remove_file(&mut self, filename: &str) -> Option<TrackedFile> {
+        if let Some((archive, _)) = filename.split_once(MEMBER_SEPARATOR) {
+            self.archives.remove(archive);
+        }
+        self.files.remove(filename)
     }
 
-    /// Checks if a file has changed since it was last tracked
+    /// Checks if an archive is unchanged since all its members were tracked
     ///
     /// # Arguments
-    /// * `filename` - Name of the file to check
-    /// * `content` - Current content of the file
+    /// * `archive` - Archive listed by a data source
     ///
     /// # Returns
-    /// * `bool` - True if the file has changed, false if it's the same or not tracked
-    pub fn is_file_changed(&self, filename: &str, content: &[u8]) -> bool {
-        let current_md5 = format!("{:x}", Md5::digest(content));
-        match self.files.get(filename) {
-            Some(stored_md5) => stored_md5 != &current_md5,
-            None => true, // File not tracked yet
+    /// * `bool` - True if the members tracked for the archive are still its members
+    pub fn is_archive_unchanged(&self, archive: &SourceFile) -> bool {
+        self.archives.get(&archive.key) == Some(&archive.entry.version)
+    }
+
+    /// Records the version of the archives whose members are all tracked
+    ///
+    /// An archive is recorded once each of its members listed during the run
+    /// is tracked with its listed version, so that it is not expanded again
+    /// until it changes. Archives with members left to index are forgotten.
+    ///
+    /// # Arguments
+    /// * `files` - Entries of the data sources, with the archives expanded
+    pub fn record_archives(&mut self, files: &[SourceFile]) {
+        let mut archives: HashMap<&str, (&str, bool)> = HashMap::new();
+        for file in files {
+            let (Some(version), Some((archive, _))) = (&file.archive_version, file.key.split_once(MEMBER_SEPARATOR))
+            else {
+                continue;
+            };
+            let tracked = self
+                .files
+                .get(&file.key)
+                .is_some_and(|entry| entry.version.as_ref() == Some(&file.entry.version));
+            let state = archives.entry(archive).or_insert((version, true));
+            state.1 &= tracked;
+        }
+        for (archive, (version, all_tracked)) in archives {
+            if all_tracked {
+                self.archives.insert(archive.to_string(), version.to_string());
+            } else {
+                self.archives.remove(archive);
+            }
         }
     }
 
-    /// Gets a list of files that have changed since last tracking
+    /// Records the checkpoint of the data sources whose listed entries are all tracked
+    ///
+    /// A source is recorded once each of its entries listed during the run is
+    /// tracked with its listed version, so that the next run only considers
+    /// the entries the source reports as changed since (see
+    /// `DataSource::changed_since`). A source with entries left to index keeps
+    /// its previous checkpoint, from which the changes are a superset.
+    ///
+    /// # Arguments
+    /// * `sources` - Data sources of the configuration, listed during the run
+    /// * `files` - Entries of the data sources, with the archives expanded
+    pub fn record_checkpoints(&mut self, sources: &[Arc<dyn DataSource>], files: &[SourceFile]) {
+        for source in sources {
+            let Some(checkpoint) = source.checkpoint() else {
+                continue;
+            };
+            let all_tracked = files.iter().filter(|file| file.source.id() == source.id()).all(|file| {
+                self.files.get(&file.key).is_some_and(|entry| entry.version.as_ref() == Some(&file.entry.version))
+            });
+            if all_tracked {
+                self.checkpoints.insert(source.id().to_string(), checkpoint);
+            }
+        }
+    }
+
+    /// Lists the names of all the tracked files
+    pub fn tracked_files(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    /// Records the version of a tracked file in its data source
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file
+    /// * `version` - Version of the entry that was indexed
+    pub fn set_file_version(&mut self, filename: &str, version: Option<String>) {
+        if let Some(entry) = self.files.get_mut(filename) {
+            entry.version = version;
+        }
+    }
+
+    /// Checks if an entry of a data source has changed since it was last tracked
+    ///
+    /// Entries are compared by version. Entries tracked without a version
+    /// (indexed before data sources had versions, or rebuilt from the
+    /// collection) are fetched and compared by MD5.
+    ///
+    /// # Arguments
+    /// * `file` - Entry of a data source
+    ///
+    /// # Returns
+    /// * `bool` - True if the entry has changed or is not tracked yet, false if it's the same
+    pub fn is_entry_changed(&self, file: &SourceFile) -> bool {
+        let Some(entry) = self.files.get(&file.key) else {
+            return true; // File not tracked yet
+        };
+        match &entry.version {
+            Some(version) => *version != file.entry.version,
+            None => {
+                entry.md5.is_empty()
+                    || file
+                        .fetch()
+                        .map_or(true, |content| format!("{:x}", Md5::digest(content)) != entry.md5)
+            }
+        }
+    }
+
+    /// Gets a list of entries that have changed since last tracking
+    ///
+    /// This function determines which entries need to be re-indexed by comparing
+    /// their current version with the version stored during the last run.
+    /// For a source with a recorded checkpoint that reports its changes since
+    /// (a git repository diffed from the last indexed commit), only the
+    /// entries it reports, and the untracked ones, are compared; the other
+    /// tracked entries are unchanged. Unchanged entries tracked without a
+    /// version get their current version, so that they are not fetched again
+    /// on the next runs.
+    ///
+    /// # Arguments
+    /// * `files` - Entries of the data sources
+    /// * `sources` - Data sources of the configuration, listed during the run
     ///
-    /// This function determines which files need to be re-indexed by comparing
-    /// the current file content with the stored MD5 checksums from the last run.
-    /// Files that haven't changed are skipped to improve performance.
+    /// # Returns
+    /// * `Vec<SourceFile>` - Entries that have changed and need re-indexing
+    pub fn get_changed_files(&mut self, files: &[SourceFile], sources: &[Arc<dyn DataSource>]) -> Vec<SourceFile> {
+        let source_changes: HashMap<&str, HashSet<String>> = sources
+            .iter()
+            .filter_map(|source| {
+                let checkpoint = self.checkpoints.get(source.id())?;
+                Some((source.id(), source.changed_since(checkpoint)?))
+            })
+            .collect();
+        let mut changed = Vec::new();
+        for file in files {
+            // Archive members change with their archive
+            let entry_root = file.entry.id.split(MEMBER_SEPARATOR).next().unwrap_or(&file.entry.id);
+            let unchanged_since_checkpoint = self.files.contains_key(&file.key)
+                && source_changes
+                    .get(file.source.id())
+                    .is_some_and(|source_changed| !source_changed.contains(entry_root));
+            if unchanged_since_checkpoint {
+                continue;
+            }
+            if self.is_entry_changed(file) {
+                changed.push(file.clone());
+            } else if let Some(entry) = self.files.get_mut(&file.key)
+                && entry.version.is_none()
+            {
+                entry.version = Some(file.entry.version.clone());
+            }
+        }
+        changed
+    }
+
+    /// Compares the current chunks of a file with the chunks stored for it
     ///
     /// # Arguments
-    /// * `files` - List of file names to check
-    /// * `data_sources_path` - Path to the data sources directory
+    /// * `filename` - Name of the file
+    /// * `chunks` - Current chunks of the file
     ///
     /// # Returns
-    /// * `Vec<String>` - List of file names that have changed and need re-indexing
-    pub fn get_changed_files(&self, files: &[String], data_sources_path: &str) -> Vec<String> {
-        files
+    /// * `ChunkDiff` - Chunks to index, chunks to keep and points to delete
+    pub fn diff_chunks(&self, filename: &str, chunks: &[DocumentChunk]) -> ChunkDiff {
+        let previous = self.files.get(filename);
+        let delete_by_source = previous.is_some_and(|entry| entry.legacy);
+        let stored: HashMap<&str, &TrackedChunk> = previous
+            .map(|entry| entry.chunks.iter().map(|c| (c.hash.as_str(), c)).collect())
+            .unwrap_or_default();
+
+        let mut diff = ChunkDiff {
+            delete_by_source,
+            ..ChunkDiff::default()
+        };
+        let mut seen = HashSet::new();
+        let hashes: Vec<String> = chunks.iter().map(located_chunk_hash).collect();
+        // Point of a chunk: the stored one if it is kept, the one it will be upserted to otherwise
+        let point_of = |hash: &str| match stored.get(hash) {
+            Some(chunk) if !delete_by_source => chunk.point_id.clone(),
+            _ => chunk_point_id(filename, hash),
+        };
+
+        // Empty chunks and repeated chunks (identical chunks of a file share a single point) are not
+        // counted, so that the indexes of consecutive chunks follow each other
+        let indexed = chunks
             .iter()
-            .filter(|filename| {
-                // Check if file exists and has changed
-                let full_path = Path::new(data_sources_path).join(filename);
-                if let Ok(content) = fs::read(&full_path) {
-                    self.is_file_changed(filename, &content)
-                } else {
-                    // If file doesn't exist, it's considered as changed
-                    true
+            .zip(&hashes)
+            .filter(|(chunk, hash)| !chunk.text.trim().is_empty() && seen.insert((*hash).clone()));
+        for (index, (chunk, hash)) in indexed.enumerate() {
+            let parent_id = chunk.parent.map(|parent| point_of(&hashes[parent]));
+            match stored.get(hash.as_str()) {
+                Some(chunk) if !delete_by_source => diff.unchanged.push(TrackedChunk {
+                    index: Some(index),
+                    parent: parent_id,
+                    ..(*chunk).clone()
+                }),
+                _ => diff.to_index.push(PendingChunk {
+                    index,
+                    text: chunk.text.clone(),
+                    location: chunk.location.clone(),
+                    ocr: chunk.ocr,
+                    lang: chunk.lang,
+                    point_id: chunk_point_id(filename, hash),
+                    // Parent sections are only shared when identical
+                    simhash: if chunk.is_parent { None } else { dedup::simhash(&chunk.text) },
+                    hash: hash.clone(),
+                    parent_id,
+                    is_parent: chunk.is_parent,
+                    sparse: chunk.sparse.clone(),
+                }),
+            }
+        }
+
+        if let Some(entry) = previous {
+            for chunk in entry.chunks.iter().filter(|chunk| !seen.contains(&chunk.hash)) {
+                match chunk.shared {
+                    true => diff.to_unshare.push(chunk.point_id.clone()),
+                    false => diff.to_delete.push(chunk.point_id.clone()),
                 }
+            }
+        }
+
+        diff
+    }
+
+    /// Finds another fully indexed file with the same content
+    ///
+    /// # Arguments
+    /// * `md5` - MD5 of the file content
+    /// * `filename` - Name of the file, excluded from the search
+    ///
+    /// # Returns
+    /// * `Option<&str>` - Name of a file with the same MD5, None if there is none
+    pub fn find_same_content(&self, md5: &str, filename: &str) -> Option<&str> {
+        self.files
+            .iter()
+            .filter(|(name, entry)| {
+                name.as_str() != filename && entry.md5 == md5 && entry.status.is_ok() && !entry.legacy && !entry.chunks.is_empty()
             })
-            .cloned()
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+
+    /// Gets the points among the given ones that are referenced by other files
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file whose references are ignored
+    /// * `point_ids` - IDs of the points to check
+    ///
+    /// # Returns
+    /// * `HashSet<String>` - IDs of the points that other files reference
+    pub fn referenced_elsewhere(&self, filename: &str, point_ids: &[String]) -> HashSet<String> {
+        let candidates: HashSet<&str> = point_ids.iter().map(String::as_str).collect();
+        self.files
+            .iter()
+            .filter(|(name, _)| name.as_str() != filename)
+            .flat_map(|(_, entry)| entry.chunks.iter())
+            .filter(|chunk| candidates.contains(chunk.point_id.as_str()))
+            .map(|chunk| chunk.point_id.clone())
+            .collect()
+    }
+
+    /// Lists the files referencing each of the given points
+    ///
+    /// # Arguments
+    /// * `point_ids` - IDs of the points
+    ///
+    /// # Returns
+    /// * `HashMap<String, Vec<(String, bool)>>` - For each referenced point, the files (sorted) and whether their chunk is shared
+    pub fn point_references(&self, point_ids: &HashSet<String>) -> HashMap<String, Vec<(String, bool)>> {
+        let mut references: HashMap<String, Vec<(String, bool)>> = HashMap::new();
+        for (filename, entry) in &self.files {
+            for chunk in entry.chunks.iter().filter(|chunk| point_ids.contains(&chunk.point_id)) {
+                references
+                    .entry(chunk.point_id.clone())
+                    .or_default()
+                    .push((filename.clone(), chunk.shared));
+            }
+        }
+        for files in references.values_mut() {
+            files.sort();
+            files.dedup();
+        }
+        references
+    }
+
+    /// Makes a file the owner of a point it shared, once the previous owner released it
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file
+    /// * `point_id` - ID of the point
+    ///
+    /// # Returns
+    /// * `Option<&TrackedChunk>` - The chunk of the file now owning the point, None if the file does not reference it
+    pub fn take_point_ownership(&mut self, filename: &str, point_id: &str) -> Option<&TrackedChunk> {
+        let chunk = self
+            .files
+            .get_mut(filename)?
+            .chunks
+            .iter_mut()
+            .find(|chunk| chunk.point_id == point_id)?;
+        chunk.shared = false;
+        Some(chunk)
+    }
+
+    /// Removes the reference of a file to a point that could not be stored
+    ///
+    /// The file is recorded as failed, so that its chunk is indexed again on
+    /// the next run.
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file
+    /// * `point_id` - ID of the missing point
+    /// * `error` - Why the point is missing
+    pub fn drop_missing_point(&mut self, filename: &str, point_id: &str, error: String) {
+        if let Some(entry) = self.files.get_mut(filename) {
+            entry.chunks.retain(|chunk| chunk.point_id != point_id);
+            if entry.status.is_ok() {
+                entry.attempts += 1;
+            }
+            entry.status = FileStatus::Failed;
+            entry.error = Some(error);
+        }
+    }
+
+    /// Checks if a failed or partial file should be retried
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file to check
+    /// * `max_attempts` - Maximum number of attempts, None to retry regardless of attempts
+    ///
+    /// # Returns
+    /// * `bool` - True if the file is failed or partial and can be retried
+    pub fn needs_retry(&self, filename: &str, max_attempts: Option<u32>) -> bool {
+        self.files.get(filename).is_some_and(|entry| {
+            entry.status.is_failed() && max_attempts.is_none_or(|max| entry.attempts < max)
+        })
+    }
+
+    /// Gets the failed or partial files that were not retried because they
+    /// reached the maximum number of attempts
+    ///
+    /// # Arguments
+    /// * `max_attempts` - Maximum number of attempts
+    ///
+    /// # Returns
+    /// * `Vec<String>` - Names of the files given up on until they change
+    pub fn get_exhausted_files(&self, max_attempts: u32) -> Vec<String> {
+        self.files
+            .iter()
+            .filter(|(_, entry)| entry.status.is_failed() && entry.attempts >= max_attempts)
+            .map(|(filename, _)| filename.clone())
             .collect()
     }
-    
+
     /// Gets the file tracker path from configuration
-    /// 
+    ///
     /// # Arguments
     /// * `config` - Configuration object containing the file tracker path
-    /// 
+    ///
     /// # Returns
     /// * `String` - The path to the file tracker file
     pub fn get_tracker_path(config: &Config) -> String {
         config.indexing.file_tracker_path.clone()
     }
-}
\ No newline at end of file
+}
+
+/// Computes the MD5 hash of a chunk text
+pub fn chunk_hash(text: &str) -> String {
+    format!("{:x}", Md5::digest(text.as_bytes()))
+}
+
+/// Computes the hash of a chunk of a document
+///
+/// The location is part of the hash, so that a chunk moved to another page
+/// or slide is stored again with its new location. Chunks without location
+/// keep the hash of their text alone. Parent sections are hashed apart from
+/// the chunks, so that they never share a point with a chunk of the same text.
+fn located_chunk_hash(chunk: &DocumentChunk) -> String {
+    let kind = if chunk.is_parent { "parent\0" } else { "" };
+    if chunk.location.is_empty() {
+        return chunk_hash(&format!("{}{}", kind, chunk.text));
+    }
+    let location = Value::Object(chunk.location.clone());
+    chunk_hash(&format!("{}{}\0{}", kind, chunk.text, location))
+}
+
+/// Computes the deterministic Qdrant point ID of a chunk of a file
+///
+/// The ID is the MD5 of the filename and the chunk hash, formatted as a UUID
+/// to comply with Qdrant requirements.
+///
+/// # Arguments
+/// * `filename` - Name of the source file
+/// * `chunk_hash` - MD5 of the chunk text
+///
+/// # Returns
+/// * `String` - The point ID in UUID format
+pub fn chunk_point_id(filename: &str, chunk_hash: &str) -> String {
+    let hash = format!("{:x}", Md5::digest(format!("{}\0{}", filename, chunk_hash).as_bytes()));
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hash[..8],
+        &hash[8..12],
+        &hash[12..16],
+        &hash[16..20],
+        &hash[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(text: &str) -> DocumentChunk {
+        DocumentChunk {
+            text: text.to_string(),
+            ..DocumentChunk::default()
+        }
+    }
+
+    fn chunks(texts: &[&str]) -> Vec<DocumentChunk> {
+        texts.iter().map(|text| chunk(text)).collect()
+    }
+
+    /// Records a file as indexed with the given chunks, as the pipeline does
+    fn index(tracker: &mut FileTracker, filename: &str, chunks: &[DocumentChunk]) {
+        let diff = tracker.diff_chunks(filename, chunks);
+        let mut tracked = diff.unchanged;
+        tracked.extend(diff.to_index.into_iter().map(|chunk| TrackedChunk {
+            hash: chunk.hash,
+            point_id: chunk.point_id,
+            index: Some(chunk.index),
+            parent: chunk.parent_id,
+            simhash: chunk.simhash.map(dedup::format_simhash),
+            shared: false,
+        }));
+        tracker.set_file(filename.to_string(), "md5".to_string(), tracked);
+    }
+
+    fn point(filename: &str, text: &str) -> String {
+        chunk_point_id(filename, &chunk_hash(text))
+    }
+
+    #[test]
+    fn chunks_of_a_new_file_are_all_indexed() {
+        let tracker = FileTracker::new();
+        let diff = tracker.diff_chunks("a.md", &chunks(&["one", "two"]));
+        let indexes: Vec<(usize, &str)> = diff.to_index.iter().map(|c| (c.index, c.text.as_str())).collect();
+        assert_eq!(indexes, vec![(0, "one"), (1, "two")]);
+        assert_eq!(diff.to_index[0].point_id, point("a.md", "one"));
+        assert!(diff.unchanged.is_empty() && diff.to_delete.is_empty() && !diff.delete_by_source);
+    }
+
+    #[test]
+    fn diff_keeps_moved_chunks_and_replaces_changed_ones() {
+        let mut tracker = FileTracker::new();
+        index(&mut tracker, "a.md", &chunks(&["one", "two", "three"]));
+
+        let diff = tracker.diff_chunks("a.md", &chunks(&["two", "one", "four"]));
+        let unchanged: Vec<(Option<usize>, &str)> =
+            diff.unchanged.iter().map(|c| (c.index, c.point_id.as_str())).collect();
+        let (one, two) = (point("a.md", "one"), point("a.md", "two"));
+        assert_eq!(unchanged, vec![(Some(0), two.as_str()), (Some(1), one.as_str())]);
+        assert_eq!(diff.to_index.len(), 1);
+        assert_eq!((diff.to_index[0].index, diff.to_index[0].text.as_str()), (2, "four"));
+        assert_eq!(diff.to_delete, vec![point("a.md", "three")]);
+        assert!(diff.to_unshare.is_empty());
+    }
+
+    #[test]
+    fn identical_and_empty_chunks_are_not_indexed_twice() {
+        let tracker = FileTracker::new();
+        let diff = tracker.diff_chunks("a.md", &chunks(&["one", " ", "one", "two"]));
+        let indexes: Vec<(usize, &str)> = diff.to_index.iter().map(|c| (c.index, c.text.as_str())).collect();
+        assert_eq!(indexes, vec![(0, "one"), (1, "two")]);
+    }
+
+    #[test]
+    fn chunk_indexes_are_contiguous_around_repeated_chunks() {
+        let mut tracker = FileTracker::new();
+        index(&mut tracker, "a.md", &chunks(&["one", "two"]));
+
+        let diff = tracker.diff_chunks("a.md", &chunks(&["zero", "one", "zero", "", "two", "one", "three"]));
+        let mut indexes: Vec<(usize, String)> = diff
+            .to_index
+            .iter()
+            .map(|chunk| (chunk.index, chunk.text.clone()))
+            .chain(diff.unchanged.iter().map(|chunk| (chunk.index.unwrap(), chunk.hash.clone())))
+            .collect();
+        indexes.sort();
+        let expected = [(0, "zero"), (1, &chunk_hash("one")), (2, &chunk_hash("two")), (3, "three")];
+        assert_eq!(indexes, expected.map(|(index, text)| (index, text.to_string())));
+    }
+
+    #[test]
+    fn vanished_shared_chunks_are_unshared_instead_of_deleted() {
+        let mut tracker = FileTracker::new();
+        let shared = TrackedChunk {
+            hash: chunk_hash("shared"),
+            point_id: point("b.md", "shared"),
+            index: Some(0),
+            parent: None,
+            simhash: None,
+            shared: true,
+        };
+        tracker.set_file("a.md".to_string(), "md5".to_string(), vec![shared]);
+
+        let diff = tracker.diff_chunks("a.md", &[]);
+        assert_eq!(diff.to_unshare, vec![point("b.md", "shared")]);
+        assert!(diff.to_delete.is_empty());
+    }
+
+    #[test]
+    fn legacy_entries_are_deleted_by_source_and_fully_indexed() {
+        let tracker = FileTracker::parse(r#"{"files": {"a.md": "0123"}}"#).unwrap();
+        let diff = tracker.diff_chunks("a.md", &chunks(&["one", "two"]));
+        assert!(diff.delete_by_source);
+        assert_eq!(diff.to_index.len(), 2);
+        assert!(diff.unchanged.is_empty() && diff.to_delete.is_empty());
+    }
+
+    #[test]
+    fn version_1_tracker_file_is_migrated_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index_tracker.json");
+        let path = path.to_str().unwrap();
+        fs::write(path, r#"{"files": {"a.md": "0123", "b.pdf": "4567"}}"#).unwrap();
+
+        let mut tracker = FileTracker::new();
+        tracker.load_from_file(path).unwrap();
+        let entry = tracker.get_file("a.md").unwrap();
+        assert!(entry.legacy && entry.chunks.is_empty() && entry.status.is_ok());
+        assert_eq!(entry.md5, "0123");
+        assert_eq!(tracker.get_file_md5("b.pdf").map(String::as_str), Some("4567"));
+
+        tracker.save_to_file(path).unwrap();
+        let saved: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(saved["version"], TRACKER_FORMAT_VERSION);
+        let mut reloaded = FileTracker::new();
+        reloaded.load_from_file(path).unwrap();
+        assert!(reloaded.get_file("a.md").unwrap().legacy);
+    }
+
+    #[test]
+    fn archives_are_recorded_once_all_their_members_are_tracked() {
+        use crate::indexing::data_source::{DataSource, FsSource, SourceEntry};
+        use std::sync::Arc;
+
+        let source: Arc<dyn DataSource> = Arc::new(FsSource::new("", "unused"));
+        let member = |id: &str| SourceFile {
+            archive_version: Some("v1".to_string()),
+            ..SourceFile::new(
+                &source,
+                SourceEntry {
+                    id: id.to_string(),
+                    version: "m1".to_string(),
+                },
+            )
+        };
+        let archive = SourceFile::new(
+            &source,
+            SourceEntry {
+                id: "docs.zip".to_string(),
+                version: "v1".to_string(),
+            },
+        );
+        let files = vec![member("docs.zip!/a.md"), member("docs.zip!/b.md")];
+        let mut tracker = FileTracker::new();
+        for file in &files[..1] {
+            index(&mut tracker, &file.key, &chunks(&["text"]));
+            tracker.set_file_version(&file.key, Some("m1".to_string()));
+        }
+
+        tracker.record_archives(&files);
+        assert!(!tracker.is_archive_unchanged(&archive));
+
+        index(&mut tracker, "docs.zip!/b.md", &chunks(&["text"]));
+        tracker.set_file_version("docs.zip!/b.md", Some("m1".to_string()));
+        tracker.record_archives(&files);
+        assert!(tracker.is_archive_unchanged(&archive));
+
+        tracker.remove_file("docs.zip!/a.md");
+        assert!(!tracker.is_archive_unchanged(&archive));
+    }
+
+    #[test]
+    fn unknown_tracker_format_version_is_rejected() {
+        assert!(FileTracker::parse(r#"{"version": 99, "files": {}}"#).is_err());
+    }
+
+    #[test]
+    fn failed_files_are_retried_until_the_attempts_run_out() {
+        let mut tracker = FileTracker::new();
+        for attempt in 1..=3 {
+            tracker.set_file_failed(
+                "a.md".to_string(),
+                "md5".to_string(),
+                Vec::new(),
+                FileStatus::Partial,
+                format!("attempt {}", attempt),
+            );
+            assert_eq!(tracker.needs_retry("a.md", Some(3)), attempt < 3);
+        }
+        let entry = tracker.get_file("a.md").unwrap();
+        assert_eq!((entry.status, entry.attempts, entry.error.as_deref()), (FileStatus::Partial, 3, Some("attempt 3")));
+        assert_eq!(tracker.get_exhausted_files(3), ["a.md"]);
+        // --retry-failed ignores the attempt count
+        assert!(tracker.needs_retry("a.md", None));
+
+        // A successful attempt resets the status and the attempt count
+        tracker.set_file("a.md".to_string(), "md5".to_string(), Vec::new());
+        let entry = tracker.get_file("a.md").unwrap();
+        assert_eq!((entry.status, entry.attempts, entry.error.as_deref()), (FileStatus::Ok, 0, None));
+        assert!(!tracker.needs_retry("a.md", None));
+        assert!(tracker.get_exhausted_files(3).is_empty());
+    }
+
+    #[test]
+    fn failed_loads_keep_the_stored_chunks_and_unsupported_files_are_not_retried() {
+        let mut tracker = FileTracker::new();
+        index(&mut tracker, "a.md", &chunks(&["one"]));
+        tracker.mark_failed("a.md", Some("new-md5".to_string()), "unreadable".to_string());
+        tracker.mark_failed("a.md", None, "still unreadable".to_string());
+        let entry = tracker.get_file("a.md").unwrap();
+        assert_eq!((entry.md5.as_str(), entry.status, entry.attempts), ("new-md5", FileStatus::Failed, 2));
+        assert_eq!(entry.chunks.len(), 1);
+        assert!(tracker.needs_retry("a.md", Some(3)));
+
+        tracker.set_file_unsupported("b.bin".to_string(), "md5".to_string(), "binary".to_string());
+        assert!(!tracker.needs_retry("b.bin", None));
+        assert!(tracker.get_exhausted_files(0).iter().all(|name| name != "b.bin"));
+        assert!(!tracker.needs_retry("missing.md", None));
+    }
+
+    #[test]
+    fn a_locked_tracker_cannot_be_opened_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.json").to_string_lossy().to_string();
+
+        let tracker = FileTracker::open(&path).unwrap();
+        let error = TrackerLock::acquire(&path).unwrap_err();
+        assert!(error.to_string().contains("locked by another indexing process"), "{}", error);
+        assert!(FileTracker::open(&path).is_err());
+
+        drop(tracker);
+        assert!(TrackerLock::acquire(&path).is_ok());
+    }
+
+    #[test]
+    fn saving_replaces_the_tracker_with_a_complete_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.json").to_string_lossy().to_string();
+        fs::write(&path, "{\"version\": 2, \"files\": {\"old.md\"").unwrap();
+        // A temporary file left behind by an interrupted save is overwritten
+        fs::write(format!("{}.tmp", path), "garbage").unwrap();
+
+        let mut tracker = FileTracker::new();
+        index(&mut tracker, "a.md", &chunks(&["one", "two"]));
+        tracker.save_to_file(&path).unwrap();
+
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+        let mut saved = FileTracker::new();
+        saved.load_from_file(&path).unwrap();
+        assert_eq!(saved.tracked_files(), ["a.md"]);
+        assert_eq!(saved.get_file("a.md").unwrap().chunks.len(), 2);
+    }
+}
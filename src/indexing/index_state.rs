@@ -0,0 +1,321 @@
+//! Indexed state module for reading what is stored in the Qdrant collection.
+//!
+//! Every point written by the indexer carries the name of its source file,
+//! the MD5 of the file content and the hash of its chunk. This module scrolls
+//! the collection to rebuild, file by file, the state the tracker should have,
+//! so that Qdrant can be used as the source of truth instead of the local
+//! tracker file (`tracker_source = "qdrant"`) and so that `--verify` can
+//! compare the disk, the tracker and the collection. The files listed in the
+//! `sources` payload of a point share it, and get it as a shared chunk; their
+//! MD5 is read from the `source_hashes` payload, so that a file whose chunks
+//! are all shared keeps its file hash.
+
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use crate::AppError;
+use crate::indexing::file_tracker::{FileTracker, TrackedChunk, TrackedFile};
+use crate::qdrant_custom_client::{QdrantClient, Record};
+
+/// Number of points fetched per scroll request
+const SCROLL_PAGE_SIZE: u64 = 256;
+
+/// Payload fields needed to rebuild the indexed state
+const STATE_PAYLOAD_KEYS: [&str; 8] = [
+    "source",
+    "file_hash",
+    "chunk_hash",
+    "chunk_index",
+    "parent_id",
+    "simhash",
+    "sources",
+    "source_hashes",
+];
+
+/// A file as stored in the Qdrant collection
+#[derive(Debug, Default)]
+pub struct IndexedFile {
+    /// MD5 of the file content, None if the points disagree or predate file hashes
+    pub file_hash: Option<String>,
+    /// Chunks stored for the file
+    pub chunks: Vec<TrackedChunk>,
+    /// Number of points without chunk hash (indexed before chunk tracking)
+    pub legacy_points: usize,
+}
+
+impl IndexedFile {
+    /// Records the file hash carried by a point of the file
+    ///
+    /// # Returns
+    /// * `bool` - False if the points of the file disagree on the file hash
+    fn record_file_hash(&mut self, hash: &str) -> bool {
+        match &self.file_hash {
+            Some(known) => known == hash,
+            None if self.chunks.iter().all(|chunk| chunk.shared) && self.legacy_points == 0 => {
+                self.file_hash = Some(hash.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Converts the stored state of the file into tracking information
+    ///
+    /// Files with legacy points, or whose points disagree on the file hash,
+    /// get an empty MD5 so they are re-indexed on the next run.
+    pub fn to_tracked_file(&self) -> TrackedFile {
+        let consistent = self.legacy_points == 0;
+        TrackedFile {
+            md5: self.file_hash.clone().filter(|_| consistent).unwrap_or_default(),
+            chunks: self.chunks.clone(),
+            legacy: !consistent,
+            ..TrackedFile::default()
+        }
+    }
+}
+
+/// State of the whole collection, by source file
+#[derive(Debug, Default)]
+pub struct IndexedState {
+    /// Files found in the collection
+    pub files: HashMap<String, IndexedFile>,
+    /// Total number of points scrolled
+    pub points: usize,
+    /// IDs of the points without `source` payload, which cannot be attributed to a file
+    pub unattributed_points: Vec<String>,
+}
+
+impl IndexedState {
+    /// Adds a point to the files owning and sharing it
+    ///
+    /// # Arguments
+    /// * `record` - The point
+    /// * `mixed_hashes` - Receives the files whose points carry different file hashes
+    fn add_point(&mut self, record: &Record, mixed_hashes: &mut Vec<String>) {
+        let Some(source) = record.payload_str("source") else {
+            self.unattributed_points.push(record.id_string());
+            return;
+        };
+        let file = self.files.entry(source.to_string()).or_default();
+
+        if !record.payload_str("file_hash").is_some_and(|hash| file.record_file_hash(hash)) {
+            mixed_hashes.push(source.to_string());
+        }
+
+        let Some(chunk_hash) = record.payload_str("chunk_hash") else {
+            file.legacy_points += 1;
+            return;
+        };
+        file.chunks.push(TrackedChunk {
+            hash: chunk_hash.to_string(),
+            point_id: record.id_string(),
+            index: record.payload_u64("chunk_index").map(|index| index as usize),
+            parent: record.payload_str("parent_id").map(str::to_string),
+            simhash: record.payload_str("simhash").map(str::to_string),
+            shared: false,
+        });
+
+        let payload = record.payload.as_ref();
+        let sources = payload.and_then(|payload| payload.get("sources")).and_then(|sources| sources.as_array());
+        let hashes = payload
+            .and_then(|payload| payload.get("source_hashes"))
+            .and_then(|hashes| hashes.as_object());
+        for sharing in sources.into_iter().flatten().filter_map(|sharing| sharing.as_str()) {
+            let file = self.files.entry(sharing.to_string()).or_default();
+            // Points shared before the hashes of the sharing files were recorded leave the file hash unknown
+            let hash = hashes.and_then(|hashes| hashes.get(sharing)).and_then(|hash| hash.as_str());
+            if hash.is_some_and(|hash| !file.record_file_hash(hash)) {
+                mixed_hashes.push(sharing.to_string());
+            }
+            file.chunks.push(TrackedChunk {
+                hash: chunk_hash.to_string(),
+                point_id: record.id_string(),
+                index: None,
+                parent: None,
+                simhash: None,
+                shared: true,
+            });
+        }
+    }
+
+    /// Forgets the file hash of the files whose points disagree on it
+    fn forget_file_hashes(&mut self, mixed_hashes: Vec<String>) {
+        for source in mixed_hashes {
+            if let Some(file) = self.files.get_mut(&source) {
+                file.file_hash = None;
+            }
+        }
+    }
+}
+
+/// Scrolls the collection and groups its points by source file
+///
+/// # Arguments
+/// * `qdrant_client` - Qdrant client
+/// * `collection_name` - Name of the collection
+///
+/// # Returns
+/// * `Result<IndexedState, AppError>` - The indexed state of the collection
+pub async fn fetch_indexed_state(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+) -> Result<IndexedState, AppError> {
+    let mut state = IndexedState::default();
+    // Files whose points carry different file hashes
+    let mut mixed_hashes: Vec<String> = Vec::new();
+    let mut offset = None;
+
+    loop {
+        let page = qdrant_client
+            .scroll_points(collection_name, None, &STATE_PAYLOAD_KEYS, SCROLL_PAGE_SIZE, offset)
+            .await?;
+        state.points += page.points.len();
+
+        for record in &page.points {
+            state.add_point(record, &mut mixed_hashes);
+        }
+
+        match page.next_page_offset {
+            Some(next) if !next.is_null() => offset = Some(next),
+            _ => break,
+        }
+    }
+
+    state.forget_file_hashes(mixed_hashes);
+
+    info!(
+        "Collection '{}' holds {} points from {} files",
+        collection_name,
+        state.points,
+        state.files.len()
+    );
+    if !state.unattributed_points.is_empty() {
+        warn!(
+            "{} points of collection '{}' have no source file",
+            state.unattributed_points.len(),
+            collection_name
+        );
+    }
+
+    Ok(state)
+}
+
+/// Replaces the content of the tracker with the state of the collection
+///
+/// The data source version and the failed or partial status of a file are
+/// kept when the collection holds the same content of the file, so that it
+/// is neither fetched again nor forgotten by the retries. Files without
+/// points recorded as failed or unsupported are kept as well.
+///
+/// # Arguments
+/// * `tracker` - File tracker to update
+/// * `state` - Indexed state of the collection
+pub fn apply_to_tracker(tracker: &mut FileTracker, state: &IndexedState) {
+    for filename in tracker.tracked_files() {
+        let keep = tracker
+            .get_file(&filename)
+            .is_some_and(|entry| !entry.status.is_ok() && entry.chunks.is_empty());
+        if !state.files.contains_key(&filename) && !keep {
+            tracker.remove_file(&filename);
+        }
+    }
+
+    for (filename, indexed_file) in &state.files {
+        let mut entry = indexed_file.to_tracked_file();
+        if let Some(previous) = tracker.get_file(filename)
+            && previous.md5 == entry.md5
+        {
+            entry.version = previous.version.clone();
+            if !previous.status.is_ok() {
+                entry.status = previous.status;
+                entry.error = previous.error.clone();
+                entry.attempts = previous.attempts;
+            }
+        }
+        tracker.insert_file(filename.clone(), entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn record(id: &str, payload: Value) -> Record {
+        Record {
+            id: json!(id),
+            payload: Some(payload),
+        }
+    }
+
+    fn state(records: &[Record]) -> IndexedState {
+        let mut state = IndexedState::default();
+        let mut mixed_hashes = Vec::new();
+        for record in records {
+            state.add_point(record, &mut mixed_hashes);
+        }
+        state.forget_file_hashes(mixed_hashes);
+        state
+    }
+
+    #[test]
+    fn files_with_only_shared_points_keep_their_file_hash() {
+        let state = state(&[
+            record(
+                "p1",
+                json!({
+                    "source": "a.md",
+                    "file_hash": "md5-a",
+                    "chunk_hash": "one",
+                    "sources": ["copy.md", "old.md"],
+                    "source_hashes": { "copy.md": "md5-a", "old.md": "md5-old" },
+                }),
+            ),
+            record(
+                "p2",
+                json!({
+                    "source": "a.md",
+                    "file_hash": "md5-a",
+                    "chunk_hash": "two",
+                    "sources": ["copy.md", "old.md"],
+                    "source_hashes": { "copy.md": "md5-a", "old.md": "md5-other" },
+                }),
+            ),
+            // Shared before the hashes of the sharing files were recorded
+            record(
+                "p3",
+                json!({ "source": "b.md", "file_hash": "md5-b", "chunk_hash": "three", "sources": ["legacy.md"] }),
+            ),
+        ]);
+
+        let copy = state.files["copy.md"].to_tracked_file();
+        assert_eq!(copy.md5, "md5-a");
+        assert_eq!(copy.chunks.len(), 2);
+        assert!(copy.chunks.iter().all(|chunk| chunk.shared));
+        assert_eq!(state.files["a.md"].to_tracked_file().md5, "md5-a");
+        // Disagreeing or unknown hashes get the file re-indexed
+        assert_eq!(state.files["old.md"].file_hash, None);
+        assert_eq!(state.files["legacy.md"].to_tracked_file().md5, "");
+    }
+
+    #[test]
+    fn shared_and_owned_points_must_agree_on_the_file_hash() {
+        let state = state(&[
+            record(
+                "p1",
+                json!({
+                    "source": "a.md",
+                    "file_hash": "md5-a",
+                    "chunk_hash": "one",
+                    "sources": ["b.md", "c.md"],
+                    "source_hashes": { "b.md": "md5-b", "c.md": "md5-c" },
+                }),
+            ),
+            record("p2", json!({ "source": "b.md", "file_hash": "md5-b", "chunk_hash": "two" })),
+            record("p3", json!({ "source": "c.md", "file_hash": "md5-stale", "chunk_hash": "three" })),
+        ]);
+
+        assert_eq!(state.files["b.md"].file_hash.as_deref(), Some("md5-b"));
+        assert_eq!(state.files["c.md"].file_hash, None);
+    }
+}
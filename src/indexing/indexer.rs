@@ -1,46 +1,47 @@
-//! Indexing module for processing text chunks and storing embeddings.
+//! Indexing module for storing chunk embeddings in Qdrant.
 //!
-//! This module handles the process of generating embeddings for text chunks
-//! and storing them in the Qdrant vector database. It serves as the bridge
-//! between text processing and database storage.
+//! This module handles the Qdrant side of the indexing process: checking the
+//! server and the collection once per run, building points from embedded
+//! chunks, upserting them in batches and deleting the points of vanished
+//! chunks. It serves as the bridge between text processing and database storage.
+//!
+//! Only the chunks reported as new by the file tracker are embedded and
+//! upserted; the points of vanished chunks are deleted.
 
 use crate::Config;
 use crate::AppError;
 use crate::qdrant_custom_client::{QdrantClient, Point};
-use crate::clients::ollama::OllamaClient;
+use crate::indexing::dedup;
+use crate::indexing::file_tracker::{ChunkDiff, PendingChunk, TrackedChunk};
 use tracing::{info, error, warn};
-use serde_json;
-use std::time::Instant;
-
-/// Indexes text chunks by generating embeddings and storing them in Qdrant
-///
-/// # Arguments
-/// * `config` - Configuration object containing indexing settings
-/// * `chunks` - Vector of text chunks to index
-/// * `filename` - Name of the source file being indexed
-///
-/// # Returns
-/// * `Result<(), AppError>` - Ok if successful, error otherwise
-pub async fn index_chunks(
-    config: &Config,
-    chunks: &[String],
-    filename: &str,
-) -> Result<(), AppError> {
-    info!("Indexing {} chunks from file: {}", chunks.len(), filename);
-
-    // Start timing the indexing process
-    let start_time = Instant::now();
+use serde_json::{Map, Value};
 
-    // Initialize Qdrant client
-    let qdrant_client = QdrantClient::new(
+/// Creates a Qdrant client from configuration
+pub fn qdrant_client(config: &Config) -> QdrantClient {
+    QdrantClient::new(
         config.qdrant.host.clone(),
         config.qdrant.port,
         config.qdrant.api_key.clone(),
         config.qdrant.vector_size as u64,
         config.qdrant.distance.clone(),
-        config.qdrant.limit as u64,
+        config.qdrant.limit,
         config.qdrant.score_threshold,
-    );
+        config.qdrant.sparse_vectors,
+    )
+}
+
+/// Checks the Qdrant server and makes sure the collection exists
+///
+/// This is done once per indexing run, before any file is processed.
+///
+/// # Arguments
+/// * `config` - Configuration object containing Qdrant settings
+///
+/// # Returns
+/// * `Result<QdrantClient, AppError>` - A Qdrant client ready to use, error otherwise
+pub async fn prepare_collection(config: &Config) -> Result<QdrantClient, AppError> {
+    // Initialize Qdrant client
+    let qdrant_client = qdrant_client(config);
 
     // Check Qdrant health
     match qdrant_client.health_check().await {
@@ -87,105 +88,188 @@ pub async fn index_chunks(
         }
     }
 
-    // Create Ollama client
-    let ollama_client = OllamaClient::new(config);
-
-    // Process chunks in batches
-    let batch_size = config.indexing.embeddings_chunk_size;
-    for (batch_idx, batch) in chunks.chunks(batch_size).enumerate() {
-        info!("Processing batch {}/{} for file: {}", batch_idx + 1, (chunks.len() + batch_size - 1) / batch_size, filename);
-
-        let mut points = Vec::new();
-
-        for (chunk_idx, chunk) in batch.iter().enumerate() {
-            // Filter out empty chunks
-            if chunk.trim().is_empty() {
-                warn!("Skipping empty chunk in batch {} for file: {}", batch_idx + 1, filename);
-                continue;
-            }
-
-            // Generate embedding for the chunk using OllamaClient
-            let embedding = match ollama_client.generate_embedding(chunk).await {
-                Ok(emb) => emb,
-                Err(e) => {
-                    error!("Failed to generate embedding for chunk {}: {}", chunk_idx, e);
-                    continue;
-                }
-            };
+    Ok(qdrant_client)
+}
 
-            // Generate a simple ID for the point
-            let point_id = format!("{}_{}", filename, batch_idx * batch_size + chunk_idx);
+/// Builds the Qdrant point of an embedded chunk
+///
+/// Besides the text, the payload holds what is needed to rebuild the tracker
+/// from the collection: the source file, its MD5 and the chunk hash, and
+/// the location of the chunk and the document metadata extracted by the
+/// loader, if any. Chunks recognized with OCR are flagged with `ocr: true`,
+/// the detected language of the chunk is stored as `lang` and its SimHash as
+/// `simhash`, for near-duplicate detection. A chunk grouped into a parent
+/// section holds the point ID of the section as `parent_id`, and the points
+/// of the parent sections are flagged with `kind: "parent"` (their vector
+/// is all zeros, see `pipeline`). The sparse (BM25) vector of the chunk, if
+/// any, is stored next to its embedding.
+///
+/// # Arguments
+/// * `chunk` - The chunk, with its position, location, hash and point ID
+/// * `filename` - Name of the source file
+/// * `file_hash` - MD5 of the source file content
+/// * `metadata` - Metadata of the document (title, front matter...)
+/// * `embedding` - Embedding of the chunk text
+///
+/// # Returns
+/// * `Point` - The point to upsert
+pub fn chunk_point(
+    chunk: &PendingChunk,
+    filename: &str,
+    file_hash: &str,
+    metadata: &Map<String, Value>,
+    embedding: Vec<f32>,
+) -> Point {
+    let mut payload = serde_json::json!({
+        "text": chunk.text,
+        "source": filename,
+        "file_hash": file_hash,
+        "chunk_index": chunk.index,
+        "chunk_hash": chunk.hash
+    });
+    if !chunk.location.is_empty() {
+        payload["location"] = Value::Object(chunk.location.clone());
+    }
+    if chunk.ocr {
+        payload["ocr"] = Value::Bool(true);
+    }
+    if let Some(lang) = chunk.lang {
+        payload["lang"] = lang.into();
+    }
+    if let Some(simhash) = chunk.simhash {
+        payload["simhash"] = dedup::format_simhash(simhash).into();
+    }
+    if let Some(parent_id) = &chunk.parent_id {
+        payload["parent_id"] = parent_id.as_str().into();
+    }
+    if chunk.is_parent {
+        payload["kind"] = "parent".into();
+    }
+    if !metadata.is_empty() {
+        payload["metadata"] = Value::Object(metadata.clone());
+    }
+    let point = Point::from_id_vector_payload(&chunk.point_id, embedding, payload);
+    match &chunk.sparse {
+        Some(sparse) => point.with_sparse_vector(sparse),
+        None => point,
+    }
+}
 
-            // Create a point for Qdrant
-            let point = Point {
-                id: serde_json::Value::String(point_id),
-                vector: embedding,
-                payload: Some(serde_json::json!({
-                    "text": chunk,
-                    "source_file": filename,
-                    "chunk_index": batch_idx * batch_size + chunk_idx
-                })),
-            };
+/// Records the new MD5 and metadata of a changed file on the points of its unchanged chunks
+///
+/// The position of the chunks in the file is updated as well, since chunks
+/// added or removed before an unchanged chunk shift its `chunk_index`, and
+/// so is the ID of their parent section, which changes with its other chunks.
+///
+/// # Arguments
+/// * `qdrant_client` - Qdrant client
+/// * `collection_name` - Name of the collection
+/// * `chunks` - Unchanged chunks of the file, with their new position
+/// * `file_hash` - New MD5 of the file content
+/// * `metadata` - New metadata of the document
+///
+/// # Returns
+/// * `Result<(), AppError>` - Ok if successful, error otherwise
+pub async fn update_file_payload(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    chunks: &[TrackedChunk],
+    file_hash: &str,
+    metadata: &Map<String, Value>,
+) -> Result<(), AppError> {
+    let point_ids: Vec<String> = chunks.iter().map(|chunk| chunk.point_id.clone()).collect();
+    let mut updates = vec![(serde_json::json!({ "file_hash": file_hash, "metadata": metadata }), point_ids)];
+    updates.extend(chunks.iter().filter_map(|chunk| {
+        let mut position = serde_json::json!({ "chunk_index": chunk.index? });
+        if let Some(parent_id) = &chunk.parent {
+            position["parent_id"] = parent_id.as_str().into();
+        }
+        Some((position, vec![chunk.point_id.clone()]))
+    }));
+    if !qdrant_client.batch_set_payload(collection_name, updates).await? {
+        return Err(AppError::Qdrant(format!(
+            "Failed to update the file payload of {} points",
+            chunks.len()
+        )));
+    }
+    Ok(())
+}
 
-            points.push(point);
+/// Upserts a batch of points into the configured collection
+///
+/// # Arguments
+/// * `qdrant_client` - Qdrant client
+/// * `collection_name` - Name of the collection
+/// * `points` - Points to upsert
+///
+/// # Returns
+/// * `Result<(), AppError>` - Ok if successful, error otherwise
+pub async fn upsert_batch(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    points: Vec<Point>,
+) -> Result<(), AppError> {
+    let points_count = points.len();
+    match qdrant_client.upsert_point_list(collection_name, points).await {
+        Ok(true) => {
+            info!(
+                "Successfully upserted {} points into collection '{}'",
+                points_count, collection_name
+            );
+            Ok(())
         }
+        Ok(false) => {
+            error!("Failed to upsert points into collection '{}'", collection_name);
+            Err(AppError::Qdrant(format!(
+                "Failed to upsert points into collection '{}'",
+                collection_name
+            )))
+        }
+        Err(e) => {
+            error!("Failed to upsert points into collection '{}': {}", collection_name, e);
+            Err(e)
+        }
+    }
+}
 
-        // Upsert points to Qdrant
-        if !points.is_empty() {
-            // Convert points to the format expected by upsert_points
-            let chunks_data: Vec<(String, Vec<f32>)> = points.iter()
-                .map(|p| {
-                    let text = p.payload.as_ref()
-                        .and_then(|payload| payload.get("text"))
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string();
-                    (text, p.vector.clone())
-                })
-                .collect();
+/// Deletes from Qdrant the points of the chunks that vanished from a file
+///
+/// For files tracked in the legacy format, whose point IDs are unknown,
+/// all the points of the file are deleted by source before re-indexing.
+///
+/// # Arguments
+/// * `qdrant_client` - Qdrant client
+/// * `collection_name` - Name of the collection
+/// * `diff` - Chunk differences computed by the file tracker
+/// * `filename` - Name of the source file
+///
+/// # Returns
+/// * `Result<(), AppError>` - Ok if successful, error otherwise
+pub async fn delete_stale_chunks(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    diff: &ChunkDiff,
+    filename: &str,
+) -> Result<(), AppError> {
+    if diff.delete_by_source {
+        info!("Deleting all previous points of legacy tracked file: {}", filename);
+        if !qdrant_client.delete_points_by_source(collection_name, filename).await? {
+            return Err(AppError::Qdrant(format!(
+                "Failed to delete previous points of file '{}'",
+                filename
+            )));
+        }
+    }
 
-            match qdrant_client.upsert_points(&collection_name, filename.to_string(), chunks_data).await {
-                Ok(success) => {
-                    if success {
-                        info!(
-                            "Successfully upserted {} points into collection '{}' for file: {}",
-                            points.len(),
-                            collection_name,
-                            filename
-                        );
-                    } else {
-                        error!(
-                            "Failed to upsert points into collection '{}' for file: {}",
-                            collection_name,
-                            filename
-                        );
-                        return Err(AppError::Qdrant(format!(
-                            "Failed to upsert points into collection '{}'",
-                            collection_name
-                        )));
-                    }
-                }
-                Err(e) => {
-                    error!(
-                        "Failed to upsert points into collection '{}' for file {}: {}",
-                        collection_name, filename, e
-                    );
-                    return Err(e);
-                }
-            }
-        } else {
-            info!("No embeddings to store in Qdrant for batch {} of file: {}", batch_idx + 1, filename);
+    if !diff.to_delete.is_empty() {
+        info!("Deleting {} vanished chunks of file: {}", diff.to_delete.len(), filename);
+        if !qdrant_client.delete_points(collection_name, &diff.to_delete).await? {
+            return Err(AppError::Qdrant(format!(
+                "Failed to delete vanished chunks of file '{}'",
+                filename
+            )));
         }
     }
 
-    // Calculate and display the duration
-    let duration = start_time.elapsed();
-    let seconds = duration.as_secs();
-    let minutes = seconds / 60;
-    let remaining_seconds = seconds % 60;
-    info!(
-        "Indexing completed for file: {} (Duration: {}m {}s)",
-        filename, minutes, remaining_seconds
-    );
     Ok(())
 }
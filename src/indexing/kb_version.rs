@@ -0,0 +1,41 @@
+//! Knowledge base version module.
+//!
+//! This module manages a small version marker file that changes every time
+//! the indexed corpus changes (indexing run that modified documents, reset).
+//! The RAG proxy reads it to invalidate answers cached against an older
+//! version of the knowledge base, on the blocking thread pool since
+//! reading it blocks.
+
+use std::fs;
+use std::path::Path;
+
+use crate::AppError;
+
+/// Writes a new random knowledge base version to the version file
+///
+/// # Arguments
+/// * `version_path` - Path to the knowledge base version file
+///
+/// # Returns
+/// * `Result<String, AppError>` - The new version if successful, error otherwise
+pub fn bump_version(version_path: &str) -> Result<String, AppError> {
+    let version = uuid::Uuid::new_v4().to_string();
+    fs::write(version_path, format!("{}\n", version))?;
+    Ok(version)
+}
+
+/// Reads the current knowledge base version
+///
+/// # Arguments
+/// * `version_path` - Path to the knowledge base version file
+///
+/// # Returns
+/// * `String` - The current version, or an empty string if the file does not exist yet
+pub fn read_version(version_path: &str) -> String {
+    if !Path::new(version_path).exists() {
+        return String::new();
+    }
+    fs::read_to_string(version_path)
+        .map(|content| content.trim().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,197 @@
+//! Language detection module for documents, chunks and questions.
+//!
+//! The language of each chunk is detected with whatlang, restricted to the
+//! languages of the corpus (`[indexing] languages`, ISO 639-1 codes). Short
+//! texts such as questions or table cells are often too short for a reliable
+//! trigram detection: they are attributed to the language whose stop words
+//! they use the most. The language of a document is the language of most of
+//! its text, and is given to its chunks whose language could not be detected.
+//!
+//! The chunk language is stored in the `lang` field of the payload of the
+//! points, so that the retriever can boost or filter the chunks written in
+//! the language of the question (see `[retrieval]`). The stop words of the
+//! language of a chunk are also left out of its sparse (BM25) vector.
+
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+use whatlang::{Detector, Lang};
+
+use crate::indexing::chunker::DocumentChunk;
+
+/// Languages that can be detected: ISO 639-1 code and whatlang language
+const SUPPORTED_LANGUAGES: &[(&str, Lang)] = &[
+    ("ar", Lang::Ara),
+    ("da", Lang::Dan),
+    ("de", Lang::Deu),
+    ("en", Lang::Eng),
+    ("es", Lang::Spa),
+    ("fi", Lang::Fin),
+    ("fr", Lang::Fra),
+    ("it", Lang::Ita),
+    ("ja", Lang::Jpn),
+    ("nl", Lang::Nld),
+    ("pl", Lang::Pol),
+    ("pt", Lang::Por),
+    ("ru", Lang::Rus),
+    ("sv", Lang::Swe),
+    ("tr", Lang::Tur),
+    ("zh", Lang::Cmn),
+];
+
+/// A language of the corpus
+struct Language {
+    /// ISO 639-1 code, stored in the payloads
+    code: &'static str,
+    lang: Lang,
+    stop_words: HashSet<&'static str>,
+}
+
+/// Detects the language of texts among the languages of the corpus
+pub struct LanguageDetector {
+    detector: Detector,
+    languages: Vec<Language>,
+}
+
+impl LanguageDetector {
+    /// Creates a detector for the languages of the corpus
+    ///
+    /// Unsupported codes are ignored with a warning. Without any language,
+    /// nothing is detected.
+    ///
+    /// # Arguments
+    /// * `codes` - ISO 639-1 codes of the languages of the corpus (`fr`, `en`...)
+    pub fn new(codes: &[String]) -> Self {
+        let mut languages: Vec<Language> = Vec::new();
+        for code in codes {
+            let code = code.trim().to_ascii_lowercase();
+            match SUPPORTED_LANGUAGES.iter().find(|(supported, _)| *supported == code) {
+                Some((code, lang)) if !languages.iter().any(|language| language.code == *code) => {
+                    languages.push(Language {
+                        code,
+                        lang: *lang,
+                        stop_words: stop_words::get(code).iter().copied().collect(),
+                    });
+                }
+                Some(_) => {}
+                None => warn!("Unsupported language '{}' in [indexing] languages, ignored", code),
+            }
+        }
+
+        LanguageDetector {
+            detector: Detector::with_allowlist(languages.iter().map(|language| language.lang).collect()),
+            languages,
+        }
+    }
+
+    /// Detects the language of a text
+    ///
+    /// The trigram detection of whatlang is used when it is reliable, the
+    /// stop words of the languages otherwise.
+    ///
+    /// # Arguments
+    /// * `text` - The text
+    ///
+    /// # Returns
+    /// * `Option<&'static str>` - ISO 639-1 code of the language, None if it could not be detected
+    pub fn detect(&self, text: &str) -> Option<&'static str> {
+        match self.languages.as_slice() {
+            [] => return None,
+            [language] => return Some(language.code),
+            _ => {}
+        }
+
+        if let Some(info) = self.detector.detect(text)
+            && info.is_reliable()
+            && let Some(language) = self.languages.iter().find(|language| language.lang == info.lang())
+        {
+            return Some(language.code);
+        }
+        self.detect_from_stop_words(text)
+    }
+
+    /// Attributes a text to the language whose stop words it uses the most
+    ///
+    /// # Returns
+    /// * `Option<&'static str>` - ISO 639-1 code of the language, None without stop words or on a tie
+    fn detect_from_stop_words(&self, text: &str) -> Option<&'static str> {
+        let words = words(text);
+
+        let mut counts: Vec<(&'static str, usize)> = self
+            .languages
+            .iter()
+            .map(|language| {
+                let count = words.iter().filter(|word| language.stop_words.contains(word.as_str())).count();
+                (language.code, count)
+            })
+            .collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+        match counts.as_slice() {
+            [(code, best), rest @ ..] if *best > 0 && rest.first().is_none_or(|(_, second)| second < best) => {
+                Some(*code)
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets the terms of a text without the stop words of its language
+    ///
+    /// These are the terms of the sparse (BM25) vectors of the chunks and of
+    /// the questions (see the `sparse` module). The stop words of every
+    /// language of the corpus are removed from a text whose language is
+    /// unknown.
+    ///
+    /// # Arguments
+    /// * `text` - The text
+    /// * `lang` - ISO 639-1 code of the language of the text, if it was detected
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The lowercase words of the text that are not stop words, in order
+    pub fn terms(&self, text: &str, lang: Option<&str>) -> Vec<String> {
+        let languages: Vec<&Language> = match lang {
+            Some(code) => self.languages.iter().filter(|language| language.code == code).collect(),
+            None => self.languages.iter().collect(),
+        };
+        words(text)
+            .into_iter()
+            .filter(|word| !languages.iter().any(|language| language.stop_words.contains(word.as_str())))
+            .collect()
+    }
+
+    /// Detects the language of the chunks of a document, and of the document
+    ///
+    /// The language of the document is the language of most of the text of
+    /// its chunks. Chunks whose language could not be detected get it.
+    ///
+    /// # Arguments
+    /// * `chunks` - Chunks of the document, whose `lang` is set
+    ///
+    /// # Returns
+    /// * `Option<&'static str>` - ISO 639-1 code of the document language, None if no chunk language was detected
+    pub fn detect_chunks(&self, chunks: &mut [DocumentChunk]) -> Option<&'static str> {
+        let mut lengths: HashMap<&'static str, usize> = HashMap::new();
+        for chunk in chunks.iter_mut() {
+            chunk.lang = self.detect(&chunk.text);
+            if let Some(code) = chunk.lang {
+                *lengths.entry(code).or_default() += chunk.text.len();
+            }
+        }
+
+        let document = lengths
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(code, _)| code)?;
+        for chunk in chunks.iter_mut().filter(|chunk| chunk.lang.is_none()) {
+            chunk.lang = Some(document);
+        }
+        Some(document)
+    }
+}
+
+/// Splits a text into lowercase words
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
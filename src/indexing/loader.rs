@@ -4,21 +4,123 @@
 //! including text files, PDFs, and DOCX documents. It handles both synchronous
 //! and asynchronous file reading operations.
 //!
-//! For PDF files, this module uses the pdf-extract crate to extract text content
-//! from PDF documents. The implementation includes robust error handling to
-//! prevent panics when processing large or problematic PDF files.
+//! For PDF files, the `pdf` submodule uses the pdf-extract crate to extract
+//! the text page by page, with the page number as location. The extraction is
+//! guarded against panics, oversized files and documents taking too long.
 //!
-//! For DOCX files, this module uses the docx-rust crate to extract text content
-//! from DOCX documents.
+//! DOCX documents are read from their XML by the `docx` submodule, which
+//! renders headings, lists and tables as Markdown and adds footnotes, text
+//! boxes, headers and footers.
+//!
+//! HTML and Markdown files go through dedicated loaders (see the `html` and
+//! `markdown` submodules) which keep the heading structure of the document as
+//! Markdown headings and extract document metadata (title, front matter...).
+//!
+//! Slide decks (PPTX), spreadsheets (XLSX, XLS, ODS), LibreOffice documents
+//! (ODT), e-books (EPUB) and RTF documents have their own loaders as well.
+//! They split the document into sections (slides, sheets, chapters, pages)
+//! whose location is stored with the chunks, so that answers can cite them.
+//!
+//! Scanned documents are recognized with OCR when `[indexing.ocr]` is enabled
+//! (see the `ocr` submodule): PDF pages without text layer and PNG, JPEG and
+//! TIFF images go through a pluggable `OcrEngine`, Tesseract by default.
+//!
+//! Source code files (Rust, Python, TypeScript, JavaScript, Go) go through
+//! the `code` loader, which records their language so that the chunker splits
+//! them at symbol boundaries.
+//!
+//! The loader of a file is selected from its content rather than from its
+//! extension alone (see the `sniff` submodule): a PDF named `.txt` goes
+//! through the PDF loader, and images, archives or executables are reported
+//! as unsupported instead of being read as text. The loader of an extension
+//! can be forced, or the extension skipped, with `[indexing.loaders]`.
+//!
+//! Entries of data sources without a local path (archives, object stores, git
+//! repositories) are loaded with `load_bytes`, from a temporary file.
+
+mod code;
+mod docx;
+mod epub;
+mod html;
+mod markdown;
+mod ocr;
+mod odt;
+mod pdf;
+mod pptx;
+mod rtf;
+mod sniff;
+mod spreadsheet;
+pub(crate) mod xml;
 
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 use crate::Config;
 use crate::AppError;
-use pdf_extract::extract_text;
-use docx_rust::DocxFile;
+use crate::{IndexingConfig, LoaderKind};
+use sniff::Detected;
+use serde_json::{Map, Value};
 use tracing::warn;
 
+pub use code::{CodeLanguage, CodeLoader};
+pub use docx::DocxLoader;
+pub use epub::EpubLoader;
+pub use html::HtmlLoader;
+pub use markdown::MarkdownLoader;
+pub use ocr::{ImageLoader, OcrEngine, TesseractOcr, ocr_engine};
+pub use odt::OdtLoader;
+pub use pdf::PdfLoader;
+pub use pptx::PptxLoader;
+pub use rtf::RtfLoader;
+pub use spreadsheet::SpreadsheetLoader;
+
+/// A part of a document with its location (slide, sheet, chapter, page...)
+#[derive(Debug, Default)]
+pub struct DocumentSection {
+    /// Text content of the section
+    pub text: String,
+    /// Location of the section in the document, stored with its chunks for citations
+    pub location: Map<String, Value>,
+    /// True if the text was recognized with OCR, flagged on its chunks
+    pub ocr: bool,
+}
+
+/// Content and metadata of a loaded document
+#[derive(Debug, Default)]
+pub struct LoadedDocument {
+    /// Sections of the document, a single one without location for flat documents
+    pub sections: Vec<DocumentSection>,
+    /// True if the text uses Markdown headings, to be chunked by section
+    pub markdown: bool,
+    /// Language of a source code document, to be chunked at symbol boundaries
+    pub code: Option<CodeLanguage>,
+    /// Document metadata (title, author, front matter fields...)
+    pub metadata: Map<String, Value>,
+}
+
+impl LoadedDocument {
+    /// Creates a document made of a single section without location
+    pub fn from_text(text: String) -> Self {
+        LoadedDocument {
+            sections: vec![DocumentSection {
+                text,
+                location: Map::new(),
+                ocr: false,
+            }],
+            ..LoadedDocument::default()
+        }
+    }
+
+    /// Gets the text content of the whole document
+    pub fn text(&self) -> String {
+        self.sections
+            .iter()
+            .map(|section| section.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
 /// Trait for loading document content from different file types
 pub trait DocumentLoader {
     /// Loads the document content
@@ -26,6 +128,16 @@ pub trait DocumentLoader {
     /// # Returns
     /// * `Result<String, AppError>` - Document content if successful, error otherwise
     fn load(&self, path: &Path) -> Result<String, AppError>;
+
+    /// Loads the document content with its structure and metadata
+    ///
+    /// Loaders without structure or metadata return the plain text content.
+    ///
+    /// # Returns
+    /// * `Result<LoadedDocument, AppError>` - Loaded document if successful, error otherwise
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        Ok(LoadedDocument::from_text(self.load(path)?))
+    }
 }
 
 /// Loader for plain text files
@@ -37,72 +149,132 @@ impl DocumentLoader for TextLoader {
     }
 }
 
-/// Loader for PDF files
-pub struct PdfLoader;
+/// Returns the loader of a given kind
+fn get_loader(kind: LoaderKind, config: &IndexingConfig) -> Option<Box<dyn DocumentLoader>> {
+    Some(match kind {
+        LoaderKind::Text => Box::new(TextLoader),
+        LoaderKind::Pdf => Box::new(PdfLoader::new(config)),
+        LoaderKind::Docx => Box::new(DocxLoader),
+        LoaderKind::Html => Box::new(HtmlLoader),
+        LoaderKind::Markdown => Box::new(MarkdownLoader),
+        LoaderKind::Pptx => Box::new(PptxLoader),
+        LoaderKind::Spreadsheet => Box::new(SpreadsheetLoader),
+        LoaderKind::Odt => Box::new(OdtLoader),
+        LoaderKind::Epub => Box::new(EpubLoader),
+        LoaderKind::Rtf => Box::new(RtfLoader),
+        LoaderKind::Code => Box::new(CodeLoader),
+        LoaderKind::Image => Box::new(ImageLoader::new(ocr_engine(&config.ocr)?)),
+        LoaderKind::Skip => return None,
+    })
+}
 
-impl DocumentLoader for PdfLoader {
-    fn load(&self, path: &Path) -> Result<String, AppError> {
-        // Use catch_unwind to prevent panics from pdf-extract
-        let result = std::panic::catch_unwind(|| {
-            extract_text(path)
-        });
-
-        match result {
-            Ok(Ok(content)) => Ok(content),
-            Ok(Err(e)) => {
-                warn!("Failed to extract PDF content: {}", e);
-                Err(AppError::Pdf(format!("PDF extraction failed: {}", e)))
-            }
-            Err(_) => {
-                warn!("PDF extraction panicked");
-                Err(AppError::Pdf("PDF extraction panicked".to_string()))
-            }
+/// Selects the loader of a file
+///
+/// A loader configured for the extension of the file is used as-is. Otherwise
+/// the type of the file is detected from its content: document formats get
+/// their loader whatever their extension, text files get the loader of their
+/// extension (Markdown, HTML, source code) or the text loader, images are
+/// recognized with OCR when it is enabled, and the other binary files are
+/// unsupported.
+///
+/// # Arguments
+/// * `file_path` - Full path of the file
+/// * `extension` - Lowercase extension of the file
+/// * `config` - Indexing configuration (loaders by extension, OCR)
+///
+/// # Returns
+/// * `Result<LoaderKind, AppError>` - The loader kind, `UnsupportedFormat` error if the file cannot be indexed
+fn select_loader(
+    file_path: &Path,
+    extension: &str,
+    config: &IndexingConfig,
+) -> Result<LoaderKind, AppError> {
+    if let Some(kind) = config.loaders.get(extension) {
+        return Ok(*kind);
+    }
+
+    match sniff::detect(file_path, extension)? {
+        Detected::Format(kind) => Ok(kind),
+        Detected::Html => Ok(LoaderKind::Html),
+        Detected::Text => Ok(match extension {
+            "html" | "htm" | "xhtml" => LoaderKind::Html,
+            "md" | "markdown" => LoaderKind::Markdown,
+            extension if CodeLanguage::from_extension(extension).is_some() => LoaderKind::Code,
+            _ => LoaderKind::Text,
+        }),
+        Detected::Image(_) if config.ocr.enabled => Ok(LoaderKind::Image),
+        Detected::Image(description) | Detected::Unsupported(description) => {
+            Err(AppError::UnsupportedFormat(description))
         }
     }
 }
 
-/// Loader for DOCX files
-pub struct DocxLoader;
+/// Loads a file with the loader selected for its extension and content
+///
+/// Loader errors are returned to the caller, so that a file that could not be
+/// read is reported as failed instead of being indexed as empty. Files that
+/// cannot be indexed, or that are skipped by the configuration, return an
+/// `UnsupportedFormat` error.
+///
+/// # Arguments
+/// * `file_path` - Full path of the file to load
+/// * `filename` - Name of the file, used for logging
+/// * `config` - Indexing configuration (loaders by extension, PDF limits)
+///
+/// # Returns
+/// * `Result<LoadedDocument, AppError>` - Loaded document if successful, error otherwise
+pub fn load_path(file_path: &Path, filename: &str, config: &IndexingConfig) -> Result<LoadedDocument, AppError> {
+    let extension = file_path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
 
-impl DocumentLoader for DocxLoader {
-    fn load(&self, path: &Path) -> Result<String, AppError> {
-        let docx = DocxFile::from_file(path)
-            .map_err(|e| AppError::Docx(format!("Failed to open DOCX file: {}", e)))?;
-
-        let docx = docx.parse()
-            .map_err(|e| AppError::Docx(format!("Failed to parse DOCX file: {}", e)))?;
-
-        let mut content = String::new();
-        for paragraph in docx.document.body.content {
-            if let docx_rust::document::BodyContent::Paragraph(p) = paragraph {
-                for run in p.content {
-                    if let docx_rust::document::ParagraphContent::Run(r) = run {
-                        for text in r.content {
-                            if let docx_rust::document::RunContent::Text(t) = text {
-                                content.push_str(&t.text);
-                            }
-                        }
-                    }
-                }
-                content.push('\n');
-            }
-        }
+    let kind = select_loader(file_path, &extension, config)?;
+    let Some(loader) = get_loader(kind, config) else {
+        return Err(AppError::UnsupportedFormat(match kind {
+            LoaderKind::Image => "image, OCR is disabled".to_string(),
+            _ => format!("files with extension '{}' are skipped by the configuration", extension),
+        }));
+    };
 
-        Ok(content)
-    }
+    // Load the file content
+    loader.load_document(file_path).inspect_err(|e| {
+        warn!("Failed to load file '{}': {}", filename, e);
+    })
 }
 
-/// Returns the appropriate loader for a given file extension
-fn get_loader(extension: &str) -> Box<dyn DocumentLoader> {
-    match extension {
-        "pdf" => Box::new(PdfLoader),
-        "docx" => Box::new(DocxLoader),
-        _ => Box::new(TextLoader),
-    }
+/// Loads a document from content fetched from a data source
+///
+/// The loaders read from a path, so the content is written to a temporary
+/// file with the extension of the entry, removed once the document is loaded.
+///
+/// # Arguments
+/// * `content` - Content of the entry
+/// * `filename` - Name of the entry, for its extension and for logging
+/// * `config` - Indexing configuration (loaders by extension, PDF limits)
+///
+/// # Returns
+/// * `Result<LoadedDocument, AppError>` - Loaded document if successful, error otherwise
+pub fn load_bytes(content: &[u8], filename: &str, config: &IndexingConfig) -> Result<LoadedDocument, AppError> {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| format!(".{}", ext))
+        .unwrap_or_default();
+    let mut file = tempfile::Builder::new()
+        .prefix("rag-rust-")
+        .suffix(&extension)
+        .tempfile()?;
+    file.write_all(content)?;
+    file.flush()?;
+    load_path(file.path(), filename, config)
 }
 
 /// Asynchronously loads file content from disk
 ///
+/// Loaders are CPU-bound and blocking (PDF extraction in particular), so the
+/// loading runs on tokio's blocking thread pool instead of the async runtime.
+///
 /// # Arguments
 /// * `config` - Configuration object containing data sources path
 /// * `filename` - Name of the file to load
@@ -111,23 +283,14 @@ fn get_loader(extension: &str) -> Box<dyn DocumentLoader> {
 /// * `Result<String, AppError>` - File content if successful, error otherwise
 pub async fn load_file(config: &Config, filename: &str) -> Result<String, AppError> {
     let file_path = Path::new(&config.data_sources.path).join(filename);
+    let filename = filename.to_string();
+    let indexing_config = config.indexing.clone();
 
-    // Get the appropriate loader based on file extension
-    let extension = file_path.extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("");
-    
-    let loader = get_loader(extension);
-    
-    // Load the file content
-    match loader.load(&file_path) {
-        Ok(content) => Ok(content),
-        Err(e) => {
-            warn!("Failed to load file '{}': {}", filename, e);
-            // Return empty string for failed loads to allow processing to continue
-            Ok(String::new())
-        }
-    }
+    tokio::task::spawn_blocking(move || {
+        load_path(&file_path, &filename, &indexing_config).map(|document| document.text())
+    })
+        .await
+        .map_err(|e| AppError::Unknown(format!("Loader task failed: {}", e)))?
 }
 
 /// Synchronously loads file content from disk
@@ -139,22 +302,18 @@ pub async fn load_file(config: &Config, filename: &str) -> Result<String, AppErr
 /// # Returns
 /// * `Result<String, AppError>` - File content if successful, error otherwise
 pub fn load_file_sync(config: &Config, filename: &str) -> Result<String, AppError> {
-    let file_path = Path::new(&config.data_sources.path).join(filename);
+    load_document_sync(config, filename).map(|document| document.text())
+}
 
-    // Get the appropriate loader based on file extension
-    let extension = file_path.extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("");
-    
-    let loader = get_loader(extension);
-    
-    // Load the file content
-    match loader.load(&file_path) {
-        Ok(content) => Ok(content),
-        Err(e) => {
-            warn!("Failed to load file '{}': {}", filename, e);
-            // Return empty string for failed loads to allow processing to continue
-            Ok(String::new())
-        }
-    }
+/// Synchronously loads a document with its structure and metadata from disk
+///
+/// # Arguments
+/// * `config` - Configuration object containing data sources path
+/// * `filename` - Name of the file to load
+///
+/// # Returns
+/// * `Result<LoadedDocument, AppError>` - Loaded document if successful, error otherwise
+pub fn load_document_sync(config: &Config, filename: &str) -> Result<LoadedDocument, AppError> {
+    let file_path = Path::new(&config.data_sources.path).join(filename);
+    load_path(&file_path, filename, &config.indexing)
 }
@@ -0,0 +1,100 @@
+//! Source code loader.
+//!
+//! Source files of the languages with a tree-sitter grammar are loaded as
+//! they are, with their language in the document metadata. The chunker then
+//! parses them to split them at function, impl and class boundaries instead
+//! of arbitrary lines (see `chunker::code`).
+
+use serde_json::Map;
+use std::fs;
+use std::path::Path;
+
+use crate::AppError;
+use crate::indexing::loader::{DocumentLoader, LoadedDocument};
+
+/// Programming language of a source file, with a tree-sitter grammar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeLanguage {
+    Rust,
+    Python,
+    TypeScript,
+    /// TypeScript with JSX, which has its own grammar
+    Tsx,
+    JavaScript,
+    Go,
+}
+
+impl CodeLanguage {
+    /// Gets the language of a source file from its extension
+    ///
+    /// # Arguments
+    /// * `extension` - Lowercase extension of the file
+    ///
+    /// # Returns
+    /// * `Option<CodeLanguage>` - The language, None if no grammar handles the extension
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Some(match extension {
+            "rs" => CodeLanguage::Rust,
+            "py" | "pyi" => CodeLanguage::Python,
+            "ts" | "mts" | "cts" => CodeLanguage::TypeScript,
+            "tsx" => CodeLanguage::Tsx,
+            "js" | "mjs" | "cjs" | "jsx" => CodeLanguage::JavaScript,
+            "go" => CodeLanguage::Go,
+            _ => return None,
+        })
+    }
+
+    /// Name of the language, stored in the `language` metadata field
+    pub fn name(&self) -> &'static str {
+        match self {
+            CodeLanguage::Rust => "rust",
+            CodeLanguage::Python => "python",
+            CodeLanguage::TypeScript | CodeLanguage::Tsx => "typescript",
+            CodeLanguage::JavaScript => "javascript",
+            CodeLanguage::Go => "go",
+        }
+    }
+
+    /// Gets the tree-sitter grammar of the language
+    pub fn grammar(&self) -> tree_sitter::Language {
+        match self {
+            CodeLanguage::Rust => tree_sitter_rust::LANGUAGE.into(),
+            CodeLanguage::Python => tree_sitter_python::LANGUAGE.into(),
+            CodeLanguage::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            CodeLanguage::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            CodeLanguage::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            CodeLanguage::Go => tree_sitter_go::LANGUAGE.into(),
+        }
+    }
+}
+
+/// Loader for source code files
+pub struct CodeLoader;
+
+impl DocumentLoader for CodeLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        fs::read_to_string(path).map_err(AppError::Io)
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let Some(language) = CodeLanguage::from_extension(&extension) else {
+            return Err(AppError::UnsupportedFormat(format!(
+                "no code parser for files with extension '{}'",
+                extension
+            )));
+        };
+
+        let mut metadata = Map::new();
+        metadata.insert("language".to_string(), language.name().into());
+        Ok(LoadedDocument {
+            code: Some(language),
+            metadata,
+            ..LoadedDocument::from_text(self.load(path)?)
+        })
+    }
+}
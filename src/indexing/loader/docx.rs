@@ -0,0 +1,867 @@
+//! DOCX loader for Word documents.
+//!
+//! The body of the document is rendered as Markdown: paragraphs styled as
+//! headings (or with an outline level) become `#` headings, numbered and
+//! bulleted paragraphs keep their list marker, and tables become Markdown
+//! tables, so the document goes through structure-aware chunking. The text
+//! of hyperlinks, fields and text boxes is kept; page numbers and tables of
+//! contents are not. Footnotes, endnotes and the distinct lines of the page
+//! headers and footers are added as their own sections.
+
+use serde_json::Map;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::AppError;
+use crate::indexing::loader::xml::{self, Archive, XmlEvent};
+use crate::indexing::loader::{DocumentLoader, DocumentSection, LoadedDocument};
+
+/// Elements whose content is not part of the current text (deleted or moved
+/// text, previous formatting, duplicated fallback of text boxes)
+const SKIPPED_ELEMENTS: [&str; 5] = ["del", "moveFrom", "pPrChange", "rPrChange", "Fallback"];
+
+/// Fields whose result is not indexed (page numbers, tables of contents...)
+const SKIPPED_FIELDS: [&str; 6] = ["PAGE", "NUMPAGES", "SECTIONPAGES", "TOC", "DATE", "TIME"];
+
+/// Relationship types of the page headers and footers
+const HEADER_FOOTER_RELATIONSHIPS: [&str; 2] = ["/header", "/footer"];
+
+/// Loader for Word documents
+pub struct DocxLoader;
+
+impl DocumentLoader for DocxLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let mut archive = xml::open_archive(path).map_err(docx_error)?;
+        let styles = Styles::read(&mut archive);
+        let numbering = Numbering::read(&mut archive);
+        let document_path = "word/document.xml";
+        let events = xml::read_entry(&mut archive, document_path)
+            .and_then(|content| xml::parse_xml(&content))
+            .map_err(docx_error)?;
+
+        let mut sections = vec![DocumentSection {
+            text: render(events, &styles, &numbering),
+            location: Map::new(),
+            ocr: false,
+        }];
+
+        for (part, element, title) in [
+            ("word/footnotes.xml", "footnote", "Footnotes"),
+            ("word/endnotes.xml", "endnote", "Endnotes"),
+        ] {
+            let notes = read_notes(&mut archive, part, element, &styles, &numbering);
+            if !notes.is_empty() {
+                sections.push(part_section(title, notes));
+            }
+        }
+
+        let mut header_footer_paths: Vec<String> = xml::relationships(&mut archive, document_path)
+            .into_values()
+            .filter(|(kind, _)| HEADER_FOOTER_RELATIONSHIPS.iter().any(|suffix| kind.ends_with(suffix)))
+            .map(|(_, path)| path)
+            .collect();
+        header_footer_paths.sort();
+        let mut lines: Vec<String> = Vec::new();
+        for part_path in header_footer_paths {
+            let Ok(events) = xml::read_entry(&mut archive, &part_path).and_then(|content| xml::parse_xml(&content))
+            else {
+                continue;
+            };
+            for line in render(events, &styles, &numbering).lines() {
+                let line = line.trim();
+                if !line.is_empty() && !lines.iter().any(|existing| existing == line) {
+                    lines.push(line.to_string());
+                }
+            }
+        }
+        if !lines.is_empty() {
+            sections.push(part_section("Headers and footers", lines));
+        }
+
+        sections.retain(|section| !section.text.trim().is_empty());
+        let metadata = xml::read_entry(&mut archive, "docProps/core.xml")
+            .and_then(|content| xml::parse_xml(&content))
+            .map(|events| xml::text_metadata(&events, &[("title", "title"), ("creator", "author")]))
+            .unwrap_or_default();
+        Ok(LoadedDocument {
+            sections,
+            markdown: true,
+            code: None,
+            metadata,
+        })
+    }
+}
+
+/// Reports the errors of the main part of the document as DOCX errors
+fn docx_error(error: AppError) -> AppError {
+    match error {
+        AppError::Document(message) => AppError::Docx(message),
+        error => error,
+    }
+}
+
+/// Builds the section of a secondary part of the document (notes, headers and footers)
+///
+/// The section starts with a heading, so that its chunks are not attached to
+/// the last heading of the body.
+fn part_section(title: &str, lines: Vec<String>) -> DocumentSection {
+    let part = title.to_lowercase();
+    let mut location = Map::new();
+    location.insert("part".to_string(), part.into());
+    DocumentSection {
+        text: format!("# {}\n\n{}", title, lines.join("\n")),
+        location,
+        ocr: false,
+    }
+}
+
+/// Reads the footnotes or endnotes of the document, as `[^id]: text` lines
+fn read_notes(
+    archive: &mut Archive,
+    part_path: &str,
+    element: &str,
+    styles: &Styles,
+    numbering: &Numbering,
+) -> Vec<String> {
+    let Ok(events) = xml::read_entry(archive, part_path).and_then(|content| xml::parse_xml(&content)) else {
+        return Vec::new();
+    };
+
+    let mut notes = Vec::new();
+    let mut current: Option<(String, Vec<XmlEvent>)> = None;
+    let mut depth = 0;
+    for event in events {
+        match &event {
+            XmlEvent::Start { name, attributes } if depth == 0 && name == element => {
+                // Separators are notes of their own, with a type and no content
+                if attribute(attributes, "type").is_none_or(|kind| kind == "normal")
+                    && let Some(id) = attribute(attributes, "id")
+                {
+                    current = Some((id.to_string(), Vec::new()));
+                }
+                depth = 1;
+                continue;
+            }
+            XmlEvent::Start { .. } if depth > 0 => depth += 1,
+            XmlEvent::End { .. } if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some((id, note_events)) = current.take() {
+                        let text = render(note_events, styles, numbering);
+                        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                        if !text.is_empty() {
+                            notes.push(format!("{}: {}", note_label(element, &id), text));
+                        }
+                    }
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        if let Some((_, note_events)) = current.as_mut() {
+            note_events.push(event);
+        }
+    }
+    notes
+}
+
+/// Gets the Markdown label of a footnote or endnote
+fn note_label(element: &str, id: &str) -> String {
+    match element {
+        "endnote" => format!("[^e{}]", id),
+        _ => format!("[^{}]", id),
+    }
+}
+
+/// Gets an attribute by its local name, whatever its namespace prefix
+fn attribute<'a>(attributes: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key.rsplit(':').next() == Some(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Paragraph properties of a style
+#[derive(Clone, Default)]
+struct StyleProperties {
+    based_on: Option<String>,
+    /// Heading level (1 for `heading 1` and titles)
+    heading_level: Option<usize>,
+    /// Numbering of the paragraphs of the style: (numbering ID, level)
+    numbering: Option<(String, usize)>,
+}
+
+/// Paragraph styles of the document, by style ID
+#[derive(Default)]
+struct Styles {
+    styles: HashMap<String, StyleProperties>,
+}
+
+impl Styles {
+    /// Reads `word/styles.xml`, returning no style if the part is missing
+    fn read(archive: &mut Archive) -> Self {
+        let Ok(events) = xml::read_entry(archive, "word/styles.xml").and_then(|content| xml::parse_xml(&content))
+        else {
+            return Styles::default();
+        };
+
+        let mut styles = HashMap::new();
+        let mut current: Option<(String, StyleProperties)> = None;
+        for event in events {
+            match event {
+                XmlEvent::Start { name, attributes } => match name.as_str() {
+                    "style" => {
+                        current = attribute(&attributes, "styleId").map(|id| (id.to_string(), StyleProperties::default()));
+                    }
+                    _ => {
+                        if let Some((_, properties)) = current.as_mut() {
+                            let value = attribute(&attributes, "val");
+                            match name.as_str() {
+                                "basedOn" => properties.based_on = value.map(str::to_string),
+                                "name" => {
+                                    if let Some(level) = value.and_then(heading_level_of_name) {
+                                        properties.heading_level = Some(level);
+                                    }
+                                }
+                                "outlineLvl" => {
+                                    if let Some(level) = value.and_then(outline_level) {
+                                        properties.heading_level = Some(level);
+                                    }
+                                }
+                                "numId" => {
+                                    let level = properties.numbering.as_ref().map_or(0, |(_, level)| *level);
+                                    properties.numbering = value.map(|id| (id.to_string(), level));
+                                }
+                                "ilvl" => {
+                                    let level = value.and_then(|level| level.parse().ok()).unwrap_or(0);
+                                    let id = properties.numbering.take().map(|(id, _)| id).unwrap_or_default();
+                                    properties.numbering = Some((id, level));
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                },
+                XmlEvent::End { name } if name == "style" => {
+                    if let Some((id, properties)) = current.take() {
+                        styles.insert(id, properties);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Styles { styles }
+    }
+
+    /// Gets the properties of a style, inherited from the styles it is based on
+    fn properties(&self, style_id: &str) -> StyleProperties {
+        let mut properties = StyleProperties::default();
+        let mut current = Some(style_id.to_string());
+        // Bounded walk, in case of a cycle in the styles
+        for _ in 0..10 {
+            let Some(style) = current.and_then(|id| self.styles.get(&id)) else {
+                break;
+            };
+            properties.heading_level = properties.heading_level.or(style.heading_level);
+            if properties.numbering.is_none() {
+                properties.numbering = style.numbering.clone().filter(|(id, _)| !id.is_empty());
+            }
+            current = style.based_on.clone();
+        }
+        properties
+    }
+}
+
+/// Gets the heading level of a built-in style name (`heading 2`, `Title`)
+fn heading_level_of_name(name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    if name == "title" {
+        return Some(1);
+    }
+    name.strip_prefix("heading ")
+        .and_then(|level| level.trim().parse::<usize>().ok())
+        .filter(|level| (1..=9).contains(level))
+        .map(|level| level.min(6))
+}
+
+/// Converts a zero-based outline level to a heading level (9 is body text)
+fn outline_level(value: &str) -> Option<usize> {
+    value.parse::<usize>().ok().filter(|level| *level < 9).map(|level| (level + 1).min(6))
+}
+
+/// A level of a numbering definition
+#[derive(Clone)]
+struct NumberingLevel {
+    start: usize,
+    format: String,
+    text: String,
+}
+
+/// Numbering definitions of the document (`word/numbering.xml`)
+#[derive(Default)]
+struct Numbering {
+    /// Levels of each abstract numbering, by abstract numbering ID
+    abstract_levels: HashMap<String, HashMap<usize, NumberingLevel>>,
+    /// Abstract numbering of each numbering ID
+    numbering: HashMap<String, String>,
+}
+
+impl Numbering {
+    /// Reads `word/numbering.xml`, returning no numbering if the part is missing
+    fn read(archive: &mut Archive) -> Self {
+        let Ok(events) = xml::read_entry(archive, "word/numbering.xml").and_then(|content| xml::parse_xml(&content))
+        else {
+            return Numbering::default();
+        };
+
+        let mut numbering = Numbering::default();
+        let mut abstract_id: Option<String> = None;
+        let mut num_id: Option<String> = None;
+        let mut level: Option<(usize, NumberingLevel)> = None;
+        for event in events {
+            match event {
+                XmlEvent::Start { name, attributes } => {
+                    let value = attribute(&attributes, "val").map(str::to_string);
+                    match name.as_str() {
+                        "abstractNum" => abstract_id = attribute(&attributes, "abstractNumId").map(str::to_string),
+                        "num" => num_id = attribute(&attributes, "numId").map(str::to_string),
+                        "abstractNumId" => {
+                            if let (Some(num_id), Some(value)) = (&num_id, value) {
+                                numbering.numbering.insert(num_id.clone(), value);
+                            }
+                        }
+                        "lvl" if abstract_id.is_some() => {
+                            let index = attribute(&attributes, "ilvl").and_then(|i| i.parse().ok()).unwrap_or(0);
+                            level = Some((
+                                index,
+                                NumberingLevel {
+                                    start: 1,
+                                    format: "decimal".to_string(),
+                                    text: format!("%{}.", index + 1),
+                                },
+                            ));
+                        }
+                        "start" => {
+                            if let (Some((_, level)), Some(value)) = (level.as_mut(), value) {
+                                level.start = value.parse().unwrap_or(1);
+                            }
+                        }
+                        "numFmt" => {
+                            if let (Some((_, level)), Some(value)) = (level.as_mut(), value) {
+                                level.format = value;
+                            }
+                        }
+                        "lvlText" => {
+                            if let (Some((_, level)), Some(value)) = (level.as_mut(), value) {
+                                level.text = value;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                XmlEvent::End { name } => match name.as_str() {
+                    "lvl" => {
+                        if let (Some(abstract_id), Some((index, level))) = (&abstract_id, level.take()) {
+                            numbering
+                                .abstract_levels
+                                .entry(abstract_id.clone())
+                                .or_default()
+                                .insert(index, level);
+                        }
+                    }
+                    "abstractNum" => abstract_id = None,
+                    "num" => num_id = None,
+                    _ => {}
+                },
+                XmlEvent::Text(_) => {}
+            }
+        }
+        numbering
+    }
+
+    /// Gets the levels of a numbering
+    fn levels(&self, num_id: &str) -> Option<&HashMap<usize, NumberingLevel>> {
+        self.numbering.get(num_id).and_then(|abstract_id| self.abstract_levels.get(abstract_id))
+    }
+}
+
+/// Formats a list counter in a numbering format
+fn format_counter(value: usize, format: &str) -> String {
+    match format {
+        "lowerLetter" => letters(value).to_lowercase(),
+        "upperLetter" => letters(value),
+        "lowerRoman" => roman(value).to_lowercase(),
+        "upperRoman" => roman(value),
+        _ => value.to_string(),
+    }
+}
+
+/// Formats a counter as letters (A, B, ..., Z, AA, BB...)
+fn letters(value: usize) -> String {
+    if value == 0 {
+        return String::new();
+    }
+    let letter = (b'A' + ((value - 1) % 26) as u8) as char;
+    letter.to_string().repeat((value - 1) / 26 + 1)
+}
+
+/// Formats a counter as a Roman numeral
+fn roman(mut value: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut result = String::new();
+    for (number, numeral) in NUMERALS {
+        while value >= number {
+            result.push_str(numeral);
+            value -= number;
+        }
+    }
+    result
+}
+
+/// Renders the events of a part of the document as Markdown
+fn render(events: Vec<XmlEvent>, styles: &Styles, numbering: &Numbering) -> String {
+    let mut renderer = DocxRenderer {
+        styles,
+        numbering,
+        counters: HashMap::new(),
+        output: String::new(),
+        paragraph: Paragraph::default(),
+        in_paragraph_properties: false,
+        in_run: false,
+        in_text: false,
+        skip_depth: 0,
+        fields: Vec::new(),
+        cell_depth: 0,
+        row: Vec::new(),
+        rows_in_table: 0,
+        in_list: false,
+        text_boxes: Vec::new(),
+        saved: Vec::new(),
+    };
+    for event in events {
+        renderer.handle(event);
+    }
+    renderer.flush_text_boxes();
+    renderer.output.trim().to_string()
+}
+
+/// Properties and text of the current paragraph
+#[derive(Default)]
+struct Paragraph {
+    text: String,
+    style: Option<String>,
+    outline_level: Option<usize>,
+    num_id: Option<String>,
+    num_level: Option<usize>,
+}
+
+/// A complex or simple field: its instruction and whether its result is being read
+struct Field {
+    instruction: String,
+    in_result: bool,
+}
+
+impl Field {
+    fn is_skipped(&self) -> bool {
+        self.in_result
+            && self
+                .instruction
+                .split_whitespace()
+                .next()
+                .is_some_and(|name| SKIPPED_FIELDS.contains(&name.to_uppercase().as_str()))
+    }
+}
+
+/// Renders the events of a part of a Word document as Markdown
+struct DocxRenderer<'a> {
+    styles: &'a Styles,
+    numbering: &'a Numbering,
+    /// Counters of the numbered paragraphs, per numbering ID and level
+    counters: HashMap<String, Vec<usize>>,
+    output: String,
+    paragraph: Paragraph,
+    in_paragraph_properties: bool,
+    in_run: bool,
+    in_text: bool,
+    skip_depth: usize,
+    fields: Vec<Field>,
+    cell_depth: usize,
+    row: Vec<String>,
+    rows_in_table: usize,
+    /// True if the last line of the output is a list item
+    in_list: bool,
+    /// Text of the text boxes anchored in the current paragraph
+    text_boxes: Vec<String>,
+    /// State of the paragraphs holding the text boxes being rendered
+    saved: Vec<(String, Paragraph, usize, bool)>,
+}
+
+impl DocxRenderer<'_> {
+    fn handle(&mut self, event: XmlEvent) {
+        if self.skip_depth > 0 {
+            match event {
+                XmlEvent::Start { .. } => self.skip_depth += 1,
+                XmlEvent::End { .. } => self.skip_depth -= 1,
+                XmlEvent::Text(_) => {}
+            }
+            return;
+        }
+
+        match event {
+            XmlEvent::Start { name, attributes } => self.start(&name, &attributes),
+            XmlEvent::End { name } => self.end(&name),
+            XmlEvent::Text(text) => {
+                if self.in_text && !self.fields.iter().any(Field::is_skipped) {
+                    self.paragraph.text.push_str(&text);
+                } else if self.fields.last().is_some_and(|field| !field.in_result) {
+                    // Text of an `instrText` element: the field instruction
+                    if let Some(field) = self.fields.last_mut() {
+                        field.instruction.push_str(&text);
+                    }
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, name: &str, attributes: &HashMap<String, String>) {
+        let value = attribute(attributes, "val");
+        match name {
+            name if SKIPPED_ELEMENTS.contains(&name) => self.skip_depth = 1,
+            "p" if self.cell_depth == 0 => self.paragraph = Paragraph::default(),
+            "pPr" => self.in_paragraph_properties = true,
+            "pStyle" if self.in_paragraph_properties => self.paragraph.style = value.map(str::to_string),
+            "outlineLvl" if self.in_paragraph_properties => {
+                self.paragraph.outline_level = value.and_then(outline_level);
+            }
+            "numId" if self.in_paragraph_properties => self.paragraph.num_id = value.map(str::to_string),
+            "ilvl" if self.in_paragraph_properties => {
+                self.paragraph.num_level = value.and_then(|level| level.parse().ok());
+            }
+            "r" => self.in_run = true,
+            "t" if self.in_run => self.in_text = true,
+            "tab" if self.in_run => self.paragraph.text.push('\t'),
+            "br" | "cr" if self.in_run => self.paragraph.text.push('\n'),
+            "noBreakHyphen" if self.in_run => self.paragraph.text.push('-'),
+            "footnoteReference" => {
+                if let Some(id) = attribute(attributes, "id") {
+                    self.paragraph.text.push_str(&note_label("footnote", id));
+                }
+            }
+            "endnoteReference" => {
+                if let Some(id) = attribute(attributes, "id") {
+                    self.paragraph.text.push_str(&note_label("endnote", id));
+                }
+            }
+            "fldChar" => match attribute(attributes, "fldCharType") {
+                Some("begin") => self.fields.push(Field {
+                    instruction: String::new(),
+                    in_result: false,
+                }),
+                Some("separate") => {
+                    if let Some(field) = self.fields.last_mut() {
+                        field.in_result = true;
+                    }
+                }
+                Some("end") => {
+                    self.fields.pop();
+                }
+                _ => {}
+            },
+            "instrText" => {}
+            "fldSimple" => self.fields.push(Field {
+                instruction: attribute(attributes, "instr").unwrap_or_default().to_string(),
+                in_result: true,
+            }),
+            "txbxContent" => {
+                // Text boxes hold paragraphs of their own, rendered apart from the paragraph they are anchored in
+                let output = std::mem::take(&mut self.output);
+                let paragraph = std::mem::take(&mut self.paragraph);
+                self.saved.push((output, paragraph, self.cell_depth, self.in_run));
+                self.cell_depth = 0;
+                self.in_run = false;
+            }
+            "tbl" if self.cell_depth == 0 => {
+                self.end_list();
+                self.rows_in_table = 0;
+            }
+            "tc" => {
+                if self.cell_depth == 0 {
+                    self.paragraph = Paragraph::default();
+                }
+                self.cell_depth += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        match name {
+            "pPr" => self.in_paragraph_properties = false,
+            "r" => self.in_run = false,
+            "t" => self.in_text = false,
+            "fldSimple" => {
+                self.fields.pop();
+            }
+            "p" if self.cell_depth == 0 => self.end_paragraph(),
+            "p" => self.paragraph.text.push(' '),
+            "tc" => {
+                self.cell_depth = self.cell_depth.saturating_sub(1);
+                if self.cell_depth == 0 {
+                    let cell = collapse_whitespace(&self.paragraph.text).replace('|', "\\|");
+                    self.row.push(cell);
+                    self.paragraph = Paragraph::default();
+                }
+            }
+            "tr" if self.cell_depth == 0 => {
+                let row = std::mem::take(&mut self.row);
+                if row.iter().any(|cell| !cell.is_empty()) {
+                    self.output.push_str(&format!("| {} |\n", row.join(" | ")));
+                    if self.rows_in_table == 0 {
+                        self.output.push_str(&format!("|{}\n", " --- |".repeat(row.len())));
+                    }
+                    self.rows_in_table += 1;
+                }
+            }
+            "tbl" if self.cell_depth == 0 => {
+                self.output.push('\n');
+                self.flush_text_boxes();
+            }
+            "txbxContent" => {
+                if let Some((output, paragraph, cell_depth, in_run)) = self.saved.pop() {
+                    let text_box = std::mem::replace(&mut self.output, output);
+                    self.paragraph = paragraph;
+                    self.cell_depth = cell_depth;
+                    self.in_run = in_run;
+                    if !text_box.trim().is_empty() {
+                        self.text_boxes.push(text_box.trim().to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Renders the current paragraph as a heading, a list item or a block of text
+    fn end_paragraph(&mut self) {
+        let paragraph = std::mem::take(&mut self.paragraph);
+        let lines: Vec<String> = paragraph.text.lines().map(collapse_whitespace).collect();
+        let text = lines.join("\n").trim().to_string();
+
+        let style = paragraph
+            .style
+            .as_deref()
+            .map(|style| self.styles.properties(style))
+            .unwrap_or_default();
+        let heading_level = paragraph.outline_level.or(style.heading_level);
+        let numbering = match (paragraph.num_id, paragraph.num_level) {
+            (Some(id), level) => Some((id, level.unwrap_or(0))),
+            (None, Some(level)) => style.numbering.map(|(id, _)| (id, level)),
+            (None, None) => style.numbering,
+        }
+        .filter(|(id, _)| id != "0");
+
+        if !text.is_empty() {
+            let marker = numbering.map(|(id, level)| (self.list_marker(&id, level), level));
+            match (heading_level, marker) {
+                (Some(level), marker) => {
+                    let mut title = text.replace('\n', " ");
+                    if let Some((Some(number), _)) = marker {
+                        title = format!("{} {}", number, title);
+                    }
+                    self.push_block(&format!("{} {}", "#".repeat(level), title));
+                }
+                (None, Some((marker, level))) => {
+                    let marker = marker.unwrap_or_else(|| "-".to_string());
+                    self.push_list_item(&format!("{}{} {}", "  ".repeat(level), marker, text));
+                }
+                (None, None) => self.push_block(&text),
+            }
+        }
+        self.flush_text_boxes();
+    }
+
+    /// Advances the counter of a numbered paragraph and formats its marker
+    ///
+    /// # Returns
+    /// * `Option<String>` - The number of the paragraph (e.g. `2.1.`), None for bullets
+    fn list_marker(&mut self, num_id: &str, level: usize) -> Option<String> {
+        let levels = self.numbering.levels(num_id)?;
+        let definition = levels.get(&level)?;
+        if matches!(definition.format.as_str(), "bullet" | "none") {
+            return None;
+        }
+
+        let counters = self.counters.entry(num_id.to_string()).or_insert_with(|| vec![0; 9]);
+        let level = level.min(counters.len() - 1);
+        counters[level] = if counters[level] == 0 { definition.start } else { counters[level] + 1 };
+        for deeper in counters.iter_mut().skip(level + 1) {
+            *deeper = 0;
+        }
+
+        let mut marker = definition.text.clone();
+        for (index, counter) in counters.iter().enumerate().take(level + 1) {
+            let Some(parent) = levels.get(&index) else {
+                continue;
+            };
+            let value = if *counter == 0 { parent.start } else { *counter };
+            marker = marker.replace(&format!("%{}", index + 1), &format_counter(value, &parent.format));
+        }
+        let marker = marker.trim().to_string();
+        (!marker.is_empty()).then_some(marker)
+    }
+
+    /// Appends a block (paragraph, heading, text box) followed by a blank line
+    fn push_block(&mut self, text: &str) {
+        self.end_list();
+        self.output.push_str(text);
+        self.output.push_str("\n\n");
+    }
+
+    /// Appends a list item, list items being kept on consecutive lines
+    fn push_list_item(&mut self, text: &str) {
+        self.output.push_str(text);
+        self.output.push('\n');
+        self.in_list = true;
+    }
+
+    /// Separates a list from the block that follows it
+    fn end_list(&mut self) {
+        if self.in_list {
+            self.output.push('\n');
+            self.in_list = false;
+        }
+    }
+
+    /// Appends the text boxes anchored in the paragraph that just ended
+    fn flush_text_boxes(&mut self) {
+        for text_box in std::mem::take(&mut self.text_boxes) {
+            self.push_block(&text_box);
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::loader::xml::tests::write_zip;
+
+    const STYLES: &str = r#"<w:styles xmlns:w="w">
+        <w:style w:type="paragraph" w:styleId="Titre1"><w:name w:val="heading 1"/></w:style>
+        <w:style w:type="paragraph" w:styleId="Titre2"><w:name w:val="heading 2"/></w:style>
+        <w:style w:type="paragraph" w:styleId="Annexe"><w:name w:val="Annexe"/><w:basedOn w:val="Titre2"/></w:style>
+        <w:style w:type="paragraph" w:styleId="Chapitre"><w:name w:val="Chapitre"/>
+            <w:pPr><w:outlineLvl w:val="2"/></w:pPr></w:style>
+        <w:style w:type="paragraph" w:styleId="Etape"><w:name w:val="Etape"/>
+            <w:pPr><w:numPr><w:numId w:val="1"/></w:numPr></w:pPr></w:style>
+    </w:styles>"#;
+    const NUMBERING: &str = r#"<w:numbering xmlns:w="w">
+        <w:abstractNum w:abstractNumId="0">
+            <w:lvl w:ilvl="0"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="%1."/></w:lvl>
+            <w:lvl w:ilvl="1"><w:start w:val="1"/><w:numFmt w:val="lowerLetter"/><w:lvlText w:val="%1.%2)"/></w:lvl>
+        </w:abstractNum>
+        <w:abstractNum w:abstractNumId="1"><w:lvl w:ilvl="0"><w:numFmt w:val="bullet"/></w:lvl></w:abstractNum>
+        <w:num w:numId="1"><w:abstractNumId w:val="0"/></w:num>
+        <w:num w:numId="2"><w:abstractNumId w:val="1"/></w:num>
+    </w:numbering>"#;
+
+    /// Builds a paragraph with the given properties and text
+    fn paragraph(properties: &str, text: &str) -> String {
+        format!("<w:p><w:pPr>{}</w:pPr><w:r><w:t>{}</w:t></w:r></w:p>", properties, text)
+    }
+
+    /// Builds a table cell holding the given paragraphs
+    fn cell(paragraphs: &[&str]) -> String {
+        let paragraphs: String = paragraphs.iter().map(|text| paragraph("", text)).collect();
+        format!("<w:tc>{}</w:tc>", paragraphs)
+    }
+
+    fn load(body: &str) -> LoadedDocument {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("document.docx");
+        let document = format!(r#"<w:document xmlns:w="w"><w:body>{}</w:body></w:document>"#, body);
+        write_zip(
+            &path,
+            &[("word/document.xml", &document), ("word/styles.xml", STYLES), ("word/numbering.xml", NUMBERING)],
+        );
+        DocxLoader.load_document(&path).unwrap()
+    }
+
+    #[test]
+    fn heading_levels_come_from_styles_and_outline_levels() {
+        let body = [
+            paragraph(r#"<w:pStyle w:val="Titre1"/>"#, "Guide"),
+            paragraph(r#"<w:pStyle w:val="Annexe"/>"#, "Annexe A"),
+            paragraph(r#"<w:pStyle w:val="Chapitre"/>"#, "Détails"),
+            paragraph(r#"<w:pStyle w:val="Titre1"/><w:outlineLvl w:val="3"/>"#, "Niveau forcé"),
+            paragraph(r#"<w:outlineLvl w:val="9"/>"#, "Corps de texte"),
+        ]
+        .concat();
+
+        let document = load(&body);
+        assert!(document.markdown);
+        assert_eq!(
+            document.sections[0].text,
+            "# Guide\n\n## Annexe A\n\n### Détails\n\n#### Niveau forcé\n\nCorps de texte"
+        );
+    }
+
+    #[test]
+    fn tables_become_markdown_tables() {
+        let body = [
+            paragraph("", "Tarifs :"),
+            "<w:tbl>".to_string(),
+            format!("<w:tr>{}{}</w:tr>", cell(&["Offre"]), cell(&["Prix"])),
+            format!("<w:tr>{}{}</w:tr>", cell(&[]), cell(&[])),
+            format!("<w:tr>{}{}</w:tr>", cell(&["Pro | équipe"]), cell(&["20 €", "par mois"])),
+            "</w:tbl>".to_string(),
+            paragraph("", "Fin"),
+        ]
+        .concat();
+
+        let document = load(&body);
+        assert_eq!(
+            document.sections[0].text,
+            "Tarifs :\n\n| Offre | Prix |\n| --- | --- |\n| Pro \\| équipe | 20 € par mois |\n\nFin"
+        );
+    }
+
+    #[test]
+    fn numbered_and_bulleted_paragraphs_keep_their_marker() {
+        let body = [
+            paragraph(r#"<w:pStyle w:val="Etape"/>"#, "Installer"),
+            paragraph(r#"<w:numPr><w:ilvl w:val="1"/><w:numId w:val="1"/></w:numPr>"#, "Télécharger"),
+            paragraph(r#"<w:pStyle w:val="Etape"/>"#, "Configurer"),
+            paragraph(r#"<w:numPr><w:ilvl w:val="0"/><w:numId w:val="2"/></w:numPr>"#, "Astuce"),
+            paragraph("", "Suite"),
+        ]
+        .concat();
+
+        let document = load(&body);
+        assert_eq!(
+            document.sections[0].text,
+            "1. Installer\n  1.a) Télécharger\n2. Configurer\n- Astuce\n\nSuite"
+        );
+    }
+}
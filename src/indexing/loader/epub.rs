@@ -0,0 +1,199 @@
+//! EPUB loader for e-books.
+//!
+//! An EPUB is a ZIP archive of XHTML chapters described by a package file
+//! (OPF): its metadata, its manifest of files and its spine, the reading
+//! order. Each chapter of the spine goes through the HTML loader and becomes
+//! a section whose location is the chapter number and title.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::warn;
+
+use crate::AppError;
+use crate::indexing::loader::html::html_to_document;
+use crate::indexing::loader::xml::{self, Archive, XmlEvent};
+use crate::indexing::loader::{DocumentLoader, DocumentSection, LoadedDocument};
+
+/// Media types of the chapters that can be loaded
+const CHAPTER_MEDIA_TYPES: [&str; 2] = ["application/xhtml+xml", "text/html"];
+
+/// Loader for EPUB e-books
+pub struct EpubLoader;
+
+impl DocumentLoader for EpubLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let mut archive = xml::open_archive(path)?;
+        let package_path = package_path(&mut archive)?;
+        let package = xml::parse_xml(&xml::read_entry(&mut archive, &package_path)?)?;
+
+        // Manifest: ID -> (path, media type); spine: IDs in reading order
+        let mut manifest: HashMap<&str, (String, &str)> = HashMap::new();
+        let mut spine: Vec<&str> = Vec::new();
+        for event in &package {
+            if let XmlEvent::Start { name, attributes } = event {
+                match name.as_str() {
+                    "item" => {
+                        if let (Some(id), Some(href)) = (attributes.get("id"), attributes.get("href")) {
+                            let media_type = attributes.get("media-type").map_or("", String::as_str);
+                            manifest.insert(id, (xml::resolve_path(&package_path, href), media_type));
+                        }
+                    }
+                    "itemref" if attributes.get("linear").map(String::as_str) != Some("no") => {
+                        if let Some(id) = attributes.get("idref") {
+                            spine.push(id);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut sections = Vec::new();
+        let mut chapter = 0;
+        for id in spine {
+            let Some((chapter_path, media_type)) = manifest.get(id) else {
+                warn!("Spine item '{}' is missing from the EPUB manifest", id);
+                continue;
+            };
+            if !CHAPTER_MEDIA_TYPES.contains(media_type) {
+                continue;
+            }
+            let content = match xml::read_entry(&mut archive, chapter_path) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Skipping EPUB chapter '{}': {}", chapter_path, e);
+                    continue;
+                }
+            };
+
+            let document = html_to_document(&content);
+            let text = document.text();
+            if text.trim().is_empty() {
+                continue;
+            }
+            chapter += 1;
+
+            let mut location = Map::new();
+            location.insert("chapter".to_string(), chapter.into());
+            let title = text
+                .lines()
+                .find_map(|line| line.starts_with('#').then(|| line.trim_start_matches('#').trim().to_string()))
+                .or_else(|| document.metadata.get("title").and_then(Value::as_str).map(str::to_string));
+            if let Some(title) = title {
+                location.insert("chapter_title".to_string(), title.into());
+            }
+            sections.push(DocumentSection {
+                text,
+                location,
+                ocr: false,
+            });
+        }
+
+        if sections.is_empty() {
+            return Err(AppError::Document("No readable chapter found in the EPUB".to_string()));
+        }
+
+        let mut metadata = xml::text_metadata(
+            &package,
+            &[("title", "title"), ("creator", "author"), ("language", "language")],
+        );
+        metadata.insert("chapters".to_string(), sections.len().into());
+        Ok(LoadedDocument {
+            sections,
+            markdown: true,
+            code: None,
+            metadata,
+        })
+    }
+}
+
+/// Gets the path of the package file (OPF) from `META-INF/container.xml`
+fn package_path(archive: &mut Archive) -> Result<String, AppError> {
+    let container = xml::parse_xml(&xml::read_entry(archive, "META-INF/container.xml")?)?;
+    container
+        .into_iter()
+        .find_map(|event| match event {
+            XmlEvent::Start { name, mut attributes } if name == "rootfile" => attributes.remove("full-path"),
+            _ => None,
+        })
+        .ok_or_else(|| AppError::Document("No package file declared in the EPUB container".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::loader::xml::tests::write_zip;
+
+    const CONTAINER: &str = r#"<container xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles>
+        <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+    </rootfiles></container>"#;
+    const PACKAGE: &str = r#"<package xmlns="http://www.idpf.org/2007/opf" xmlns:dc="http://purl.org/dc/elements/1.1/">
+        <metadata><dc:title>Le Tour du monde</dc:title><dc:creator>Jules Verne</dc:creator>
+            <dc:language>fr</dc:language></metadata>
+        <manifest>
+            <item id="cover" href="text/cover.xhtml" media-type="application/xhtml+xml"/>
+            <item id="c1" href="text/chapitre%201.xhtml" media-type="application/xhtml+xml"/>
+            <item id="c2" href="text/chapitre2.xhtml" media-type="application/xhtml+xml"/>
+            <item id="css" href="style.css" media-type="text/css"/>
+        </manifest>
+        <spine><itemref idref="cover" linear="no"/><itemref idref="c2"/><itemref idref="css"/>
+            <itemref idref="missing"/><itemref idref="c1"/></spine>
+    </package>"#;
+
+    #[test]
+    fn chapters_are_read_in_spine_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tour.epub");
+        write_zip(
+            &path,
+            &[
+                ("mimetype", "application/epub+zip"),
+                ("META-INF/container.xml", CONTAINER),
+                ("OEBPS/content.opf", PACKAGE),
+                ("OEBPS/text/cover.xhtml", "<html><body><p>Couverture</p></body></html>"),
+                ("OEBPS/text/chapitre 1.xhtml", "<html><body><h1>Le pari</h1><p>Phileas Fogg parie.</p></body></html>"),
+                (
+                    "OEBPS/text/chapitre2.xhtml",
+                    "<html><head><title>Départ</title></head><body><p>Passepartout arrive.</p></body></html>",
+                ),
+            ],
+        );
+
+        let document = EpubLoader.load_document(&path).unwrap();
+        assert!(document.markdown);
+        assert_eq!(document.sections.len(), 2);
+        assert_eq!(document.sections[0].text, "# Départ\n\nPassepartout arrive.");
+        assert_eq!(document.sections[0].location["chapter"], 1);
+        assert_eq!(document.sections[0].location["chapter_title"], "Départ");
+        assert!(document.sections[1].text.contains("Phileas Fogg parie."));
+        assert_eq!(document.sections[1].location["chapter"], 2);
+        assert_eq!(document.sections[1].location["chapter_title"], "Le pari");
+
+        assert_eq!(document.metadata["title"], "Le Tour du monde");
+        assert_eq!(document.metadata["author"], "Jules Verne");
+        assert_eq!(document.metadata["language"], "fr");
+        assert_eq!(document.metadata["chapters"], 2);
+    }
+
+    #[test]
+    fn books_without_readable_chapters_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vide.epub");
+        let package = r#"<package>
+            <manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+            <spine><itemref idref="c1"/></spine></package>"#;
+        write_zip(
+            &path,
+            &[("META-INF/container.xml", CONTAINER), ("OEBPS/content.opf", package), ("OEBPS/c1.xhtml", "<html/>")],
+        );
+        assert!(EpubLoader.load_document(&path).is_err());
+
+        write_zip(&path, &[("META-INF/container.xml", "<container/>")]);
+        assert!(EpubLoader.load_document(&path).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! HTML loader with main content extraction.
+//!
+//! Web pages contain much more than their content: scripts, styles,
+//! navigation bars, headers, footers, cookie banners... Embedding them as-is
+//! pollutes the knowledge base. This loader picks the main content of the
+//! page in a readability-like way (the `article` or `main` element, or the
+//! container holding most paragraph text), drops boilerplate elements, and
+//! renders the result as Markdown-like text with `#` heading markers, so that
+//! it goes through structure-aware chunking.
+
+use scraper::{ElementRef, Html, Node, Selector};
+use serde_json::Map;
+use std::fs;
+use std::path::Path;
+
+use crate::AppError;
+use crate::indexing::loader::{DocumentLoader, LoadedDocument};
+
+/// Elements never part of the content of a page
+const SKIPPED_TAGS: [&str; 17] = [
+    "script", "style", "noscript", "template", "nav", "aside", "footer", "form", "iframe", "svg", "button",
+    "select", "input", "object", "embed", "canvas", "dialog",
+];
+
+/// Class or ID fragments of boilerplate elements
+const BOILERPLATE_HINTS: [&str; 15] = [
+    "navbar", "navigation", "menu", "footer", "sidebar", "cookie", "banner", "breadcrumb", "share", "social",
+    "comment", "advert", "promo", "subscribe", "popup",
+];
+
+/// Elements rendered as separate blocks of text
+const BLOCK_TAGS: [&str; 22] = [
+    "p", "div", "section", "article", "main", "header", "ul", "ol", "table", "thead", "tbody", "blockquote",
+    "dl", "dt", "dd", "figure", "figcaption", "body", "hr", "address", "details", "summary",
+];
+
+/// Minimum length of a paragraph to count in the content score of its container
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+
+/// Loader for HTML files
+pub struct HtmlLoader;
+
+impl DocumentLoader for HtmlLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let bytes = fs::read(path)?;
+        Ok(html_to_document(&String::from_utf8_lossy(&bytes)))
+    }
+}
+
+/// Extracts the main content of an HTML page as Markdown-like text
+///
+/// # Arguments
+/// * `html` - HTML source of the page
+///
+/// # Returns
+/// * `LoadedDocument` - The content with heading markers, and the title,
+///   description and language of the page as metadata
+pub fn html_to_document(html: &str) -> LoadedDocument {
+    let document = Html::parse_document(html);
+    let mut metadata = Map::new();
+
+    let title = select_first(&document, "title").map(|title| collapse_whitespace(&element_text(title)));
+    if let Some(title) = title.as_ref().filter(|title| !title.is_empty()) {
+        metadata.insert("title".to_string(), title.clone().into());
+    }
+    if let Some(description) = select_first(&document, r#"meta[name="description"]"#)
+        .and_then(|meta| meta.value().attr("content"))
+    {
+        metadata.insert("description".to_string(), description.trim().into());
+    }
+    if let Some(lang) = select_first(&document, "html").and_then(|html| html.value().attr("lang")) {
+        metadata.insert("lang".to_string(), lang.into());
+    }
+
+    let mut renderer = TextRenderer::default();
+    if let Some(root) = main_content(&document) {
+        renderer.render_children(root);
+    }
+    let mut text = renderer.finish();
+
+    // Keep the page title when the extracted content has no heading of its own
+    if let Some(title) = title.filter(|title| !title.is_empty())
+        && !text.lines().any(|line| line.starts_with('#'))
+    {
+        text = format!("# {}\n\n{}", title, text);
+    }
+    LoadedDocument {
+        metadata,
+        markdown: true,
+        code: None,
+        ..LoadedDocument::from_text(text)
+    }
+}
+
+/// Picks the element holding the main content of the page
+fn main_content(document: &Html) -> Option<ElementRef<'_>> {
+    // Explicit content elements first, the longest article if there are several
+    if let Ok(selector) = Selector::parse("article")
+        && let Some(article) = document
+            .select(&selector)
+            .max_by_key(|article| element_text(*article).len())
+    {
+        return Some(article);
+    }
+    if let Some(main) = select_first(document, r#"main, [role="main"]"#) {
+        return Some(main);
+    }
+
+    // Otherwise, the container holding most paragraph text
+    let body = select_first(document, "body");
+    let mut best: Option<(ElementRef, usize)> = None;
+    if let Ok(selector) = Selector::parse("p") {
+        let mut scores: Vec<(ElementRef, usize)> = Vec::new();
+        for paragraph in document.select(&selector) {
+            let length = collapse_whitespace(&element_text(paragraph)).len();
+            if length < MIN_PARAGRAPH_LENGTH {
+                continue;
+            }
+            let Some(parent) = paragraph.parent().and_then(ElementRef::wrap) else {
+                continue;
+            };
+            match scores.iter_mut().find(|(element, _)| element.id() == parent.id()) {
+                Some((_, score)) => *score += length,
+                None => scores.push((parent, length)),
+            }
+        }
+        best = scores.into_iter().max_by_key(|(_, score)| *score);
+    }
+
+    match best {
+        Some((element, _)) if Some(element) != body => Some(element),
+        _ => body.or_else(|| Some(document.root_element())),
+    }
+}
+
+/// Checks if an element is boilerplate (navigation, banners...) rather than content
+fn is_boilerplate(element: ElementRef) -> bool {
+    let value = element.value();
+    let name = value.name();
+    if SKIPPED_TAGS.contains(&name) {
+        return true;
+    }
+    // Page headers hold logos and menus, article headers hold the title
+    if name == "header" && select_in(element, "h1, h2").is_none() {
+        return true;
+    }
+    if value.attr("aria-hidden") == Some("true") || value.attr("hidden").is_some() {
+        return true;
+    }
+
+    let mut hints = value.classes().chain(value.id()).map(|hint| hint.to_ascii_lowercase());
+    hints.any(|hint| BOILERPLATE_HINTS.iter().any(|boilerplate| hint.contains(boilerplate)))
+}
+
+/// Renders elements to text with Markdown heading and list markers
+#[derive(Default)]
+struct TextRenderer {
+    out: String,
+}
+
+impl TextRenderer {
+    fn render_children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.push_inline(text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.render_element(child);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn render_element(&mut self, element: ElementRef) {
+        if is_boilerplate(element) {
+            return;
+        }
+
+        let name = element.value().name();
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let heading = collapse_whitespace(&element_text(element));
+                if !heading.is_empty() {
+                    let level = name[1..].parse::<usize>().unwrap_or(1);
+                    self.block();
+                    self.out.push_str(&"#".repeat(level));
+                    self.out.push(' ');
+                    self.out.push_str(&heading);
+                    self.block();
+                }
+            }
+            "br" => self.newline(),
+            "pre" => {
+                self.block();
+                self.out.push_str(element_text(element).trim_end());
+                self.block();
+            }
+            "li" => {
+                self.newline();
+                self.out.push_str("- ");
+                self.render_children(element);
+                self.newline();
+            }
+            "tr" => {
+                self.newline();
+                self.render_children(element);
+                self.newline();
+            }
+            "td" | "th" => {
+                self.render_children(element);
+                self.out.push_str(" | ");
+            }
+            "img" => {
+                if let Some(alt) = element.value().attr("alt").filter(|alt| !alt.trim().is_empty()) {
+                    self.push_inline(alt);
+                }
+            }
+            _ if BLOCK_TAGS.contains(&name) => {
+                self.block();
+                self.render_children(element);
+                self.block();
+            }
+            _ => self.render_children(element),
+        }
+    }
+
+    /// Appends inline text, collapsing whitespace
+    fn push_inline(&mut self, text: &str) {
+        let collapsed = collapse_whitespace(text);
+        if collapsed.is_empty() {
+            if text.chars().next().is_some_and(char::is_whitespace) {
+                self.space();
+            }
+            return;
+        }
+        if text.starts_with(char::is_whitespace) {
+            self.space();
+        }
+        self.out.push_str(&collapsed);
+        if text.ends_with(char::is_whitespace) {
+            self.space();
+        }
+    }
+
+    fn space(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with(char::is_whitespace) {
+            self.out.push(' ');
+        }
+    }
+
+    fn newline(&mut self) {
+        self.trim_trailing_spaces();
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn block(&mut self) {
+        self.trim_trailing_spaces();
+        if self.out.is_empty() || self.out.ends_with("\n\n") {
+            return;
+        }
+        self.out.push_str(if self.out.ends_with('\n') { "\n" } else { "\n\n" });
+    }
+
+    fn trim_trailing_spaces(&mut self) {
+        let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(trimmed);
+    }
+
+    /// Returns the rendered text, trimmed line by line
+    fn finish(self) -> String {
+        let mut text = String::new();
+        let mut blank_lines = 0;
+        for line in self.out.lines() {
+            let line = line.trim().trim_end_matches('|').trim_end();
+            if line.is_empty() {
+                blank_lines += 1;
+                continue;
+            }
+            if !text.is_empty() {
+                text.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+            }
+            text.push_str(line);
+            blank_lines = 0;
+        }
+        text
+    }
+}
+
+fn select_first<'a>(document: &'a Html, selector: &str) -> Option<ElementRef<'a>> {
+    let selector = Selector::parse(selector).ok()?;
+    document.select(&selector).next()
+}
+
+fn select_in<'a>(element: ElementRef<'a>, selector: &str) -> Option<ElementRef<'a>> {
+    let selector = Selector::parse(selector).ok()?;
+    element.select(&selector).next()
+}
+
+fn element_text(element: ElementRef) -> String {
+    element.text().collect()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html lang="fr">
+<head>
+  <title>Guide d'installation</title>
+  <meta name="description" content=" Installer le proxy ">
+  <style>body { color: red; }</style>
+  <script>trackVisitor();</script>
+</head>
+<body>
+  <header><img src="logo.png" alt="Logo"><a href="/">Accueil</a></header>
+  <nav><ul><li>Produits</li><li>Contact</li></ul></nav>
+  <div class="cookie-banner">Nous utilisons des cookies.</div>
+  <article>
+    <header><h1>Installation</h1></header>
+    <p>Le proxy se compile avec   cargo build --release sur toutes les plateformes.</p>
+    <h2>Configuration</h2>
+    <ul><li>Copier config.toml.example</li><li>Renseigner l'endpoint</li></ul>
+    <table><tr><th>Option</th><th>Défaut</th></tr><tr><td>port</td><td>8080</td></tr></table>
+    <div class="share-buttons">Partager sur les réseaux</div>
+    <p hidden>Texte masqué</p>
+  </article>
+  <aside>Articles similaires</aside>
+  <footer>© 2025 Exemple</footer>
+</body>
+</html>"#;
+
+    #[test]
+    fn boilerplate_is_dropped_and_structure_kept() {
+        let document = html_to_document(PAGE);
+        assert_eq!(
+            document.text(),
+            "# Installation\n\n\
+             Le proxy se compile avec cargo build --release sur toutes les plateformes.\n\n\
+             ## Configuration\n\n\
+             - Copier config.toml.example\n\
+             - Renseigner l'endpoint\n\n\
+             Option | Défaut\n\
+             port | 8080"
+        );
+        assert!(document.markdown);
+        assert_eq!(document.metadata["title"], "Guide d'installation");
+        assert_eq!(document.metadata["description"], "Installer le proxy");
+        assert_eq!(document.metadata["lang"], "fr");
+    }
+
+    #[test]
+    fn container_with_most_paragraph_text_is_the_content() {
+        let html = r#"<html><head><title>Notes</title></head><body>
+            <div class="menu"><p>Un lien de menu assez long pour compter comme paragraphe</p></div>
+            <div id="content">
+              <p>Premier paragraphe du contenu, suffisamment long pour compter.</p>
+              <p>Second paragraphe du contenu, lui aussi suffisamment long.</p>
+            </div>
+            <div><p>Un pied de page assez long pour compter comme paragraphe</p></div>
+        </body></html>"#;
+        let text = html_to_document(html).text();
+        assert_eq!(
+            text,
+            "# Notes\n\n\
+             Premier paragraphe du contenu, suffisamment long pour compter.\n\n\
+             Second paragraphe du contenu, lui aussi suffisamment long."
+        );
+    }
+}
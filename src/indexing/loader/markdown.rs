@@ -0,0 +1,214 @@
+//! Markdown loader with front matter parsing.
+//!
+//! Markdown files often start with a front matter block holding the title,
+//! the author, the date or the tags of the document, either in YAML between
+//! `---` lines or in TOML between `+++` lines. This loader parses it into the
+//! metadata of the document and keeps the body as Markdown, so that its
+//! headings are used by structure-aware chunking.
+
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+use yaml_rust2::{Yaml, YamlLoader};
+
+use crate::AppError;
+use crate::indexing::loader::{DocumentLoader, LoadedDocument};
+
+/// Loader for Markdown files
+pub struct MarkdownLoader;
+
+impl DocumentLoader for MarkdownLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let content = fs::read_to_string(path)?;
+        let (front_matter, body) = split_front_matter(&content);
+
+        let mut metadata = match front_matter {
+            Some(front_matter) => parse_front_matter(front_matter).unwrap_or_else(|e| {
+                warn!("Ignoring invalid front matter of '{}': {}", path.display(), e);
+                Map::new()
+            }),
+            None => Map::new(),
+        };
+        if !metadata.contains_key("title")
+            && let Some(title) = body.lines().find_map(|line| line.strip_prefix("# "))
+        {
+            metadata.insert("title".to_string(), title.trim().into());
+        }
+
+        Ok(LoadedDocument {
+            markdown: true,
+            code: None,
+            metadata,
+            ..LoadedDocument::from_text(body.trim().to_string())
+        })
+    }
+}
+
+/// Front matter block of a Markdown file
+enum FrontMatter<'a> {
+    Yaml(&'a str),
+    Toml(&'a str),
+}
+
+/// Splits the front matter block from the body of a Markdown file
+fn split_front_matter(content: &str) -> (Option<FrontMatter<'_>>, &str) {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let Some(first_line) = content.lines().next() else {
+        return (None, content);
+    };
+
+    let delimiter = first_line.trim_end();
+    if delimiter != "---" && delimiter != "+++" {
+        return (None, content);
+    }
+
+    let start = first_line.len() + 1;
+    let mut offset = start;
+    for line in content.get(start..).unwrap_or_default().split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            let block = &content[start..offset];
+            let body = &content[offset + line.len()..];
+            let front_matter = if delimiter == "---" {
+                FrontMatter::Yaml(block)
+            } else {
+                FrontMatter::Toml(block)
+            };
+            return (Some(front_matter), body);
+        }
+        offset += line.len();
+    }
+
+    // No closing delimiter: not a front matter block
+    (None, content)
+}
+
+/// Parses a front matter block into a JSON object
+fn parse_front_matter(front_matter: FrontMatter) -> Result<Map<String, Value>, String> {
+    let value = match front_matter {
+        FrontMatter::Yaml(block) => {
+            let documents = YamlLoader::load_from_str(block).map_err(|e| e.to_string())?;
+            documents.into_iter().next().map(yaml_to_json).unwrap_or(Value::Null)
+        }
+        FrontMatter::Toml(block) => {
+            let table: toml::Table = toml::from_str(block).map_err(|e| e.to_string())?;
+            toml_to_json(toml::Value::Table(table))
+        }
+    };
+
+    match value {
+        Value::Object(map) => Ok(map),
+        Value::Null => Ok(Map::new()),
+        _ => Err("front matter is not a mapping".to_string()),
+    }
+}
+
+/// Converts a TOML value to JSON, dates included as strings
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(string) => string.into(),
+        toml::Value::Integer(integer) => integer.into(),
+        toml::Value::Float(float) => serde_json::Number::from_f64(float).map_or(Value::Null, Value::Number),
+        toml::Value::Boolean(boolean) => boolean.into(),
+        toml::Value::Datetime(datetime) => datetime.to_string().into(),
+        toml::Value::Array(array) => array.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, toml_to_json(value)))
+            .collect::<Map<String, Value>>()
+            .into(),
+    }
+}
+
+/// Converts a YAML value to JSON
+fn yaml_to_json(yaml: Yaml) -> Value {
+    match yaml {
+        Yaml::Real(real) => real
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or(Value::String(real), Value::Number),
+        Yaml::Integer(integer) => integer.into(),
+        Yaml::String(string) => string.into(),
+        Yaml::Boolean(boolean) => boolean.into(),
+        Yaml::Array(array) => array.into_iter().map(yaml_to_json).collect(),
+        Yaml::Hash(hash) => hash
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let key = match key {
+                    Yaml::String(key) | Yaml::Real(key) => key,
+                    Yaml::Integer(key) => key.to_string(),
+                    Yaml::Boolean(key) => key.to_string(),
+                    _ => return None,
+                };
+                Some((key, yaml_to_json(value)))
+            })
+            .collect::<Map<String, Value>>()
+            .into(),
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(content: &str) -> LoadedDocument {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doc.md");
+        fs::write(&path, content).unwrap();
+        MarkdownLoader.load_document(&path).unwrap()
+    }
+
+    #[test]
+    fn yaml_front_matter_becomes_metadata() {
+        let document = load(
+            "\u{feff}---\ntitle: Guide\ntags: [rust, rag]\nversion: 1.5\ndraft: false\nauthor:\n  name: Alice\n---\n\n# Introduction\n\nTexte.\n",
+        );
+        assert_eq!(document.text(), "# Introduction\n\nTexte.");
+        assert!(document.markdown);
+        assert_eq!(
+            Value::Object(document.metadata),
+            serde_json::json!({
+                "title": "Guide",
+                "tags": ["rust", "rag"],
+                "version": 1.5,
+                "draft": false,
+                "author": {"name": "Alice"},
+            })
+        );
+    }
+
+    #[test]
+    fn toml_front_matter_becomes_metadata_with_dates_as_strings() {
+        let document = load("+++\ntitle = \"Notes\"\ndate = 2024-05-01\nweight = 3\n+++\nCorps du document\n");
+        assert_eq!(document.text(), "Corps du document");
+        assert_eq!(document.metadata["title"], "Notes");
+        assert_eq!(document.metadata["date"], "2024-05-01");
+        assert_eq!(document.metadata["weight"], 3);
+    }
+
+    #[test]
+    fn title_comes_from_the_first_heading_without_front_matter() {
+        let document = load("Intro\n\n# Titre principal\n\n## Partie\n");
+        assert_eq!(document.metadata["title"], "Titre principal");
+        assert_eq!(document.metadata.len(), 1);
+    }
+
+    #[test]
+    fn unclosed_or_invalid_front_matter_is_not_metadata() {
+        // A thematic break without a closing delimiter stays in the body
+        let document = load("---\nPas de front matter\n");
+        assert_eq!(document.text(), "---\nPas de front matter");
+        assert!(document.metadata.is_empty());
+
+        let document = load("---\n- une liste\n- pas un objet\n---\n# Titre\n");
+        assert_eq!(document.text(), "# Titre");
+        assert_eq!(document.metadata.len(), 1);
+        assert_eq!(document.metadata["title"], "Titre");
+    }
+}
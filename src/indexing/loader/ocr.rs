@@ -0,0 +1,190 @@
+//! OCR of images, for scanned documents.
+//!
+//! The `OcrEngine` trait recognizes the text of an image file, so that the
+//! engine can be replaced without touching the loaders. `TesseractOcr` runs
+//! the local Tesseract command line (`tesseract <image> stdout -l <languages>`),
+//! which must be installed with the data of the configured languages.
+//!
+//! `ImageLoader` loads PNG, JPEG and TIFF files with OCR, one section per
+//! page of multi-page TIFF files. The PDF loader uses the same engine for the
+//! pages without text layer. Sections recognized with OCR are flagged, and
+//! their chunks carry `ocr: true` in their payload.
+
+use serde_json::Map;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::AppError;
+use crate::{OcrConfig, OcrEngineKind};
+use crate::indexing::loader::{DocumentLoader, DocumentSection, LoadedDocument};
+
+/// Interval between two checks of a running OCR command
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Trait for the engines recognizing the text of images
+pub trait OcrEngine: Send + Sync {
+    /// Gets the name of the engine, recorded in the metadata of the documents
+    fn name(&self) -> &str;
+
+    /// Recognizes the text of an image file
+    ///
+    /// # Arguments
+    /// * `image` - Path of the image (PNG, JPEG, TIFF or PNM)
+    ///
+    /// # Returns
+    /// * `Result<String, AppError>` - The recognized text, pages separated by form feeds
+    fn recognize(&self, image: &Path) -> Result<String, AppError>;
+}
+
+/// Creates the OCR engine of the configuration
+///
+/// # Returns
+/// * `Option<Arc<dyn OcrEngine>>` - The engine, None if OCR is disabled
+pub fn ocr_engine(config: &OcrConfig) -> Option<Arc<dyn OcrEngine>> {
+    if !config.enabled {
+        return None;
+    }
+    Some(match config.engine {
+        OcrEngineKind::Tesseract => Arc::new(TesseractOcr::new(config)),
+    })
+}
+
+/// OCR with the local Tesseract command line
+pub struct TesseractOcr {
+    command: String,
+    languages: String,
+    timeout: Duration,
+}
+
+impl TesseractOcr {
+    /// Creates a Tesseract engine
+    ///
+    /// # Arguments
+    /// * `config` - OCR configuration holding the command, the languages and the timeout
+    pub fn new(config: &OcrConfig) -> Self {
+        TesseractOcr {
+            command: config.command.clone(),
+            languages: config.languages.clone(),
+            timeout: Duration::from_secs(config.timeout_secs),
+        }
+    }
+}
+
+impl OcrEngine for TesseractOcr {
+    fn name(&self) -> &str {
+        "tesseract"
+    }
+
+    fn recognize(&self, image: &Path) -> Result<String, AppError> {
+        // Files are loaded in parallel already, Tesseract's own threads would compete with them
+        let mut child = Command::new(&self.command)
+            .arg(image)
+            .arg("stdout")
+            .arg("-l")
+            .arg(&self.languages)
+            .env("OMP_THREAD_LIMIT", "1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| AppError::Ocr(format!("Failed to run '{}': {}", self.command, e)))?;
+
+        // Read the output on other threads, so that a full pipe does not block the command
+        let mut stdout = child.stdout.take();
+        let mut stderr = child.stderr.take();
+        let stdout_reader = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            if let Some(stdout) = stdout.as_mut() {
+                let _ = stdout.read_to_end(&mut output);
+            }
+            output
+        });
+        let stderr_reader = std::thread::spawn(move || {
+            let mut output = String::new();
+            if let Some(stderr) = stderr.as_mut() {
+                let _ = stderr.read_to_string(&mut output);
+            }
+            output
+        });
+
+        let started = Instant::now();
+        let status = loop {
+            match child.try_wait()? {
+                Some(status) => break status,
+                None if started.elapsed() > self.timeout => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(AppError::Ocr(format!("OCR timed out after {} s", self.timeout.as_secs())));
+                }
+                None => std::thread::sleep(POLL_INTERVAL),
+            }
+        };
+
+        let output = stdout_reader.join().unwrap_or_default();
+        let errors = stderr_reader.join().unwrap_or_default();
+        if !status.success() {
+            return Err(AppError::Ocr(format!("{} failed ({}): {}", self.command, status, errors.trim())));
+        }
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
+
+/// Loader for images, whose text is recognized with OCR
+pub struct ImageLoader {
+    engine: Arc<dyn OcrEngine>,
+}
+
+impl ImageLoader {
+    /// Creates an image loader
+    ///
+    /// # Arguments
+    /// * `engine` - OCR engine recognizing the text of the images
+    pub fn new(engine: Arc<dyn OcrEngine>) -> Self {
+        ImageLoader { engine }
+    }
+}
+
+impl DocumentLoader for ImageLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let text = self.engine.recognize(path)?;
+
+        // Tesseract separates the pages of multi-page TIFF files with form feeds
+        let pages: Vec<&str> = text.split('\x0c').collect();
+        let paged = pages.iter().filter(|page| !page.trim().is_empty()).count() > 1;
+        let sections: Vec<DocumentSection> = pages
+            .iter()
+            .enumerate()
+            .filter(|(_, page)| !page.trim().is_empty())
+            .map(|(index, page)| {
+                let mut location = Map::new();
+                if paged {
+                    location.insert("page".to_string(), (index + 1).into());
+                }
+                DocumentSection {
+                    text: page.trim().to_string(),
+                    location,
+                    ocr: true,
+                }
+            })
+            .collect();
+        if sections.is_empty() {
+            return Err(AppError::Ocr("No text recognized in the image".to_string()));
+        }
+
+        let mut metadata = Map::new();
+        metadata.insert("ocr_engine".to_string(), self.engine.name().into());
+        Ok(LoadedDocument {
+            sections,
+            markdown: false,
+            code: None,
+            metadata,
+        })
+    }
+}
@@ -0,0 +1,304 @@
+//! ODT loader for LibreOffice and OpenDocument text documents.
+//!
+//! The content of the document is rendered as Markdown: headings keep their
+//! outline level, list items become `-` items and tables become Markdown
+//! table rows, so the document goes through structure-aware chunking. When
+//! the document holds the page breaks computed by LibreOffice when it was
+//! saved, it is split into one section per page, with the page number as
+//! location.
+
+use serde_json::Map;
+use std::path::Path;
+
+use crate::AppError;
+use crate::indexing::loader::xml::{self, XmlEvent};
+use crate::indexing::loader::{DocumentLoader, DocumentSection, LoadedDocument};
+
+/// Elements whose content is not part of the text (comments, footnote bodies, tables of content...)
+const SKIPPED_ELEMENTS: [&str; 11] = [
+    "annotation",
+    "tracked-changes",
+    "note-body",
+    "table-of-content",
+    "alphabetical-index",
+    "illustration-index",
+    "table-index",
+    "object-index",
+    "user-index",
+    "bibliography",
+    "sequence-decls",
+];
+
+/// Loader for OpenDocument text files
+pub struct OdtLoader;
+
+impl DocumentLoader for OdtLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let mut archive = xml::open_archive(path)?;
+        let events = xml::parse_xml(&xml::read_entry(&mut archive, "content.xml")?)?;
+        let metadata = xml::read_entry(&mut archive, "meta.xml")
+            .and_then(|content| xml::parse_xml(&content))
+            .map(|events| {
+                xml::text_metadata(
+                    &events,
+                    &[("title", "title"), ("initial-creator", "author"), ("creator", "author"), ("language", "language")],
+                )
+            })
+            .unwrap_or_default();
+
+        let mut renderer = OdtRenderer::default();
+        for event in events {
+            renderer.handle(event);
+        }
+
+        let paginated = renderer.pages.len() > 1;
+        let sections = renderer
+            .pages
+            .into_iter()
+            .enumerate()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .map(|(index, text)| {
+                let mut location = Map::new();
+                if paginated {
+                    location.insert("page".to_string(), (index + 1).into());
+                }
+                DocumentSection {
+                    text: text.trim().to_string(),
+                    location,
+                    ocr: false,
+                }
+            })
+            .collect();
+
+        Ok(LoadedDocument {
+            sections,
+            markdown: true,
+            code: None,
+            metadata,
+        })
+    }
+}
+
+/// Renders the events of `content.xml` as Markdown, page by page
+struct OdtRenderer {
+    pages: Vec<String>,
+    /// Text of the current paragraph, heading or table cell
+    block: String,
+    heading_level: Option<usize>,
+    list_depth: usize,
+    /// True until the first paragraph of a list item is rendered
+    list_item_start: bool,
+    cell_depth: usize,
+    row: Vec<String>,
+    skip_depth: usize,
+    page_break_pending: bool,
+}
+
+impl Default for OdtRenderer {
+    fn default() -> Self {
+        OdtRenderer {
+            pages: vec![String::new()],
+            block: String::new(),
+            heading_level: None,
+            list_depth: 0,
+            list_item_start: false,
+            cell_depth: 0,
+            row: Vec::new(),
+            skip_depth: 0,
+            page_break_pending: false,
+        }
+    }
+}
+
+impl OdtRenderer {
+    fn handle(&mut self, event: XmlEvent) {
+        if self.skip_depth > 0 {
+            match event {
+                XmlEvent::Start { .. } => self.skip_depth += 1,
+                XmlEvent::End { .. } => self.skip_depth -= 1,
+                XmlEvent::Text(_) => {}
+            }
+            return;
+        }
+
+        match event {
+            XmlEvent::Start { name, attributes } => match name.as_str() {
+                name if SKIPPED_ELEMENTS.contains(&name) => self.skip_depth = 1,
+                "h" | "p" if self.cell_depth == 0 => {
+                    self.start_block();
+                    if name == "h" {
+                        let level = attributes.get("text:outline-level").and_then(|level| level.parse().ok());
+                        self.heading_level = Some(level.unwrap_or(1).clamp(1, 6));
+                    }
+                }
+                "list" => self.list_depth += 1,
+                "list-item" => self.list_item_start = true,
+                "table-cell" => {
+                    if self.cell_depth == 0 {
+                        self.block.clear();
+                    }
+                    self.cell_depth += 1;
+                }
+                "s" => {
+                    let count = attributes.get("text:c").and_then(|c| c.parse().ok()).unwrap_or(1);
+                    self.block.push_str(&" ".repeat(count));
+                }
+                "tab" => self.block.push('\t'),
+                "line-break" => self.block.push('\n'),
+                // A break before any text of the block starts the page with the block
+                "soft-page-break" if self.cell_depth == 0 && self.block.trim().is_empty() => self.new_page(),
+                "soft-page-break" => self.page_break_pending = true,
+                _ => {}
+            },
+            XmlEvent::End { name } => match name.as_str() {
+                "h" | "p" if self.cell_depth == 0 => self.end_block(),
+                "h" | "p" => self.block.push(' '),
+                "list" => {
+                    self.list_depth = self.list_depth.saturating_sub(1);
+                    if self.list_depth == 0 {
+                        self.push_line("", false);
+                    }
+                }
+                "table-cell" => {
+                    self.cell_depth = self.cell_depth.saturating_sub(1);
+                    if self.cell_depth == 0 {
+                        self.row.push(collapse_whitespace(&self.block).replace('|', "\\|"));
+                        self.block.clear();
+                    }
+                }
+                "table-row" if self.cell_depth == 0 => {
+                    if self.row.iter().any(|cell| !cell.is_empty()) {
+                        let line = format!("| {} |", self.row.join(" | "));
+                        self.push_line(&line, false);
+                    }
+                    self.row.clear();
+                }
+                "table" if self.cell_depth == 0 => self.push_line("", true),
+                _ => {}
+            },
+            XmlEvent::Text(text) => self.block.push_str(&text.replace(['\n', '\r'], " ")),
+        }
+    }
+
+    fn start_block(&mut self) {
+        if self.page_break_pending {
+            self.new_page();
+        }
+        self.block.clear();
+        self.heading_level = None;
+    }
+
+    fn end_block(&mut self) {
+        let text: Vec<String> = self.block.lines().map(collapse_whitespace).collect();
+        let text = text.join("\n").trim().to_string();
+        self.block.clear();
+
+        if !text.is_empty() {
+            let line = match self.heading_level.take() {
+                Some(level) => format!("{} {}", "#".repeat(level), text.replace('\n', " ")),
+                None if self.list_depth > 0 && self.list_item_start => {
+                    format!("{}- {}", "  ".repeat(self.list_depth - 1), text)
+                }
+                None => text,
+            };
+            self.push_line(&line, self.list_depth == 0);
+            self.list_item_start = false;
+        }
+        if self.page_break_pending {
+            self.new_page();
+        }
+    }
+
+    /// Appends a line to the current page, followed by a blank line for blocks
+    fn push_line(&mut self, line: &str, block: bool) {
+        if let Some(page) = self.pages.last_mut() {
+            page.push_str(line);
+            page.push_str(if block { "\n\n" } else { "\n" });
+        }
+    }
+
+    fn new_page(&mut self) {
+        self.page_break_pending = false;
+        if self.pages.last().is_some_and(|page| !page.trim().is_empty()) {
+            self.pages.push(String::new());
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::loader::xml::tests::write_zip;
+
+    const CONTENT: &str = r#"<office:document-content xmlns:office="o" xmlns:text="t" xmlns:table="tb">
+    <office:body><office:text>
+        <text:sequence-decls><text:sequence-decl text:name="Table"/></text:sequence-decls>
+        <text:h text:outline-level="1">Procédure</text:h>
+        <text:p>Ouvrir<text:s text:c="2"/>le ticket<office:annotation>
+            <text:p>À revoir</text:p></office:annotation>.</text:p>
+        <text:list>
+            <text:list-item><text:p>Qualifier</text:p></text:list-item>
+            <text:list-item><text:p>Assigner</text:p>
+                <text:list><text:list-item><text:p>Niveau 2</text:p></text:list-item></text:list>
+            </text:list-item>
+        </text:list>
+        <text:h text:outline-level="2"><text:soft-page-break/>Délais</text:h>
+        <table:table>
+            <table:table-row><table:table-cell><text:p>Priorité</text:p></table:table-cell>
+                <table:table-cell><text:p>Délai</text:p></table:table-cell></table:table-row>
+            <table:table-row><table:table-cell><text:p>P1 | urgent</text:p></table:table-cell>
+                <table:table-cell><text:p>4 h</text:p></table:table-cell></table:table-row>
+        </table:table>
+    </office:text></office:body></office:document-content>"#;
+    const META: &str = r#"<office:document-meta xmlns:office="o" xmlns:meta="m" xmlns:dc="dc"><office:meta>
+        <dc:title>Support</dc:title><meta:initial-creator>Équipe support</meta:initial-creator>
+        <dc:language>fr-FR</dc:language>
+    </office:meta></office:document-meta>"#;
+
+    #[test]
+    fn content_is_rendered_as_markdown_split_at_page_breaks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("support.odt");
+        write_zip(&path, &[("content.xml", CONTENT), ("meta.xml", META)]);
+
+        let document = OdtLoader.load_document(&path).unwrap();
+        assert!(document.markdown);
+        let texts: Vec<&str> = document.sections.iter().map(|section| section.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "# Procédure\n\nOuvrir le ticket.\n\n- Qualifier\n- Assigner\n  - Niveau 2",
+                "## Délais\n\n| Priorité | Délai |\n| P1 \\| urgent | 4 h |",
+            ]
+        );
+        assert_eq!(document.sections[0].location["page"], 1);
+        assert_eq!(document.sections[1].location["page"], 2);
+        assert_eq!(document.metadata["title"], "Support");
+        assert_eq!(document.metadata["author"], "Équipe support");
+        assert_eq!(document.metadata["language"], "fr-FR");
+    }
+
+    #[test]
+    fn documents_without_page_breaks_have_no_page_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("note.odt");
+        let content = r#"<office:document-content xmlns:office="o" xmlns:text="t">
+            <office:body><office:text><text:p>Une seule page</text:p></office:text></office:body>
+        </office:document-content>"#;
+        write_zip(&path, &[("content.xml", content)]);
+
+        let document = OdtLoader.load_document(&path).unwrap();
+        assert_eq!(document.sections.len(), 1);
+        assert_eq!(document.sections[0].text, "Une seule page");
+        assert!(document.sections[0].location.is_empty());
+        assert!(document.metadata.is_empty());
+    }
+}
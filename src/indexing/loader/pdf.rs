@@ -0,0 +1,933 @@
+//! PDF loader with page-aware extraction.
+//!
+//! The text of each page is extracted separately with pdf-extract, so that
+//! every chunk carries the number of the page it comes from. The text is then
+//! cleaned up: lines repeated at the top or bottom of most pages (running
+//! headers, footers, page numbers) are removed, and words hyphenated at the
+//! end of a line are rejoined (compound words keep their hyphen). The title and author are read from the
+//! document information dictionary.
+//!
+//! Scanned pages have no text layer, or just a few characters: when OCR is
+//! enabled, the images of the pages with fewer than `min_page_chars`
+//! characters are extracted from the PDF and recognized with the OCR engine,
+//! and the recognized text replaces the extracted one when it is longer.
+//! Images are extracted as they are stored: JPEG and JPEG 2000 as-is, CCITT
+//! fax (the usual encoding of black and white scans) wrapped in a TIFF
+//! header, and uncompressed or Flate pixels as PNM. Other encodings (JBIG2,
+//! indexed colors) are skipped.
+//!
+//! pdf-extract can panic or spin for a long time on malformed documents: the
+//! extraction runs on a dedicated thread under `catch_unwind`, files larger
+//! than `pdf_max_size_mb` are rejected, and the extraction is abandoned after
+//! `pdf_timeout_secs`. An abandoned thread cannot be killed and keeps running
+//! until pdf-extract returns: once `MAX_ABANDONED_EXTRACTIONS` of them are
+//! still running, the next PDF files fail right away instead of piling up
+//! more stuck threads.
+//!
+//! A PDF without any text when OCR is disabled (a scanned document) is
+//! reported as unsupported rather than failed: it is skipped until modified.
+
+use pdf_extract::{Dictionary, Document, Object, ObjectId, PlainTextOutput, Stream, decode_text_string, output_doc_page};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::AppError;
+use crate::IndexingConfig;
+use crate::indexing::loader::ocr::{OcrEngine, ocr_engine};
+use crate::indexing::loader::{DocumentLoader, DocumentSection, LoadedDocument};
+
+/// Number of non-empty lines at the top and at the bottom of a page searched for headers and footers
+const EDGE_LINES: usize = 3;
+
+/// Minimum number of pages for header and footer detection
+const MIN_PAGES_FOR_EDGES: usize = 3;
+
+/// Minimum width and height of a page image to be recognized, smaller ones are logos or icons
+const MIN_OCR_IMAGE_SIZE: i64 = 100;
+
+/// Maximum number of timed out extractions still running before new extractions fail fast
+const MAX_ABANDONED_EXTRACTIONS: usize = 4;
+
+/// Number of timed out extractions whose thread is still running
+static ABANDONED_EXTRACTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Reason given for a PDF without text when OCR is disabled
+const NO_TEXT_WITHOUT_OCR: &str = "No text found in the PDF file (scanned document? enable [indexing.ocr])";
+
+/// Loader for PDF files
+pub struct PdfLoader {
+    /// Maximum size of a PDF file, in bytes
+    max_size: u64,
+    /// Maximum duration of the extraction of a PDF file
+    timeout: Duration,
+    /// OCR engine for the pages without text layer, None if OCR is disabled
+    ocr: Option<Arc<dyn OcrEngine>>,
+    /// Pages with fewer non-whitespace characters are recognized with OCR
+    min_page_chars: usize,
+}
+
+impl PdfLoader {
+    /// Creates a PDF loader with the limits of the indexing configuration
+    ///
+    /// # Arguments
+    /// * `config` - Indexing configuration holding `pdf_max_size_mb`, `pdf_timeout_secs` and the OCR settings
+    pub fn new(config: &IndexingConfig) -> Self {
+        PdfLoader {
+            max_size: config.pdf_max_size_mb.saturating_mul(1024 * 1024),
+            timeout: Duration::from_secs(config.pdf_timeout_secs),
+            ocr: ocr_engine(&config.ocr),
+            min_page_chars: config.ocr.min_page_chars,
+        }
+    }
+
+    /// Recognizes the text of the images of a page with OCR
+    ///
+    /// # Returns
+    /// * `Result<String, AppError>` - The text of the images, error if the OCR engine failed
+    fn recognize_page(&self, engine: &dyn OcrEngine, images: &[PageImage]) -> Result<String, AppError> {
+        let mut texts = Vec::new();
+        for image in images {
+            let mut file = tempfile::Builder::new()
+                .prefix("rag-rust-ocr-")
+                .suffix(image.extension)
+                .tempfile()?;
+            file.write_all(&image.content)?;
+            file.flush()?;
+            let text = engine.recognize(file.path())?;
+            if !text.trim().is_empty() {
+                texts.push(text.trim().to_string());
+            }
+        }
+        Ok(texts.join("\n\n"))
+    }
+}
+
+/// Text and metadata extracted from a PDF file
+struct ExtractedPdf {
+    /// Text of each page, with its page number
+    pages: Vec<(u32, String)>,
+    /// Images of the pages with little text, by page number, when OCR is enabled
+    images: HashMap<u32, Vec<PageImage>>,
+    metadata: Map<String, Value>,
+}
+
+/// An image of a page, in a format the OCR engine reads
+struct PageImage {
+    /// Extension of the image file (`.jpg`, `.tif`, `.pgm`...)
+    extension: &'static str,
+    content: Vec<u8>,
+}
+
+impl DocumentLoader for PdfLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let size = fs::metadata(path)?.len();
+        if size > self.max_size {
+            return Err(AppError::Pdf(format!(
+                "PDF file too large ({:.1} MB, limit {} MB)",
+                size as f64 / (1024.0 * 1024.0),
+                self.max_size / (1024 * 1024)
+            )));
+        }
+
+        let ocr_threshold = self.ocr.as_ref().map(|_| self.min_page_chars);
+        let ExtractedPdf {
+            pages,
+            images,
+            mut metadata,
+        } = extract_with_timeout(path.to_path_buf(), ocr_threshold, self.timeout)?;
+        let texts: Vec<String> = pages.iter().map(|(_, text)| rejoin_hyphenated(text)).collect();
+        let mut texts: Vec<(String, bool)> = remove_headers_and_footers(texts)
+            .into_iter()
+            .map(|text| (text, false))
+            .collect();
+
+        // Replace the text of scanned pages with the text recognized in their images
+        if let Some(engine) = &self.ocr {
+            for ((page, _), (text, ocr)) in pages.iter().zip(texts.iter_mut()) {
+                let Some(page_images) = images.get(page) else {
+                    continue;
+                };
+                let recognized = self.recognize_page(engine.as_ref(), page_images)?;
+                if non_whitespace_chars(&recognized) > non_whitespace_chars(text) {
+                    *text = recognized;
+                    *ocr = true;
+                }
+            }
+        }
+
+        let sections: Vec<DocumentSection> = pages
+            .iter()
+            .zip(texts)
+            .filter(|(_, (text, _))| !text.trim().is_empty())
+            .map(|((page, _), (text, ocr))| {
+                let mut location = Map::new();
+                location.insert("page".to_string(), (*page).into());
+                DocumentSection {
+                    text: text.trim().to_string(),
+                    location,
+                    ocr,
+                }
+            })
+            .collect();
+        if sections.is_empty() {
+            return Err(match self.ocr {
+                Some(_) => AppError::Pdf("No text found in the PDF file, even with OCR".to_string()),
+                None => AppError::UnsupportedFormat(NO_TEXT_WITHOUT_OCR.to_string()),
+            });
+        }
+
+        let ocr_pages = sections.iter().filter(|section| section.ocr).count();
+        if let Some(engine) = self.ocr.as_ref().filter(|_| ocr_pages > 0) {
+            info!("Recognized {} pages of '{}' with OCR", ocr_pages, path.display());
+            metadata.insert("ocr_engine".to_string(), engine.name().into());
+        }
+        metadata.insert("pages".to_string(), pages.len().into());
+        Ok(LoadedDocument {
+            sections,
+            markdown: false,
+            code: None,
+            metadata,
+        })
+    }
+}
+
+/// Extracts a PDF file on a dedicated thread, giving up after a timeout
+///
+/// A timed out extraction cannot be interrupted: its thread is detached and
+/// keeps running until pdf-extract returns, but the file is reported as
+/// failed right away. While `MAX_ABANDONED_EXTRACTIONS` detached threads are
+/// still running, no new extraction is started and the file fails at once.
+fn extract_with_timeout(
+    path: PathBuf,
+    ocr_threshold: Option<usize>,
+    timeout: Duration,
+) -> Result<ExtractedPdf, AppError> {
+    run_with_timeout(timeout, move || extract_pages(&path, ocr_threshold))
+}
+
+/// Runs an extraction on a dedicated thread, giving up after a timeout
+///
+/// See `extract_with_timeout`: panics are caught, and timed out extractions
+/// are counted in `ABANDONED_EXTRACTIONS` until their thread returns.
+fn run_with_timeout<T, F>(timeout: Duration, extract: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, AppError> + Send + std::panic::UnwindSafe + 'static,
+{
+    let abandoned = ABANDONED_EXTRACTIONS.load(Ordering::SeqCst);
+    if abandoned >= MAX_ABANDONED_EXTRACTIONS {
+        return Err(AppError::Pdf(format!(
+            "PDF extraction not started: {} timed out extractions are still running",
+            abandoned
+        )));
+    }
+
+    // Set by whichever of the extraction thread (done) and the caller (timed out) comes first
+    let settled = Arc::new(AtomicBool::new(false));
+    let thread_settled = settled.clone();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("pdf-extract".to_string())
+        .spawn(move || {
+            let result = std::panic::catch_unwind(extract);
+            if thread_settled.swap(true, Ordering::SeqCst) {
+                // The caller gave up on this extraction and counted it as abandoned
+                ABANDONED_EXTRACTIONS.fetch_sub(1, Ordering::SeqCst);
+            }
+            let _ = sender.send(result);
+        })
+        .map_err(|e| AppError::Pdf(format!("Failed to start PDF extraction: {}", e)))?;
+
+    let result = receiver.recv_timeout(timeout);
+    if matches!(result, Err(mpsc::RecvTimeoutError::Timeout)) {
+        ABANDONED_EXTRACTIONS.fetch_add(1, Ordering::SeqCst);
+        if settled.swap(true, Ordering::SeqCst) {
+            // The extraction finished in the meantime
+            ABANDONED_EXTRACTIONS.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+    match result {
+        Ok(Ok(result)) => result,
+        Ok(Err(_)) => {
+            warn!("PDF extraction panicked");
+            Err(AppError::Pdf("PDF extraction panicked".to_string()))
+        }
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(AppError::Pdf(format!(
+            "PDF extraction timed out after {} s",
+            timeout.as_secs()
+        ))),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(AppError::Pdf("PDF extraction panicked".to_string())),
+    }
+}
+
+/// Extracts the text of each page and the metadata of a PDF file
+///
+/// # Arguments
+/// * `path` - Path of the PDF file
+/// * `ocr_threshold` - Minimum number of characters of a page below which its images are extracted, None without OCR
+fn extract_pages(path: &Path, ocr_threshold: Option<usize>) -> Result<ExtractedPdf, AppError> {
+    let mut document =
+        Document::load(path).map_err(|e| AppError::Pdf(format!("Failed to open PDF file: {}", e)))?;
+    if document.is_encrypted() {
+        document
+            .decrypt("")
+            .map_err(|e| AppError::Pdf(format!("Encrypted PDF file: {}", e)))?;
+    }
+
+    let mut pages = Vec::new();
+    let mut images = HashMap::new();
+    for (page, page_id) in document.get_pages() {
+        let mut text = String::new();
+        if let Err(e) = output_doc_page(&document, &mut PlainTextOutput::new(&mut text), page) {
+            warn!("Failed to extract page {} of PDF file: {}", page, e);
+            text.clear();
+        }
+        if let Some(threshold) = ocr_threshold
+            && non_whitespace_chars(&text) < threshold
+        {
+            let page_images = page_images(&document, page_id);
+            if !page_images.is_empty() {
+                images.insert(page, page_images);
+            }
+        }
+        pages.push((page, text));
+    }
+    if images.is_empty() && pages.iter().all(|(_, text)| text.trim().is_empty()) {
+        return Err(match ocr_threshold {
+            Some(_) => AppError::Pdf("No text nor image found in the PDF file".to_string()),
+            None => AppError::UnsupportedFormat(NO_TEXT_WITHOUT_OCR.to_string()),
+        });
+    }
+
+    Ok(ExtractedPdf {
+        pages,
+        images,
+        metadata: info_metadata(&document),
+    })
+}
+
+/// Counts the non-whitespace characters of a text
+fn non_whitespace_chars(text: &str) -> usize {
+    text.chars().filter(|c| !c.is_whitespace()).count()
+}
+
+/// Extracts the images drawn on a page that OCR can read
+fn page_images(document: &Document, page_id: ObjectId) -> Vec<PageImage> {
+    let Ok((resources, resource_ids)) = document.get_page_resources(page_id) else {
+        return Vec::new();
+    };
+    let dictionaries = resources
+        .into_iter()
+        .chain(resource_ids.iter().filter_map(|id| document.get_dictionary(*id).ok()));
+
+    let mut images = Vec::new();
+    for resources in dictionaries {
+        let Ok(xobjects) = resources.get_deref(b"XObject", document).and_then(Object::as_dict) else {
+            continue;
+        };
+        for (_, xobject) in xobjects.iter() {
+            let Ok((_, Object::Stream(stream))) = document.dereference(xobject) else {
+                continue;
+            };
+            if stream.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image")
+                && let Some(image) = convert_image(document, stream)
+            {
+                images.push(image);
+            }
+        }
+    }
+    images
+}
+
+/// Converts an image XObject to a file format the OCR engine reads
+///
+/// # Returns
+/// * `Option<PageImage>` - The image, None if it is too small or its encoding is not supported
+fn convert_image(document: &Document, stream: &Stream) -> Option<PageImage> {
+    let dict = &stream.dict;
+    let width = dict.get_deref(b"Width", document).and_then(Object::as_i64).ok()?;
+    let height = dict.get_deref(b"Height", document).and_then(Object::as_i64).ok()?;
+    if width < MIN_OCR_IMAGE_SIZE || height < MIN_OCR_IMAGE_SIZE {
+        return None;
+    }
+
+    let filters: Vec<&[u8]> = stream.filters().unwrap_or_default();
+    match filters.as_slice() {
+        [b"DCTDecode"] => Some(PageImage {
+            extension: ".jpg",
+            content: stream.content.clone(),
+        }),
+        [b"JPXDecode"] => Some(PageImage {
+            extension: ".jp2",
+            content: stream.content.clone(),
+        }),
+        [b"CCITTFaxDecode"] => {
+            let params = dict
+                .get_deref(b"DecodeParms", document)
+                .and_then(Object::as_dict)
+                .ok();
+            Some(PageImage {
+                extension: ".tif",
+                content: ccitt_to_tiff(&stream.content, width as u32, height as u32, params),
+            })
+        }
+        _ => {
+            let pixels = if filters.is_empty() {
+                stream.content.clone()
+            } else {
+                stream.decompressed_content().ok()?
+            };
+            pixels_to_pnm(document, dict, &pixels, width as usize, height as usize)
+        }
+    }
+}
+
+/// Wraps the pixels of an image in a PNM file (PBM, PGM or PPM)
+///
+/// Grayscale, RGB and CMYK (converted to RGB) images with 8 bits per
+/// component are supported, and black and white images with 1 bit.
+fn pixels_to_pnm(
+    document: &Document,
+    dict: &Dictionary,
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+) -> Option<PageImage> {
+    let image_mask = dict.get(b"ImageMask").and_then(Object::as_bool).unwrap_or(false);
+    let bits = if image_mask {
+        1
+    } else {
+        dict.get_deref(b"BitsPerComponent", document).and_then(Object::as_i64).ok()?
+    };
+    let components = if image_mask { 1 } else { color_components(document, dict)? };
+
+    if bits == 1 && components == 1 {
+        // PDF uses 0 for black, PBM 1, unless the decode array is inverted
+        let inverted = dict
+            .get_deref(b"Decode", document)
+            .and_then(Object::as_array)
+            .is_ok_and(|decode| decode.first().and_then(|first| first.as_float().ok()) == Some(1.0));
+        let row = width.div_ceil(8);
+        let pixels = pixels.get(..row * height)?;
+        let mut content = format!("P4\n{} {}\n", width, height).into_bytes();
+        content.extend(pixels.iter().map(|byte| if inverted { *byte } else { !byte }));
+        return Some(PageImage {
+            extension: ".pbm",
+            content,
+        });
+    }
+    if bits != 8 {
+        return None;
+    }
+
+    let pixels = pixels.get(..width * height * components)?;
+    let (extension, magic, content) = match components {
+        1 => (".pgm", "P5", pixels.to_vec()),
+        3 => (".ppm", "P6", pixels.to_vec()),
+        4 => (
+            ".ppm",
+            "P6",
+            pixels
+                .chunks_exact(4)
+                .flat_map(|cmyk| {
+                    let black = 255 - u16::from(cmyk[3]);
+                    cmyk[..3].iter().map(move |ink| ((255 - u16::from(*ink)) * black / 255) as u8)
+                })
+                .collect(),
+        ),
+        _ => return None,
+    };
+    let mut file = format!("{}\n{} {}\n255\n", magic, width, height).into_bytes();
+    file.extend(content);
+    Some(PageImage {
+        extension,
+        content: file,
+    })
+}
+
+/// Gets the number of color components of an image, None for indexed or unknown color spaces
+fn color_components(document: &Document, dict: &Dictionary) -> Option<usize> {
+    let color_space = dict.get_deref(b"ColorSpace", document).ok()?;
+    let (name, icc_profile) = match color_space {
+        Object::Name(name) => (name.as_slice(), None),
+        Object::Array(array) => (array.first()?.as_name().ok()?, array.get(1)),
+        _ => return None,
+    };
+    match name {
+        b"DeviceGray" | b"CalGray" => Some(1),
+        b"DeviceRGB" | b"CalRGB" => Some(3),
+        b"DeviceCMYK" => Some(4),
+        b"ICCBased" => {
+            let (_, profile) = document.dereference(icc_profile?).ok()?;
+            let components = profile.as_stream().ok()?.dict.get(b"N").and_then(Object::as_i64).ok()?;
+            usize::try_from(components).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Wraps CCITT fax encoded data in a single-strip TIFF file
+///
+/// # Arguments
+/// * `data` - CCITT encoded data of the image
+/// * `width` - Width of the image, used when the parameters have no `Columns`
+/// * `height` - Height of the image, used when the parameters have no `Rows`
+/// * `params` - `DecodeParms` of the image (`K`, `Columns`, `Rows`, `BlackIs1`)
+fn ccitt_to_tiff(data: &[u8], width: u32, height: u32, params: Option<&Dictionary>) -> Vec<u8> {
+    let param = |key: &[u8]| params.and_then(|params| params.get(key).ok());
+    let k = param(b"K").and_then(|k| k.as_i64().ok()).unwrap_or(0);
+    let columns = param(b"Columns")
+        .and_then(|columns| columns.as_i64().ok())
+        .map_or(width, |columns| columns as u32);
+    let rows = param(b"Rows")
+        .and_then(|rows| rows.as_i64().ok())
+        .filter(|rows| *rows > 0)
+        .map_or(height, |rows| rows as u32);
+    let black_is_1 = param(b"BlackIs1").and_then(|value| value.as_bool().ok()).unwrap_or(false);
+
+    // Group 4 (K < 0), or group 3 with 2D coding (K > 0) or 1D coding (K = 0)
+    let (compression, options_tag, options) = match k {
+        k if k < 0 => (4u16, 293u16, 0u32),
+        k if k > 0 => (3, 292, 1),
+        _ => (3, 292, 0),
+    };
+    let entries: [(u16, u16, u32); 10] = [
+        (256, 4, columns),                   // ImageWidth
+        (257, 4, rows),                      // ImageLength
+        (258, 3, 1),                         // BitsPerSample
+        (259, 3, u32::from(compression)),    // Compression
+        (262, 3, u32::from(black_is_1)),     // PhotometricInterpretation
+        (273, 4, 0),                         // StripOffsets, set below
+        (277, 3, 1),                         // SamplesPerPixel
+        (278, 4, rows),                      // RowsPerStrip
+        (279, 4, data.len() as u32),         // StripByteCounts
+        (options_tag, 4, options),           // T4Options or T6Options
+    ];
+
+    let data_offset = 8 + 2 + entries.len() as u32 * 12 + 4;
+    let mut tiff = Vec::with_capacity(data_offset as usize + data.len());
+    tiff.extend_from_slice(b"II*\x00");
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    tiff.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, field_type, value) in entries {
+        let value = if tag == 273 { data_offset } else { value };
+        tiff.extend_from_slice(&tag.to_le_bytes());
+        tiff.extend_from_slice(&field_type.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        if field_type == 3 {
+            tiff.extend_from_slice(&(value as u16).to_le_bytes());
+            tiff.extend_from_slice(&[0, 0]);
+        } else {
+            tiff.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(data);
+    tiff
+}
+
+/// Reads the title and author from the document information dictionary
+fn info_metadata(document: &Document) -> Map<String, Value> {
+    let mut metadata = Map::new();
+    let Ok(info) = document
+        .trailer
+        .get_deref(b"Info", document)
+        .and_then(Object::as_dict)
+    else {
+        return metadata;
+    };
+
+    for (field, key) in [(&b"Title"[..], "title"), (&b"Author"[..], "author")] {
+        if let Ok(value) = info.get_deref(field, document).and_then(decode_text_string) {
+            let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+            if !value.is_empty() {
+                metadata.insert(key.to_string(), value.into());
+            }
+        }
+    }
+    metadata
+}
+
+/// Rejoins the words hyphenated at the end of a line
+///
+/// A hyphen between a lowercase letter and a line starting with a lowercase
+/// letter is a line break inside a word; soft hyphens always are. The blank
+/// lines pdf-extract may insert between the two lines are dropped. Genuine
+/// compound words split at their hyphen (see `is_compound`) are rejoined
+/// with their hyphen kept.
+fn rejoin_hyphenated(text: &str) -> String {
+    let compounds = inline_compounds(text);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut result = String::with_capacity(text.len());
+    let mut index = 0;
+    let mut continued = false;
+    while index < lines.len() {
+        let line = if continued { lines[index].trim() } else { lines[index].trim_end() };
+        let next = (index + 1..lines.len()).find(|next| !lines[*next].trim().is_empty());
+        let next_line = next.map(|next| lines[next].trim_start());
+        let next_start = next_line.and_then(|next_line| next_line.chars().next());
+
+        let hyphenated = next_start.and_then(|next_start| {
+            if let Some(word_start) = line.strip_suffix('\u{ad}') {
+                return Some(word_start);
+            }
+            let word_start = line
+                .strip_suffix('-')
+                .filter(|word| word.chars().last().is_some_and(char::is_lowercase) && next_start.is_lowercase())?;
+            let prefix = word_start.rsplit(char::is_whitespace).next().unwrap_or(word_start);
+            let suffix = next_line.and_then(|next_line| next_line.split_whitespace().next()).unwrap_or("");
+            Some(if is_compound(prefix, suffix, &compounds) { line } else { word_start })
+        });
+        continued = hyphenated.is_some();
+        match (hyphenated, next) {
+            (Some(word_start), Some(next)) => {
+                result.push_str(word_start);
+                index = next;
+            }
+            _ => {
+                result.push_str(line);
+                result.push('\n');
+                index += 1;
+            }
+        }
+    }
+    result
+}
+
+/// First parts of common compound words, whose hyphen is kept at a line break
+const COMPOUND_PREFIXES: &[&str] = &[
+    "all", "anti", "cross", "ex", "full", "half", "high", "long", "low", "multi", "non", "post", "pre", "self",
+    "semi", "short", "so", "state", "well", "wide", "après", "arrière", "avant", "contre", "demi", "grand",
+    "mi", "sans", "sous", "vice",
+];
+
+/// Last parts of hyphenated forms that are never a single word (French inverted pronouns...)
+const COMPOUND_SUFFIXES: &[&str] = &[
+    "ce", "ci", "elle", "elles", "en", "il", "ils", "je", "là", "lui", "moi", "même", "mêmes", "nous", "on", "t",
+    "toi", "tu", "vous", "y",
+];
+
+/// Tells whether a word split at a hyphen at the end of a line is a genuine compound word
+///
+/// The word is a compound when the page uses the same hyphenated form on a
+/// single line, when its first part is already hyphenated (`state-of-the-art`)
+/// or when one of its parts is a usual member of compound words.
+///
+/// # Arguments
+/// * `prefix` - Part of the word before the hyphen
+/// * `suffix` - Part of the word after the line break
+/// * `compounds` - Hyphenated words found inside the lines of the page, lowercase
+fn is_compound(prefix: &str, suffix: &str, compounds: &HashSet<String>) -> bool {
+    let prefix = prefix.trim_start_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+    let suffix = suffix.trim_end_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+    prefix.contains('-')
+        || compounds.contains(&format!("{}-{}", prefix, suffix))
+        || COMPOUND_PREFIXES.contains(&prefix.rsplit('\'').next().unwrap_or(&prefix))
+        || COMPOUND_SUFFIXES.contains(&suffix.as_str())
+}
+
+/// Gets the hyphenated words written inside the lines of a page, lowercase
+fn inline_compounds(text: &str) -> HashSet<String> {
+    text.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.contains('-'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Removes the lines repeated at the top or bottom of most pages
+///
+/// Lines are compared with their digits ignored, so that running headers
+/// with a page number (`Page 3 of 12`) are detected as well.
+fn remove_headers_and_footers(pages: Vec<String>) -> Vec<String> {
+    let content_pages = pages.iter().filter(|page| !page.trim().is_empty()).count();
+    if content_pages < MIN_PAGES_FOR_EDGES {
+        return pages;
+    }
+
+    // Count, for each normalized line, the pages having it at their top or bottom
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for page in &pages {
+        let mut edge_lines: Vec<String> = edge_lines(page).into_iter().map(|(_, line)| line).collect();
+        edge_lines.sort();
+        edge_lines.dedup();
+        for line in edge_lines {
+            *counts.entry(line).or_default() += 1;
+        }
+    }
+    let threshold = content_pages.div_ceil(2).max(2);
+
+    pages
+        .into_iter()
+        .map(|page| {
+            let repeated: Vec<usize> = edge_lines(&page)
+                .into_iter()
+                .filter(|(_, line)| counts.get(line).is_some_and(|count| *count >= threshold))
+                .map(|(index, _)| index)
+                .collect();
+            if repeated.is_empty() {
+                return page;
+            }
+            page.lines()
+                .enumerate()
+                .filter(|(index, _)| !repeated.contains(index))
+                .map(|(_, line)| line)
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect()
+}
+
+/// Gets the first and last non-empty lines of a page, normalized, with their line index
+fn edge_lines(page: &str) -> Vec<(usize, String)> {
+    let lines: Vec<(usize, &str)> = page
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+    let top = lines.iter().take(EDGE_LINES);
+    let bottom = lines.iter().skip(EDGE_LINES).rev().take(EDGE_LINES);
+    top.chain(bottom)
+        .map(|(index, line)| (*index, normalize_line(line)))
+        .collect()
+}
+
+/// Normalizes a line for comparison: whitespace collapsed, digits replaced, lowercase
+fn normalize_line(line: &str) -> String {
+    line.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .map(|c| if c.is_ascii_digit() { '#' } else { c })
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf_extract::{Stream, dictionary};
+    use std::sync::Mutex;
+
+    /// OCR engine returning a fixed text, recording the images it was given
+    struct FakeOcr {
+        text: &'static str,
+        images: Mutex<Vec<String>>,
+    }
+
+    impl OcrEngine for FakeOcr {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn recognize(&self, image: &Path) -> Result<String, AppError> {
+            let extension = image.extension().unwrap_or_default().to_string_lossy().into_owned();
+            self.images.lock().unwrap().push(extension);
+            Ok(self.text.to_string())
+        }
+    }
+
+    /// Writes a PDF file whose pages hold the given text and, if set, a scanned image
+    fn write_pdf(path: &Path, pages: &[(&str, bool)]) {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let font_id = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+        let image_id = document.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 200,
+                "Height" => 200,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+                "Filter" => "DCTDecode",
+            },
+            b"\xff\xd8\xff\xe0 scanned page".to_vec(),
+        ));
+
+        let mut kids = Vec::new();
+        for (text, scanned) in pages {
+            let mut content = String::new();
+            if !text.is_empty() {
+                content.push_str(&format!("BT /F1 12 Tf 72 720 Td ({}) Tj ET\n", text));
+            }
+            if *scanned {
+                content.push_str("q 200 0 0 200 72 400 cm /Im1 Do Q\n");
+            }
+            let mut resources = dictionary! { "Font" => dictionary! { "F1" => font_id } };
+            if *scanned {
+                resources.set("XObject", dictionary! { "Im1" => image_id });
+            }
+            let content_id = document.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+            let page_id = document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+                "Contents" => content_id,
+                "Resources" => resources,
+            });
+            kids.push(page_id.into());
+        }
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Count" => kids.len() as i64,
+                "Kids" => kids,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+        document.save(path).unwrap();
+    }
+
+    fn loader(ocr: Option<Arc<dyn OcrEngine>>) -> PdfLoader {
+        PdfLoader {
+            max_size: 10 * 1024 * 1024,
+            timeout: Duration::from_secs(30),
+            ocr,
+            min_page_chars: 20,
+        }
+    }
+
+    const BODIES: [&str; 6] = [
+        "Revenue grew in every region",
+        "Costs were kept under control",
+        "The board approved the dividend",
+        "Hiring slowed in the second half",
+        "New offices opened in Lyon",
+        "Outlook for next year is stable",
+    ];
+
+    /// Pages with a header, a numbered footer and a body line of their own
+    fn pages(count: usize) -> Vec<String> {
+        (1..=count)
+            .map(|page| format!("ACME Annual Report\n{}\nPage {} of {}", BODIES[page - 1], page, count))
+            .collect()
+    }
+
+    #[test]
+    fn repeated_headers_and_numbered_footers_are_removed() {
+        let cleaned = remove_headers_and_footers(pages(4));
+        assert_eq!(cleaned[0], BODIES[0]);
+        assert_eq!(cleaned[3], BODIES[3]);
+    }
+
+    #[test]
+    fn lines_repeated_on_few_pages_are_kept() {
+        let mut pages: Vec<String> = BODIES.iter().map(|body| body.to_string()).collect();
+        for page in &mut pages[..2] {
+            *page = format!("Warning: draft\n{}", page);
+        }
+        let cleaned = remove_headers_and_footers(pages.clone());
+        assert_eq!(cleaned, pages);
+    }
+
+    #[test]
+    fn short_documents_are_not_cleaned() {
+        let pages = pages(2);
+        assert_eq!(remove_headers_and_footers(pages.clone()), pages);
+    }
+
+    #[test]
+    fn words_hyphenated_at_a_line_end_are_rejoined() {
+        assert_eq!(rejoin_hyphenated("the docu-\nment is long\n"), "the document is long\n");
+        assert_eq!(rejoin_hyphenated("a soft\u{ad}\n\nhyphen\n"), "a softhyphen\n");
+        // A capitalized next line is not the end of the word
+        assert_eq!(rejoin_hyphenated("Paris-\nLondon\n"), "Paris-\nLondon\n");
+    }
+
+    #[test]
+    fn compound_words_keep_their_hyphen() {
+        assert_eq!(rejoin_hyphenated("a well-\nknown issue\n"), "a well-known issue\n");
+        assert_eq!(rejoin_hyphenated("state-of-\nthe-art\n"), "state-of-the-art\n");
+        assert_eq!(rejoin_hyphenated("Que dit-\nil ?\n"), "Que dit-il ?\n");
+        // The page writes the same compound on a single line elsewhere
+        assert_eq!(
+            rejoin_hyphenated("a cache-\naware design, cache-aware\n"),
+            "a cache-aware design, cache-aware\n"
+        );
+    }
+
+    #[test]
+    fn extractions_are_abandoned_after_the_timeout() {
+        // Single test touching the global counter of abandoned extractions
+        let panicked = run_with_timeout(Duration::from_secs(5), || -> Result<(), AppError> { panic!("malformed") });
+        assert!(panicked.unwrap_err().to_string().contains("panicked"));
+
+        let slow = run_with_timeout(Duration::from_millis(20), || {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(())
+        });
+        assert!(slow.unwrap_err().to_string().contains("timed out"));
+        assert_eq!(ABANDONED_EXTRACTIONS.load(Ordering::SeqCst), 1);
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(ABANDONED_EXTRACTIONS.load(Ordering::SeqCst), 0);
+
+        ABANDONED_EXTRACTIONS.store(MAX_ABANDONED_EXTRACTIONS, Ordering::SeqCst);
+        let refused = run_with_timeout(Duration::from_secs(5), || Ok(()));
+        ABANDONED_EXTRACTIONS.store(0, Ordering::SeqCst);
+        assert!(refused.unwrap_err().to_string().contains("not started"));
+    }
+
+    #[test]
+    fn pages_with_little_text_are_recognized_from_their_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.pdf");
+        write_pdf(
+            &path,
+            &[("Le rapport annuel est en ligne", true), ("Annexe", true), ("", true), ("12", false)],
+        );
+        let engine = Arc::new(FakeOcr {
+            text: "Texte reconnu sur la page",
+            images: Mutex::new(Vec::new()),
+        });
+
+        let document = loader(Some(engine.clone())).load_document(&path).unwrap();
+        let pages: Vec<(&str, &Value, bool)> = document
+            .sections
+            .iter()
+            .map(|section| (section.text.as_str(), &section.location["page"], section.ocr))
+            .collect();
+        assert_eq!(
+            pages,
+            [
+                ("Le rapport annuel est en ligne", &1.into(), false),
+                ("Texte reconnu sur la page", &2.into(), true),
+                ("Texte reconnu sur la page", &3.into(), true),
+                ("12", &4.into(), false),
+            ]
+        );
+        // Only the images of the pages below `min_page_chars` went through OCR
+        assert_eq!(*engine.images.lock().unwrap(), ["jpg", "jpg"]);
+        assert_eq!(document.metadata["ocr_engine"], "fake");
+        assert_eq!(document.metadata["pages"], 4);
+    }
+
+    #[test]
+    fn scanned_pdfs_are_unsupported_without_ocr() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.pdf");
+        write_pdf(&path, &[("", true)]);
+        let error = loader(None).load_document(&path).unwrap_err();
+        assert!(matches!(error, AppError::UnsupportedFormat(_)), "{:?}", error);
+    }
+}
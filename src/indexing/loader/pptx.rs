@@ -0,0 +1,269 @@
+//! PPTX loader for slide decks.
+//!
+//! Each slide becomes a section holding its title, the text of its shapes
+//! and tables, and its speaker notes. Slides are read in presentation order
+//! and their number (and title, if any) is the location of the section.
+
+use serde_json::Map;
+use std::path::Path;
+use tracing::warn;
+
+use crate::AppError;
+use crate::indexing::loader::xml::{self, Archive, XmlEvent};
+use crate::indexing::loader::{DocumentLoader, DocumentSection, LoadedDocument};
+
+/// Relationship type of the slides of a presentation
+const SLIDE_RELATIONSHIP: &str = "/slide";
+/// Relationship type of the notes of a slide
+const NOTES_RELATIONSHIP: &str = "/notesSlide";
+
+/// Placeholders holding the title of a slide
+const TITLE_PLACEHOLDERS: [&str; 2] = ["title", "ctrTitle"];
+/// Placeholders holding no content (slide number, date, footer, slide image)
+const IGNORED_PLACEHOLDERS: [&str; 5] = ["sldNum", "dt", "ftr", "hdr", "sldImg"];
+
+/// Loader for PowerPoint presentations
+pub struct PptxLoader;
+
+impl DocumentLoader for PptxLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let mut archive = xml::open_archive(path)?;
+        let slide_paths = slide_paths(&mut archive)?;
+
+        let mut sections = Vec::new();
+        for (index, slide_path) in slide_paths.iter().enumerate() {
+            let slide = read_paragraphs(&mut archive, slide_path)?;
+            let notes = xml::relationships(&mut archive, slide_path)
+                .into_values()
+                .find(|(kind, _)| kind.ends_with(NOTES_RELATIONSHIP))
+                .map(|(_, notes_path)| {
+                    read_paragraphs(&mut archive, &notes_path).unwrap_or_else(|e| {
+                        warn!("Failed to read the notes of slide {}: {}", index + 1, e);
+                        Vec::new()
+                    })
+                })
+                .unwrap_or_default();
+
+            let title = slide
+                .iter()
+                .filter(|(placeholder, _)| placeholder.as_deref().is_some_and(|p| TITLE_PLACEHOLDERS.contains(&p)))
+                .map(|(_, text)| text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let body = content_lines(&slide, true);
+            let notes = content_lines(&notes, false);
+            if title.is_empty() && body.is_empty() && notes.is_empty() {
+                continue;
+            }
+
+            let mut text = Vec::new();
+            if !title.is_empty() {
+                text.push(title.clone());
+            }
+            text.extend(body);
+            if !notes.is_empty() {
+                text.push(format!("\nSpeaker notes:\n{}", notes.join("\n")));
+            }
+
+            let mut location = Map::new();
+            location.insert("slide".to_string(), (index + 1).into());
+            if !title.is_empty() {
+                location.insert("slide_title".to_string(), title.into());
+            }
+            sections.push(DocumentSection {
+                text: text.join("\n"),
+                location,
+                ocr: false,
+            });
+        }
+
+        let mut metadata = xml::read_entry(&mut archive, "docProps/core.xml")
+            .and_then(|content| xml::parse_xml(&content))
+            .map(|events| xml::text_metadata(&events, &[("title", "title"), ("creator", "author")]))
+            .unwrap_or_default();
+        metadata.insert("slides".to_string(), slide_paths.len().into());
+        Ok(LoadedDocument {
+            sections,
+            markdown: false,
+            code: None,
+            metadata,
+        })
+    }
+}
+
+/// Gets the paths of the slides, in presentation order
+fn slide_paths(archive: &mut Archive) -> Result<Vec<String>, AppError> {
+    let presentation_path = "ppt/presentation.xml";
+    let presentation = xml::parse_xml(&xml::read_entry(archive, presentation_path)?)?;
+    let relationships = xml::relationships(archive, presentation_path);
+
+    let slide_paths: Vec<String> = presentation
+        .iter()
+        .filter_map(|event| match event {
+            XmlEvent::Start { name, attributes } if name == "sldId" => attributes.get("r:id"),
+            _ => None,
+        })
+        .filter_map(|id| relationships.get(id))
+        .filter(|(kind, _)| kind.ends_with(SLIDE_RELATIONSHIP))
+        .map(|(_, path)| path.clone())
+        .collect();
+
+    if slide_paths.is_empty() {
+        return Err(AppError::Document("No slide found in the presentation".to_string()));
+    }
+    Ok(slide_paths)
+}
+
+/// Reads the paragraphs of a slide or notes part, with the placeholder type of their shape
+fn read_paragraphs(archive: &mut Archive, part_path: &str) -> Result<Vec<(Option<String>, String)>, AppError> {
+    let events = xml::parse_xml(&xml::read_entry(archive, part_path)?)?;
+
+    let mut paragraphs = Vec::new();
+    let mut placeholder: Option<String> = None;
+    let mut paragraph = String::new();
+    let mut in_text = false;
+    let mut in_table_cell = false;
+    // Depth inside fields (slide numbers, dates), whose text is skipped
+    let mut in_field = 0;
+
+    for event in events {
+        match event {
+            XmlEvent::Start { name, attributes } => match name.as_str() {
+                "sp" => placeholder = None,
+                // A placeholder without type holds the body of the slide
+                "ph" => placeholder = Some(attributes.get("type").cloned().unwrap_or_else(|| "body".to_string())),
+                "t" => in_text = true,
+                "tc" => in_table_cell = true,
+                "fld" => in_field += 1,
+                "br" => paragraph.push('\n'),
+                "tab" => paragraph.push('\t'),
+                _ => {}
+            },
+            XmlEvent::End { name } => match name.as_str() {
+                "sp" => placeholder = None,
+                "t" => in_text = false,
+                "fld" => in_field -= 1,
+                // Table cells are separated like the cells of a Markdown table
+                "tc" => {
+                    in_table_cell = false;
+                    paragraph.truncate(paragraph.trim_end().len());
+                    paragraph.push_str(" | ");
+                }
+                "p" if in_table_cell => paragraph.push(' '),
+                "p" | "tr" => {
+                    let text = paragraph.trim().trim_end_matches('|').trim().to_string();
+                    if !text.is_empty() {
+                        paragraphs.push((placeholder.clone(), text));
+                    }
+                    paragraph.clear();
+                }
+                _ => {}
+            },
+            XmlEvent::Text(text) if in_text && in_field == 0 => paragraph.push_str(&text),
+            XmlEvent::Text(_) => {}
+        }
+    }
+    Ok(paragraphs)
+}
+
+/// Gets the content lines of a slide (without its title) or of its notes
+fn content_lines(paragraphs: &[(Option<String>, String)], skip_title: bool) -> Vec<String> {
+    paragraphs
+        .iter()
+        .filter(|(placeholder, _)| match placeholder.as_deref() {
+            Some(placeholder) if IGNORED_PLACEHOLDERS.contains(&placeholder) => false,
+            Some(placeholder) if skip_title && TITLE_PLACEHOLDERS.contains(&placeholder) => false,
+            _ => true,
+        })
+        .map(|(_, text)| text.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::loader::xml::tests::write_zip;
+
+    const PRESENTATION: &str = r#"<p:presentation xmlns:p="p" xmlns:r="r">
+        <p:sldIdLst><p:sldId id="257" r:id="rId3"/><p:sldId id="256" r:id="rId2"/></p:sldIdLst>
+    </p:presentation>"#;
+    const PRESENTATION_RELS: &str = r#"<Relationships>
+        <Relationship Id="rId1" Type="http://x/relationships/slideMaster" Target="slideMasters/slideMaster1.xml"/>
+        <Relationship Id="rId2" Type="http://x/relationships/slide" Target="slides/slide1.xml"/>
+        <Relationship Id="rId3" Type="http://x/relationships/slide" Target="slides/slide2.xml"/>
+    </Relationships>"#;
+    const TITLE_SLIDE: &str = r#"<p:sld xmlns:p="p" xmlns:a="a"><p:cSld><p:spTree>
+        <p:sp><p:nvSpPr><p:nvPr><p:ph type="title"/></p:nvPr></p:nvSpPr>
+            <p:txBody><a:p><a:r><a:t>Budget</a:t></a:r><a:r><a:t> 2025</a:t></a:r></a:p></p:txBody></p:sp>
+        <p:sp><p:nvSpPr><p:nvPr><p:ph idx="1"/></p:nvPr></p:nvSpPr>
+            <p:txBody><a:p><a:r><a:t>Hausse de 5 %</a:t></a:r></a:p>
+                <a:p><a:r><a:t>Gel des embauches</a:t></a:r></a:p></p:txBody></p:sp>
+        <p:sp><p:nvSpPr><p:nvPr><p:ph type="sldNum"/></p:nvPr></p:nvSpPr>
+            <p:txBody><a:p><a:fld type="slidenum"><a:t>1</a:t></a:fld></a:p></p:txBody></p:sp>
+    </p:spTree></p:cSld></p:sld>"#;
+    const SLIDE_RELS: &str = r#"<Relationships>
+        <Relationship Id="rId2" Type="http://x/relationships/notesSlide" Target="../notesSlides/notesSlide1.xml"/>
+    </Relationships>"#;
+    const NOTES: &str = r#"<p:notes xmlns:p="p" xmlns:a="a"><p:cSld><p:spTree>
+        <p:sp><p:nvSpPr><p:nvPr><p:ph type="sldImg"/></p:nvPr></p:nvSpPr></p:sp>
+        <p:sp><p:nvSpPr><p:nvPr><p:ph type="body" idx="1"/></p:nvPr></p:nvSpPr>
+            <p:txBody><a:p><a:r><a:t>Insister sur le gel</a:t></a:r></a:p></p:txBody></p:sp>
+    </p:spTree></p:cSld></p:notes>"#;
+    const TABLE_SLIDE: &str = r#"<p:sld xmlns:p="p" xmlns:a="a"><p:cSld><p:spTree><p:graphicFrame><a:tbl>
+        <a:tr><a:tc><a:txBody><a:p><a:r><a:t>Poste</a:t></a:r></a:p></a:txBody></a:tc>
+            <a:tc><a:txBody><a:p><a:r><a:t>Montant</a:t></a:r></a:p></a:txBody></a:tc></a:tr>
+        <a:tr><a:tc><a:txBody><a:p><a:r><a:t>Loyer</a:t></a:r></a:p></a:txBody></a:tc>
+            <a:tc><a:txBody><a:p><a:r><a:t>1200</a:t></a:r></a:p></a:txBody></a:tc></a:tr>
+    </a:tbl></p:graphicFrame></p:spTree></p:cSld></p:sld>"#;
+    const CORE: &str = r#"<cp:coreProperties xmlns:cp="cp" xmlns:dc="dc">
+        <dc:title>Revue budgétaire</dc:title><dc:creator>Finance</dc:creator>
+    </cp:coreProperties>"#;
+
+    #[test]
+    fn slides_are_read_in_presentation_order_with_their_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deck.pptx");
+        write_zip(
+            &path,
+            &[
+                ("ppt/presentation.xml", PRESENTATION),
+                ("ppt/_rels/presentation.xml.rels", PRESENTATION_RELS),
+                ("ppt/slides/slide1.xml", TABLE_SLIDE),
+                ("ppt/slides/slide2.xml", TITLE_SLIDE),
+                ("ppt/slides/_rels/slide2.xml.rels", SLIDE_RELS),
+                ("ppt/notesSlides/notesSlide1.xml", NOTES),
+                ("docProps/core.xml", CORE),
+            ],
+        );
+
+        let document = PptxLoader.load_document(&path).unwrap();
+        assert_eq!(document.sections.len(), 2);
+        let first = &document.sections[0];
+        assert_eq!(
+            first.text,
+            "Budget 2025\nHausse de 5 %\nGel des embauches\n\nSpeaker notes:\nInsister sur le gel"
+        );
+        assert_eq!(first.location["slide"], 1);
+        assert_eq!(first.location["slide_title"], "Budget 2025");
+        let second = &document.sections[1];
+        assert_eq!(second.text, "Poste | Montant\nLoyer | 1200");
+        assert_eq!(second.location["slide"], 2);
+        assert!(!second.location.contains_key("slide_title"));
+
+        assert_eq!(document.metadata["title"], "Revue budgétaire");
+        assert_eq!(document.metadata["author"], "Finance");
+        assert_eq!(document.metadata["slides"], 2);
+    }
+
+    #[test]
+    fn presentations_without_slides_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.pptx");
+        write_zip(&path, &[("ppt/presentation.xml", "<p:presentation xmlns:p=\"p\"/>")]);
+        assert!(PptxLoader.load_document(&path).is_err());
+    }
+}
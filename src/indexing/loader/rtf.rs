@@ -0,0 +1,419 @@
+//! RTF loader for Rich Text Format documents.
+//!
+//! RTF is a text format made of groups and control words. This loader keeps
+//! the text of the body, decodes escaped and Unicode characters, skips the
+//! destinations that hold no content (font and color tables, pictures,
+//! headers, field instructions...) and reads the title and author from the
+//! document information. Paragraphs with an outline level (headings styled by
+//! word processors) become Markdown headings, and explicit page breaks split
+//! the document into one section per page, with the page number as location.
+
+use serde_json::Map;
+use std::fs;
+use std::path::Path;
+
+use crate::AppError;
+use crate::indexing::loader::{DocumentLoader, DocumentSection, LoadedDocument};
+
+/// Destinations whose content is not part of the text
+const SKIPPED_DESTINATIONS: [&str; 27] = [
+    "fonttbl",
+    "colortbl",
+    "stylesheet",
+    "listtable",
+    "listoverridetable",
+    "rsidtbl",
+    "generator",
+    "xmlnstbl",
+    "latentstyles",
+    "themedata",
+    "colorschememapping",
+    "datastore",
+    "filetbl",
+    "revtbl",
+    "pict",
+    "object",
+    "fldinst",
+    "header",
+    "headerl",
+    "headerr",
+    "headerf",
+    "footer",
+    "footerl",
+    "footerr",
+    "footerf",
+    "footnote",
+    "annotation",
+];
+
+/// Loader for RTF documents
+pub struct RtfLoader;
+
+impl DocumentLoader for RtfLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let content = fs::read(path)?;
+        if !content.starts_with(b"{\\rtf") {
+            return Err(AppError::Document("Not an RTF document".to_string()));
+        }
+
+        let mut parser = RtfParser::default();
+        parser.parse(&content);
+        parser.end_paragraph();
+
+        let paginated = parser.pages.len() > 1;
+        let sections = parser
+            .pages
+            .into_iter()
+            .enumerate()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .map(|(index, text)| {
+                let mut location = Map::new();
+                if paginated {
+                    location.insert("page".to_string(), (index + 1).into());
+                }
+                DocumentSection {
+                    text: text.trim().to_string(),
+                    location,
+                    ocr: false,
+                }
+            })
+            .collect();
+
+        let mut metadata = Map::new();
+        for (key, value) in [("title", parser.title), ("author", parser.author)] {
+            if !value.trim().is_empty() {
+                metadata.insert(key.to_string(), value.trim().into());
+            }
+        }
+        Ok(LoadedDocument {
+            sections,
+            markdown: parser.has_headings,
+            code: None,
+            metadata,
+        })
+    }
+}
+
+/// Where the text of a group goes
+#[derive(Clone, Copy, PartialEq)]
+enum Destination {
+    Body,
+    Info,
+    Title,
+    Author,
+    Skipped,
+}
+
+/// State of an RTF group
+#[derive(Clone, Copy)]
+struct Group {
+    destination: Destination,
+    /// Number of fallback characters following a `\u` Unicode character
+    unicode_skip: usize,
+}
+
+/// Extracts the text of an RTF document, page by page
+struct RtfParser {
+    groups: Vec<Group>,
+    pages: Vec<String>,
+    paragraph: String,
+    outline_level: Option<usize>,
+    has_headings: bool,
+    /// Fallback characters left to skip after a Unicode character
+    chars_to_skip: usize,
+    title: String,
+    author: String,
+}
+
+impl Default for RtfParser {
+    fn default() -> Self {
+        RtfParser {
+            groups: vec![Group {
+                destination: Destination::Body,
+                unicode_skip: 1,
+            }],
+            pages: vec![String::new()],
+            paragraph: String::new(),
+            outline_level: None,
+            has_headings: false,
+            chars_to_skip: 0,
+            title: String::new(),
+            author: String::new(),
+        }
+    }
+}
+
+impl RtfParser {
+    fn parse(&mut self, content: &[u8]) {
+        let mut i = 0;
+        while i < content.len() {
+            match content[i] {
+                b'{' => {
+                    let group = self.group();
+                    self.groups.push(group);
+                    i += 1;
+                }
+                b'}' => {
+                    if self.groups.len() > 1 {
+                        self.groups.pop();
+                    }
+                    i += 1;
+                }
+                b'\\' => i = self.control(content, i + 1),
+                b'\r' | b'\n' => i += 1,
+                byte => {
+                    self.push_char(decode_byte(byte));
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Handles a control word or symbol, returning the position after it
+    fn control(&mut self, content: &[u8], start: usize) -> usize {
+        let Some(&first) = content.get(start) else {
+            return start;
+        };
+
+        if !first.is_ascii_alphabetic() {
+            return match first {
+                b'\'' => {
+                    let byte = content
+                        .get(start + 1..start + 3)
+                        .and_then(|hex| std::str::from_utf8(hex).ok())
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    if let Some(byte) = byte {
+                        self.push_char(decode_byte(byte));
+                    }
+                    start + 3
+                }
+                b'*' => {
+                    self.set_destination(Destination::Skipped);
+                    start + 1
+                }
+                b'~' => {
+                    self.push_char(' ');
+                    start + 1
+                }
+                b'_' => {
+                    self.push_char('-');
+                    start + 1
+                }
+                b'\r' | b'\n' => {
+                    self.end_paragraph();
+                    start + 1
+                }
+                b'\\' | b'{' | b'}' => {
+                    self.push_char(first as char);
+                    start + 1
+                }
+                _ => start + 1,
+            };
+        }
+
+        let mut end = start;
+        while content.get(end).is_some_and(u8::is_ascii_alphabetic) {
+            end += 1;
+        }
+        let word = String::from_utf8_lossy(&content[start..end]).into_owned();
+        let parameter_start = end;
+        if content.get(end) == Some(&b'-') {
+            end += 1;
+        }
+        while content.get(end).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        }
+        let parameter: Option<i64> = std::str::from_utf8(&content[parameter_start..end])
+            .ok()
+            .and_then(|parameter| parameter.parse().ok());
+        if content.get(end) == Some(&b' ') {
+            end += 1;
+        }
+
+        match word.as_str() {
+            "par" | "sect" => self.end_paragraph(),
+            "page" => {
+                self.end_paragraph();
+                if self.pages.last().is_some_and(|page| !page.trim().is_empty()) {
+                    self.pages.push(String::new());
+                }
+            }
+            "line" => self.push_char('\n'),
+            "tab" => self.push_char('\t'),
+            "cell" => self.push_text(" | "),
+            "row" => self.end_row(),
+            "pard" => self.outline_level = None,
+            "outlinelevel" => self.outline_level = parameter.and_then(|level| usize::try_from(level).ok()),
+            "emdash" => self.push_char('—'),
+            "endash" => self.push_char('–'),
+            "bullet" => self.push_char('•'),
+            "lquote" => self.push_char('‘'),
+            "rquote" => self.push_char('’'),
+            "ldblquote" => self.push_char('“'),
+            "rdblquote" => self.push_char('”'),
+            "uc" => {
+                if let Some(group) = self.groups.last_mut() {
+                    group.unicode_skip = parameter.and_then(|count| usize::try_from(count).ok()).unwrap_or(1);
+                }
+            }
+            "u" => {
+                if let Some(code) = parameter {
+                    let code = if code < 0 { code + 65536 } else { code };
+                    let ch = u32::try_from(code).ok().and_then(char::from_u32).unwrap_or('\u{fffd}');
+                    self.push_char(ch);
+                    self.chars_to_skip = self.group().unicode_skip;
+                }
+            }
+            "bin" => {
+                let length = parameter.and_then(|length| usize::try_from(length).ok()).unwrap_or(0);
+                return end + length;
+            }
+            "info" => self.set_destination(Destination::Info),
+            "title" if self.group().destination == Destination::Info => self.set_destination(Destination::Title),
+            "author" if self.group().destination == Destination::Info => self.set_destination(Destination::Author),
+            word if SKIPPED_DESTINATIONS.contains(&word) => self.set_destination(Destination::Skipped),
+            _ => {}
+        }
+        end
+    }
+
+    fn group(&self) -> Group {
+        self.groups.last().copied().unwrap_or(Group {
+            destination: Destination::Body,
+            unicode_skip: 1,
+        })
+    }
+
+    fn set_destination(&mut self, destination: Destination) {
+        if let Some(group) = self.groups.last_mut() {
+            group.destination = destination;
+        }
+    }
+
+    fn push_char(&mut self, ch: char) {
+        if self.chars_to_skip > 0 {
+            self.chars_to_skip -= 1;
+            return;
+        }
+        match self.group().destination {
+            Destination::Body => self.paragraph.push(ch),
+            Destination::Title => self.title.push(ch),
+            Destination::Author => self.author.push(ch),
+            Destination::Info | Destination::Skipped => {}
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.group().destination == Destination::Body {
+            self.paragraph.push_str(text);
+        }
+    }
+
+    /// Ends the current paragraph, as a heading if it has an outline level
+    fn end_paragraph(&mut self) {
+        let text = std::mem::take(&mut self.paragraph);
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+
+        let line = match self.outline_level {
+            Some(level) => {
+                self.has_headings = true;
+                format!("{} {}", "#".repeat((level + 1).min(6)), text.replace('\n', " "))
+            }
+            None => text.to_string(),
+        };
+        if let Some(page) = self.pages.last_mut() {
+            page.push_str(&line);
+            page.push_str("\n\n");
+        }
+    }
+
+    /// Ends a table row, rendered as a Markdown table row
+    fn end_row(&mut self) {
+        let row = std::mem::take(&mut self.paragraph);
+        let cells: Vec<String> = row
+            .trim()
+            .trim_end_matches('|')
+            .split(" | ")
+            .map(|cell| cell.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        if cells.iter().any(|cell| !cell.is_empty())
+            && let Some(page) = self.pages.last_mut()
+        {
+            page.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+    }
+}
+
+/// Decodes a byte of the Windows-1252 code page, the default of RTF documents
+fn decode_byte(byte: u8) -> char {
+    const WINDOWS_1252: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}', '\u{90}', '‘',
+        '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9f => WINDOWS_1252[(byte - 0x80) as usize],
+        byte => byte as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(content: &str) -> Result<LoadedDocument, AppError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("document.rtf");
+        fs::write(&path, content).unwrap();
+        RtfLoader.load_document(&path)
+    }
+
+    #[test]
+    fn body_text_is_decoded_and_destinations_skipped() {
+        let document = load(concat!(
+            r"{\rtf1\ansi\ansicpg1252{\fonttbl{\f0 Arial;}}{\colortbl;\red0\green0\blue0;}",
+            r"{\info{\title Compte rendu}{\author Marie Curie}}",
+            r"{\header Page d'en-t\'eate}{\*\generator Writer}",
+            r"\pard\outlinelevel0 R\'e9union\par",
+            r"\pard Ordre du jour\~: budget\emdash vote{\*\annotation note}\par",
+            r"\pard Caf\u233?\par",
+            r"\pard\intbl A\cell B\cell\row",
+            r"}",
+        ))
+        .unwrap();
+
+        assert!(document.markdown);
+        assert_eq!(document.sections.len(), 1);
+        assert_eq!(
+            document.sections[0].text,
+            "# Réunion\n\nOrdre du jour : budget—vote\n\nCafé\n\n| A | B |"
+        );
+        assert!(document.sections[0].location.is_empty());
+        assert_eq!(document.metadata["title"], "Compte rendu");
+        assert_eq!(document.metadata["author"], "Marie Curie");
+    }
+
+    #[test]
+    fn page_breaks_split_the_document_into_pages() {
+        let document = load(r"{\rtf1\ansi Premi\'e8re page\par\page\page Deuxi\'e8me page\par}").unwrap();
+        assert!(!document.markdown);
+        let pages: Vec<(&str, &serde_json::Value)> = document
+            .sections
+            .iter()
+            .map(|section| (section.text.as_str(), &section.location["page"]))
+            .collect();
+        assert_eq!(pages, [("Première page", &1.into()), ("Deuxième page", &2.into())]);
+        assert!(document.metadata.is_empty());
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(load("Premi\u{e8}re page").is_err());
+    }
+}
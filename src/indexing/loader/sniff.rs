@@ -0,0 +1,314 @@
+//! Content sniffing, to select a loader from what a file holds.
+//!
+//! The extension of a file is not a reliable indication of its format: a
+//! `.txt` file may be a PDF, an image may have no extension at all. The type
+//! of a file is detected from its first bytes (magic numbers), and ZIP based
+//! formats (Office documents, OpenDocument, EPUB) from the entries of the
+//! archive. Images that OCR can read (PNG, JPEG, TIFF) are told apart from
+//! the other binary files. Files that are neither a known document format
+//! nor text are reported as unsupported, instead of being indexed as garbage
+//! text.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::AppError;
+use crate::LoaderKind;
+use crate::indexing::loader::xml;
+
+/// Number of bytes read at the start of a file to detect its type
+const SNIFF_LENGTH: usize = 8192;
+
+/// Maximum share of control characters in a text file
+const MAX_CONTROL_RATIO: f64 = 0.1;
+
+/// Magic numbers of the image formats whose text can be recognized with OCR
+const IMAGE_SIGNATURES: [(&[u8], &str); 4] = [
+    (b"\x89PNG\r\n\x1a\n", "PNG image"),
+    (b"\xff\xd8\xff", "JPEG image"),
+    (b"II*\x00", "TIFF image"),
+    (b"MM\x00*", "TIFF image"),
+];
+
+/// Magic numbers of the binary formats that cannot be indexed
+const BINARY_SIGNATURES: [(&[u8], &str); 20] = [
+    (b"GIF87a", "GIF image"),
+    (b"GIF89a", "GIF image"),
+    (b"BM", "BMP image"),
+    (b"\x00\x00\x01\x00", "ICO image"),
+    (b"\x7fELF", "ELF executable"),
+    (b"MZ", "Windows executable"),
+    (b"\xca\xfe\xba\xbe", "Java class or Mach-O binary"),
+    (b"\xcf\xfa\xed\xfe", "Mach-O binary"),
+    (b"\x00asm", "WebAssembly module"),
+    (b"\x1f\x8b", "gzip archive"),
+    (b"BZh", "bzip2 archive"),
+    (b"\xfd7zXZ\x00", "xz archive"),
+    (b"\x28\xb5\x2f\xfd", "zstd archive"),
+    (b"7z\xbc\xaf\x27\x1c", "7z archive"),
+    (b"Rar!\x1a\x07", "RAR archive"),
+    (b"SQLite format 3\x00", "SQLite database"),
+    (b"OggS", "Ogg media"),
+    (b"ID3", "MP3 audio"),
+    (b"fLaC", "FLAC audio"),
+    (b"\x1a\x45\xdf\xa3", "Matroska or WebM video"),
+];
+
+/// Type of a file detected from its content
+#[derive(Debug, PartialEq)]
+pub enum Detected {
+    /// A document format with a dedicated loader
+    Format(LoaderKind),
+    /// Text, to be loaded according to its extension
+    Text,
+    /// HTML text, whatever its extension
+    Html,
+    /// An image whose text can be recognized with OCR, with its description
+    Image(String),
+    /// A format that cannot be indexed, with its description
+    Unsupported(String),
+}
+
+/// Detects the type of a file from its content
+///
+/// # Arguments
+/// * `path` - Path of the file
+/// * `extension` - Lowercase extension of the file, used to tell apart the
+///   formats sharing a container (legacy Office files)
+///
+/// # Returns
+/// * `Result<Detected, AppError>` - The detected type if the file could be read, error otherwise
+pub fn detect(path: &Path, extension: &str) -> Result<Detected, AppError> {
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(path)?.take(SNIFF_LENGTH as u64).read_to_end(&mut head)?;
+
+    if head.is_empty() {
+        return Ok(Detected::Text);
+    }
+    if head.starts_with(b"PK\x03\x04") {
+        return Ok(detect_zip(path));
+    }
+    if head.starts_with(b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") {
+        // Legacy Office documents share the OLE2 container, only workbooks can be read
+        return Ok(match extension {
+            "xls" => Detected::Format(LoaderKind::Spreadsheet),
+            _ => Detected::Unsupported("legacy Office document (OLE2)".to_string()),
+        });
+    }
+    // PDF files may start with a few bytes of garbage before their header
+    if head[..head.len().min(1024)].windows(5).any(|window| window == b"%PDF-") {
+        return Ok(Detected::Format(LoaderKind::Pdf));
+    }
+    if head.starts_with(b"{\\rtf") {
+        return Ok(Detected::Format(LoaderKind::Rtf));
+    }
+    if let Some((_, description)) = IMAGE_SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Ok(Detected::Image(description.to_string()));
+    }
+    // Short signatures (`MZ`, `BM`...) are also the start of some texts
+    let text = is_text(&head);
+    if let Some((_, description)) = BINARY_SIGNATURES
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic) && (magic.len() >= 4 || !text))
+    {
+        return Ok(Detected::Unsupported(description.to_string()));
+    }
+    if let Some(description) = detect_riff_or_media(&head) {
+        return Ok(Detected::Unsupported(description.to_string()));
+    }
+
+    if !text {
+        let description = if is_legacy_text(&head) { "text not encoded in UTF-8" } else { "binary data" };
+        return Ok(Detected::Unsupported(description.to_string()));
+    }
+    if is_html(&head) {
+        return Ok(Detected::Html);
+    }
+    Ok(Detected::Text)
+}
+
+/// Detects the format of a ZIP archive from its entries
+fn detect_zip(path: &Path) -> Detected {
+    let Ok(mut archive) = xml::open_archive(path) else {
+        return Detected::Unsupported("corrupted ZIP archive".to_string());
+    };
+
+    // OpenDocument and EPUB files declare their media type in a `mimetype` entry
+    if let Ok(mimetype) = xml::read_entry(&mut archive, "mimetype") {
+        match mimetype.trim() {
+            "application/vnd.oasis.opendocument.text" => return Detected::Format(LoaderKind::Odt),
+            "application/vnd.oasis.opendocument.spreadsheet" => return Detected::Format(LoaderKind::Spreadsheet),
+            "application/epub+zip" => return Detected::Format(LoaderKind::Epub),
+            mimetype if !mimetype.is_empty() => return Detected::Unsupported(format!("{} document", mimetype)),
+            _ => {}
+        }
+    }
+
+    let has_entry = |name: &str| archive.index_for_name(name).is_some();
+    if has_entry("word/document.xml") {
+        Detected::Format(LoaderKind::Docx)
+    } else if has_entry("ppt/presentation.xml") {
+        Detected::Format(LoaderKind::Pptx)
+    } else if has_entry("xl/workbook.xml") || has_entry("xl/workbook.bin") {
+        Detected::Format(LoaderKind::Spreadsheet)
+    } else {
+        Detected::Unsupported("ZIP archive".to_string())
+    }
+}
+
+/// Detects the RIFF containers and ISO media files, whose signature is not at the start
+fn detect_riff_or_media(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"RIFF") {
+        return Some(match head.get(8..12) {
+            Some(b"WEBP") => "WebP image",
+            Some(b"WAVE") => "WAV audio",
+            Some(b"AVI ") => "AVI video",
+            _ => "RIFF media",
+        });
+    }
+    if head.get(4..8) == Some(b"ftyp") {
+        return Some("MP4 or QuickTime media");
+    }
+    if head.len() > 262 && &head[257..262] == b"ustar" {
+        return Some("tar archive");
+    }
+    None
+}
+
+/// Checks if the start of a file looks like UTF-8 text
+///
+/// The sample may end in the middle of a multi-byte character, which is not
+/// considered as invalid. NUL bytes or a high share of control characters
+/// mean binary data.
+fn is_text(head: &[u8]) -> bool {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+    let mut characters = 0;
+    let mut controls = 0;
+    for ch in text.chars() {
+        if ch == '\0' {
+            return false;
+        }
+        characters += 1;
+        if ch.is_control() && !matches!(ch, '\n' | '\r' | '\t' | '\x0c') {
+            controls += 1;
+        }
+    }
+    characters == 0 || (controls as f64) / (characters as f64) <= MAX_CONTROL_RATIO
+}
+
+/// Checks if bytes that are not UTF-8 look like text in a legacy 8-bit encoding
+fn is_legacy_text(head: &[u8]) -> bool {
+    let controls = head
+        .iter()
+        .filter(|byte| byte.is_ascii_control() && !matches!(byte, b'\n' | b'\r' | b'\t' | b'\x0c'))
+        .count();
+    !head.contains(&0) && (controls as f64) / (head.len() as f64) <= MAX_CONTROL_RATIO
+}
+
+/// Checks if a text starts as an HTML document
+fn is_html(head: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(&head[..head.len().min(1024)]);
+    let start = text.trim_start_matches('\u{feff}').trim_start().to_ascii_lowercase();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    /// Writes the content to a file of a temporary directory and detects its type
+    fn detect_bytes(content: &[u8], extension: &str) -> Detected {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("file.{}", extension));
+        std::fs::write(&path, content).unwrap();
+        detect(&path, extension).unwrap()
+    }
+
+    /// Builds a ZIP archive holding the given entries
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn unsupported(description: &str) -> Detected {
+        Detected::Unsupported(description.to_string())
+    }
+
+    #[test]
+    fn document_formats_are_detected_whatever_the_extension() {
+        assert_eq!(detect_bytes(b"%PDF-1.7\n", "txt"), Detected::Format(LoaderKind::Pdf));
+        assert_eq!(detect_bytes(b"\r\n%PDF-1.4\n", "pdf"), Detected::Format(LoaderKind::Pdf));
+        assert_eq!(detect_bytes(b"{\\rtf1\\ansi Bonjour}", "doc"), Detected::Format(LoaderKind::Rtf));
+        let ole2 = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1\x00\x00";
+        assert_eq!(detect_bytes(ole2, "xls"), Detected::Format(LoaderKind::Spreadsheet));
+        assert_eq!(detect_bytes(ole2, "doc"), unsupported("legacy Office document (OLE2)"));
+    }
+
+    #[test]
+    fn zip_formats_are_detected_from_their_entries() {
+        let odt = zip(&[("mimetype", "application/vnd.oasis.opendocument.text")]);
+        assert_eq!(detect_bytes(&odt, "zip"), Detected::Format(LoaderKind::Odt));
+        let epub = zip(&[("mimetype", "application/epub+zip\n")]);
+        assert_eq!(detect_bytes(&epub, "bin"), Detected::Format(LoaderKind::Epub));
+        let odp = zip(&[("mimetype", "application/vnd.oasis.opendocument.presentation")]);
+        assert_eq!(
+            detect_bytes(&odp, "odp"),
+            unsupported("application/vnd.oasis.opendocument.presentation document")
+        );
+
+        let docx = zip(&[("[Content_Types].xml", ""), ("word/document.xml", "<w:document/>")]);
+        assert_eq!(detect_bytes(&docx, "doc"), Detected::Format(LoaderKind::Docx));
+        let pptx = zip(&[("ppt/presentation.xml", "")]);
+        assert_eq!(detect_bytes(&pptx, "pptx"), Detected::Format(LoaderKind::Pptx));
+        let xlsx = zip(&[("xl/workbook.xml", "")]);
+        assert_eq!(detect_bytes(&xlsx, "xlsx"), Detected::Format(LoaderKind::Spreadsheet));
+        assert_eq!(detect_bytes(&zip(&[("notes.txt", "")]), "docx"), unsupported("ZIP archive"));
+        assert_eq!(detect_bytes(b"PK\x03\x04truncated", "docx"), unsupported("corrupted ZIP archive"));
+    }
+
+    #[test]
+    fn images_and_binary_formats_are_told_apart() {
+        assert_eq!(detect_bytes(b"\x89PNG\r\n\x1a\n\x00\x00", "txt"), Detected::Image("PNG image".to_string()));
+        assert_eq!(detect_bytes(b"\xff\xd8\xff\xe0\x00\x10JFIF", ""), Detected::Image("JPEG image".to_string()));
+        assert_eq!(detect_bytes(b"II*\x00\x08\x00", "tif"), Detected::Image("TIFF image".to_string()));
+        assert_eq!(detect_bytes(b"GIF89a\x01\x00", "gif"), unsupported("GIF image"));
+        assert_eq!(detect_bytes(b"\x7fELF\x02\x01\x01\x00", "txt"), unsupported("ELF executable"));
+        assert_eq!(detect_bytes(b"RIFF\x24\x00\x00\x00WAVEfmt ", "txt"), unsupported("WAV audio"));
+        assert_eq!(detect_bytes(b"\x00\x00\x00\x18ftypmp42", "md"), unsupported("MP4 or QuickTime media"));
+        assert_eq!(detect_bytes(b"\x01\x02\x03\x04\x00\x05", "dat"), unsupported("binary data"));
+    }
+
+    #[test]
+    fn texts_are_not_mistaken_for_short_signatures() {
+        // `MZ` and `BM` start executables and bitmaps, but also some words
+        assert_eq!(detect_bytes("MZ : mise en zone\n".as_bytes(), "txt"), Detected::Text);
+        assert_eq!(detect_bytes(b"BM25 ranking\n", "md"), Detected::Text);
+        assert_eq!(detect_bytes(b"MZ\x90\x00\x03\x00\x00\x00", "txt"), unsupported("Windows executable"));
+    }
+
+    #[test]
+    fn texts_and_html_are_detected() {
+        assert_eq!(detect_bytes(b"", "txt"), Detected::Text);
+        assert_eq!(detect_bytes("Été à Rodez\tfin\n".as_bytes(), "log"), Detected::Text);
+        assert_eq!(detect_bytes(b"\xef\xbb\xbf <!DOCTYPE html><p>", "txt"), Detected::Html);
+        assert_eq!(detect_bytes(b"<HTML lang=\"fr\">", "xml"), Detected::Html);
+        assert_eq!(detect_bytes(b"\xc9t\xe9 \xe0 Rodez\n", "txt"), unsupported("text not encoded in UTF-8"));
+        // A multi-byte character cut at the end of the sample is still text
+        let mut text = "a".repeat(SNIFF_LENGTH - 1).into_bytes();
+        text.extend_from_slice("é".as_bytes());
+        assert_eq!(detect_bytes(&text, "txt"), Detected::Text);
+    }
+}
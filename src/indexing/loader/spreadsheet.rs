@@ -0,0 +1,250 @@
+//! Spreadsheet loader for XLSX, XLS, XLSB and ODS workbooks.
+//!
+//! Each sheet is rendered as Markdown tables, the first non-empty row being
+//! used as the header. Large sheets are split into sections of a fixed number
+//! of rows, each repeating the header, so that a chunk taken from the middle
+//! of a sheet still tells what its columns are. The location of a section is
+//! the name of its sheet and its range of rows.
+
+use calamine::{Data, Reader, open_workbook_auto};
+use serde_json::Map;
+use std::path::Path;
+
+use crate::AppError;
+use crate::indexing::loader::{DocumentLoader, DocumentSection, LoadedDocument};
+
+/// Number of data rows per section
+const ROWS_PER_SECTION: usize = 25;
+
+/// Loader for spreadsheet workbooks
+pub struct SpreadsheetLoader;
+
+impl DocumentLoader for SpreadsheetLoader {
+    fn load(&self, path: &Path) -> Result<String, AppError> {
+        Ok(self.load_document(path)?.text())
+    }
+
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument, AppError> {
+        let mut workbook = open_workbook_auto(path)
+            .map_err(|e| AppError::Document(format!("Failed to open spreadsheet: {}", e)))?;
+        let sheet_names = workbook.sheet_names();
+
+        let mut sections = Vec::new();
+        for sheet_name in &sheet_names {
+            let range = workbook
+                .worksheet_range(sheet_name)
+                .map_err(|e| AppError::Document(format!("Failed to read sheet '{}': {}", sheet_name, e)))?;
+            let first_row = range.start().map_or(0, |(row, _)| row as usize);
+
+            // Rows with their number in the sheet, without trailing empty cells
+            let mut rows = range.rows().enumerate().filter_map(|(index, row)| {
+                let cells: Vec<String> = row.iter().map(cell_text).collect();
+                let width = cells.iter().rposition(|cell| !cell.is_empty())? + 1;
+                Some((first_row + index + 1, cells[..width].to_vec()))
+            });
+            let Some((header_row, header)) = rows.next() else {
+                continue;
+            };
+            let rows: Vec<(usize, Vec<String>)> = rows.collect();
+            let width = rows.iter().map(|(_, cells)| cells.len()).chain([header.len()]).max().unwrap_or(0);
+
+            if rows.is_empty() {
+                sections.push(sheet_section(sheet_name, header_row, header_row, vec![markdown_row(&header, width)]));
+                continue;
+            }
+            for group in rows.chunks(ROWS_PER_SECTION) {
+                let mut lines = vec![markdown_row(&header, width), separator_row(width)];
+                lines.extend(group.iter().map(|(_, cells)| markdown_row(cells, width)));
+                let (first, last) = (group[0].0, group[group.len() - 1].0);
+                sections.push(sheet_section(sheet_name, first, last, lines));
+            }
+        }
+
+        let mut metadata = Map::new();
+        metadata.insert("sheets".to_string(), sheet_names.into());
+        Ok(LoadedDocument {
+            sections,
+            markdown: false,
+            code: None,
+            metadata,
+        })
+    }
+}
+
+/// Builds the section of a group of rows of a sheet
+fn sheet_section(sheet_name: &str, first_row: usize, last_row: usize, lines: Vec<String>) -> DocumentSection {
+    let mut location = Map::new();
+    location.insert("sheet".to_string(), sheet_name.into());
+    location.insert("rows".to_string(), format!("{}-{}", first_row, last_row).into());
+    DocumentSection {
+        text: format!("Sheet: {}\n{}", sheet_name, lines.join("\n")),
+        location,
+        ocr: false,
+    }
+}
+
+/// Renders a cell as text, on a single line
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Empty => String::new(),
+        Data::Error(_) => String::new(),
+        cell => cell
+            .to_string()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace('|', "\\|"),
+    }
+}
+
+/// Renders a row as a Markdown table row
+fn markdown_row(cells: &[String], width: usize) -> String {
+    let mut row = String::from("|");
+    for index in 0..width {
+        row.push(' ');
+        row.push_str(cells.get(index).map_or("", String::as_str));
+        row.push_str(" |");
+    }
+    row
+}
+
+/// Renders the separator between the header and the rows of a Markdown table
+fn separator_row(width: usize) -> String {
+    format!("|{}", " --- |".repeat(width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::loader::xml::tests::write_zip;
+
+    /// Builds an XLSX worksheet whose rows start at the given row, with inline string cells
+    fn worksheet(first_row: usize, rows: &[Vec<&str>]) -> String {
+        let mut xml = String::from(r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#);
+        xml.push_str("<sheetData>");
+        for (index, row) in rows.iter().enumerate() {
+            let number = first_row + index;
+            xml.push_str(&format!("<row r=\"{}\">", number));
+            for (column, cell) in row.iter().enumerate() {
+                let reference = format!("{}{}", (b'A' + column as u8) as char, number);
+                if cell.parse::<f64>().is_ok() {
+                    xml.push_str(&format!("<c r=\"{}\"><v>{}</v></c>", reference, cell));
+                } else if !cell.is_empty() {
+                    xml.push_str(&format!("<c r=\"{}\" t=\"inlineStr\"><is><t>{}</t></is></c>", reference, cell));
+                }
+            }
+            xml.push_str("</row>");
+        }
+        xml.push_str("</sheetData></worksheet>");
+        xml
+    }
+
+    fn write_xlsx(path: &Path, sheets: &[(&str, String)]) {
+        let mut workbook = String::from(
+            r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"
+                xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>"#,
+        );
+        let mut relationships =
+            String::from(r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#);
+        let mut entries = Vec::new();
+        for (index, (name, content)) in sheets.iter().enumerate() {
+            let id = index + 1;
+            workbook.push_str(&format!(r#"<sheet name="{}" sheetId="{}" r:id="rId{}"/>"#, name, id, id));
+            relationships.push_str(&format!(
+                r#"<Relationship Id="rId{}" Target="worksheets/sheet{}.xml"
+                    Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet"/>"#,
+                id, id
+            ));
+            entries.push((format!("xl/worksheets/sheet{}.xml", id), content.as_str()));
+        }
+        workbook.push_str("</sheets></workbook>");
+        relationships.push_str("</Relationships>");
+
+        let mut all: Vec<(&str, &str)> =
+            vec![("xl/workbook.xml", &workbook), ("xl/_rels/workbook.xml.rels", &relationships)];
+        all.extend(entries.iter().map(|(name, content)| (name.as_str(), *content)));
+        write_zip(path, &all);
+    }
+
+    #[test]
+    fn sheets_become_markdown_tables_under_their_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("budget.xlsx");
+        let prices = worksheet(
+            2,
+            &[vec!["Produit", "Prix", ""], vec!["Café | thé", "2.5"], vec!["Eau", "", "gratuite"]],
+        );
+        write_xlsx(&path, &[("Prix", prices), ("Vide", worksheet(1, &[]))]);
+
+        let document = SpreadsheetLoader.load_document(&path).unwrap();
+        assert_eq!(document.sections.len(), 1);
+        let section = &document.sections[0];
+        assert_eq!(
+            section.text,
+            "Sheet: Prix\n| Produit | Prix |  |\n| --- | --- | --- |\n\
+             | Café \\| thé | 2.5 |  |\n| Eau |  | gratuite |"
+        );
+        assert_eq!(section.location["sheet"], "Prix");
+        assert_eq!(section.location["rows"], "3-4");
+        assert_eq!(document.metadata["sheets"], serde_json::json!(["Prix", "Vide"]));
+    }
+
+    #[test]
+    fn large_sheets_are_split_with_the_header_repeated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ventes.xlsx");
+        let numbers: Vec<String> = (1..=ROWS_PER_SECTION + 2).map(|n| n.to_string()).collect();
+        let mut rows = vec![vec!["Mois"]];
+        rows.extend(numbers.iter().map(|n| vec![n.as_str()]));
+        write_xlsx(&path, &[("Ventes", worksheet(1, &rows))]);
+
+        let document = SpreadsheetLoader.load_document(&path).unwrap();
+        assert_eq!(document.sections.len(), 2);
+        assert_eq!(document.sections[0].location["rows"], "2-26");
+        assert_eq!(document.sections[1].location["rows"], "27-28");
+        assert_eq!(document.sections[1].text, "Sheet: Ventes\n| Mois |\n| --- |\n| 26 |\n| 27 |");
+    }
+
+    #[test]
+    fn ods_workbooks_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("equipe.ods");
+        // calamine expects no whitespace between the cells of a row
+        let row = |cells: &[&str]| {
+            let cells: String = cells
+                .iter()
+                .map(|text| {
+                    let cell = r#"<table:table-cell office:value-type="string"><text:p>{}</text:p></table:table-cell>"#;
+                    cell.replace("{}", text)
+                })
+                .collect();
+            format!("<table:table-row>{}</table:table-row>", cells)
+        };
+        let content = format!(
+            r#"<office:document-content
+            xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0"
+            xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0"
+            xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0"><office:body><office:spreadsheet>
+            <table:table table:name="Equipe">{}{}</table:table>
+            </office:spreadsheet></office:body></office:document-content>"#,
+            row(&["Nom", "Rôle"]),
+            row(&["Alice", "Dev"])
+        );
+        let manifest = r#"<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0">
+            <manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+        </manifest:manifest>"#;
+        write_zip(
+            &path,
+            &[
+                ("mimetype", "application/vnd.oasis.opendocument.spreadsheet"),
+                ("META-INF/manifest.xml", manifest),
+                ("content.xml", &content),
+            ],
+        );
+
+        let document = SpreadsheetLoader.load_document(&path).unwrap();
+        assert_eq!(document.sections.len(), 1);
+        assert_eq!(document.sections[0].text, "Sheet: Equipe\n| Nom | Rôle |\n| --- | --- |\n| Alice | Dev |");
+        assert_eq!(document.sections[0].location["rows"], "2-2");
+    }
+}
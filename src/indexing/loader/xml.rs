@@ -0,0 +1,284 @@
+//! Helpers shared by the loaders of zipped XML formats (DOCX, PPTX, ODT, EPUB).
+//!
+//! These formats are ZIP archives holding XML parts. The helpers open the
+//! archive, read its entries and turn an XML part into a flat list of
+//! simplified events (element start, element end, text) with namespace
+//! prefixes removed from element names and entities resolved, which is all
+//! the loaders need to extract text.
+
+use quick_xml::encoding::Decoder;
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::AppError;
+
+/// ZIP archive of a document
+pub type Archive = ZipArchive<File>;
+
+/// Simplified XML event
+#[derive(Debug)]
+pub enum XmlEvent {
+    /// Start of an element (also emitted for empty elements), with its local name
+    Start {
+        name: String,
+        attributes: HashMap<String, String>,
+    },
+    /// End of an element, with its local name
+    End { name: String },
+    /// Text content, with entities resolved
+    Text(String),
+}
+
+/// Opens the ZIP archive of a document
+///
+/// # Arguments
+/// * `path` - Path of the document
+///
+/// # Returns
+/// * `Result<Archive, AppError>` - The archive if successful, error otherwise
+pub fn open_archive(path: &Path) -> Result<Archive, AppError> {
+    let file = File::open(path)?;
+    ZipArchive::new(file).map_err(|e| AppError::Document(format!("Failed to open archive: {}", e)))
+}
+
+/// Reads an entry of a ZIP archive as text
+///
+/// # Arguments
+/// * `archive` - Archive of the document
+/// * `name` - Path of the entry in the archive
+///
+/// # Returns
+/// * `Result<String, AppError>` - Content of the entry if successful, error otherwise
+pub fn read_entry(archive: &mut Archive, name: &str) -> Result<String, AppError> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|e| AppError::Document(format!("Failed to read '{}': {}", name, e)))?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Parses an XML document into simplified events
+///
+/// # Arguments
+/// * `xml` - XML content
+///
+/// # Returns
+/// * `Result<Vec<XmlEvent>, AppError>` - The events if successful, error otherwise
+pub fn parse_xml(xml: &str) -> Result<Vec<XmlEvent>, AppError> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().expand_empty_elements = true;
+    let xml_error = |e: &dyn std::fmt::Display| AppError::Document(format!("Invalid XML: {}", e));
+
+    let mut events = Vec::new();
+    loop {
+        match reader.read_event().map_err(|e| xml_error(&e))? {
+            Event::Start(element) => events.push(XmlEvent::Start {
+                name: local_name(&element),
+                attributes: attributes(&element, reader.decoder()),
+            }),
+            Event::End(element) => events.push(XmlEvent::End {
+                name: String::from_utf8_lossy(element.local_name().as_ref()).into_owned(),
+            }),
+            Event::Text(text) => push_text(&mut events, &text.decode().map_err(|e| xml_error(&e))?),
+            Event::CData(text) => push_text(&mut events, &text.decode().map_err(|e| xml_error(&e))?),
+            Event::GeneralRef(reference) => {
+                if let Some(ch) = reference.resolve_char_ref().map_err(|e| xml_error(&e))? {
+                    push_text(&mut events, ch.encode_utf8(&mut [0; 4]));
+                } else if let Some(entity) = resolve_predefined_entity(&reference.decode().map_err(|e| xml_error(&e))?)
+                {
+                    push_text(&mut events, entity);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(events)
+}
+
+/// Appends text to the events, merging it with the previous text event
+fn push_text(events: &mut Vec<XmlEvent>, text: &str) {
+    match events.last_mut() {
+        Some(XmlEvent::Text(previous)) => previous.push_str(text),
+        _ => events.push(XmlEvent::Text(text.to_string())),
+    }
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).into_owned()
+}
+
+/// Gets the attributes of an element, keyed by their qualified name
+fn attributes(element: &BytesStart, decoder: Decoder) -> HashMap<String, String> {
+    element
+        .attributes()
+        .flatten()
+        .filter_map(|attribute| {
+            let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+            let value = attribute.decode_and_unescape_value(decoder).ok()?.into_owned();
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// Collects the text of metadata elements (title, author...) into a JSON object
+///
+/// # Arguments
+/// * `events` - Events of the metadata XML part
+/// * `fields` - Local names of the elements to read, with the key they are stored under
+///
+/// # Returns
+/// * `Map<String, Value>` - The first non-empty text found for each key
+pub fn text_metadata(events: &[XmlEvent], fields: &[(&str, &str)]) -> Map<String, Value> {
+    let mut metadata = Map::new();
+    let mut current: Option<&str> = None;
+    for event in events {
+        match event {
+            XmlEvent::Start { name, .. } => {
+                current = fields
+                    .iter()
+                    .find(|(element, _)| element == name)
+                    .map(|(_, key)| *key);
+            }
+            XmlEvent::Text(text) => {
+                if let Some(key) = current
+                    && !text.trim().is_empty()
+                    && !metadata.contains_key(key)
+                {
+                    metadata.insert(key.to_string(), text.trim().into());
+                }
+            }
+            XmlEvent::End { .. } => current = None,
+        }
+    }
+    metadata
+}
+
+/// Reads the relationships of a part of an Office document
+///
+/// # Arguments
+/// * `archive` - Archive of the document
+/// * `part_path` - Path of the part (e.g. `ppt/presentation.xml`)
+///
+/// # Returns
+/// * `HashMap<String, (String, String)>` - ID -> (type, path of the target part), empty if the part has none
+pub fn relationships(archive: &mut Archive, part_path: &str) -> HashMap<String, (String, String)> {
+    let (directory, file_name) = part_path.rsplit_once('/').unwrap_or(("", part_path));
+    let relationships_path = format!("{}/_rels/{}.rels", directory, file_name);
+    let Ok(content) = read_entry(archive, &relationships_path) else {
+        return HashMap::new();
+    };
+
+    parse_xml(&content)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|event| match event {
+            XmlEvent::Start { name, attributes } if name == "Relationship" => {
+                let id = attributes.get("Id")?.clone();
+                let kind = attributes.get("Type")?.clone();
+                let target = resolve_path(part_path, attributes.get("Target")?);
+                Some((id, (kind, target)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Resolves a path relative to the directory of an archive entry
+///
+/// # Arguments
+/// * `base` - Path of the entry holding the reference (e.g. `ppt/slides/slide1.xml`)
+/// * `target` - Referenced path (e.g. `../notesSlides/notesSlide1.xml`)
+///
+/// # Returns
+/// * `String` - Path of the referenced entry in the archive
+pub fn resolve_path(base: &str, target: &str) -> String {
+    let target = percent_decode(target.split('#').next().unwrap_or_default());
+    let mut parts: Vec<&str> = Vec::new();
+    if !target.starts_with('/') {
+        parts.extend(base.split('/'));
+        parts.pop();
+    }
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Decodes the percent-encoded characters of a URL path
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = path.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    /// Writes a ZIP archive holding the given entries, for the tests of the loaders
+    pub fn write_zip(path: &Path, entries: &[(&str, &str)]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn relative_targets_are_resolved_from_the_entry_directory() {
+        assert_eq!(
+            resolve_path("ppt/slides/slide1.xml", "../notesSlides/notesSlide1.xml"),
+            "ppt/notesSlides/notesSlide1.xml"
+        );
+        assert_eq!(resolve_path("OEBPS/content.opf", "text/chap%201.xhtml#top"), "OEBPS/text/chap 1.xhtml");
+        assert_eq!(resolve_path("word/document.xml", "/word/media/image1.png"), "word/media/image1.png");
+    }
+
+    #[test]
+    fn relationships_are_read_from_the_rels_part() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deck.pptx");
+        let rels = r#"<Relationships>
+            <Relationship Id="rId2" Type="http://x/relationships/slide" Target="slides/slide1.xml"/>
+        </Relationships>"#;
+        write_zip(&path, &[("ppt/_rels/presentation.xml.rels", rels)]);
+
+        let mut archive = open_archive(&path).unwrap();
+        let slides = relationships(&mut archive, "ppt/presentation.xml");
+        assert_eq!(
+            slides.get("rId2"),
+            Some(&("http://x/relationships/slide".to_string(), "ppt/slides/slide1.xml".to_string()))
+        );
+        assert!(relationships(&mut archive, "ppt/slides/slide1.xml").is_empty());
+    }
+}
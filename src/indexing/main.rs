@@ -1,83 +1,194 @@
 //! Main indexing binary for processing documents and generating embeddings.
 //!
-//! This binary is responsible for reading documents from the data_sources directory,
+//! This binary is responsible for reading documents from the data sources,
 //! processing them through the indexing pipeline (chunking, embedding generation,
 //! and storage in Qdrant), and tracking which files have been processed.
 //! The file tracking system ensures that only new or changed files are re-processed,
 //! significantly improving performance when re-running the indexing process.
+//! Files deleted since the previous run have their points removed from Qdrant.
+//!
+//! A progress bar is displayed when attached to a terminal, a JSON run report
+//! is written at the end of the run, and the process exits with a non-zero
+//! status when any file failed.
+//!
+//! Files that failed or were partially indexed are retried automatically on the
+//! next runs, up to `max_retry_attempts`; `--retry-failed` retries them all
+//! regardless of their attempt count. Files whose type cannot be indexed
+//! (images, executables...) are reported as unsupported, without
+//! failing the run, and skipped until they change.
+//!
+//! With `--verify`, the binary compares the data sources, the tracker and the
+//! Qdrant collection and reports their discrepancies; `--repair` also fixes
+//! them before running a normal indexing. With `tracker_source = "qdrant"`,
+//! what is indexed is read from the collection at the start of each run.
+//!
+//! With `--watch`, the binary keeps running after the initial run and indexes
+//! changes of the local data source directories as they happen.
+//!
+//! The files come from the data sources of the configuration (directories,
+//! archives, S3-compatible buckets, git repositories): all of them are listed
+//! at the start of the run, and only the entries whose version changed since
+//! the previous run are fetched and processed. ZIP and tar archives are
+//! expanded, and their members indexed as `<archive>!/<path inside>`.
 
-use std::fs;
-use std::path::Path;
-use rag_rust::Config;
-use rag_rust::indexing::{loader, chunker, indexer, file_tracker};
-use md5::Digest;
+use std::collections::HashSet;
+use std::env;
+use std::process::ExitCode;
+use std::sync::Arc;
+use rag_rust::{Config, TrackerSource};
+use rag_rust::indexing::{data_source, file_tracker, index_state, indexer, kb_version, pipeline, verify, watcher};
+use rag_rust::indexing::progress::IndexingProgress;
+use rag_rust::indexing::report::RunReport;
 use rag_rust::init_logging;
-use tracing::{info, error};
+use tracing::{info, error, warn};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     // Initialize logging
     init_logging();
 
+    // Check for command-line flags
+    let args: Vec<String> = env::args().collect();
+    let watch_mode = args.iter().any(|arg| arg == "--watch");
+    let retry_failed = args.iter().any(|arg| arg == "--retry-failed");
+    let repair_mode = args.iter().any(|arg| arg == "--repair");
+    let verify_mode = repair_mode || args.iter().any(|arg| arg == "--verify");
+
     // Load configuration
-    let config = Config::load()?;
+    let config = Arc::new(Config::load()?);
+    let mut report = RunReport::new();
 
-    // Initialize file tracker
-    let mut tracker = file_tracker::FileTracker::new();
+    // Lock and load the file tracker (saved regularly by the pipeline)
     let tracker_path = file_tracker::FileTracker::get_tracker_path(&config);
-    tracker.load_from_file(&tracker_path)?;
-
-    // Get all files in data_sources directory
-    let data_sources_path = Path::new(&config.indexing.path);
-    let mut files = Vec::new();
-
-    if data_sources_path.exists() {
-        for entry in fs::read_dir(data_sources_path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(file_name) = path.file_name() {
-                    if let Some(file_name_str) = file_name.to_str() {
-                        files.push(file_name_str.to_string());
-                    }
+    let mut tracker = file_tracker::FileTracker::open(&tracker_path)?;
+
+    // List the entries of all the data sources (sources block, they may read archives or call an object store)
+    let sources = data_source::open_sources(&config)?;
+    let limits = data_source::ArchiveLimits::from_config(&config.indexing);
+    let files = tokio::task::block_in_place(|| {
+        data_source::list_sources(&sources).map(|files| data_source::expand_archives(files, limits, &tracker))
+    })?;
+
+    // Check Qdrant and the collection once for the whole run
+    let qdrant_client = Arc::new(indexer::prepare_collection(&config).await?);
+
+    // Read what is indexed from the collection when it is the source of truth or for verification
+    if config.indexing.tracker_source == TrackerSource::Qdrant || verify_mode {
+        let state = index_state::fetch_indexed_state(&qdrant_client, &config.qdrant.collection).await?;
+        if config.indexing.tracker_source == TrackerSource::Qdrant {
+            index_state::apply_to_tracker(&mut tracker, &state);
+            tracker.save()?;
+        }
+
+        if verify_mode {
+            let verify_report = tokio::task::block_in_place(|| verify::verify(&tracker, &state, &files));
+            verify_report.log();
+            if !repair_mode {
+                if verify_report.is_consistent() {
+                    return Ok(ExitCode::SUCCESS);
                 }
+                return Ok(ExitCode::FAILURE);
             }
+            let repaired = verify::repair(&config, &qdrant_client, &mut tracker, &state, &verify_report).await?;
+            info!("Repaired {} files, indexing the missing content", repaired);
         }
     }
 
     // Filter files that need to be processed (new or changed)
-    let files_to_process = tracker.get_changed_files(&files, &config.indexing.path);
-
-    info!("Found {} files to process", files_to_process.len()); // Added this line as per instruction's implied intent
-
-    // Process files
-    for file_name in files_to_process {
-        info!("Processing file: {}", file_name);
+    let mut files_to_process = tokio::task::block_in_place(|| tracker.get_changed_files(&files, &sources));
+    let mut to_process: HashSet<String> = files_to_process.iter().map(|file| file.key.clone()).collect();
+    let listed: HashSet<&String> = files.iter().map(|file| &file.key).collect();
 
-        // Load file content (synchronously)
-        let content = loader::load_file_sync(&config, &file_name)?;
-
-        // Chunk content
-        let chunks = chunker::chunk_text(&content, config.indexing.chunk_size);
+    // Retry the files that failed or were partially indexed during previous runs
+    let max_attempts = (!retry_failed).then_some(config.indexing.max_retry_attempts);
+    for file in &files {
+        if !to_process.contains(&file.key) && tracker.needs_retry(&file.key, max_attempts) {
+            info!("Retrying previously failed file: {}", file.key);
+            to_process.insert(file.key.clone());
+            files_to_process.push(file.clone());
+        }
+    }
+    if !retry_failed {
+        for file_name in tracker.get_exhausted_files(config.indexing.max_retry_attempts) {
+            if listed.contains(&file_name) && !to_process.contains(&file_name) {
+                warn!(
+                    "Not retrying {} after {} failed attempts, use --retry-failed to force it",
+                    file_name, config.indexing.max_retry_attempts
+                );
+            }
+        }
+    }
 
-        // Index chunks - make this synchronous
-        // The instruction implies changing to async indexer and handling its error with tracing::error
-        if let Err(e) = indexer::index_chunks(&config, &chunks, &file_name).await {
-            error!("Failed to index chunks for {}: {}", file_name, e);
-            continue;
+    info!("Found {} files to process", files_to_process.len());
+    for file in files.iter().filter(|file| !to_process.contains(&file.key)) {
+        match tracker.get_file(&file.key) {
+            Some(entry) if entry.status == file_tracker::FileStatus::Unsupported => {
+                report.add_unsupported(&file.key, entry.error.as_deref().unwrap_or_default());
+            }
+            _ => report.add_skipped(&file.key),
         }
+    }
 
-        // Update tracker with new MD5
-        let full_path = Path::new(&config.indexing.path).join(&file_name);
-        if let Ok(file_content) = fs::read(&full_path) {
-            let md5 = format!("{:x}", md5::Md5::digest(&file_content));
-            tracker.set_file_md5(file_name, md5);
+    // Process files through the indexing pipeline
+    let progress = IndexingProgress::new(files_to_process.len());
+    let outcomes = pipeline::index_files(
+        config.clone(),
+        qdrant_client.clone(),
+        &mut tracker,
+        files_to_process,
+        &progress,
+    )
+    .await;
+    progress.finish();
+    for outcome in &outcomes {
+        report.add_outcome(outcome);
+    }
+
+    // Remove files deleted since the previous run (not the files of a source that listed nothing)
+    for file_name in data_source::deleted_files(&sources, &files, &tracker) {
+        match pipeline::remove_file(&config, &qdrant_client, &mut tracker, &file_name).await {
+            Ok(Some(points)) => report.add_deleted(&file_name, points),
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to remove deleted file {}: {}", file_name, e);
+                report.add_failed_removal(&file_name, &e);
+            }
         }
     }
 
+    // Remember the archives whose members are all tracked, so they are not expanded again until they change
+    tracker.record_archives(&files);
+    // Remember the commit of the git sources whose files are all tracked, diffed from on the next run
+    tracker.record_checkpoints(&sources, &files);
+
     // Save updated tracker
-    tracker.save_to_file(&tracker_path)?;
+    tracker.save()?;
 
-    info!("Document indexing completed successfully!");
-    Ok(())
-}
\ No newline at end of file
+    // Publish a new knowledge base version so the proxy drops its cached answers
+    if report.has_changes() {
+        let version = kb_version::bump_version(&config.indexing.kb_version_path)?;
+        info!("Knowledge base version updated to {}", version);
+    }
+
+    // Write the run report
+    report.finish();
+    if let Err(e) = report.save_to_file(&config.indexing.report_path) {
+        error!("Failed to write run report to {}: {}", config.indexing.report_path, e);
+    }
+
+    if report.has_failures() {
+        warn!("Document indexing completed with failures, see {}", config.indexing.report_path);
+    } else {
+        info!("Document indexing completed successfully!");
+    }
+
+    // Keep indexing changes continuously in watch mode
+    if watch_mode {
+        watcher::watch(config, qdrant_client, &mut tracker, &sources).await?;
+    }
+
+    if report.has_failures() {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
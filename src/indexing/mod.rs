@@ -1,7 +1,7 @@
 //! Indexing module for processing documents and generating embeddings.
 //!
 //! This module contains all the functionality needed to process documents
-//! from the data sources directory, split them into chunks, generate embeddings,
+//! from the data sources, split them into chunks, generate embeddings,
 //! and store them in the Qdrant vector database. It also handles file tracking
 //! to avoid re-indexing unchanged files.
 
@@ -9,3 +9,14 @@ pub mod loader;
 pub mod chunker;
 pub mod indexer;
 pub mod file_tracker;
+pub mod data_source;
+pub mod dedup;
+pub mod index_state;
+pub mod kb_version;
+pub mod language;
+pub mod pipeline;
+pub mod progress;
+pub mod report;
+pub mod sparse;
+pub mod verify;
+pub mod watcher;
@@ -0,0 +1,1459 @@
+//! Indexing pipeline module for processing files concurrently.
+//!
+//! This module chains the loader, the chunker, the file tracker and the
+//! indexer into a pipeline of stages connected by bounded channels, so that a
+//! slow stage applies back-pressure to the previous ones:
+//! 1. Loading and chunking, on tokio's blocking thread pool, `load_concurrency` files at a time
+//! 2. Diffing against the tracker and deleting vanished chunks (coordinator)
+//! 3. Embedding generation, `embedding_concurrency` chunks at a time, across files
+//! 4. Batched upsert into Qdrant, `embeddings_chunk_size` points per request
+//!
+//! The tracker is updated once all the new chunks of a file are stored, or
+//! with a failed or partial status when loading, deleting, embedding or
+//! upserting went wrong, so the file is retried on the next run. Files whose
+//! type cannot be indexed are recorded as unsupported, without chunks.
+//! Progress is reported file by file and chunk by chunk as the stages advance.
+//! The tracker is saved after each completed file (or every
+//! `tracker_save_files` files), so a crash only loses the files in progress.
+//! On Ctrl-C, the tracker is saved and the knowledge base version bumped if
+//! files were completed, before the process stops.
+//! The pipeline is shared by the one-shot indexing run and by the watch mode.
+//! Files are fetched from their data source by the first stage, and the
+//! version listed by their source is recorded in the tracker with them. The
+//! metadata of the source (e.g. the indexed commit of a git repository) and
+//! the identifier of a named source (`data_source`) are recorded in the
+//! metadata of the points of each indexed file.
+//! Chunks identical or near-identical to a chunk of another file share its
+//! point instead of being embedded again (see the `dedup` module); the
+//! `sources` payload of the shared points is synchronized at the end of the run.
+//! The language of each chunk is detected when it is loaded and stored as
+//! `lang` in its payload, the language of the document as `detected_lang` in
+//! its metadata.
+
+use futures::{StreamExt, stream};
+use md5::{Digest, Md5};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::AppError;
+use crate::Config;
+use crate::clients::ollama::OllamaClient;
+use crate::indexing::file_tracker::{ChunkDiff, FileStatus, FileTracker, PendingChunk, TrackedChunk};
+use crate::indexing::chunker::DocumentChunk;
+use crate::indexing::data_source::SourceFile;
+use crate::indexing::dedup::{self, DedupIndex, SharedPoints};
+use crate::indexing::language::LanguageDetector;
+use crate::indexing::progress::IndexingProgress;
+use crate::indexing::{chunker, indexer, kb_version, loader, sparse};
+use crate::qdrant_custom_client::QdrantClient;
+
+/// Number of messages buffered between two stages, per unit of concurrency
+const CHANNEL_CAPACITY_FACTOR: usize = 4;
+
+/// A file loaded and chunked by the first stage
+struct LoadedFile {
+    file_name: String,
+    started: Instant,
+    md5: String,
+    chunks: Vec<DocumentChunk>,
+    metadata: Map<String, Value>,
+}
+
+/// A file that could not be loaded by the first stage
+struct LoadError {
+    file_name: String,
+    started: Instant,
+    /// MD5 of the file content, if the file could be read
+    md5: Option<String>,
+    error: String,
+    /// True if the type of the file cannot be indexed (not a loading failure)
+    unsupported: bool,
+}
+
+/// A file whose new chunks are going through the embedding and upsert stages
+struct FileJob {
+    file_name: String,
+    md5: String,
+    metadata: Map<String, Value>,
+    started: Instant,
+    total_chunks: usize,
+    unchanged: usize,
+    shared: usize,
+    deleted: usize,
+    duplicate_of: Option<String>,
+}
+
+/// A chunk waiting for its embedding
+struct ChunkJob {
+    job: Arc<FileJob>,
+    chunk: PendingChunk,
+}
+
+/// A chunk with its embedding (or the embedding error)
+struct EmbeddedChunk {
+    job: Arc<FileJob>,
+    chunk: PendingChunk,
+    embedding: Result<Vec<f32>, AppError>,
+}
+
+/// Progress of a file in the upsert stage
+#[derive(Default)]
+struct UpsertProgress {
+    received: usize,
+    in_batch: usize,
+    stored: Vec<TrackedChunk>,
+    failed_embeddings: Vec<(usize, String)>,
+    error: Option<String>,
+}
+
+/// Result of the indexing of a single file
+#[derive(Debug)]
+pub struct FileOutcome {
+    /// Name of the file
+    pub file_name: String,
+    /// Chunks stored in Qdrant during this run
+    pub stored: Vec<TrackedChunk>,
+    /// Number of chunks already stored in Qdrant and kept as-is
+    pub unchanged: usize,
+    /// Number of new chunks stored in the point of an identical or near-identical chunk of another file
+    pub shared: usize,
+    /// Indexed file with the same content, whose points the file shares
+    pub duplicate_of: Option<String>,
+    /// Number of points of vanished chunks deleted from Qdrant
+    pub deleted: usize,
+    /// Chunks whose embedding failed: (chunk index, error)
+    pub failed_embeddings: Vec<(usize, String)>,
+    /// Error that prevented the file from being indexed, or why its type is unsupported
+    pub error: Option<String>,
+    /// True if the file was skipped because its type cannot be indexed
+    pub unsupported: bool,
+    /// Time spent on the file, from loading to the last upsert
+    pub duration: Duration,
+}
+
+impl FileOutcome {
+    /// Gets the indexing status of the file
+    ///
+    /// A file whose new chunks all failed to be embedded is failed, not partial.
+    pub fn status(&self) -> FileStatus {
+        if self.unsupported {
+            FileStatus::Unsupported
+        } else if self.error.is_some() || (!self.failed_embeddings.is_empty() && self.stored.is_empty()) {
+            FileStatus::Failed
+        } else if !self.failed_embeddings.is_empty() {
+            FileStatus::Partial
+        } else {
+            FileStatus::Ok
+        }
+    }
+
+    /// Gets the error of a failed or partial file
+    pub fn error_message(&self) -> Option<String> {
+        if let Some(error) = &self.error {
+            return Some(error.clone());
+        }
+        let (index, error) = self.failed_embeddings.first()?;
+        Some(format!(
+            "{} chunk embeddings failed (first: chunk {}: {})",
+            self.failed_embeddings.len(),
+            index,
+            error
+        ))
+    }
+
+    fn failed(file_name: String, started: Instant, error: String) -> Self {
+        FileOutcome {
+            file_name,
+            stored: Vec::new(),
+            unchanged: 0,
+            shared: 0,
+            duplicate_of: None,
+            deleted: 0,
+            failed_embeddings: Vec::new(),
+            error: Some(error),
+            unsupported: false,
+            duration: started.elapsed(),
+        }
+    }
+
+    fn unsupported(file_name: String, started: Instant, deleted: usize, reason: String) -> Self {
+        FileOutcome {
+            deleted,
+            unsupported: true,
+            ..FileOutcome::failed(file_name, started, reason)
+        }
+    }
+}
+
+/// Tracking information kept by the coordinator until a file is fully stored
+struct PendingFile {
+    started: Instant,
+    md5: String,
+    unchanged: Vec<TrackedChunk>,
+}
+
+/// Indexes new or changed files through the pipeline
+///
+/// For each file, its chunks are compared with the ones stored during the
+/// previous run, vanished chunks are deleted from Qdrant, new chunks are
+/// embedded and upserted, unless they duplicate a chunk of another file,
+/// and the tracker is updated. Files that fail are recorded as `failed`, or
+/// `partial` with the chunks stored so far, along with the error and the
+/// number of attempts; the next runs retry them while they have fewer than
+/// `max_retry_attempts` attempts, or always with `--retry-failed`, and when
+/// their version changes. Files whose type cannot be indexed are recorded as
+/// `unsupported` and only indexed again once they change.
+///
+/// # Arguments
+/// * `config` - Configuration object
+/// * `qdrant_client` - Qdrant client, with the collection already checked
+/// * `tracker` - File tracker to compare with and update
+/// * `files` - Entries of the data sources to index
+/// * `progress` - Progress bars updated as files and chunks are processed
+///
+/// # Returns
+/// * `Vec<FileOutcome>` - The outcome of each file
+pub async fn index_files(
+    config: Arc<Config>,
+    qdrant_client: Arc<QdrantClient>,
+    tracker: &mut FileTracker,
+    files: Vec<SourceFile>,
+    progress: &IndexingProgress,
+) -> Vec<FileOutcome> {
+    let load_concurrency = config.indexing.load_concurrency.max(1);
+    let embedding_concurrency = config.indexing.embedding_concurrency.max(1);
+
+    let (loaded_tx, mut loaded_rx) = mpsc::channel(load_concurrency * CHANNEL_CAPACITY_FACTOR);
+    let (chunk_tx, chunk_rx) = mpsc::channel(embedding_concurrency * CHANNEL_CAPACITY_FACTOR);
+    let (embedded_tx, embedded_rx) = mpsc::channel(embedding_concurrency * CHANNEL_CAPACITY_FACTOR);
+    let (outcome_tx, mut outcome_rx) = mpsc::unbounded_channel();
+
+    let mut saver = TrackerSaver::new(
+        files
+            .iter()
+            .map(|file| (file.key.clone(), file.entry.version.clone()))
+            .collect(),
+        config.indexing.tracker_save_files,
+        config.indexing.kb_version_path.clone(),
+    );
+    let loader_task = tokio::spawn(load_stage(config.clone(), files, loaded_tx, load_concurrency));
+    let embedding_task = tokio::spawn(embedding_stage(
+        config.clone(),
+        chunk_rx,
+        embedded_tx,
+        embedding_concurrency,
+    ));
+    let upsert_task = tokio::spawn(upsert_stage(
+        config.clone(),
+        qdrant_client.clone(),
+        embedded_rx,
+        outcome_tx,
+        progress.clone(),
+    ));
+
+    let collection_name = &config.qdrant.collection;
+    let mut pending: HashMap<String, PendingFile> = HashMap::new();
+    let mut outcomes = Vec::new();
+    let mut dedup_index = config
+        .indexing
+        .dedup
+        .enabled
+        .then(|| DedupIndex::from_tracker(tracker, &config.indexing.dedup));
+    let mut shared_points = SharedPoints::default();
+    let mut interrupted = std::pin::pin!(tokio::signal::ctrl_c());
+
+    loop {
+        let loaded = tokio::select! {
+            loaded = loaded_rx.recv() => loaded,
+            Ok(()) = &mut interrupted => saver.interrupt(tracker),
+        };
+        let Some(loaded) = loaded else {
+            break;
+        };
+        let loaded: LoadedFile = match loaded {
+            Ok(loaded) => loaded,
+            Err(LoadError {
+                file_name,
+                started,
+                md5: Some(md5),
+                error,
+                unsupported: true,
+            }) => {
+                let outcome = skip_unsupported(
+                    &qdrant_client,
+                    collection_name,
+                    tracker,
+                    (dedup_index.as_mut(), &mut shared_points),
+                    file_name,
+                    started,
+                    md5,
+                    error,
+                )
+                .await;
+                saver.file_done(tracker, &outcome.file_name);
+                progress.file_done(&outcome.file_name);
+                outcomes.push(outcome);
+                continue;
+            }
+            Err(LoadError {
+                file_name,
+                started,
+                md5,
+                error,
+                ..
+            }) => {
+                error!("Failed to load {}: {}", file_name, error);
+                tracker.mark_failed(&file_name, md5, error.clone());
+                saver.file_done(tracker, &file_name);
+                progress.file_done(&file_name);
+                outcomes.push(FileOutcome::failed(file_name, started, error));
+                continue;
+            }
+        };
+
+        // Compare with the chunks indexed during the previous run
+        let previous_md5 = tracker.get_file_md5(&loaded.file_name).cloned();
+        let mut diff = tracker.diff_chunks(&loaded.file_name, &loaded.chunks);
+
+        // Share the points of the chunks already stored for other files
+        let mut duplicate_of = None;
+        let mut shared = Vec::new();
+        if let Some(index) = dedup_index.as_mut() {
+            duplicate_of = tracker
+                .find_same_content(&loaded.md5, &loaded.file_name)
+                .map(str::to_string)
+                .or_else(|| {
+                    pending
+                        .iter()
+                        .find(|(name, file)| file.md5 == loaded.md5 && **name != loaded.file_name)
+                        .map(|(name, _)| name.clone())
+                });
+            if let Some(original) = &duplicate_of {
+                info!("File {} has the same content as {}", loaded.file_name, original);
+            }
+            shared = share_duplicates(index, &mut diff, &loaded.file_name);
+            shared_points.touched.extend(shared.iter().map(|chunk| chunk.point_id.clone()));
+        }
+        shared_points.release(tracker, &loaded.file_name, &mut diff.to_delete, &diff.to_unshare);
+        // The points the file shares record its file hash, which changes with its content
+        if previous_md5.as_deref() != Some(loaded.md5.as_str()) {
+            let still_shared = diff.unchanged.iter().filter(|chunk| chunk.shared);
+            shared_points.touched.extend(still_shared.map(|chunk| chunk.point_id.clone()));
+        }
+        info!(
+            "File {}: {} new chunks, {} shared, {} unchanged, {} vanished",
+            loaded.file_name,
+            diff.to_index.len(),
+            shared.len(),
+            diff.unchanged.len(),
+            diff.to_delete.len() + diff.to_unshare.len()
+        );
+
+        // Remove the points of vanished chunks (or of the whole legacy file),
+        // and record the new file hash and metadata on the points it owns among the unchanged ones
+        let result = match indexer::delete_stale_chunks(&qdrant_client, collection_name, &diff, &loaded.file_name)
+            .await
+        {
+            Ok(()) if previous_md5.as_deref() != Some(loaded.md5.as_str()) => {
+                let owned: Vec<TrackedChunk> = diff.unchanged.iter().filter(|chunk| !chunk.shared).cloned().collect();
+                indexer::update_file_payload(&qdrant_client, collection_name, &owned, &loaded.md5, &loaded.metadata)
+                    .await
+            }
+            result => result,
+        };
+        if let Some(index) = dedup_index.as_mut() {
+            diff.to_delete.iter().for_each(|point_id| index.remove_point(point_id));
+        }
+        if let Err(e) = result {
+            error!("Failed to update previous chunks for {}: {}", loaded.file_name, e);
+            tracker.mark_failed(&loaded.file_name, Some(loaded.md5), e.to_string());
+            saver.file_done(tracker, &loaded.file_name);
+            progress.file_done(&loaded.file_name);
+            outcomes.push(FileOutcome::failed(loaded.file_name, loaded.started, e.to_string()));
+            continue;
+        }
+
+        let ChunkDiff {
+            to_index,
+            mut unchanged,
+            to_delete,
+            ..
+        } = diff;
+        let unchanged_count = unchanged.len();
+        let shared_count = shared.len();
+        unchanged.extend(shared);
+        if to_index.is_empty() {
+            progress.file_done(&loaded.file_name);
+            outcomes.push(FileOutcome {
+                file_name: loaded.file_name.clone(),
+                stored: Vec::new(),
+                unchanged: unchanged_count,
+                shared: shared_count,
+                duplicate_of,
+                deleted: to_delete.len(),
+                failed_embeddings: Vec::new(),
+                error: None,
+                unsupported: false,
+                duration: loaded.started.elapsed(),
+            });
+            tracker.set_file(loaded.file_name.clone(), loaded.md5, unchanged);
+            saver.file_done(tracker, &loaded.file_name);
+            continue;
+        }
+
+        // Send the new chunks to the embedding stage (waits when the stage is saturated)
+        progress.add_chunks(to_index.len());
+        let job = Arc::new(FileJob {
+            file_name: loaded.file_name.clone(),
+            md5: loaded.md5.clone(),
+            metadata: loaded.metadata,
+            started: loaded.started,
+            total_chunks: to_index.len(),
+            unchanged: unchanged_count,
+            shared: shared_count,
+            deleted: to_delete.len(),
+            duplicate_of,
+        });
+        pending.insert(
+            loaded.file_name,
+            PendingFile {
+                started: loaded.started,
+                md5: loaded.md5,
+                unchanged,
+            },
+        );
+        for chunk in to_index {
+            if chunk_tx.send(ChunkJob { job: job.clone(), chunk }).await.is_err() {
+                error!("Embedding stage stopped unexpectedly");
+                break;
+            }
+        }
+
+        // Record the files completed in the meantime
+        while let Ok(outcome) = outcome_rx.try_recv() {
+            apply_outcome(tracker, &mut pending, dedup_index.as_mut(), &mut saver, &outcome);
+            progress.file_done(&outcome.file_name);
+            outcomes.push(outcome);
+        }
+    }
+
+    // No more chunks: let the stages drain and collect the remaining outcomes
+    drop(chunk_tx);
+    loop {
+        let outcome = tokio::select! {
+            outcome = outcome_rx.recv() => outcome,
+            Ok(()) = &mut interrupted => saver.interrupt(tracker),
+        };
+        let Some(outcome) = outcome else {
+            break;
+        };
+        apply_outcome(tracker, &mut pending, dedup_index.as_mut(), &mut saver, &outcome);
+        progress.file_done(&outcome.file_name);
+        outcomes.push(outcome);
+    }
+
+    for (task, name) in [(loader_task, "loading"), (embedding_task, "embedding"), (upsert_task, "upsert")] {
+        if let Err(e) = task.await {
+            error!("Indexing {} stage failed: {}", name, e);
+        }
+    }
+
+    // Files still pending were interrupted by a stage failure
+    for (file_name, file) in pending {
+        let error = "Indexing pipeline interrupted".to_string();
+        tracker.set_file_failed(file_name.clone(), file.md5, file.unchanged, FileStatus::Failed, error.clone());
+        saver.file_done(tracker, &file_name);
+        progress.file_done(&file_name);
+        outcomes.push(FileOutcome::failed(file_name, file.started, error));
+    }
+
+    saver.flush(tracker);
+
+    // Record the files sharing each point whose references changed
+    for (file_name, error) in dedup::sync_shared_points(&qdrant_client, collection_name, tracker, shared_points).await {
+        if let Some(outcome) = outcomes.iter_mut().find(|outcome| outcome.file_name == file_name) {
+            outcome.error = Some(error);
+        }
+    }
+
+    outcomes
+}
+
+/// Replaces the new chunks of a file that duplicate a chunk of another file by references to its point
+///
+/// The other new chunks are added to the index as pending, so that the next
+/// files of the run can share them once their upsert succeeded. The chunks whose parent section is shared are
+/// linked to the shared point.
+///
+/// # Arguments
+/// * `index` - Index of the stored chunks
+/// * `diff` - Chunk differences of the file, whose chunks to index are filtered
+/// * `file_name` - Name of the file
+///
+/// # Returns
+/// * `Vec<TrackedChunk>` - The duplicate chunks, tracked as shared
+fn share_duplicates(index: &mut DedupIndex, diff: &mut ChunkDiff, file_name: &str) -> Vec<TrackedChunk> {
+    let mut shared = Vec::new();
+    let mut to_index = Vec::new();
+    let mut shared_parents: HashMap<String, String> = HashMap::new();
+    for chunk in std::mem::take(&mut diff.to_index) {
+        match index.find(&chunk, file_name) {
+            Some(duplicate) => {
+                if chunk.is_parent {
+                    shared_parents.insert(chunk.point_id.clone(), duplicate.point_id.clone());
+                }
+                info!(
+                    "Chunk {} of {} is {} chunk of {}",
+                    chunk.index,
+                    file_name,
+                    if duplicate.exact { "identical to a" } else { "a near-duplicate of a" },
+                    duplicate.file
+                );
+                shared.push(dedup::shared_chunk(&chunk, &duplicate));
+            }
+            None => {
+                index.insert_pending(&chunk.hash, chunk.simhash, &chunk.point_id, file_name);
+                to_index.push(chunk);
+            }
+        }
+    }
+    diff.to_index = to_index;
+
+    let parents = diff.to_index.iter_mut().map(|chunk| &mut chunk.parent_id);
+    for parent in parents.chain(diff.unchanged.iter_mut().map(|chunk| &mut chunk.parent)).flatten() {
+        if let Some(point_id) = shared_parents.get(parent) {
+            *parent = point_id.clone();
+        }
+    }
+    shared
+}
+
+/// Records a file whose type cannot be indexed, removing the points of its previous version
+///
+/// A file indexed before it became unsupported (or before content sniffing
+/// was introduced) has its previous points deleted, so that the collection
+/// does not keep garbage text extracted from a binary file. The points other
+/// files share are released instead.
+#[allow(clippy::too_many_arguments)]
+async fn skip_unsupported(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    tracker: &mut FileTracker,
+    (dedup_index, shared_points): (Option<&mut DedupIndex>, &mut SharedPoints),
+    file_name: String,
+    started: Instant,
+    md5: String,
+    reason: String,
+) -> FileOutcome {
+    warn!("Skipping unsupported file {}: {}", file_name, reason);
+    let mut diff = tracker.diff_chunks(&file_name, &[]);
+    shared_points.release(tracker, &file_name, &mut diff.to_delete, &diff.to_unshare);
+    let has_points = !diff.to_delete.is_empty() || diff.delete_by_source;
+    if has_points && let Err(e) = indexer::delete_stale_chunks(qdrant_client, collection_name, &diff, &file_name).await {
+        error!("Failed to remove the previous chunks of unsupported file {}: {}", file_name, e);
+        tracker.mark_failed(&file_name, Some(md5), e.to_string());
+        return FileOutcome::failed(file_name, started, e.to_string());
+    }
+    if let Some(index) = dedup_index {
+        diff.to_delete.iter().for_each(|point_id| index.remove_point(point_id));
+    }
+
+    tracker.set_file_unsupported(file_name.clone(), md5, reason.clone());
+    FileOutcome::unsupported(file_name, started, diff.to_delete.len(), reason)
+}
+
+/// Updates the tracker with the outcome of a file that went through the upsert stage
+///
+/// The chunks stored during a failed or partial attempt are recorded as well,
+/// so that the next attempt only indexes the missing ones, and the chunks of
+/// the next files can share their points.
+fn apply_outcome(
+    tracker: &mut FileTracker,
+    pending: &mut HashMap<String, PendingFile>,
+    dedup_index: Option<&mut DedupIndex>,
+    saver: &mut TrackerSaver,
+    outcome: &FileOutcome,
+) {
+    let Some(file) = pending.remove(&outcome.file_name) else {
+        return;
+    };
+    if let Some(index) = dedup_index {
+        outcome.stored.iter().for_each(|chunk| index.mark_stored(&chunk.point_id));
+    }
+    let mut tracked_chunks = file.unchanged;
+    tracked_chunks.extend(outcome.stored.iter().cloned());
+
+    match outcome.status() {
+        FileStatus::Ok => tracker.set_file(outcome.file_name.clone(), file.md5, tracked_chunks),
+        status => tracker.set_file_failed(
+            outcome.file_name.clone(),
+            file.md5,
+            tracked_chunks,
+            status,
+            outcome.error_message().unwrap_or_default(),
+        ),
+    }
+    saver.file_done(tracker, &outcome.file_name);
+}
+
+/// Saves the tracker as the files of a run are completed
+struct TrackerSaver {
+    /// Version of each entry of the run in its data source
+    versions: HashMap<String, String>,
+    /// Number of completed files after which the tracker is saved (`tracker_save_files`)
+    save_every: usize,
+    /// Number of files completed since the last save
+    unsaved: usize,
+    /// Number of files completed during the run
+    completed: usize,
+    /// Knowledge base version file, bumped when the run is interrupted
+    kb_version_path: String,
+}
+
+impl TrackerSaver {
+    fn new(versions: HashMap<String, String>, save_every: usize, kb_version_path: String) -> Self {
+        TrackerSaver {
+            versions,
+            save_every: save_every.max(1),
+            unsaved: 0,
+            completed: 0,
+            kb_version_path,
+        }
+    }
+
+    /// Records the version of a file whose tracking information was just
+    /// updated, and saves the tracker once `save_every` files were completed
+    fn file_done(&mut self, tracker: &mut FileTracker, file_name: &str) {
+        tracker.set_file_version(file_name, self.versions.get(file_name).cloned());
+        self.unsaved += 1;
+        self.completed += 1;
+        if self.unsaved >= self.save_every {
+            self.flush(tracker);
+        }
+    }
+
+    /// Saves the tracker if files were completed since the last save
+    fn flush(&mut self, tracker: &FileTracker) {
+        if self.unsaved > 0 {
+            save_tracker(tracker);
+            self.unsaved = 0;
+        }
+    }
+
+    /// Saves the files completed so far and stops the process, on Ctrl-C
+    ///
+    /// The knowledge base version is bumped if files were completed, so the
+    /// proxy drops the answers cached before them. The files in progress are
+    /// not recorded and are indexed again on the next run.
+    fn interrupt(&mut self, tracker: &FileTracker) -> ! {
+        warn!("Indexing interrupted after {} completed files", self.completed);
+        self.flush(tracker);
+        if self.completed > 0 {
+            match kb_version::bump_version(&self.kb_version_path) {
+                Ok(version) => info!("Knowledge base version updated to {}", version),
+                Err(e) => error!("Failed to update the knowledge base version: {}", e),
+            }
+        }
+        std::process::exit(130)
+    }
+}
+
+/// Saves the tracker, logging a failure
+fn save_tracker(tracker: &FileTracker) {
+    if let Err(e) = tracker.save() {
+        error!("{}", e);
+    }
+}
+
+/// First stage: loads and chunks files on the blocking thread pool
+async fn load_stage(
+    config: Arc<Config>,
+    files: Vec<SourceFile>,
+    loaded_tx: mpsc::Sender<Result<LoadedFile, LoadError>>,
+    load_concurrency: usize,
+) {
+    let detector = Arc::new(LanguageDetector::new(&config.indexing.languages));
+    let mut loads = stream::iter(files)
+        .map(|file| {
+            let config = config.clone();
+            let detector = detector.clone();
+            async move {
+                let started = Instant::now();
+                let file_name = file.key.clone();
+                tokio::task::spawn_blocking(move || load_and_chunk(&config, &detector, file, started))
+                    .await
+                    .unwrap_or_else(|e| {
+                        Err(LoadError {
+                            file_name,
+                            started,
+                            md5: None,
+                            error: format!("Loader task failed: {}", e),
+                            unsupported: false,
+                        })
+                    })
+            }
+        })
+        .buffer_unordered(load_concurrency);
+
+    while let Some(loaded) = loads.next().await {
+        if loaded_tx.send(loaded).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Fetches, hashes, loads and chunks a single file (blocking)
+///
+/// Files of the local filesystem are loaded in place, the content of the
+/// other entries is loaded from a temporary file. The language of the chunks
+/// and of the document is detected, except for source code.
+fn load_and_chunk(
+    config: &Config,
+    detector: &LanguageDetector,
+    file: SourceFile,
+    started: Instant,
+) -> Result<LoadedFile, LoadError> {
+    let file_name = file.key.clone();
+    info!("Processing file: {}", file_name);
+
+    let content = match file.fetch() {
+        Ok(content) => content,
+        Err(e) => {
+            return Err(LoadError {
+                file_name,
+                started,
+                md5: None,
+                error: e.to_string(),
+                unsupported: false,
+            });
+        }
+    };
+    let md5 = format!("{:x}", Md5::digest(&content));
+    let loaded = match file.source.local_path(&file.entry.id) {
+        Some(path) => loader::load_path(&path, &file_name, &config.indexing),
+        None => loader::load_bytes(&content, &file_name, &config.indexing),
+    };
+    drop(content);
+    let mut document = match loaded {
+        Ok(document) => document,
+        Err(AppError::UnsupportedFormat(reason)) => {
+            return Err(LoadError {
+                file_name,
+                started,
+                md5: Some(md5),
+                error: reason,
+                unsupported: true,
+            });
+        }
+        Err(error) => {
+            return Err(LoadError {
+                file_name,
+                started,
+                md5: Some(md5),
+                error: error.to_string(),
+                unsupported: false,
+            });
+        }
+    };
+    let mut chunks = chunker::chunk_document(&document, config.indexing.chunk_size);
+    if config.indexing.parents.enabled && document.code.is_none() {
+        chunker::attach_parents(&mut chunks, &config.indexing.parents);
+    }
+    if document.code.is_none()
+        && let Some(lang) = detector.detect_chunks(&mut chunks)
+    {
+        document.metadata.insert("detected_lang".to_string(), lang.into());
+    }
+    if config.qdrant.sparse_vectors {
+        for chunk in chunks.iter_mut().filter(|chunk| !chunk.is_parent) {
+            chunk.sparse = Some(sparse::document_vector(detector, &chunk.text, chunk.lang));
+        }
+    }
+    document.metadata.extend(file.source.metadata());
+    if !file.source.id().is_empty() {
+        document.metadata.insert("data_source".to_string(), file.source.id().into());
+    }
+
+    Ok(LoadedFile {
+        file_name,
+        started,
+        md5,
+        chunks,
+        metadata: document.metadata,
+    })
+}
+
+/// Third stage: generates the embeddings of the chunks with bounded concurrency
+///
+/// Parent sections are never matched with the question, so they are not
+/// embedded: their points get an all-zero vector of the collection size.
+/// The collection has a single unnamed vector, so the points still have one,
+/// and it is indexed like the others; under the Cosine distance it has no
+/// direction and would score 0 against every question. The sections stay out
+/// of the results because every search (chunks and neighbours) excludes
+/// `kind: "parent"` with a `must_not` filter, and they are only read by ID.
+async fn embedding_stage(
+    config: Arc<Config>,
+    chunk_rx: mpsc::Receiver<ChunkJob>,
+    embedded_tx: mpsc::Sender<EmbeddedChunk>,
+    embedding_concurrency: usize,
+) {
+    let ollama_client = Arc::new(OllamaClient::new(&config));
+    let chunks = stream::unfold(chunk_rx, |mut rx| async move { rx.recv().await.map(|job| (job, rx)) });
+
+    let mut embedded = std::pin::pin!(chunks
+        .map(|ChunkJob { job, chunk }| {
+            let ollama_client = ollama_client.clone();
+            let vector_size = config.qdrant.vector_size;
+            async move {
+                let embedding = if chunk.is_parent {
+                    Ok(vec![0.0; vector_size])
+                } else {
+                    ollama_client.generate_embedding(&chunk.text).await
+                };
+                EmbeddedChunk { job, chunk, embedding }
+            }
+        })
+        .buffer_unordered(embedding_concurrency));
+
+    while let Some(chunk) = embedded.next().await {
+        if embedded_tx.send(chunk).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Fourth stage: upserts the embedded chunks in batches and reports completed files
+async fn upsert_stage(
+    config: Arc<Config>,
+    qdrant_client: Arc<QdrantClient>,
+    mut embedded_rx: mpsc::Receiver<EmbeddedChunk>,
+    outcome_tx: mpsc::UnboundedSender<FileOutcome>,
+    progress: IndexingProgress,
+) {
+    let batch_size = config.indexing.embeddings_chunk_size.max(1);
+    let collection_name = &config.qdrant.collection;
+    let mut files: HashMap<String, UpsertProgress> = HashMap::new();
+    let mut batch: Vec<(Arc<FileJob>, PendingChunk, Vec<f32>)> = Vec::new();
+
+    while let Some(EmbeddedChunk { job, chunk, embedding }) = embedded_rx.recv().await {
+        let file_progress = files.entry(job.file_name.clone()).or_default();
+        file_progress.received += 1;
+
+        match embedding {
+            Ok(embedding) => {
+                file_progress.in_batch += 1;
+                batch.push((job.clone(), chunk, embedding));
+            }
+            Err(e) => {
+                error!("Failed to generate embedding for chunk {} of {}: {}", chunk.index, job.file_name, e);
+                file_progress.failed_embeddings.push((chunk.index, e.to_string()));
+                progress.chunk_done();
+            }
+        }
+
+        if batch.len() >= batch_size {
+            for flushed_job in flush_batch(&qdrant_client, collection_name, &mut batch, &mut files, &progress).await {
+                report_completed(&flushed_job, &mut files, &outcome_tx);
+            }
+        }
+        report_completed(&job, &mut files, &outcome_tx);
+    }
+
+    // Flush the last partial batch and report the remaining files
+    for flushed_job in flush_batch(&qdrant_client, collection_name, &mut batch, &mut files, &progress).await {
+        report_completed(&flushed_job, &mut files, &outcome_tx);
+    }
+}
+
+/// Upserts the current batch and records the stored chunks of each file
+///
+/// # Returns
+/// * `Vec<Arc<FileJob>>` - The files that had chunks in the batch
+async fn flush_batch(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    batch: &mut Vec<(Arc<FileJob>, PendingChunk, Vec<f32>)>,
+    files: &mut HashMap<String, UpsertProgress>,
+    progress: &IndexingProgress,
+) -> Vec<Arc<FileJob>> {
+    let mut flushed_jobs: Vec<Arc<FileJob>> = Vec::new();
+    if batch.is_empty() {
+        return flushed_jobs;
+    }
+
+    let entries = std::mem::take(batch);
+    let points = entries
+        .iter()
+        .map(|(job, chunk, embedding)| {
+            indexer::chunk_point(chunk, &job.file_name, &job.md5, &job.metadata, embedding.clone())
+        })
+        .collect();
+    let result = indexer::upsert_batch(qdrant_client, collection_name, points).await;
+
+    for (job, chunk, _) in entries {
+        if !flushed_jobs.iter().any(|flushed| Arc::ptr_eq(flushed, &job)) {
+            flushed_jobs.push(job.clone());
+        }
+        let file_progress = files.entry(job.file_name.clone()).or_default();
+        file_progress.in_batch -= 1;
+        match &result {
+            Ok(()) => file_progress.stored.push(TrackedChunk {
+                hash: chunk.hash,
+                point_id: chunk.point_id,
+                index: Some(chunk.index),
+                parent: chunk.parent_id,
+                simhash: chunk.simhash.map(dedup::format_simhash),
+                shared: false,
+            }),
+            Err(e) => file_progress.error = Some(e.to_string()),
+        }
+        progress.chunk_done();
+    }
+
+    flushed_jobs
+}
+
+/// Reports a file once all its chunks were received and none is waiting in the batch
+fn report_completed(
+    job: &FileJob,
+    files: &mut HashMap<String, UpsertProgress>,
+    outcome_tx: &mpsc::UnboundedSender<FileOutcome>,
+) {
+    let completed = files
+        .get(&job.file_name)
+        .is_some_and(|p| p.received == job.total_chunks && p.in_batch == 0);
+    if !completed {
+        return;
+    }
+
+    if let Some(file_progress) = files.remove(&job.file_name) {
+        let _ = outcome_tx.send(FileOutcome {
+            file_name: job.file_name.clone(),
+            stored: file_progress.stored,
+            unchanged: job.unchanged,
+            shared: job.shared,
+            duplicate_of: job.duplicate_of.clone(),
+            deleted: job.deleted,
+            failed_embeddings: file_progress.failed_embeddings,
+            error: file_progress.error,
+            unsupported: false,
+            duration: job.started.elapsed(),
+        });
+    }
+}
+
+/// Removes a deleted file from Qdrant and from the tracker
+///
+/// The points of the file that other files share are kept and handed over
+/// to one of them.
+///
+/// # Arguments
+/// * `config` - Configuration object
+/// * `qdrant_client` - Qdrant client
+/// * `tracker` - File tracker to update
+/// * `file_name` - Name of the deleted file
+///
+/// # Returns
+/// * `Result<Option<usize>, AppError>` - The number of points removed if the file was tracked, None otherwise
+pub async fn remove_file(
+    config: &Config,
+    qdrant_client: &QdrantClient,
+    tracker: &mut FileTracker,
+    file_name: &str,
+) -> Result<Option<usize>, AppError> {
+    if tracker.get_file(file_name).is_none() {
+        return Ok(None);
+    }
+    info!("Removing deleted file: {}", file_name);
+
+    let mut diff = tracker.diff_chunks(file_name, &[]);
+    let mut shared_points = SharedPoints::default();
+    shared_points.release(tracker, file_name, &mut diff.to_delete, &diff.to_unshare);
+    indexer::delete_stale_chunks(qdrant_client, &config.qdrant.collection, &diff, file_name).await?;
+    tracker.remove_file(file_name);
+    save_tracker(tracker);
+    dedup::sync_shared_points(qdrant_client, &config.qdrant.collection, tracker, shared_points).await;
+
+    Ok(Some(diff.to_delete.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::data_source::{DataSource, FsSource, list_sources};
+    use serde_json::json;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    /// Lists the files recorded in the tracker file on disk
+    fn saved_files(path: &str) -> Vec<String> {
+        let mut saved = FileTracker::new();
+        saved.load_from_file(path).unwrap();
+        saved.tracked_files()
+    }
+
+    fn tracked_chunks(point_ids: &[&str]) -> Vec<TrackedChunk> {
+        point_ids
+            .iter()
+            .map(|point_id| TrackedChunk {
+                hash: point_id.to_string(),
+                point_id: point_id.to_string(),
+                index: None,
+                parent: None,
+                simhash: None,
+                shared: false,
+            })
+            .collect()
+    }
+
+    fn outcome(file_name: &str, stored: &[&str], failed_embeddings: usize, error: Option<&str>) -> FileOutcome {
+        FileOutcome {
+            stored: tracked_chunks(stored),
+            failed_embeddings: (0..failed_embeddings).map(|index| (index, "timeout".to_string())).collect(),
+            error: error.map(str::to_string),
+            ..FileOutcome::failed(file_name.to_string(), Instant::now(), String::new())
+        }
+    }
+
+    /// Applies the outcome of a file sent through the stages with one unchanged chunk
+    fn apply(tracker: &mut FileTracker, outcome: &FileOutcome) {
+        let mut pending = HashMap::from([(
+            outcome.file_name.clone(),
+            PendingFile {
+                started: Instant::now(),
+                md5: "md5".to_string(),
+                unchanged: tracked_chunks(&["kept"]),
+            },
+        )]);
+        let mut saver = TrackerSaver::new(HashMap::new(), 1, String::new());
+        apply_outcome(tracker, &mut pending, None, &mut saver, outcome);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn outcomes_move_files_between_failed_partial_and_ok() {
+        let mut tracker = FileTracker::new();
+
+        let partial = outcome("a.md", &["stored"], 2, None);
+        assert_eq!(partial.status(), FileStatus::Partial);
+        apply(&mut tracker, &partial);
+        let entry = tracker.get_file("a.md").unwrap();
+        assert_eq!((entry.status, entry.attempts, entry.chunks.len()), (FileStatus::Partial, 1, 2));
+        assert_eq!(entry.error.as_deref(), Some("2 chunk embeddings failed (first: chunk 0: timeout)"));
+
+        let failed = outcome("a.md", &[], 0, Some("Qdrant unavailable"));
+        assert_eq!(failed.status(), FileStatus::Failed);
+        apply(&mut tracker, &failed);
+        let entry = tracker.get_file("a.md").unwrap();
+        assert_eq!((entry.status, entry.attempts), (FileStatus::Failed, 2));
+        assert_eq!(entry.error.as_deref(), Some("Qdrant unavailable"));
+
+        let all_failed = outcome("a.md", &[], 2, None);
+        assert_eq!(all_failed.status(), FileStatus::Failed);
+        apply(&mut tracker, &all_failed);
+        let entry = tracker.get_file("a.md").unwrap();
+        assert_eq!((entry.status, entry.attempts, entry.chunks.len()), (FileStatus::Failed, 3, 1));
+        assert_eq!(entry.error.as_deref(), Some("2 chunk embeddings failed (first: chunk 0: timeout)"));
+
+        let ok = outcome("a.md", &["stored", "missing"], 0, None);
+        assert_eq!(ok.status(), FileStatus::Ok);
+        apply(&mut tracker, &ok);
+        let entry = tracker.get_file("a.md").unwrap();
+        assert_eq!((entry.status, entry.attempts, entry.chunks.len()), (FileStatus::Ok, 0, 3));
+    }
+
+    #[test]
+    fn tracker_is_saved_after_each_file_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.json").to_string_lossy().to_string();
+        let mut tracker = FileTracker::open(&path).unwrap();
+        let versions = HashMap::from([("a.md".to_string(), "v-a".to_string())]);
+        let mut saver = TrackerSaver::new(versions, 1, String::new());
+
+        for name in ["a.md", "b.md"] {
+            tracker.set_file(name.to_string(), "md5".to_string(), Vec::new());
+            saver.file_done(&mut tracker, name);
+        }
+        assert_eq!(saved_files(&path).len(), 2);
+        assert_eq!(tracker.get_file("a.md").unwrap().version.as_deref(), Some("v-a"));
+    }
+
+    #[test]
+    fn tracker_is_saved_by_batches_when_configured_and_on_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tracker.json").to_string_lossy().to_string();
+        let mut tracker = FileTracker::open(&path).unwrap();
+        let mut saver = TrackerSaver::new(HashMap::new(), 3, String::new());
+
+        for (index, name) in ["a.md", "b.md", "c.md", "d.md"].into_iter().enumerate() {
+            tracker.set_file(name.to_string(), "md5".to_string(), Vec::new());
+            saver.file_done(&mut tracker, name);
+            assert_eq!(saved_files(&path).len(), if index < 2 { 0 } else { 3 });
+        }
+        saver.flush(&tracker);
+        assert_eq!(saved_files(&path).len(), 4);
+    }
+
+    /// Requests received by a stand-in server: request path and JSON body
+    type Received = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// Starts a minimal HTTP server answering each request with `respond`
+    ///
+    /// # Arguments
+    /// * `respond` - Gets the status and the JSON body of the response from the path and the body of the request
+    ///
+    /// # Returns
+    /// * `(u16, Received)` - The port of the server and the requests received
+    fn serve(respond: impl Fn(&str, &Value) -> (u16, Value) + Send + 'static) -> (u16, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received: Received = Arc::default();
+        let log = received.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                let body_start = loop {
+                    if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break request.len(),
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..body_start]).to_string();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .and_then(|length| length.trim().parse().ok())
+                    .unwrap_or(0);
+                while request.len() < body_start + length {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+                let body = serde_json::from_slice(&request[body_start..]).unwrap_or(Value::Null);
+                let (status, response) = respond(&path, &body);
+                log.lock().unwrap().push((path, body));
+
+                let response = response.to_string();
+                let reason = if status == 200 { "OK" } else { "Internal Server Error" };
+                let response = format!(
+                    "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    status,
+                    reason,
+                    response.len(),
+                    response
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (port, received)
+    }
+
+    /// Starts a stand-in Ollama server, failing the embedding of the texts that mention `unavailable`
+    fn start_embedder() -> (u16, Received) {
+        serve(|_, body| match body["prompt"].as_str() {
+            Some(prompt) if prompt.contains("unavailable") => (500, json!({ "error": "model unavailable" })),
+            _ => (200, json!({ "embedding": [0.1, 0.2, 0.3, 0.4] })),
+        })
+    }
+
+    /// Starts a stand-in Qdrant server accepting every request
+    fn start_qdrant() -> (QdrantClient, Received) {
+        let (port, received) = serve(|_, _| (200, json!({ "result": { "status": "completed" }, "status": "ok" })));
+        (qdrant_client(port), received)
+    }
+
+    fn qdrant_client(port: u16) -> QdrantClient {
+        QdrantClient::new("127.0.0.1".to_string(), port, String::new(), 4, "Cosine".to_string(), 10, 0.5, false)
+    }
+
+    /// Sizes of the upsert requests received by the stand-in Qdrant server
+    fn upserted_batches(received: &Received) -> Vec<usize> {
+        received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(path, _)| path == "/collections/docs/points?wait=true")
+            .map(|(_, body)| body["points"].as_array().unwrap().len())
+            .collect()
+    }
+
+    /// Configuration indexing `dir` with the stand-in servers, upserting `batch_size` points per request
+    fn config(dir: &Path, embedder_port: u16, batch_size: usize) -> Arc<Config> {
+        let config = format!(
+            r#"
+            [data_sources]
+            path = "{dir}"
+
+            [indexing]
+            path = "{dir}"
+            file_tracker_path = "{dir}/tracker.json"
+            kb_version_path = "{dir}/kb_version.txt"
+            chunk_size = 40
+            embeddings_chunk_size = {batch_size}
+            load_concurrency = 2
+            embedding_concurrency = 2
+
+            [rag_proxy]
+            port = 0
+            host = "127.0.0.1"
+            chat_completion_endpoint = ""
+            system_message_fingerprint_length = 0
+
+            [llm]
+            endpoint = ""
+            model = ""
+            api_key = ""
+
+            [embeddings]
+            endpoint = "http://127.0.0.1:{embedder_port}"
+            model = "test"
+
+            [qdrant]
+            host = "127.0.0.1"
+            port = 0
+            api_key = ""
+            collection = "docs"
+            vector_size = 4
+            distance = "Cosine"
+            limit = 10
+            score_threshold = 0.5
+            "#,
+            dir = dir.display()
+        );
+        Arc::new(toml::from_str(&config).unwrap())
+    }
+
+    /// Chunks of a file not indexed yet
+    fn pending_chunks(file_name: &str, texts: &[&str]) -> Vec<PendingChunk> {
+        let chunks: Vec<DocumentChunk> = texts
+            .iter()
+            .map(|text| DocumentChunk {
+                text: text.to_string(),
+                location: Map::new(),
+                ocr: false,
+                lang: None,
+                parent: None,
+                is_parent: false,
+                sparse: None,
+            })
+            .collect();
+        FileTracker::new().diff_chunks(file_name, &chunks).to_index
+    }
+
+    fn file_job(file_name: &str, total_chunks: usize) -> Arc<FileJob> {
+        Arc::new(FileJob {
+            file_name: file_name.to_string(),
+            md5: "md5".to_string(),
+            metadata: Map::new(),
+            started: Instant::now(),
+            total_chunks,
+            unchanged: 0,
+            shared: 0,
+            deleted: 0,
+            duplicate_of: None,
+        })
+    }
+
+    /// Writes the files of a directory and lists them as the entries of a source
+    fn source_files(dir: &Path, source_id: &str, files: &[(&str, &[u8])]) -> Vec<SourceFile> {
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        let source: Arc<dyn DataSource> = Arc::new(FsSource::new(source_id, dir.to_str().unwrap()));
+        let mut files = list_sources(&[source]).unwrap();
+        files.sort_by(|a, b| a.key.cmp(&b.key));
+        files
+    }
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn loaded_files_are_hashed_chunked_and_attributed_to_their_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = b"First paragraph of the file.\n\nSecond paragraph of the file.";
+        let files = source_files(dir.path(), "docs", &[("a.txt", text), ("image.png", PNG), ("missing.txt", b"gone")]);
+        std::fs::remove_file(dir.path().join("missing.txt")).unwrap();
+        let config = config(dir.path(), 0, 2);
+        let detector = LanguageDetector::new(&config.indexing.languages);
+        let mut files = files.into_iter();
+
+        let Ok(loaded) = load_and_chunk(&config, &detector, files.next().unwrap(), Instant::now()) else {
+            panic!("a.txt could not be loaded");
+        };
+        assert_eq!(loaded.file_name, "docs:a.txt");
+        assert_eq!(loaded.md5, format!("{:x}", Md5::digest(text)));
+        assert_eq!(loaded.chunks.len(), 2);
+        assert_eq!(loaded.metadata["data_source"], "docs");
+
+        let Err(unsupported) = load_and_chunk(&config, &detector, files.next().unwrap(), Instant::now()) else {
+            panic!("image.png was loaded");
+        };
+        assert!(unsupported.unsupported);
+        assert_eq!(unsupported.md5, Some(format!("{:x}", Md5::digest(PNG))));
+
+        let Err(missing) = load_and_chunk(&config, &detector, files.next().unwrap(), Instant::now()) else {
+            panic!("missing.txt was loaded");
+        };
+        assert!(!missing.unsupported && missing.md5.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_saturated_stage_holds_back_the_previous_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let (embedder_port, _) = start_embedder();
+        let (chunk_tx, chunk_rx) = mpsc::channel(1);
+        let (embedded_tx, mut embedded_rx) = mpsc::channel(1);
+        let stage = tokio::spawn(embedding_stage(config(dir.path(), embedder_port, 2), chunk_rx, embedded_tx, 1));
+
+        // Nothing reads the embedded chunks: one waits in the channel, one is held by the stage
+        // waiting for room, one waits in the chunk channel, and the next send blocks
+        let texts: Vec<String> = (0..10).map(|index| format!("chunk {}", index)).collect();
+        let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+        let job = file_job("a.txt", texts.len());
+        let mut sent = 0;
+        for chunk in pending_chunks("a.txt", &texts) {
+            let send = chunk_tx.send(ChunkJob { job: job.clone(), chunk });
+            if tokio::time::timeout(Duration::from_millis(500), send).await.is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        assert_eq!(sent, 3);
+
+        // The stage resumes once the chunks are read
+        drop(chunk_tx);
+        let mut embedded = 0;
+        while let Some(chunk) = embedded_rx.recv().await {
+            assert!(chunk.embedding.is_ok());
+            embedded += 1;
+        }
+        assert_eq!(embedded, sent);
+        stage.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn chunks_are_upserted_in_batches_and_the_last_partial_batch_is_flushed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (client, received) = start_qdrant();
+        let (embedded_tx, embedded_rx) = mpsc::channel(16);
+        let (outcome_tx, mut outcome_rx) = mpsc::unbounded_channel();
+        let a = file_job("a.txt", 4);
+        let b = file_job("b.txt", 2);
+        let mut a_chunks = pending_chunks("a.txt", &["a one", "a two", "a three", "a four"]).into_iter();
+        let mut b_chunks = pending_chunks("b.txt", &["b one", "b two"]).into_iter();
+
+        let embedded = [
+            (&a, a_chunks.next(), Ok(vec![0.1; 4])),
+            (&b, b_chunks.next(), Ok(vec![0.1; 4])),
+            (&a, a_chunks.next(), Ok(vec![0.1; 4])),
+            (&b, b_chunks.next(), Err(AppError::Unknown("timeout".to_string()))),
+            (&a, a_chunks.next(), Ok(vec![0.1; 4])),
+            (&a, a_chunks.next(), Ok(vec![0.1; 4])),
+        ];
+        for (job, chunk, embedding) in embedded {
+            let chunk = chunk.unwrap();
+            embedded_tx.send(EmbeddedChunk { job: job.clone(), chunk, embedding }).await.unwrap();
+        }
+        drop(embedded_tx);
+        upsert_stage(config(dir.path(), 0, 2), Arc::new(client), embedded_rx, outcome_tx, IndexingProgress::hidden())
+            .await;
+
+        assert_eq!(upserted_batches(&received), [2, 2, 1]);
+        let b_outcome = outcome_rx.recv().await.unwrap();
+        assert_eq!((b_outcome.file_name.as_str(), b_outcome.status()), ("b.txt", FileStatus::Partial));
+        assert_eq!((b_outcome.stored.len(), b_outcome.failed_embeddings.len()), (1, 1));
+        let a_outcome = outcome_rx.recv().await.unwrap();
+        assert_eq!((a_outcome.file_name.as_str(), a_outcome.status()), ("a.txt", FileStatus::Ok));
+        assert_eq!(a_outcome.stored.len(), 4);
+        assert!(outcome_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_failed_upsert_fails_every_file_of_the_batch() {
+        let (port, _) = serve(|_, _| (500, json!({ "status": { "error": "No space left on device" } })));
+        let a = file_job("a.txt", 1);
+        let b = file_job("b.txt", 1);
+        let mut batch = Vec::new();
+        let mut files: HashMap<String, UpsertProgress> = HashMap::new();
+        for (job, chunk) in [(&a, "a one"), (&b, "b one")] {
+            let chunk = pending_chunks(&job.file_name, &[chunk]).remove(0);
+            batch.push((job.clone(), chunk, vec![0.1; 4]));
+            files.entry(job.file_name.clone()).or_default().in_batch += 1;
+        }
+
+        let progress = IndexingProgress::hidden();
+        let flushed = flush_batch(&qdrant_client(port), "docs", &mut batch, &mut files, &progress).await;
+        let flushed: Vec<&str> = flushed.iter().map(|job| job.file_name.as_str()).collect();
+        assert_eq!(flushed, ["a.txt", "b.txt"]);
+        assert!(batch.is_empty());
+        for file in files.values() {
+            assert!(file.error.is_some() && file.stored.is_empty() && file.in_batch == 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn files_go_through_the_stages_and_a_load_error_does_not_stop_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        std::fs::create_dir(&docs).unwrap();
+        let files = source_files(
+            &docs,
+            "",
+            &[
+                ("a.txt", b"First paragraph of the file.\n\nSecond paragraph of the file."),
+                ("b.txt", b"The only paragraph of the file."),
+                ("down.txt", b"The embedder is unavailable."),
+                ("image.png", PNG),
+                ("missing.txt", b"Removed before it is loaded."),
+            ],
+        );
+        std::fs::remove_file(docs.join("missing.txt")).unwrap();
+        let (embedder_port, _) = start_embedder();
+        let (client, received) = start_qdrant();
+        let config = config(dir.path(), embedder_port, 2);
+        let mut tracker = FileTracker::open(&config.indexing.file_tracker_path).unwrap();
+
+        let outcomes =
+            index_files(config.clone(), Arc::new(client), &mut tracker, files, &IndexingProgress::hidden()).await;
+
+        let mut statuses: Vec<(&str, FileStatus, usize)> = outcomes
+            .iter()
+            .map(|outcome| (outcome.file_name.as_str(), outcome.status(), outcome.stored.len()))
+            .collect();
+        statuses.sort_by_key(|(file_name, _, _)| *file_name);
+        assert_eq!(
+            statuses,
+            [
+                ("a.txt", FileStatus::Ok, 2),
+                ("b.txt", FileStatus::Ok, 1),
+                ("down.txt", FileStatus::Failed, 0),
+                ("image.png", FileStatus::Unsupported, 0),
+                ("missing.txt", FileStatus::Failed, 0),
+            ]
+        );
+        assert_eq!(upserted_batches(&received), [2, 1]);
+
+        // Every file is tracked, and the tracker was saved
+        let mut saved = saved_files(&config.indexing.file_tracker_path);
+        saved.sort();
+        assert_eq!(saved, ["a.txt", "b.txt", "down.txt", "image.png", "missing.txt"]);
+        assert_eq!(tracker.get_file("a.txt").unwrap().chunks.len(), 2);
+        assert_eq!(tracker.get_file("missing.txt").unwrap().status, FileStatus::Failed);
+        assert_eq!(tracker.get_file("down.txt").unwrap().status, FileStatus::Failed);
+    }
+}
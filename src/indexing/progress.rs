@@ -0,0 +1,75 @@
+//! Progress reporting module for the indexing process.
+//!
+//! This module displays two progress bars on stderr while files go through
+//! the indexing pipeline: one for the files and one for the chunks to embed,
+//! with an estimated time remaining. The chunk total grows as files are
+//! loaded and compared with the tracker. When stderr is not a terminal (CI,
+//! redirected output, watch mode), the bars are hidden and only the logs remain.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+
+/// Progress bars of an indexing run
+#[derive(Clone)]
+pub struct IndexingProgress {
+    files: ProgressBar,
+    chunks: ProgressBar,
+}
+
+impl IndexingProgress {
+    /// Creates the progress bars, visible only if stderr is a terminal
+    ///
+    /// # Arguments
+    /// * `total_files` - Number of files to index
+    pub fn new(total_files: usize) -> Self {
+        if !std::io::stderr().is_terminal() {
+            return Self::hidden();
+        }
+
+        let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
+        let files = multi.add(ProgressBar::new(total_files as u64));
+        files.set_style(bar_style("files "));
+        let chunks = multi.add(ProgressBar::new(0));
+        chunks.set_style(bar_style("chunks"));
+
+        IndexingProgress { files, chunks }
+    }
+
+    /// Creates progress bars that are never displayed
+    pub fn hidden() -> Self {
+        IndexingProgress {
+            files: ProgressBar::hidden(),
+            chunks: ProgressBar::hidden(),
+        }
+    }
+
+    /// Adds chunks to embed to the chunk total
+    pub fn add_chunks(&self, count: usize) {
+        self.chunks.inc_length(count as u64);
+    }
+
+    /// Records a chunk that went through the upsert stage (stored or failed)
+    pub fn chunk_done(&self) {
+        self.chunks.inc(1);
+    }
+
+    /// Records a file that is done (indexed or failed)
+    pub fn file_done(&self, file_name: &str) {
+        self.files.set_message(file_name.to_string());
+        self.files.inc(1);
+    }
+
+    /// Removes the progress bars from the terminal
+    pub fn finish(&self) {
+        self.files.finish_and_clear();
+        self.chunks.finish_and_clear();
+    }
+}
+
+fn bar_style(label: &str) -> ProgressStyle {
+    ProgressStyle::with_template(&format!(
+        "{} [{{elapsed_precise}}] {{bar:40}} {{pos}}/{{len}} (ETA {{eta}}) {{msg}}",
+        label
+    ))
+    .unwrap_or_else(|_| ProgressStyle::default_bar())
+}
@@ -0,0 +1,362 @@
+//! Run report module for the indexing process.
+//!
+//! This module gathers the outcome of every file of an indexing run (indexed,
+//! skipped because unchanged, unsupported, failed, deleted) with chunk
+//! counts (including the chunks shared with duplicate files), embedding
+//! failures and durations, and writes it as a JSON file at the end of the run.
+//! The report is meant to be read by scripts and CI jobs; the indexing binary
+//! also exits with a non-zero status when the report contains failures.
+
+use serde::Serialize;
+use std::fs;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::AppError;
+use crate::indexing::file_tracker::FileStatus;
+use crate::indexing::pipeline::FileOutcome;
+
+/// A chunk whose embedding failed
+#[derive(Debug, Serialize)]
+pub struct EmbeddingFailure {
+    pub chunk_index: usize,
+    pub error: String,
+}
+
+/// Report of a single indexed or failed file
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub file: String,
+    pub status: FileStatus,
+    /// Chunks embedded and upserted during this run
+    pub chunks_indexed: usize,
+    /// Chunks already stored in Qdrant and kept as-is
+    pub chunks_unchanged: usize,
+    /// New chunks stored in the point of a duplicate chunk of another file
+    pub chunks_shared: usize,
+    /// Points of vanished chunks deleted from Qdrant
+    pub chunks_deleted: usize,
+    /// Indexed file with the same content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+    pub embedding_failures: Vec<EmbeddingFailure>,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Report of a file deleted from the data sources
+#[derive(Debug, Serialize)]
+pub struct DeletedFileReport {
+    pub file: String,
+    /// Points removed from Qdrant (unknown for files tracked in the legacy format)
+    pub chunks_deleted: usize,
+}
+
+/// Report of a file skipped because its type cannot be indexed
+#[derive(Debug, Serialize)]
+pub struct UnsupportedFileReport {
+    pub file: String,
+    pub reason: String,
+}
+
+/// Totals of an indexing run
+#[derive(Debug, Default, Serialize)]
+pub struct RunTotals {
+    pub files_indexed: usize,
+    pub files_skipped: usize,
+    pub files_unsupported: usize,
+    pub files_failed: usize,
+    pub files_deleted: usize,
+    pub chunks_indexed: usize,
+    pub chunks_unchanged: usize,
+    pub chunks_shared: usize,
+    pub chunks_deleted: usize,
+    pub embedding_failures: usize,
+}
+
+/// Machine-readable report of an indexing run
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    /// Start of the run, in seconds since the Unix epoch
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub success: bool,
+    pub totals: RunTotals,
+    pub indexed: Vec<FileReport>,
+    pub skipped: Vec<String>,
+    pub unsupported: Vec<UnsupportedFileReport>,
+    pub failed: Vec<FileReport>,
+    pub deleted: Vec<DeletedFileReport>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl Default for RunReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunReport {
+    /// Creates an empty report, starting the run clock
+    pub fn new() -> Self {
+        RunReport {
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            duration_ms: 0,
+            success: true,
+            totals: RunTotals::default(),
+            indexed: Vec::new(),
+            skipped: Vec::new(),
+            unsupported: Vec::new(),
+            failed: Vec::new(),
+            deleted: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Records the outcome of a file that went through the indexing pipeline
+    ///
+    /// A file is reported as failed if it could not be indexed (`failed`) or
+    /// if the embedding of any of its chunks failed (`partial`). Unsupported
+    /// files are reported apart and do not count as failures.
+    pub fn add_outcome(&mut self, outcome: &FileOutcome) {
+        if outcome.unsupported {
+            self.totals.chunks_deleted += outcome.deleted;
+            self.add_unsupported(&outcome.file_name, outcome.error.as_deref().unwrap_or_default());
+            return;
+        }
+
+        let file_report = FileReport {
+            file: outcome.file_name.clone(),
+            status: outcome.status(),
+            chunks_indexed: outcome.stored.len(),
+            chunks_unchanged: outcome.unchanged,
+            chunks_shared: outcome.shared,
+            chunks_deleted: outcome.deleted,
+            duplicate_of: outcome.duplicate_of.clone(),
+            embedding_failures: outcome
+                .failed_embeddings
+                .iter()
+                .map(|(chunk_index, error)| EmbeddingFailure {
+                    chunk_index: *chunk_index,
+                    error: error.clone(),
+                })
+                .collect(),
+            duration_ms: outcome.duration.as_millis() as u64,
+            error: outcome.error.clone(),
+        };
+
+        self.totals.chunks_indexed += file_report.chunks_indexed;
+        self.totals.chunks_unchanged += file_report.chunks_unchanged;
+        self.totals.chunks_shared += file_report.chunks_shared;
+        self.totals.chunks_deleted += file_report.chunks_deleted;
+        self.totals.embedding_failures += file_report.embedding_failures.len();
+
+        if !file_report.status.is_ok() {
+            self.totals.files_failed += 1;
+            self.failed.push(file_report);
+        } else {
+            self.totals.files_indexed += 1;
+            self.indexed.push(file_report);
+        }
+    }
+
+    /// Records a file skipped because it did not change since the previous run
+    pub fn add_skipped(&mut self, file_name: &str) {
+        self.totals.files_skipped += 1;
+        self.skipped.push(file_name.to_string());
+    }
+
+    /// Records a file skipped because its type cannot be indexed
+    pub fn add_unsupported(&mut self, file_name: &str, reason: &str) {
+        self.totals.files_unsupported += 1;
+        self.unsupported.push(UnsupportedFileReport {
+            file: file_name.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Records a file deleted from the data sources and removed from Qdrant
+    pub fn add_deleted(&mut self, file_name: &str, chunks_deleted: usize) {
+        self.totals.files_deleted += 1;
+        self.totals.chunks_deleted += chunks_deleted;
+        self.deleted.push(DeletedFileReport {
+            file: file_name.to_string(),
+            chunks_deleted,
+        });
+    }
+
+    /// Records a deleted file whose points could not be removed from Qdrant
+    pub fn add_failed_removal(&mut self, file_name: &str, error: &AppError) {
+        self.totals.files_failed += 1;
+        self.failed.push(FileReport {
+            file: file_name.to_string(),
+            status: FileStatus::Failed,
+            chunks_indexed: 0,
+            chunks_unchanged: 0,
+            chunks_shared: 0,
+            chunks_deleted: 0,
+            duplicate_of: None,
+            embedding_failures: Vec::new(),
+            duration_ms: 0,
+            error: Some(format!("Failed to remove deleted file: {}", error)),
+        });
+    }
+
+    /// Returns true if any file failed during the run
+    pub fn has_failures(&self) -> bool {
+        self.totals.files_failed > 0
+    }
+
+    /// Returns true if the run changed the content of the collection
+    pub fn has_changes(&self) -> bool {
+        self.totals.chunks_indexed > 0
+            || self.totals.chunks_shared > 0
+            || self.totals.chunks_deleted > 0
+            || self.totals.files_deleted > 0
+    }
+
+    /// Stops the run clock and logs a summary of the run
+    pub fn finish(&mut self) {
+        self.duration_ms = self.started.elapsed().as_millis() as u64;
+        self.success = !self.has_failures();
+
+        info!(
+            "Indexing run: {} indexed, {} skipped, {} unsupported, {} failed, {} deleted files; {} chunks indexed, {} shared, {} deleted in {} ms",
+            self.totals.files_indexed,
+            self.totals.files_skipped,
+            self.totals.files_unsupported,
+            self.totals.files_failed,
+            self.totals.files_deleted,
+            self.totals.chunks_indexed,
+            self.totals.chunks_shared,
+            self.totals.chunks_deleted,
+            self.duration_ms
+        );
+        for file_report in &self.failed {
+            match &file_report.error {
+                Some(error) => warn!("Failed file {}: {}", file_report.file, error),
+                None => warn!(
+                    "Failed file {}: {} chunk embeddings failed",
+                    file_report.file,
+                    file_report.embedding_failures.len()
+                ),
+            }
+        }
+    }
+
+    /// Writes the report to a JSON file
+    ///
+    /// # Arguments
+    /// * `file_path` - Path to the JSON report file
+    ///
+    /// # Returns
+    /// * `Result<(), AppError>` - Ok if successful, error otherwise
+    pub fn save_to_file(&self, file_path: &str) -> Result<(), AppError> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(file_path, json + "\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::file_tracker::TrackedChunk;
+    use std::time::Duration;
+
+    fn outcome(file_name: &str, stored: usize, failed_embeddings: usize, error: Option<&str>) -> FileOutcome {
+        FileOutcome {
+            file_name: file_name.to_string(),
+            stored: (0..stored)
+                .map(|index| TrackedChunk {
+                    hash: format!("hash-{}", index),
+                    point_id: format!("point-{}", index),
+                    index: None,
+                    parent: None,
+                    simhash: None,
+                    shared: false,
+                })
+                .collect(),
+            unchanged: 1,
+            shared: 0,
+            duplicate_of: None,
+            deleted: 0,
+            failed_embeddings: (0..failed_embeddings).map(|index| (index, "timeout".to_string())).collect(),
+            error: error.map(str::to_string),
+            unsupported: false,
+            duration: Duration::from_millis(40),
+        }
+    }
+
+    #[test]
+    fn partial_and_failed_files_are_reported_as_failures() {
+        let mut report = RunReport::new();
+        report.add_outcome(&outcome("ok.md", 3, 0, None));
+        report.add_outcome(&outcome("partial.md", 1, 2, None));
+        report.add_outcome(&outcome("failed.pdf", 0, 0, Some("PDF extraction timed out")));
+        report.add_skipped("same.md");
+        report.add_deleted("old.md", 4);
+        report.finish();
+
+        assert_eq!(report.indexed.len(), 1);
+        let failed: Vec<(&str, FileStatus)> =
+            report.failed.iter().map(|file| (file.file.as_str(), file.status)).collect();
+        assert_eq!(failed, [("partial.md", FileStatus::Partial), ("failed.pdf", FileStatus::Failed)]);
+        assert_eq!(report.failed[0].embedding_failures.len(), 2);
+
+        let totals = &report.totals;
+        let files = (totals.files_indexed, totals.files_failed, totals.files_skipped, totals.files_deleted);
+        assert_eq!(files, (1, 2, 1, 1));
+        assert_eq!((totals.chunks_indexed, totals.chunks_unchanged, totals.chunks_deleted), (4, 3, 4));
+        assert_eq!(totals.embedding_failures, 2);
+        assert!(report.has_failures());
+        assert!(!report.success);
+    }
+
+    #[test]
+    fn unsupported_files_are_neither_failures_nor_changes() {
+        let mut report = RunReport::new();
+        report.add_outcome(&FileOutcome {
+            unsupported: true,
+            ..outcome("scan.pdf", 0, 0, Some("No text found in the PDF file"))
+        });
+        report.add_skipped("same.md");
+        report.finish();
+
+        assert!(report.success);
+        assert!(!report.has_changes());
+        assert_eq!(report.totals.files_unsupported, 1);
+        assert_eq!(report.unsupported[0].reason, "No text found in the PDF file");
+        assert!(report.indexed.is_empty() && report.failed.is_empty());
+    }
+
+    #[test]
+    fn report_is_written_as_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        let mut report = RunReport::new();
+        report.add_outcome(&FileOutcome {
+            duplicate_of: Some("original.md".to_string()),
+            ..outcome("copy.md", 0, 0, None)
+        });
+        report.add_outcome(&outcome("partial.md", 1, 1, None));
+        report.finish();
+        report.save_to_file(path.to_str().unwrap()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["success"], false);
+        assert_eq!(json["indexed"][0]["status"], "ok");
+        assert_eq!(json["indexed"][0]["duplicate_of"], "original.md");
+        assert_eq!(json["failed"][0]["status"], "partial");
+        assert_eq!(json["failed"][0]["embedding_failures"][0]["error"], "timeout");
+        // Optional fields are left out rather than written as null
+        assert!(json["failed"][0].get("duplicate_of").is_none());
+        assert!(json["failed"][0].get("error").is_none());
+        assert!(json.get("started").is_none());
+    }
+}
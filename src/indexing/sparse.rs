@@ -0,0 +1,118 @@
+//! Sparse (BM25) vectors of the chunks and of the questions.
+//!
+//! With `[qdrant] sparse_vectors`, each chunk point also stores a sparse
+//! vector of its terms, searched along with the dense vector and fused with
+//! it by the retriever (hybrid search). The terms are the lowercase words of
+//! the text without the stop words of its language (see
+//! `LanguageDetector::terms`): a French chunk drops "le", "la", "de"... so
+//! that they do not match every French question. Terms are not stemmed.
+//!
+//! Each term is hashed to the index of its dimension. The values of a chunk
+//! vector are the BM25 term frequency weights, normalized by the length of
+//! the chunk; the inverse document frequency is applied by Qdrant (`idf`
+//! modifier of the sparse vectors of the collection). The terms of a question
+//! all have the weight 1.
+
+use md5::{Digest, Md5};
+use std::collections::BTreeMap;
+
+use crate::indexing::language::LanguageDetector;
+use crate::qdrant_custom_client::SparseVector;
+
+/// Term frequency saturation of BM25
+const K1: f32 = 1.2;
+/// Length normalization of BM25
+const B: f32 = 0.75;
+/// Average length of a chunk in terms, for the length normalization
+const AVERAGE_TERMS: f32 = 256.0;
+
+/// Builds the sparse vector of a chunk, weighted with BM25 term frequencies
+///
+/// # Arguments
+/// * `detector` - Language detector, holding the stop words of the languages of the corpus
+/// * `text` - Text of the chunk
+/// * `lang` - ISO 639-1 code of the language of the chunk, if it was detected
+///
+/// # Returns
+/// * `SparseVector` - The vector, empty if the chunk only has stop words
+pub fn document_vector(detector: &LanguageDetector, text: &str, lang: Option<&str>) -> SparseVector {
+    let terms = detector.terms(text, lang);
+    let length = terms.len() as f32;
+    let mut frequencies: BTreeMap<u32, f32> = BTreeMap::new();
+    for term in &terms {
+        *frequencies.entry(term_index(term)).or_default() += 1.0;
+    }
+
+    let norm = K1 * (1.0 - B + B * length / AVERAGE_TERMS);
+    let (indices, values) = frequencies
+        .into_iter()
+        .map(|(index, frequency)| (index, frequency * (K1 + 1.0) / (frequency + norm)))
+        .unzip();
+    SparseVector { indices, values }
+}
+
+/// Builds the sparse vector of a question
+///
+/// # Arguments
+/// * `detector` - Language detector, holding the stop words of the languages of the corpus
+/// * `question` - Text of the question
+/// * `lang` - ISO 639-1 code of the language of the question, if it was detected
+///
+/// # Returns
+/// * `SparseVector` - The vector, with a weight of 1 for each distinct term
+pub fn query_vector(detector: &LanguageDetector, question: &str, lang: Option<&str>) -> SparseVector {
+    let indices: Vec<u32> = detector
+        .terms(question, lang)
+        .iter()
+        .map(|term| term_index(term))
+        .collect::<std::collections::BTreeSet<u32>>()
+        .into_iter()
+        .collect();
+    SparseVector {
+        values: vec![1.0; indices.len()],
+        indices,
+    }
+}
+
+/// Hashes a term to the index of its dimension
+fn term_index(term: &str) -> u32 {
+    let digest = Md5::digest(term.as_bytes());
+    u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> LanguageDetector {
+        LanguageDetector::new(&["fr".to_string(), "en".to_string()])
+    }
+
+    #[test]
+    fn stop_words_of_the_chunk_language_are_left_out() {
+        let vector = document_vector(&detector(), "Le chat de la voisine", Some("fr"));
+        let mut expected = vec![term_index("chat"), term_index("voisine")];
+        expected.sort();
+        assert_eq!(vector.indices, expected);
+    }
+
+    #[test]
+    fn stop_words_of_every_language_are_left_out_without_language() {
+        let vector = query_vector(&detector(), "the cat and le chat", None);
+        let mut expected = vec![term_index("cat"), term_index("chat")];
+        expected.sort();
+        assert_eq!(vector.indices, expected);
+        assert_eq!(vector.values, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn repeated_terms_weigh_more_with_saturation() {
+        let vector = document_vector(&detector(), "rust rust rust cargo", Some("en"));
+        let weight = |term: &str| {
+            let position = vector.indices.iter().position(|index| *index == term_index(term)).unwrap();
+            vector.values[position]
+        };
+        assert!(weight("rust") > weight("cargo"));
+        assert!(weight("rust") < 3.0 * weight("cargo"));
+    }
+}
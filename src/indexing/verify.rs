@@ -0,0 +1,382 @@
+//! Verification module for detecting drift between data sources, tracker and Qdrant.
+//!
+//! The local tracker and the Qdrant collection can drift apart: a reset that
+//! only cleared one of them, a collection deleted by hand, or indexing runs
+//! from several machines. This module compares, file by file, the data
+//! sources, the tracker and the state rebuilt from the collection,
+//! reports the discrepancies, and repairs them by removing the files that no
+//! longer exist and by resetting the tracker entries of drifted files from
+//! the collection, so that the following indexing run re-indexes exactly what
+//! is missing. Removed files release the points they share with other files
+//! (see the `dedup` module) instead of deleting them.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use tracing::{info, warn};
+
+use crate::AppError;
+use crate::Config;
+use crate::indexing::data_source::SourceFile;
+use crate::indexing::file_tracker::FileTracker;
+use crate::indexing::index_state::IndexedState;
+use crate::indexing::pipeline;
+use crate::qdrant_custom_client::QdrantClient;
+
+/// A discrepancy found for a file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// The file is in a data source but neither tracked nor in the collection
+    NotIndexed,
+    /// The file is tracked or in the collection but no longer in its data source
+    /// (`points`: points it owns, its chunks shared with other files are not counted)
+    Orphaned { points: usize },
+    /// The file is tracked but has no points in the collection
+    MissingFromCollection,
+    /// The file has points in the collection but is not tracked
+    MissingFromTracker { points: usize },
+    /// The tracked chunks and the points of the collection differ
+    ChunkMismatch { missing_points: usize, extra_points: usize },
+    /// The collection holds another version of the file than the tracker
+    HashMismatch,
+    /// The collection holds points indexed before chunk tracking
+    LegacyPoints { points: usize },
+    /// The file changed in its data source since it was indexed (fixed by a normal run)
+    Outdated,
+}
+
+/// Discrepancies of a single file
+#[derive(Debug, Serialize)]
+pub struct FileDrift {
+    pub file: String,
+    pub issues: Vec<Discrepancy>,
+}
+
+impl FileDrift {
+    /// Returns true if the file needs a repair (not only a normal indexing run)
+    fn needs_repair(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|issue| !matches!(issue, Discrepancy::NotIndexed | Discrepancy::Outdated))
+    }
+}
+
+/// Result of the comparison between data sources, tracker and collection
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub files_on_disk: usize,
+    pub files_tracked: usize,
+    pub files_in_collection: usize,
+    pub points: usize,
+    pub unattributed_points: usize,
+    pub drifts: Vec<FileDrift>,
+}
+
+impl VerifyReport {
+    /// Returns true if data sources, tracker and collection are consistent
+    ///
+    /// Points without source file are reported but not considered as drift,
+    /// since they may have been written by another tool.
+    pub fn is_consistent(&self) -> bool {
+        self.drifts.is_empty()
+    }
+
+    /// Splits the files needing a repair into orphaned files, to remove, and drifted files, to reset
+    ///
+    /// Files only missing from the index or outdated are left to the
+    /// following indexing run.
+    fn files_to_repair(&self) -> (Vec<&FileDrift>, Vec<&FileDrift>) {
+        self.drifts.iter().filter(|drift| drift.needs_repair()).partition(|drift| {
+            drift
+                .issues
+                .iter()
+                .any(|issue| matches!(issue, Discrepancy::Orphaned { .. }))
+        })
+    }
+
+    /// Logs the discrepancies found
+    pub fn log(&self) {
+        info!(
+            "Verification: {} files in the data sources, {} tracked, {} in collection ({} points)",
+            self.files_on_disk, self.files_tracked, self.files_in_collection, self.points
+        );
+        for drift in &self.drifts {
+            for issue in &drift.issues {
+                warn!("{}: {:?}", drift.file, issue);
+            }
+        }
+        if self.unattributed_points > 0 {
+            warn!("{} points have no source file and were left untouched", self.unattributed_points);
+        }
+        if self.is_consistent() {
+            info!("Data sources, tracker and collection are consistent");
+        }
+    }
+}
+
+/// Compares the entries of the data sources, the tracker and the collection
+///
+/// Entries tracked without a version are fetched to detect whether they are
+/// outdated, so this function blocks.
+///
+/// # Arguments
+/// * `tracker` - File tracker
+/// * `state` - Indexed state of the collection
+/// * `files` - Entries of the data sources
+///
+/// # Returns
+/// * `VerifyReport` - The discrepancies found
+pub fn verify(tracker: &FileTracker, state: &IndexedState, files: &[SourceFile]) -> VerifyReport {
+    let tracked_files = tracker.tracked_files();
+    let listed_files: HashMap<&String, &SourceFile> = files.iter().map(|file| (&file.key, file)).collect();
+    let mut all_files: BTreeSet<&String> = listed_files.keys().copied().collect();
+    all_files.extend(tracked_files.iter());
+    all_files.extend(state.files.keys());
+
+    let mut report = VerifyReport {
+        files_on_disk: listed_files.len(),
+        files_tracked: tracked_files.len(),
+        files_in_collection: state.files.len(),
+        points: state.points,
+        unattributed_points: state.unattributed_points.len(),
+        drifts: Vec::new(),
+    };
+
+    for filename in all_files {
+        let tracked = tracker.get_file(filename);
+        let indexed = state.files.get(filename);
+        let listed = listed_files.get(filename).copied();
+        let mut issues = Vec::new();
+
+        match (listed, tracked, indexed) {
+            (None, None, None) => {}
+            (None, _, indexed) => issues.push(Discrepancy::Orphaned {
+                points: indexed.map_or(0, |file| {
+                    file.chunks.iter().filter(|chunk| !chunk.shared).count() + file.legacy_points
+                }),
+            }),
+            (Some(_), None, None) => issues.push(Discrepancy::NotIndexed),
+            (Some(_), Some(entry), None) => {
+                if !entry.chunks.is_empty() || entry.legacy {
+                    issues.push(Discrepancy::MissingFromCollection);
+                }
+            }
+            (Some(_), None, Some(file)) => issues.push(Discrepancy::MissingFromTracker {
+                points: file.chunks.len() + file.legacy_points,
+            }),
+            (Some(_), Some(entry), Some(file)) => {
+                let tracked_ids: BTreeSet<&str> = entry.chunks.iter().map(|c| c.point_id.as_str()).collect();
+                let stored_ids: BTreeSet<&str> = file.chunks.iter().map(|c| c.point_id.as_str()).collect();
+                let missing_points = tracked_ids.difference(&stored_ids).count();
+                let extra_points = stored_ids.difference(&tracked_ids).count();
+                if !entry.legacy && (missing_points > 0 || extra_points > 0) {
+                    issues.push(Discrepancy::ChunkMismatch {
+                        missing_points,
+                        extra_points,
+                    });
+                }
+                if !entry.md5.is_empty() && file.file_hash.as_deref().is_some_and(|hash| hash != entry.md5) {
+                    issues.push(Discrepancy::HashMismatch);
+                }
+            }
+        }
+
+        if let Some(file) = indexed
+            && file.legacy_points > 0
+            && tracked.is_some_and(|entry| !entry.legacy)
+        {
+            issues.push(Discrepancy::LegacyPoints {
+                points: file.legacy_points,
+            });
+        }
+        if let (Some(file), Some(entry)) = (listed, tracked)
+            && entry.status.is_ok()
+            && !entry.md5.is_empty()
+            && tracker.is_entry_changed(file)
+        {
+            issues.push(Discrepancy::Outdated);
+        }
+
+        if !issues.is_empty() {
+            report.drifts.push(FileDrift {
+                file: filename.clone(),
+                issues,
+            });
+        }
+    }
+
+    report
+}
+
+/// Resets the tracker entries of drifted files from the collection
+///
+/// The MD5 is cleared, so that the next indexing run compares the chunks of
+/// the files with the points actually stored and only indexes the missing ones.
+fn reset_from_collection(tracker: &mut FileTracker, state: &IndexedState, drifted: &[&FileDrift]) {
+    for drift in drifted {
+        info!("Resetting the tracking information of {} from the collection", drift.file);
+        let mut entry = state
+            .files
+            .get(&drift.file)
+            .map(|file| file.to_tracked_file())
+            .unwrap_or_default();
+        entry.md5 = String::new();
+        tracker.insert_file(drift.file.clone(), entry);
+    }
+}
+
+/// Repairs the discrepancies of a verification report
+///
+/// The tracker entries of drifted files are rebuilt from the collection with
+/// an empty MD5, so that the next indexing run compares their chunks with
+/// the points actually stored and only indexes the missing ones. Orphaned
+/// files are then removed like deleted files (`pipeline::remove_file`): their
+/// points are deleted, except the ones other files share, which are handed
+/// over to one of these files, and they are removed from the `sources` of
+/// the points they shared.
+///
+/// # Arguments
+/// * `config` - Configuration object
+/// * `qdrant_client` - Qdrant client
+/// * `tracker` - File tracker to repair
+/// * `state` - Indexed state of the collection
+/// * `report` - Verification report
+///
+/// # Returns
+/// * `Result<usize, AppError>` - Number of files repaired
+pub async fn repair(
+    config: &Config,
+    qdrant_client: &QdrantClient,
+    tracker: &mut FileTracker,
+    state: &IndexedState,
+    report: &VerifyReport,
+) -> Result<usize, AppError> {
+    let (orphaned, drifted) = report.files_to_repair();
+
+    // Rebuild the drifted entries first, so that the points the orphaned files
+    // share are released according to the files that actually reference them
+    reset_from_collection(tracker, state, &drifted);
+
+    for drift in &orphaned {
+        info!("Removing orphaned file: {}", drift.file);
+        // Remove what the collection holds for the file, which the tracker may not know
+        if let Some(file) = state.files.get(&drift.file) {
+            tracker.insert_file(drift.file.clone(), file.to_tracked_file());
+        }
+        pipeline::remove_file(config, qdrant_client, tracker, &drift.file).await?;
+    }
+
+    tracker.save()?;
+    Ok(orphaned.len() + drifted.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::data_source::{DataSource, FsSource, list_sources};
+    use crate::indexing::file_tracker::TrackedChunk;
+    use crate::indexing::index_state::IndexedFile;
+    use std::sync::Arc;
+
+    fn chunk(point_id: &str, shared: bool) -> TrackedChunk {
+        TrackedChunk {
+            hash: format!("hash-{}", point_id),
+            point_id: point_id.to_string(),
+            index: Some(0),
+            parent: None,
+            simhash: None,
+            shared,
+        }
+    }
+
+    fn indexed(file_hash: &str, chunks: Vec<TrackedChunk>) -> IndexedFile {
+        IndexedFile {
+            file_hash: Some(file_hash.to_string()),
+            chunks,
+            legacy_points: 0,
+        }
+    }
+
+    /// Verifies a directory whose files are all indexed but "new.md", against
+    /// a tracker and a collection with one discrepancy per file
+    fn report() -> (tempfile::TempDir, FileTracker, IndexedState, VerifyReport) {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["ok.md", "new.md", "missing.md", "untracked.md", "mismatch.md", "rehashed.md", "changed.md"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let sources: Vec<Arc<dyn DataSource>> = vec![Arc::new(FsSource::new("", dir.path().to_str().unwrap()))];
+        let files = list_sources(&sources).unwrap();
+
+        let mut tracker = FileTracker::new();
+        let mut state = IndexedState::default();
+        for file in &files {
+            let name = file.key.as_str();
+            if name == "new.md" {
+                continue;
+            }
+            tracker.set_file(name.to_string(), "md5".to_string(), vec![chunk(name, false)]);
+            let version = if name == "changed.md" { "old".to_string() } else { file.entry.version.clone() };
+            tracker.set_file_version(name, Some(version));
+            let stored = match name {
+                "missing.md" => continue,
+                "mismatch.md" => vec![chunk("other", false)],
+                _ => vec![chunk(name, false)],
+            };
+            state.files.insert(name.to_string(), indexed(if name == "rehashed.md" { "md5-2" } else { "md5" }, stored));
+        }
+        tracker.remove_file("untracked.md");
+        tracker.set_file("gone.md".to_string(), "md5".to_string(), vec![chunk("gone", false)]);
+        state.files.insert("gone.md".to_string(), indexed("md5", vec![chunk("gone", false), chunk("ok.md", true)]));
+
+        let report = verify(&tracker, &state, &files);
+        (dir, tracker, state, report)
+    }
+
+    fn issues<'a>(report: &'a VerifyReport, file: &str) -> &'a [Discrepancy] {
+        report.drifts.iter().find(|drift| drift.file == file).map_or(&[], |drift| &drift.issues)
+    }
+
+    #[test]
+    fn each_discrepancy_is_reported_for_its_file() {
+        let (_dir, _, _, report) = report();
+        assert!(issues(&report, "ok.md").is_empty());
+        assert_eq!(issues(&report, "new.md"), [Discrepancy::NotIndexed]);
+        assert_eq!(issues(&report, "missing.md"), [Discrepancy::MissingFromCollection]);
+        assert_eq!(issues(&report, "untracked.md"), [Discrepancy::MissingFromTracker { points: 1 }]);
+        assert_eq!(
+            issues(&report, "mismatch.md"),
+            [Discrepancy::ChunkMismatch {
+                missing_points: 1,
+                extra_points: 1
+            }]
+        );
+        assert_eq!(issues(&report, "rehashed.md"), [Discrepancy::HashMismatch]);
+        assert_eq!(issues(&report, "changed.md"), [Discrepancy::Outdated]);
+        // The chunk shared with another file is not counted as an orphaned point
+        assert_eq!(issues(&report, "gone.md"), [Discrepancy::Orphaned { points: 1 }]);
+        assert!(!report.is_consistent());
+    }
+
+    #[test]
+    fn repair_removes_orphans_resets_drifted_files_and_leaves_the_rest_to_indexing() {
+        let (_dir, mut tracker, state, report) = report();
+        let (orphaned, drifted) = report.files_to_repair();
+        let names = |drifts: &[&FileDrift]| -> Vec<String> {
+            let mut names: Vec<String> = drifts.iter().map(|drift| drift.file.clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&orphaned), ["gone.md"]);
+        assert_eq!(names(&drifted), ["mismatch.md", "missing.md", "rehashed.md", "untracked.md"]);
+
+        reset_from_collection(&mut tracker, &state, &drifted);
+        let point_ids = |file: &str| -> Vec<String> {
+            tracker.get_file(file).unwrap().chunks.iter().map(|chunk| chunk.point_id.clone()).collect()
+        };
+        assert_eq!(point_ids("mismatch.md"), ["other"]);
+        assert!(point_ids("missing.md").is_empty());
+        assert_eq!(point_ids("untracked.md"), ["untracked.md"]);
+        assert!(drifted.iter().all(|drift| tracker.get_file_md5(&drift.file).unwrap().is_empty()));
+        assert_eq!(tracker.get_file_md5("ok.md").unwrap(), "md5");
+    }
+}
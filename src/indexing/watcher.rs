@@ -0,0 +1,330 @@
+//! Watch mode module for continuous indexing.
+//!
+//! This module watches the directory trees of the local data sources with
+//! filesystem notifications (notify crate). Bursts of events are debounced,
+//! then the sources touched are listed again: created and modified files are
+//! (re-)indexed, archives are expanded again, and deleted files and archive
+//! members have their points removed from Qdrant, using the same pipeline as
+//! a one-shot run. Listing the sources, expanding archives and comparing
+//! versions block, so they run with `block_in_place`. Sources without a local
+//! directory (archives, object stores, git repositories) are not watched.
+//! The tracker is saved after each debounced batch (and regularly by the
+//! pipeline during long batches), and the knowledge base version is bumped
+//! after each batch, so the proxy's answers reflect document edits within
+//! seconds. Ctrl-C stops watching once the tracker is saved.
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::AppError;
+use crate::Config;
+use crate::indexing::data_source::{ArchiveLimits, DataSource, MEMBER_SEPARATOR, SourceFile, entry_key, expand_archives};
+use crate::indexing::file_tracker::FileTracker;
+use crate::indexing::progress::IndexingProgress;
+use crate::indexing::{kb_version, pipeline};
+use crate::qdrant_custom_client::QdrantClient;
+
+/// Watches the directories of the data sources and indexes changes until the process stops
+///
+/// # Arguments
+/// * `config` - Configuration object
+/// * `qdrant_client` - Qdrant client, with the collection already checked
+/// * `tracker` - File tracker, opened with `FileTracker::open` so that it can be saved
+/// * `sources` - Data sources of the configuration, only the ones with a local directory are watched
+///
+/// # Returns
+/// * `Result<(), AppError>` - Returns on Ctrl-C, or on watcher failure
+pub async fn watch(
+    config: Arc<Config>,
+    qdrant_client: Arc<QdrantClient>,
+    tracker: &mut FileTracker,
+    sources: &[Arc<dyn DataSource>],
+) -> Result<(), AppError> {
+    for source in sources.iter().filter(|source| source.local_root().is_none()) {
+        warn!(
+            "Data source '{}' has no local directory and is not watched, run index_documents again to index its changes",
+            if source.id().is_empty() { "default" } else { source.id() }
+        );
+    }
+    let sources: Vec<(PathBuf, Arc<dyn DataSource>)> = sources
+        .iter()
+        .filter_map(|source| {
+            let root = source.local_root()?;
+            Some((root.canonicalize().unwrap_or_else(|_| root.to_path_buf()), source.clone()))
+        })
+        .collect();
+    if sources.is_empty() {
+        warn!("No data source with a local directory to watch");
+        return Ok(());
+    }
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<notify::Result<Event>>();
+
+    // The notify callback runs on its own thread and forwards events to the async loop
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })
+    .map_err(watch_error)?;
+    for (root, _) in &sources {
+        watcher.watch(root, RecursiveMode::Recursive).map_err(watch_error)?;
+        info!(
+            "Watching '{}' for changes (debounce: {} ms)",
+            root.display(),
+            config.indexing.watch_debounce_ms
+        );
+    }
+    let debounce = Duration::from_millis(config.indexing.watch_debounce_ms);
+    let limits = ArchiveLimits::from_config(&config.indexing);
+
+    loop {
+        let event = tokio::select! {
+            event = receiver.recv() => event,
+            Ok(()) = tokio::signal::ctrl_c() => {
+                info!("Watch mode stopped");
+                save_tracker(tracker);
+                return Ok(());
+            }
+        };
+        let Some(event) = event else {
+            break;
+        };
+        let changed = collect_burst(event, &mut receiver, debounce).await;
+
+        // List the sources touched again, and split the burst into changed
+        // files to index and deleted files to remove (sources may block)
+        let (files, to_remove) = tokio::task::block_in_place(|| collect_changes(limits, &sources, tracker, &changed));
+        let to_index: Vec<SourceFile> = tokio::task::block_in_place(|| {
+            files.iter().filter(|file| tracker.is_entry_changed(file)).cloned().collect()
+        });
+
+        let outcomes = pipeline::index_files(
+            config.clone(),
+            qdrant_client.clone(),
+            tracker,
+            to_index,
+            &IndexingProgress::hidden(),
+        )
+        .await;
+        let mut updated = outcomes
+            .iter()
+            .filter(|outcome| outcome.error.is_none() || outcome.deleted > 0)
+            .count();
+
+        for file_name in to_remove {
+            match pipeline::remove_file(&config, &qdrant_client, tracker, &file_name).await {
+                Ok(Some(_)) => updated += 1,
+                Ok(None) => {}
+                Err(e) => error!("Failed to remove {} from the index: {}", file_name, e),
+            }
+        }
+
+        tracker.record_archives(&files);
+        save_tracker(tracker);
+
+        if updated > 0 {
+            match kb_version::bump_version(&config.indexing.kb_version_path) {
+                Ok(version) => info!("Knowledge base version updated to {}", version),
+                Err(e) => error!("Failed to update knowledge base version: {}", e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the sources touched by a burst of events again
+///
+/// A path touched matches the entries of its source with the same relative
+/// path, or below it when it is a directory. This function blocks.
+///
+/// # Arguments
+/// * `limits` - Limits of the expanded archives
+/// * `sources` - Watched sources, with their canonical root directory
+/// * `tracker` - File tracker
+/// * `changed` - Paths touched during the burst
+///
+/// # Returns
+/// * `(Vec<SourceFile>, Vec<String>)` - Files touched, with the archives expanded, and tracked files to remove
+fn collect_changes(
+    limits: ArchiveLimits,
+    sources: &[(PathBuf, Arc<dyn DataSource>)],
+    tracker: &FileTracker,
+    changed: &BTreeSet<PathBuf>,
+) -> (Vec<SourceFile>, Vec<String>) {
+    let mut touched_files = Vec::new();
+    let mut to_remove = Vec::new();
+    for (root, source) in sources {
+        let touched: Vec<String> = changed.iter().filter_map(|path| relative_id(root, path)).collect();
+        if touched.is_empty() {
+            continue;
+        }
+        let is_touched = |id: &str| touched.iter().any(|path| is_under(id, path));
+        let entries = match source.list() {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to list {}: {}", root.display(), e);
+                continue;
+            }
+        };
+        // Archives touched are expanded again, and their members that are gone removed
+        let files = expand_archives(
+            entries
+                .into_iter()
+                .filter(|entry| is_touched(&entry.id))
+                .map(|entry| SourceFile::new(source, entry))
+                .collect(),
+            limits,
+            tracker,
+        );
+        let listed: HashSet<&str> = files.iter().map(|file| file.key.as_str()).collect();
+        let source_prefix = entry_key(source.id(), "");
+        to_remove.extend(tracker.tracked_files().into_iter().filter(|tracked| {
+            let Some(id) = tracked.strip_prefix(&source_prefix) else {
+                return false;
+            };
+            let id = id.split(MEMBER_SEPARATOR).next().unwrap_or(id);
+            is_touched(id) && !listed.contains(tracked.as_str())
+        }));
+        touched_files.extend(files);
+    }
+    (touched_files, to_remove)
+}
+
+/// Returns the identifier of the entry at a path of a watched directory
+fn relative_id(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<&str> = relative.iter().map(|part| part.to_str()).collect::<Option<_>>()?;
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Returns true if an entry is the path touched or lies below it
+fn is_under(entry_id: &str, path: &str) -> bool {
+    entry_id
+        .strip_prefix(path)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Collects every path touched during a burst of events, until the directories are quiet
+///
+/// # Arguments
+/// * `event` - First event of the burst
+/// * `receiver` - Events forwarded by the watcher
+/// * `debounce` - Time without events ending the burst
+///
+/// # Returns
+/// * `BTreeSet<PathBuf>` - Paths touched during the burst
+async fn collect_burst(
+    event: notify::Result<Event>,
+    receiver: &mut mpsc::UnboundedReceiver<notify::Result<Event>>,
+    debounce: Duration,
+) -> BTreeSet<PathBuf> {
+    let mut changed = BTreeSet::new();
+    collect_paths(event, &mut changed);
+    while let Ok(Some(event)) = tokio::time::timeout(debounce, receiver.recv()).await {
+        collect_paths(event, &mut changed);
+    }
+    changed
+}
+
+/// Adds the paths of the files touched by a filesystem event
+fn collect_paths(event: notify::Result<Event>, changed: &mut BTreeSet<PathBuf>) {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            warn!("Filesystem watch error: {}", e);
+            return;
+        }
+    };
+
+    if matches!(event.kind, EventKind::Access(_)) {
+        return;
+    }
+
+    changed.extend(event.paths);
+}
+
+/// Saves the tracker, logging a failure
+fn save_tracker(tracker: &FileTracker) {
+    if let Err(e) = tracker.save() {
+        error!("{}", e);
+    }
+}
+
+fn watch_error(e: notify::Error) -> AppError {
+    AppError::Unknown(format!("Filesystem watcher error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexing::data_source::FsSource;
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RemoveKind};
+
+    fn event(kind: EventKind, path: &Path) -> notify::Result<Event> {
+        Ok(Event::new(kind).add_path(path.to_path_buf()))
+    }
+
+    #[tokio::test]
+    async fn burst_ends_once_the_directories_are_quiet() {
+        let root = Path::new("/data");
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        sender.send(event(EventKind::Modify(ModifyKind::Any), &root.join("a.md"))).unwrap();
+        sender.send(event(EventKind::Access(AccessKind::Any), &root.join("read.md"))).unwrap();
+        sender.send(Err(notify::Error::generic("overflow"))).unwrap();
+        let late_sender = sender.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            sender.send(event(EventKind::Remove(RemoveKind::Any), &root.join("b.md"))).unwrap();
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            late_sender.send(event(EventKind::Create(CreateKind::Any), &root.join("late.md"))).unwrap();
+        });
+
+        let first = event(EventKind::Create(CreateKind::Any), &root.join("a.md"));
+        let changed = collect_burst(first, &mut receiver, Duration::from_millis(200)).await;
+        assert_eq!(changed, BTreeSet::from([root.join("a.md"), root.join("b.md")]));
+
+        // The next event starts the next burst
+        let next = receiver.recv().await.unwrap().unwrap();
+        assert_eq!(next.paths, [root.join("late.md")]);
+    }
+
+    #[test]
+    fn touched_paths_match_entries_below_them_only() {
+        let root = Path::new("/data");
+        assert_eq!(relative_id(root, &root.join("dir/a.md")).as_deref(), Some("dir/a.md"));
+        assert_eq!(relative_id(root, root), None);
+        assert_eq!(relative_id(root, Path::new("/other/a.md")), None);
+        assert!(is_under("dir/a.md", "dir"));
+        assert!(is_under("dir/a.md", "dir/a.md"));
+        assert!(!is_under("directory/a.md", "dir"));
+    }
+
+    #[test]
+    fn deleted_files_below_a_touched_directory_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/new.md"), "new").unwrap();
+        std::fs::write(root.join("untouched.md"), "untouched").unwrap();
+        let source: Arc<dyn DataSource> = Arc::new(FsSource::new("docs", root.to_str().unwrap()));
+        let mut tracker = FileTracker::new();
+        for key in ["docs:sub/gone.md", "docs:gone.md", "docs:untouched.md", "other:sub/gone.md"] {
+            tracker.set_file(key.to_string(), "md5".to_string(), Vec::new());
+        }
+
+        let limits = ArchiveLimits {
+            max_depth: 0,
+            max_size: 0,
+        };
+        let changed = BTreeSet::from([root.join("sub")]);
+        let (files, to_remove) = collect_changes(limits, &[(root.clone(), source)], &tracker, &changed);
+        let keys: Vec<&str> = files.iter().map(|file| file.key.as_str()).collect();
+        assert_eq!(keys, ["docs:sub/new.md"]);
+        assert_eq!(to_remove, ["docs:sub/gone.md"]);
+    }
+}
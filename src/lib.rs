@@ -4,6 +4,7 @@
 //! used by both the indexing binary and the RAG proxy server.
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use tracing_subscriber::FmtSubscriber;
 
@@ -25,19 +26,307 @@ pub struct Config {
     pub llm: LlmConfig,
     pub embeddings: EmbeddingsConfig,
     pub qdrant: QdrantConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub semantic_cache: SemanticCacheConfig,
+    #[serde(default)]
+    pub retrieval: RetrievalConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DataSourcesConfig {
     pub path: String,
+    /// Git repository whose files are indexed, instead of the files of the directory
+    #[serde(default)]
+    pub git: Option<GitSourceConfig>,
+    /// Named data sources indexed together, replacing the directory (or `git`) source when set
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A named data source, whose entries are tracked as `<id>:<entry>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceConfig {
+    /// Identifier of the source, unique in the configuration
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: SourceKind,
+}
+
+/// Type and settings of a data source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SourceKind {
+    /// Files of a local directory and of its subdirectories
+    Fs { path: String },
+    /// Files of a local ZIP or tar (optionally gzipped) archive
+    Archive { path: String },
+    /// Objects of an S3-compatible bucket
+    S3(S3SourceConfig),
+    /// Files of a commit of a local git repository
+    Git(GitSourceConfig),
+}
+
+/// Bucket of an S3-compatible object store (AWS S3, MinIO...)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3SourceConfig {
+    /// URL of the object store, e.g. `http://localhost:9000` (path-style requests)
+    pub endpoint: String,
+    pub bucket: String,
+    /// Only the objects whose key starts with this prefix are indexed
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// Credentials used to sign the requests, anonymous requests without them
+    #[serde(default)]
+    pub access_key: Option<String>,
+    #[serde(default)]
+    pub secret_key: Option<String>,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// Local git repository indexed at a given ref
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitSourceConfig {
+    /// Path of the repository (bare or with a working tree)
+    pub repository: String,
+    /// Branch, tag or commit to index
+    #[serde(rename = "ref", default = "default_git_ref")]
+    pub reference: String,
+}
+
+fn default_git_ref() -> String {
+    "HEAD".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexingConfig {
     pub path: String,
     pub file_tracker_path: String,
     pub chunk_size: usize,
     pub embeddings_chunk_size: usize,
+    #[serde(default = "default_kb_version_path")]
+    pub kb_version_path: String,
+    #[serde(default = "default_watch_debounce_ms")]
+    pub watch_debounce_ms: u64,
+    #[serde(default = "default_load_concurrency")]
+    pub load_concurrency: usize,
+    #[serde(default = "default_embedding_concurrency")]
+    pub embedding_concurrency: usize,
+    #[serde(default = "default_report_path")]
+    pub report_path: String,
+    #[serde(default = "default_max_retry_attempts")]
+    pub max_retry_attempts: u32,
+    /// Number of completed files after which the tracker is saved (1 saves it after each file)
+    #[serde(default = "default_tracker_save_files")]
+    pub tracker_save_files: usize,
+    #[serde(default)]
+    pub tracker_source: TrackerSource,
+    #[serde(default = "default_pdf_max_size_mb")]
+    pub pdf_max_size_mb: u64,
+    #[serde(default = "default_pdf_timeout_secs")]
+    pub pdf_timeout_secs: u64,
+    /// Maximum nesting depth of the archives whose members are indexed (0 disables archive expansion)
+    #[serde(default = "default_archive_max_depth")]
+    pub archive_max_depth: usize,
+    /// Maximum uncompressed size of an archive, nested archives included (MB)
+    #[serde(default = "default_archive_max_size_mb")]
+    pub archive_max_size_mb: u64,
+    /// OCR of scanned PDF pages and images
+    #[serde(default)]
+    pub ocr: OcrConfig,
+    /// Deduplication of identical files and near-duplicate chunks
+    #[serde(default)]
+    pub dedup: DedupConfig,
+    /// Parent sections stored with the chunks, injected as context instead of the matched chunks
+    #[serde(default)]
+    pub parents: ParentConfig,
+    /// Languages of the corpus (ISO 639-1 codes), detected on the documents and chunks
+    #[serde(default = "default_languages")]
+    pub languages: Vec<String>,
+    /// Loaders forced for file extensions (lowercase, without the dot)
+    #[serde(default)]
+    pub loaders: HashMap<String, LoaderKind>,
+}
+
+/// Where the indexing process reads what is already indexed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackerSource {
+    /// The local tracker file
+    #[default]
+    File,
+    /// The payloads of the points of the Qdrant collection
+    Qdrant,
+}
+
+/// OCR of the PDF pages without text layer and of image files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OcrConfig {
+    pub enabled: bool,
+    pub engine: OcrEngineKind,
+    /// Command of the OCR engine (name in the PATH or full path)
+    pub command: String,
+    /// Languages of the documents, in the format of the engine (`eng+fra` for Tesseract)
+    pub languages: String,
+    /// PDF pages with fewer non-whitespace characters than this are recognized with OCR
+    pub min_page_chars: usize,
+    /// Maximum duration of the recognition of an image
+    pub timeout_secs: u64,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        OcrConfig {
+            enabled: false,
+            engine: OcrEngineKind::Tesseract,
+            command: "tesseract".to_string(),
+            languages: "eng".to_string(),
+            min_page_chars: 20,
+            timeout_secs: 120,
+        }
+    }
+}
+
+/// Deduplication of the chunks stored in Qdrant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DedupConfig {
+    /// Chunks identical to a stored chunk share its point instead of getting their own
+    pub enabled: bool,
+    /// Maximum Hamming distance between the SimHash of two near-duplicate chunks sharing a point,
+    /// None to only share the points of identical chunks
+    pub near_duplicate_distance: Option<u32>,
+}
+
+impl Default for DedupConfig {
+    /// Identical chunks share their point by default; near-duplicate matching
+    /// is opted into, since a near-duplicate chunk is answered with the text
+    /// of the chunk whose point it shares
+    fn default() -> Self {
+        DedupConfig {
+            enabled: true,
+            near_duplicate_distance: None,
+        }
+    }
+}
+
+/// Parent sections of the chunks (hierarchical retrieval)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParentConfig {
+    /// Stores the parent sections of the chunks as points linked to their chunks
+    pub enabled: bool,
+    /// How consecutive chunks are grouped into parent sections
+    pub mode: ParentMode,
+    /// Maximum size of a parent section in characters
+    pub max_size: usize,
+}
+
+impl Default for ParentConfig {
+    fn default() -> Self {
+        ParentConfig {
+            enabled: false,
+            mode: ParentMode::Section,
+            max_size: 2048,
+        }
+    }
+}
+
+/// Grouping of the chunks into parent sections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParentMode {
+    /// Consecutive chunks of the same section (heading, page, slide...)
+    #[default]
+    Section,
+    /// Consecutive chunks, whatever their section
+    Fixed,
+}
+
+/// OCR engine used to recognize the text of images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrEngineKind {
+    /// Local Tesseract command line
+    #[default]
+    Tesseract,
+}
+
+/// Document loader selected for a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoaderKind {
+    Text,
+    Pdf,
+    Docx,
+    Html,
+    Markdown,
+    Pptx,
+    Spreadsheet,
+    Odt,
+    Epub,
+    Rtf,
+    /// Source code, chunked at symbol boundaries (language from the extension)
+    Code,
+    /// Image (PNG, JPEG, TIFF) whose text is recognized with OCR
+    Image,
+    /// The file is not indexed
+    Skip,
+}
+
+fn default_kb_version_path() -> String {
+    "kb_version.txt".to_string()
+}
+
+fn default_watch_debounce_ms() -> u64 {
+    500
+}
+
+fn default_load_concurrency() -> usize {
+    2
+}
+
+fn default_embedding_concurrency() -> usize {
+    4
+}
+
+fn default_report_path() -> String {
+    "index_report.json".to_string()
+}
+
+fn default_max_retry_attempts() -> u32 {
+    3
+}
+
+fn default_tracker_save_files() -> usize {
+    1
+}
+
+fn default_pdf_max_size_mb() -> u64 {
+    100
+}
+
+fn default_pdf_timeout_secs() -> u64 {
+    120
+}
+
+fn default_archive_max_depth() -> usize {
+    3
+}
+
+fn default_archive_max_size_mb() -> u64 {
+    1024
+}
+
+fn default_languages() -> Vec<String> {
+    vec!["fr".to_string(), "en".to_string()]
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -59,6 +348,8 @@ pub struct LlmConfig {
 pub struct EmbeddingsConfig {
     pub endpoint: String,
     pub model: String,
+    #[serde(default)]
+    pub cache_path: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -71,6 +362,105 @@ pub struct QdrantConfig {
     pub distance: String,
     pub limit: u64,
     pub score_threshold: f32,
+    /// Stores a sparse (BM25) vector of the chunk terms with each point, and searches it along with the dense vector
+    #[serde(default)]
+    pub sparse_vectors: bool,
+}
+
+/// Per-client rate limits and global concurrency limits of the RAG proxy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub requests_per_minute: u32,
+    pub llm_tokens_per_day: u64,
+    pub max_concurrent_embeddings: usize,
+    pub max_concurrent_llm: usize,
+    /// API keys (`Authorization: Bearer`) identifying the clients; requests without one of these keys are limited by IP address
+    pub api_keys: Vec<String>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: false,
+            requests_per_minute: 60,
+            llm_tokens_per_day: 1_000_000,
+            max_concurrent_embeddings: 0,
+            max_concurrent_llm: 0,
+            api_keys: Vec::new(),
+        }
+    }
+}
+
+/// Semantic cache of LLM answers in the RAG proxy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SemanticCacheConfig {
+    pub enabled: bool,
+    pub similarity_threshold: f32,
+    pub ttl_seconds: u64,
+    pub max_entries: usize,
+    pub bypass_header: String,
+}
+
+impl Default for SemanticCacheConfig {
+    fn default() -> Self {
+        SemanticCacheConfig {
+            enabled: false,
+            similarity_threshold: 0.95,
+            ttl_seconds: 3600,
+            max_entries: 1000,
+            bypass_header: "x-rag-cache-bypass".to_string(),
+        }
+    }
+}
+
+/// Selection of the chunks injected as context by the RAG proxy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetrievalConfig {
+    /// How the language of the question is matched with the language of the chunks
+    pub language_mode: LanguageMode,
+    /// Factor applied to the score of the chunks in the language of the question (`boost` mode)
+    pub language_boost: f32,
+    /// Reorders the chunks with Maximal Marginal Relevance, so that similar chunks do not crowd out the others
+    pub mmr: bool,
+    /// Trade-off between relevance (1.0) and diversity (0.0) of the chunks selected by MMR
+    pub mmr_lambda: f32,
+    /// Maximum number of chunks of the same source file (0 for no limit)
+    pub max_chunks_per_source: usize,
+    /// Number of candidates searched per returned chunk when the chunks are diversified
+    pub diversity_candidates: u64,
+    /// Number of chunks before and after each retrieved chunk added from its file (0 to disable)
+    pub neighbor_chunks: usize,
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        RetrievalConfig {
+            language_mode: LanguageMode::Off,
+            language_boost: 1.2,
+            mmr: false,
+            mmr_lambda: 0.7,
+            max_chunks_per_source: 0,
+            diversity_candidates: 4,
+            neighbor_chunks: 0,
+        }
+    }
+}
+
+/// Matching of the language of the question with the language of the chunks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LanguageMode {
+    /// The language is ignored
+    #[default]
+    Off,
+    /// The chunks in the language of the question are ranked higher
+    Boost,
+    /// Only the chunks in the language of the question, or without language, are retrieved
+    Filter,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -91,14 +481,32 @@ pub enum AppError {
     Pdf(String),
     #[error("DOCX extraction error: {0}")]
     Docx(String),
+    #[error("Document extraction error: {0}")]
+    Document(String),
+    #[error("OCR error: {0}")]
+    Ocr(String),
+    #[error("Unsupported file type: {0}")]
+    UnsupportedFormat(String),
+    #[error("Git error: {0}")]
+    Git(String),
+    #[error("Object store error: {0}")]
+    ObjectStore(String),
     #[error("LLM error: {0}")]
     Llm(String),
+    #[error("Cache error: {0}")]
+    Cache(String),
+    #[error("Rate limit exceeded: {message}")]
+    RateLimited { message: String, retry_after: u64 },
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
 impl axum::response::IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        let retry_after = match &self {
+            AppError::RateLimited { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        };
         let (status, error_message) = match self {
             AppError::Io(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             AppError::Toml(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
@@ -108,7 +516,14 @@ impl axum::response::IntoResponse for AppError {
             AppError::Config(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e),
             AppError::Pdf(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e),
             AppError::Docx(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e),
+            AppError::Document(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e),
+            AppError::Ocr(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e),
+            AppError::UnsupportedFormat(e) => (axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE, e),
+            AppError::Git(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e),
+            AppError::ObjectStore(e) => (axum::http::StatusCode::BAD_GATEWAY, e),
             AppError::Llm(e) => (axum::http::StatusCode::BAD_GATEWAY, e),
+            AppError::Cache(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e),
+            AppError::RateLimited { message, .. } => (axum::http::StatusCode::TOO_MANY_REQUESTS, message),
             AppError::Unknown(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e),
         };
 
@@ -119,7 +534,13 @@ impl axum::response::IntoResponse for AppError {
             }
         });
 
-        (status, axum::Json(body)).into_response()
+        let mut response = (status, axum::Json(body)).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(axum::http::header::RETRY_AFTER, axum::http::HeaderValue::from(seconds));
+        }
+        response
     }
 }
 
@@ -18,6 +18,9 @@ pub struct QdrantClient {
     pub distance: String,
     pub limit: u64,
     pub score_threshold: f32,
+    /// The collection stores a sparse (BM25) vector per point, besides the dense vector
+    #[serde(default)]
+    pub sparse_vectors: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,6 +64,18 @@ pub struct SparseVectorIndex {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateCollectionRequest {
     pub vectors: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparse_vectors: Option<serde_json::Value>,
+}
+
+/// Name of the sparse (BM25) vector of the points, when the collection stores one
+pub const SPARSE_VECTOR_NAME: &str = "bm25";
+
+// https://qdrant.tech/documentation/concepts/vectors/#sparse-vectors
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -92,21 +107,109 @@ pub struct SearchPointsResult {
 pub struct SearchPointsPayload {
     pub source: String,
     pub text: String,
+    /// Language of the chunk, if it was detected
+    #[serde(default)]
+    pub lang: Option<String>,
+    /// Position of the chunk in its source file
+    #[serde(default)]
+    pub chunk_index: Option<u64>,
+    /// ID of the point of the parent section of the chunk
+    #[serde(default)]
+    pub parent_id: Option<String>,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ScoredPoint {
     pub id: serde_json::Value,
+    /// Dense vector of the point, also read from the named vectors of a collection with sparse vectors
+    #[serde(default, deserialize_with = "deserialize_dense_vector")]
     pub vector: Option<Vec<f32>>,
     pub payload: Option<SearchPointsPayload>,
     pub score: f32,
     pub version: u64,
 }
 
+// https://api.qdrant.tech/api-reference/points/scroll-points
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScrollPointsRequest {
+    pub limit: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<serde_json::Value>,
+    pub with_payload: serde_json::Value,
+    pub with_vector: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScrollPointsResponse {
+    pub result: ScrollPointsResult,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScrollPointsResult {
+    pub points: Vec<Record>,
+    pub next_page_offset: Option<serde_json::Value>,
+}
+
+/// A point returned by a scroll or retrieved by ID, without its vector
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub id: serde_json::Value,
+    #[serde(default)]
+    pub payload: Option<serde_json::Value>,
+}
+
+impl Record {
+    /// Gets the point ID as a string (UUID or unsigned integer)
+    pub fn id_string(&self) -> String {
+        match &self.id {
+            serde_json::Value::String(id) => id.clone(),
+            id => id.to_string(),
+        }
+    }
+
+    /// Gets a string field of the payload
+    pub fn payload_str(&self, key: &str) -> Option<&str> {
+        self.payload.as_ref()?.get(key)?.as_str()
+    }
+
+    /// Gets an unsigned integer field of the payload
+    pub fn payload_u64(&self, key: &str) -> Option<u64> {
+        self.payload.as_ref()?.get(key)?.as_u64()
+    }
+}
+
+// https://api.qdrant.tech/api-reference/points/get-points
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetPointsResponse {
+    pub result: Vec<Record>,
+}
+
+/// Reads the dense vector of a scored point
+///
+/// The vector is a plain list in a collection with a single vector, and the
+/// unnamed (`""`) entry of the named vectors when the collection also stores
+/// sparse vectors.
+fn deserialize_dense_vector<'de, D>(deserializer: D) -> Result<Option<Vec<f32>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let vector = match Option::<serde_json::Value>::deserialize(deserializer)? {
+        Some(serde_json::Value::Object(mut vectors)) => vectors.remove(""),
+        vector => vector,
+    };
+    match vector {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(vector) => serde_json::from_value(vector).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
 // https://api.qdrant.tech/api-reference/points/upsert-points
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Point {
     pub id: serde_json::Value,
-    pub vector: Vec<f32>,
+    /// Dense vector, or named vectors (`""` for the dense vector) when the point has a sparse vector
+    pub vector: serde_json::Value,
     pub payload: Option<serde_json::Value>,
 }
 
@@ -118,7 +221,7 @@ impl Point {
     ) -> Self {
         Point {
             id,
-            vector,
+            vector: serde_json::json!(vector),
             payload,
         }
     }
@@ -126,10 +229,17 @@ impl Point {
     pub fn from_id_vector_payload(id: &str, vector: Vec<f32>, payload: serde_json::Value) -> Self {
         Point {
             id: serde_json::Value::String(id.to_string()),
-            vector,
+            vector: serde_json::json!(vector),
             payload: Some(payload),
         }
     }
+
+    /// Adds the sparse (BM25) vector of the point, next to its dense vector
+    pub fn with_sparse_vector(mut self, sparse: &SparseVector) -> Self {
+        let dense = std::mem::take(&mut self.vector);
+        self.vector = serde_json::json!({ "": dense, SPARSE_VECTOR_NAME: sparse });
+        self
+    }
 }
 
 impl QdrantClient {
@@ -143,9 +253,11 @@ impl QdrantClient {
     /// * `distance` - The distance metric to use for vector similarity
     /// * `limit` - The max number of result to return
     /// * `score_threshold` - Only the points with score better than the threshold are returned
+    /// * `sparse_vectors` - Whether the collection stores a sparse (BM25) vector per point
     ///
     /// # Returns
     /// * `QdrantClient` - A new instance of the Qdrant client
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         host: String,
         port: u16,
@@ -154,6 +266,7 @@ impl QdrantClient {
         distance: String,
         limit: u64,
         score_threshold: f32,
+        sparse_vectors: bool,
     ) -> Self {
         QdrantClient {
             host,
@@ -163,6 +276,7 @@ impl QdrantClient {
             distance,
             limit,
             score_threshold,
+            sparse_vectors,
         }
     }
 
@@ -261,12 +375,7 @@ impl QdrantClient {
             self.host, self.port, collection_name
         );
 
-        let request_body = CreateCollectionRequest {
-            vectors: serde_json::json!({
-                "size": self.vector_size,
-                "distance": self.distance
-            }),
-        };
+        let request_body = self.create_collection_request();
 
         let response = client
             .put(&url)
@@ -280,6 +389,22 @@ impl QdrantClient {
         Ok(result.result)
     }
 
+    /// Builds the body of a collection creation request
+    ///
+    /// The sparse vectors get the `idf` modifier, so that Qdrant weights the
+    /// terms of the BM25 vectors by their inverse document frequency.
+    fn create_collection_request(&self) -> CreateCollectionRequest {
+        CreateCollectionRequest {
+            vectors: serde_json::json!({
+                "size": self.vector_size,
+                "distance": self.distance
+            }),
+            sparse_vectors: self.sparse_vectors.then(|| {
+                serde_json::json!({ SPARSE_VECTOR_NAME: { "modifier": "idf" } })
+            }),
+        }
+    }
+
     /// Blocking version of create_collection for synchronous contexts
     ///
     /// # Arguments
@@ -297,12 +422,7 @@ impl QdrantClient {
             self.host, self.port, collection_name
         );
 
-        let request_body = CreateCollectionRequest {
-            vectors: serde_json::json!({
-                "size": self.vector_size,
-                "distance": self.distance
-            }),
-        };
+        let request_body = self.create_collection_request();
 
         let response = client
             .put(&url)
@@ -460,17 +580,337 @@ impl QdrantClient {
         Ok(status == 200)
     }
 
+    /// Upserts already built points (with their own IDs and payloads) into a Qdrant collection
+    ///
+    /// # Arguments
+    /// * `collection_name` - Name of the collection to upsert points into
+    /// * `points` - Points to upsert
+    ///
+    /// # Returns
+    /// * `Result<bool, AppError>` - True if points were upserted successfully, false otherwise, or error
+    pub async fn upsert_point_list(
+        &self,
+        collection_name: &str,
+        points: Vec<Point>,
+    ) -> Result<bool, AppError> {
+        let client = reqwest::Client::new();
+        let url = format!(
+            "http://{}:{}/collections/{}/points?wait=true",
+            self.host, self.port, collection_name
+        );
+
+        let request_body = UpsertPointsRequest { points };
+
+        let response = client
+            .put(&url)
+            .header("api-key", &self.api_key)
+            .header("Content-Type", "application/json")
+            .json(&request_body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read error response".to_string());
+            tracing::error!("Qdrant upsert failed with status {}: {}", status, error_text);
+        }
+        Ok(status.is_success())
+    }
+
+    /// Deletes points from a Qdrant collection by ID
+    ///
+    /// # Arguments
+    /// * `collection_name` - Name of the collection to delete points from
+    /// * `point_ids` - IDs of the points to delete
+    ///
+    /// # Returns
+    /// * `Result<bool, AppError>` - True if points were deleted successfully, false otherwise, or error
+    pub async fn delete_points(
+        &self,
+        collection_name: &str,
+        point_ids: &[String],
+    ) -> Result<bool, AppError> {
+        if point_ids.is_empty() {
+            return Ok(true);
+        }
+        self.delete_points_matching(collection_name, serde_json::json!({ "points": point_ids }))
+            .await
+    }
+
+    /// Deletes all the points of a source file from a Qdrant collection
+    ///
+    /// # Arguments
+    /// * `collection_name` - Name of the collection to delete points from
+    /// * `source` - Value of the `source` payload field of the points to delete
+    ///
+    /// # Returns
+    /// * `Result<bool, AppError>` - True if points were deleted successfully, false otherwise, or error
+    pub async fn delete_points_by_source(
+        &self,
+        collection_name: &str,
+        source: &str,
+    ) -> Result<bool, AppError> {
+        let selector = serde_json::json!({
+            "filter": {
+                "must": [{ "key": "source", "match": { "value": source } }]
+            }
+        });
+        self.delete_points_matching(collection_name, selector).await
+    }
+
+    /// Scrolls one page of points of a Qdrant collection, without their vectors
+    ///
+    /// # Arguments
+    /// * `collection_name` - Name of the collection to scroll
+    /// * `filter` - Optional filter on the points
+    /// * `payload_keys` - Payload fields to return with each point
+    /// * `limit` - Maximum number of points in the page
+    /// * `offset` - Offset returned by the previous page, None for the first page
+    ///
+    /// # Returns
+    /// * `Result<ScrollPointsResult, AppError>` - The points of the page and the offset of the next one
+    pub async fn scroll_points(
+        &self,
+        collection_name: &str,
+        filter: Option<serde_json::Value>,
+        payload_keys: &[&str],
+        limit: u64,
+        offset: Option<serde_json::Value>,
+    ) -> Result<ScrollPointsResult, AppError> {
+        let client = reqwest::Client::new();
+        let url = format!(
+            "http://{}:{}/collections/{}/points/scroll",
+            self.host, self.port, collection_name
+        );
+
+        let request_body = ScrollPointsRequest {
+            limit,
+            offset,
+            filter,
+            with_payload: serde_json::json!(payload_keys),
+            with_vector: false,
+        };
+
+        let response = client
+            .post(&url)
+            .header("api-key", &self.api_key)
+            .header("Content-Type", "application/json")
+            .json(&request_body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read error response".to_string());
+            return Err(AppError::Qdrant(format!(
+                "Scroll failed with status {}: {}",
+                status, error_text
+            )));
+        }
+
+        let scroll_response: ScrollPointsResponse = response.json().await?;
+        Ok(scroll_response.result)
+    }
+
+    /// Retrieves points of a Qdrant collection by ID, without their vectors
+    ///
+    /// # Arguments
+    /// * `collection_name` - Name of the collection
+    /// * `point_ids` - IDs of the points to retrieve
+    /// * `payload_keys` - Payload fields to return with each point
+    ///
+    /// # Returns
+    /// * `Result<Vec<Record>, AppError>` - The points found, missing IDs are ignored
+    pub async fn get_points(
+        &self,
+        collection_name: &str,
+        point_ids: &[String],
+        payload_keys: &[&str],
+    ) -> Result<Vec<Record>, AppError> {
+        if point_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let client = reqwest::Client::new();
+        let url = format!(
+            "http://{}:{}/collections/{}/points",
+            self.host, self.port, collection_name
+        );
+
+        let response = client
+            .post(&url)
+            .header("api-key", &self.api_key)
+            .header("Content-Type", "application/json")
+            .json(&serde_json::json!({
+                "ids": point_ids,
+                "with_payload": payload_keys,
+                "with_vector": false
+            }))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read error response".to_string());
+            return Err(AppError::Qdrant(format!(
+                "Get points failed with status {}: {}",
+                status, error_text
+            )));
+        }
+
+        let get_response: GetPointsResponse = response.json().await?;
+        Ok(get_response.result)
+    }
+
+    /// Sets payload fields on points of a Qdrant collection, keeping their other fields
+    ///
+    /// # Arguments
+    /// * `collection_name` - Name of the collection
+    /// * `payload` - Payload fields to set
+    /// * `point_ids` - IDs of the points to update
+    ///
+    /// # Returns
+    /// * `Result<bool, AppError>` - True if the payload was set successfully, false otherwise, or error
+    pub async fn set_payload(
+        &self,
+        collection_name: &str,
+        payload: serde_json::Value,
+        point_ids: &[String],
+    ) -> Result<bool, AppError> {
+        if point_ids.is_empty() {
+            return Ok(true);
+        }
+        let client = reqwest::Client::new();
+        let url = format!(
+            "http://{}:{}/collections/{}/points/payload?wait=true",
+            self.host, self.port, collection_name
+        );
+
+        let response = client
+            .post(&url)
+            .header("api-key", &self.api_key)
+            .header("Content-Type", "application/json")
+            .json(&serde_json::json!({ "payload": payload, "points": point_ids }))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read error response".to_string());
+            tracing::error!("Qdrant set payload failed with status {}: {}", status, error_text);
+        }
+        Ok(status.is_success())
+    }
+
+    /// Sets different payload fields on several groups of points in a single request
+    ///
+    /// # Arguments
+    /// * `collection_name` - Name of the collection
+    /// * `updates` - Payload fields to set and IDs of the points to update with them
+    ///
+    /// # Returns
+    /// * `Result<bool, AppError>` - True if the payloads were set successfully, false otherwise, or error
+    pub async fn batch_set_payload(
+        &self,
+        collection_name: &str,
+        updates: Vec<(serde_json::Value, Vec<String>)>,
+    ) -> Result<bool, AppError> {
+        let operations: Vec<serde_json::Value> = updates
+            .into_iter()
+            .filter(|(_, point_ids)| !point_ids.is_empty())
+            .map(|(payload, point_ids)| {
+                serde_json::json!({ "set_payload": { "payload": payload, "points": point_ids } })
+            })
+            .collect();
+        if operations.is_empty() {
+            return Ok(true);
+        }
+        let client = reqwest::Client::new();
+        let url = format!(
+            "http://{}:{}/collections/{}/points/batch?wait=true",
+            self.host, self.port, collection_name
+        );
+
+        let response = client
+            .post(&url)
+            .header("api-key", &self.api_key)
+            .header("Content-Type", "application/json")
+            .json(&serde_json::json!({ "operations": operations }))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read error response".to_string());
+            tracing::error!("Qdrant batch update failed with status {}: {}", status, error_text);
+        }
+        Ok(status.is_success())
+    }
+
+    /// Sends a delete request with a points selector (IDs or filter)
+    async fn delete_points_matching(
+        &self,
+        collection_name: &str,
+        selector: serde_json::Value,
+    ) -> Result<bool, AppError> {
+        let client = reqwest::Client::new();
+        let url = format!(
+            "http://{}:{}/collections/{}/points/delete?wait=true",
+            self.host, self.port, collection_name
+        );
+
+        let response = client
+            .post(&url)
+            .header("api-key", &self.api_key)
+            .header("Content-Type", "application/json")
+            .json(&selector)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read error response".to_string());
+            tracing::error!("Qdrant delete failed with status {}: {}", status, error_text);
+        }
+        Ok(status.is_success())
+    }
+
     /// Searches for points in a Qdrant collection based on a question embedding
     ///
+    /// With a sparse vector, the dense and the sparse searches are run as
+    /// prefetches and their results fused by Reciprocal Rank Fusion: the
+    /// scores returned are then fused ranks (higher is better), and the score
+    /// threshold only applies to the dense search.
+    ///
     /// # Arguments
     /// * `collection_name` - Name of the collection to search in
     /// * `question_vector` - Vector representation of the question
     /// * `limit` - Maximum number of results to return
     /// * `score_threshold` - Only the points with score better than the threshold are returned
     /// * `filter` - Optional filter to apply to the search
+    /// * `with_vector` - Whether the vectors of the points are returned
+    /// * `sparse_query` - Sparse (BM25) vector of the question, for a hybrid search
     ///
     /// # Returns
     /// * `Result<Vec<ScoredPoint>, String>` - Search results or error message
+    #[allow(clippy::too_many_arguments)]
     pub async fn search_points(
         &self,
         collection_name: &str,
@@ -478,6 +918,8 @@ impl QdrantClient {
         limit: u64,
         score_threshold: f32,
         filter: Option<serde_json::Value>,
+        with_vector: bool,
+        sparse_query: Option<&SparseVector>,
     ) -> Result<Vec<ScoredPoint>, AppError> {
         let client = reqwest::Client::new();
         let url = format!(
@@ -486,13 +928,38 @@ impl QdrantClient {
         );
 
         // Create the query request body for the new endpoint
-        let mut request_body = serde_json::json!({
-            "query": question_vector,
-            "limit": limit,
-            "score_threshold": score_threshold,
-            "with_payload": true,
-            "with_vector": false
-        });
+        let mut request_body = match sparse_query {
+            Some(sparse_query) => {
+                let mut dense = serde_json::json!({
+                    "query": question_vector,
+                    "limit": limit,
+                    "score_threshold": score_threshold
+                });
+                let mut sparse = serde_json::json!({
+                    "query": sparse_query,
+                    "using": SPARSE_VECTOR_NAME,
+                    "limit": limit
+                });
+                if let Some(f) = &filter {
+                    dense["filter"] = f.clone();
+                    sparse["filter"] = f.clone();
+                }
+                serde_json::json!({
+                    "prefetch": [dense, sparse],
+                    "query": { "fusion": "rrf" },
+                    "limit": limit,
+                    "with_payload": true,
+                    "with_vector": with_vector
+                })
+            }
+            None => serde_json::json!({
+                "query": question_vector,
+                "limit": limit,
+                "score_threshold": score_threshold,
+                "with_payload": true,
+                "with_vector": with_vector
+            }),
+        };
         // Add filter if provided
         if let Some(f) = filter {
             request_body["filter"] = f;
@@ -533,6 +1000,7 @@ impl QdrantClient {
     /// * `limit` - Maximum number of results to return
     /// * `score_threshold` - Only the points with score better than the threshold are returned
     /// * `filter` - Optional filter to apply to the search
+    /// * `with_vector` - Whether the vectors of the points are returned
     ///
     /// # Returns
     /// * `Result<Vec<ScoredPoint>, String>` - Search results or error message
@@ -543,6 +1011,7 @@ impl QdrantClient {
         limit: u64,
         score_threshold: f32,
         filter: Option<serde_json::Value>,
+        with_vector: bool,
     ) -> Result<Vec<ScoredPoint>, AppError> {
         let client = reqwest::blocking::Client::new();
         let url = format!(
@@ -556,7 +1025,7 @@ impl QdrantClient {
             "limit": limit,
             "score_threshold": score_threshold,
             "with_payload": true,
-            "with_vector": false
+            "with_vector": with_vector
         });
 
         // Add filter if provided
@@ -0,0 +1,212 @@
+//! Diversification of the retrieved chunks.
+//!
+//! The best scored chunks are often adjacent chunks of the same file, which
+//! say nearly the same thing and crowd out the other relevant documents. The
+//! retriever therefore searches more candidates than it returns and selects
+//! among them:
+//! - with Maximal Marginal Relevance (`[retrieval] mmr`), each chunk is chosen
+//!   for its score minus its similarity to the chunks already chosen, using
+//!   the vectors of the points returned by Qdrant;
+//! - with `max_chunks_per_source`, no more than that number of chunks of the
+//!   same source file are kept.
+//!
+//! MMR mixes the search scores with cosine similarities, so it needs scores
+//! that grow with similarity: with a `Euclid` or `Manhattan` collection it is
+//! disabled, with a warning, and only the cap per source applies. So it is
+//! with the fused ranks of a hybrid search (`[qdrant] sparse_vectors`).
+
+use std::collections::HashMap;
+use std::sync::Once;
+use tracing::warn;
+
+use crate::RetrievalConfig;
+use crate::qdrant_custom_client::ScoredPoint;
+use crate::rag_proxy::similarity::{cosine_similarity, scores_are_similarities};
+
+/// Selects the chunks returned to the proxy among the candidates of the search
+///
+/// # Arguments
+/// * `candidates` - Points returned by the search, sorted by score
+/// * `limit` - Maximum number of chunks to return
+/// * `config` - Retrieval configuration (MMR and cap per source)
+/// * `distance` - Distance of the collection (or scoring of a hybrid search), MMR is only applied to similarity scores
+///
+/// # Returns
+/// * `Vec<ScoredPoint>` - The selected points, in selection order
+pub fn diversify(candidates: Vec<ScoredPoint>, limit: usize, config: &RetrievalConfig, distance: &str) -> Vec<ScoredPoint> {
+    let mmr = config.mmr && scores_are_similarities(distance);
+    if config.mmr && !mmr {
+        static WARNING: Once = Once::new();
+        WARNING.call_once(|| {
+            warn!(
+                "MMR needs similarity scores and is disabled for '{}' scores, only max_chunks_per_source applies",
+                distance
+            )
+        });
+    }
+    if mmr {
+        select_mmr(candidates, limit, config.mmr_lambda, config.max_chunks_per_source)
+    } else {
+        cap_per_source(candidates, limit, config.max_chunks_per_source)
+    }
+}
+
+/// Keeps the best scored chunks, at most `max_per_source` of each source file
+///
+/// # Arguments
+/// * `candidates` - Points returned by the search, sorted by score
+/// * `limit` - Maximum number of chunks to return
+/// * `max_per_source` - Maximum number of chunks of the same source file (0 for no limit)
+///
+/// # Returns
+/// * `Vec<ScoredPoint>` - The kept points
+fn cap_per_source(candidates: Vec<ScoredPoint>, limit: usize, max_per_source: usize) -> Vec<ScoredPoint> {
+    let mut per_source: HashMap<String, usize> = HashMap::new();
+    candidates
+        .into_iter()
+        .filter(|point| take_source_slot(&mut per_source, point, max_per_source))
+        .take(limit)
+        .collect()
+}
+
+/// Selects the chunks by Maximal Marginal Relevance
+///
+/// At each step, the candidate maximizing `lambda * score - (1 - lambda) *
+/// max similarity with the selected chunks` is selected. Candidates without
+/// vector are considered dissimilar to every chunk.
+///
+/// # Arguments
+/// * `candidates` - Points returned by the search, with their vectors
+/// * `limit` - Maximum number of chunks to return
+/// * `lambda` - Trade-off between relevance (1.0) and diversity (0.0)
+/// * `max_per_source` - Maximum number of chunks of the same source file (0 for no limit)
+///
+/// # Returns
+/// * `Vec<ScoredPoint>` - The selected points, in selection order
+fn select_mmr(candidates: Vec<ScoredPoint>, limit: usize, lambda: f32, max_per_source: usize) -> Vec<ScoredPoint> {
+    let lambda = lambda.clamp(0.0, 1.0);
+    let mut remaining: Vec<(ScoredPoint, f32)> = candidates.into_iter().map(|point| (point, 0.0)).collect();
+    let mut selected: Vec<ScoredPoint> = Vec::new();
+    let mut per_source: HashMap<String, usize> = HashMap::new();
+
+    while selected.len() < limit && !remaining.is_empty() {
+        let best = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, (point, _))| source_has_slot(&per_source, point, max_per_source))
+            .max_by(|(_, (a, a_similarity)), (_, (b, b_similarity))| {
+                let a_relevance = lambda * a.score - (1.0 - lambda) * a_similarity;
+                let b_relevance = lambda * b.score - (1.0 - lambda) * b_similarity;
+                // On a tie, the first candidate (best score) wins
+                a_relevance.total_cmp(&b_relevance).then(std::cmp::Ordering::Greater)
+            })
+            .map(|(position, _)| position);
+        let Some(position) = best else {
+            break;
+        };
+
+        let (point, _) = remaining.remove(position);
+        take_source_slot(&mut per_source, &point, max_per_source);
+        if let Some(vector) = &point.vector {
+            for (candidate, similarity) in remaining.iter_mut() {
+                if let Some(candidate_vector) = &candidate.vector {
+                    *similarity = similarity.max(cosine_similarity(vector, candidate_vector));
+                }
+            }
+        }
+        selected.push(point);
+    }
+    selected
+}
+
+/// Tells whether another chunk of the source file of a point can be kept
+fn source_has_slot(per_source: &HashMap<String, usize>, point: &ScoredPoint, max_per_source: usize) -> bool {
+    match point.payload.as_ref() {
+        Some(payload) if max_per_source > 0 => per_source.get(&payload.source).copied().unwrap_or(0) < max_per_source,
+        _ => true,
+    }
+}
+
+/// Counts a kept chunk for its source file
+///
+/// # Returns
+/// * `bool` - False if the source file already has `max_per_source` chunks, the chunk is then not counted
+fn take_source_slot(per_source: &mut HashMap<String, usize>, point: &ScoredPoint, max_per_source: usize) -> bool {
+    if !source_has_slot(per_source, point, max_per_source) {
+        return false;
+    }
+    if let Some(payload) = point.payload.as_ref() {
+        *per_source.entry(payload.source.clone()).or_default() += 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qdrant_custom_client::SearchPointsPayload;
+
+    fn point(id: &str, score: f32, vector: Option<Vec<f32>>, source: &str) -> ScoredPoint {
+        ScoredPoint {
+            id: id.into(),
+            vector,
+            payload: Some(SearchPointsPayload {
+                source: source.to_string(),
+                text: id.to_string(),
+                lang: None,
+                chunk_index: None,
+                parent_id: None,
+            }),
+            score,
+            version: 0,
+        }
+    }
+
+    /// Two nearly identical chunks of `a.md` followed by a different chunk of `b.md`
+    fn candidates() -> Vec<ScoredPoint> {
+        vec![
+            point("a1", 0.9, Some(vec![1.0, 0.0]), "a.md"),
+            point("a2", 0.89, Some(vec![0.99, 0.01]), "a.md"),
+            point("b1", 0.7, Some(vec![0.0, 1.0]), "b.md"),
+        ]
+    }
+
+    fn ids(points: &[ScoredPoint]) -> Vec<&str> {
+        points.iter().map(|point| point.id.as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn mmr_moves_near_duplicates_after_different_chunks() {
+        assert_eq!(ids(&select_mmr(candidates(), 3, 0.5, 0)), ["a1", "b1", "a2"]);
+        assert_eq!(ids(&select_mmr(candidates(), 2, 0.5, 0)), ["a1", "b1"]);
+        // Relevance only keeps the order of the scores
+        assert_eq!(ids(&select_mmr(candidates(), 3, 1.0, 0)), ["a1", "a2", "b1"]);
+    }
+
+    #[test]
+    fn mmr_treats_chunks_without_vector_as_dissimilar() {
+        let mut candidates = candidates();
+        candidates[1].vector = None;
+        assert_eq!(ids(&select_mmr(candidates, 3, 0.5, 0)), ["a1", "a2", "b1"]);
+    }
+
+    #[test]
+    fn cap_per_source_keeps_the_best_chunks_of_each_file() {
+        assert_eq!(ids(&cap_per_source(candidates(), 3, 1)), ["a1", "b1"]);
+        assert_eq!(ids(&cap_per_source(candidates(), 2, 0)), ["a1", "a2"]);
+        assert_eq!(ids(&select_mmr(candidates(), 3, 1.0, 1)), ["a1", "b1"]);
+    }
+
+    #[test]
+    fn mmr_only_applies_to_similarity_scores() {
+        let config = RetrievalConfig {
+            mmr: true,
+            mmr_lambda: 0.5,
+            ..RetrievalConfig::default()
+        };
+        assert_eq!(ids(&diversify(candidates(), 3, &config, "Cosine")), ["a1", "b1", "a2"]);
+        assert_eq!(ids(&diversify(candidates(), 3, &config, "dot")), ["a1", "b1", "a2"]);
+        assert_eq!(ids(&diversify(candidates(), 3, &config, "Euclid")), ["a1", "a2", "b1"]);
+        assert_eq!(ids(&diversify(candidates(), 3, &config, "Manhattan")), ["a1", "a2", "b1"]);
+    }
+}
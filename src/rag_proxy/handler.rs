@@ -8,16 +8,25 @@
 use axum::{
     body::Bytes,
     response::IntoResponse,
-    extract::State,
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
 };
+use std::net::SocketAddr;
 use std::sync::Arc;
 use serde::{Deserialize, Serialize};
+use tracing::info;
 
-use crate::Config;
 use crate::AppError;
-use crate::rag_proxy::retriever::retrieve_context;
+use crate::rag_proxy::rate_limiter::{stream_usage_total_tokens, usage_total_tokens};
+use crate::indexing::kb_version;
+use crate::rag_proxy::retriever::{embed_question, retrieve_context_for_embedding};
+use crate::rag_proxy::semantic_cache::request_context_hash;
+use crate::rag_proxy::state::AppState;
 use crate::clients::llm::LlmClient;
 
+/// Response header telling whether the answer came from the semantic cache
+const CACHE_STATUS_HEADER: &str = "x-rag-cache";
+
 /// Chat completion request structure
 /// This matches the OpenAI API format for chat completions
 #[derive(Serialize, Deserialize, Debug)]
@@ -62,11 +71,14 @@ pub struct ChatMessage {
 /// Handles incoming RAG requests
 ///
 /// This function processes an incoming chat completion request by:
+/// 0. Checking the client's rate limits (request rate and daily LLM tokens)
 /// 1. Extracting the user's question from the messages
-/// 2. Retrieving relevant context from Qdrant using the question
-/// 3. Modifying the original JSON string by replacing system message content with enhanced context
-/// 4. Forwarding the modified request directly to the LLM endpoint
-/// 5. Returning the LLM's response directly to the client
+/// 2. Answering from the semantic cache when a similar question was already answered
+///    (if enabled, for non-streaming requests, unless the bypass header is sent)
+/// 3. Retrieving relevant context from Qdrant using the question
+/// 4. Modifying the original JSON string by replacing system message content with enhanced context
+/// 5. Forwarding the modified request directly to the LLM endpoint
+/// 6. Returning the LLM's response directly to the client (and caching it)
 ///
 /// This approach preserves the exact JSON structure of the original request
 /// to ensure compatibility with various clients like QwenCLI while enhancing
@@ -74,15 +86,25 @@ pub struct ChatMessage {
 /// passthrough mode but with the addition of context injection.
 ///
 /// # Arguments
+/// * `state` - Shared application state (configuration, rate limiter, semantic cache)
+/// * `peer` - Address of the remote client, used when no configured API key is sent
+/// * `headers` - Headers of the incoming request
 /// * `request` - The incoming chat completion request as raw bytes
 ///
 /// # Returns
 /// * `Result<impl IntoResponse, AppError>` - The response from the LLM service
 pub async fn handle_rag_request(
-    State(config): State<Arc<Config>>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     request: Bytes
 ) -> Result<impl IntoResponse, AppError> {
-    // Convert bytes to string for JSON manipulation
+    let config = &state.config;
+
+    // Reject the request early if the client exceeded its limits
+    let client_key = state.rate_limiter.client_key(&headers, peer.ip());
+    state.rate_limiter.check_request(&client_key)?;
+
     // Convert bytes to string for JSON manipulation
     let request_str = std::str::from_utf8(&request).map_err(|e| {
         eprintln!("Failed to parse request as UTF-8: {}", e);
@@ -119,11 +141,43 @@ pub async fn handle_rag_request(
             .unwrap_or_else(|| "No question provided".to_string())
     };
 
-    // Configuration is now injected via State
+    // Embed the question, bounded by the embeddings concurrency limit
+    let question_embedding = {
+        let _permit = state.rate_limiter.acquire_embedding_permit().await?;
+        embed_question(&user_question, &state.ollama_client).await?
+    };
+
+    // Answer from the semantic cache when a similar question was already answered
+    // in the same conversation, with the same parameters and against the same
+    // knowledge base version
+    let use_cache = state.semantic_cache.is_enabled() && !parsed_request.stream.unwrap_or(false);
+    let (kb_version, context_hash) = if use_cache {
+        let request_json: serde_json::Value = serde_json::from_slice(&request)?;
+        // The version file is read on the blocking thread pool, not on the tokio workers
+        let version_path = config.indexing.kb_version_path.clone();
+        let kb_version = tokio::task::spawn_blocking(move || kb_version::read_version(&version_path))
+            .await
+            .unwrap_or_default();
+        (kb_version, request_context_hash(&request_json))
+    } else {
+        (String::new(), String::new())
+    };
+    if use_cache && !headers.contains_key(state.semantic_cache.bypass_header())
+        && let Some(answer) = state.semantic_cache.lookup(
+            &question_embedding,
+            &parsed_request.model,
+            &context_hash,
+            &kb_version,
+        )
+    {
+        info!("Answering from semantic cache");
+        let mut response = axum::Json(answer).into_response();
+        response.headers_mut().insert(CACHE_STATUS_HEADER, axum::http::HeaderValue::from_static("hit"));
+        return Ok(response);
+    }
 
     // Retrieve relevant context from Qdrant
-    // Retrieve relevant context from Qdrant
-    let context = retrieve_context(&user_question, &config).await?;
+    let context = retrieve_context_for_embedding(&user_question, question_embedding.clone(), config).await?;
 
     // If we have context, modify the original JSON string by replacing system message content
     let modified_request_str = if !context.is_empty() {
@@ -138,36 +192,36 @@ pub async fn handle_rag_request(
                 // Look for the last system message to identify its content
                 let mut result = None;
                 for msg in messages_array.iter().rev() {
-                    if msg.get("role").and_then(|r| r.as_str()) == Some("system") {
-                        if let Some(content) = msg.get("content") {
-                            if let Some(content_str) = content.as_str() {
-                                // Extract the original system content to replace
-                                result = Some(content_str.to_string());
-                                break;
-                            } else {
-                                // For complex content with parts
-                                match serde_json::from_value::<MessageContent>(content.clone()) {
-                                    Ok(MessageContent::Text(text)) => {
-                                        result = Some(text);
-                                        break;
-                                    },
-                                    Ok(MessageContent::Parts(parts)) => {
-                                        // For parts, concatenate text parts
-                                        let text_parts: Vec<String> = parts
-                                            .iter()
-                                            .filter_map(|part| {
-                                                if part.r#type == "text" && part.text.is_some() {
-                                                    Some(part.text.clone().unwrap_or_default())
-                                                } else {
-                                                    None
-                                                }
-                                            })
-                                            .collect();
-                                        result = Some(text_parts.join(" "));
-                                        break;
-                                    },
-                                    Err(_) => continue,
-                                }
+                    if msg.get("role").and_then(|r| r.as_str()) == Some("system")
+                        && let Some(content) = msg.get("content")
+                    {
+                        if let Some(content_str) = content.as_str() {
+                            // Extract the original system content to replace
+                            result = Some(content_str.to_string());
+                            break;
+                        } else {
+                            // For complex content with parts
+                            match serde_json::from_value::<MessageContent>(content.clone()) {
+                                Ok(MessageContent::Text(text)) => {
+                                    result = Some(text);
+                                    break;
+                                },
+                                Ok(MessageContent::Parts(parts)) => {
+                                    // For parts, concatenate text parts
+                                    let text_parts: Vec<String> = parts
+                                        .iter()
+                                        .filter_map(|part| {
+                                            if part.r#type == "text" && part.text.is_some() {
+                                                Some(part.text.clone().unwrap_or_default())
+                                            } else {
+                                                None
+                                            }
+                                        })
+                                        .collect();
+                                    result = Some(text_parts.join(" "));
+                                    break;
+                                },
+                                Err(_) => continue,
                             }
                         }
                     }
@@ -245,30 +299,56 @@ pub async fn handle_rag_request(
     };
 
     // Create LLM client
-    let llm_client = LlmClient::new(&config);
+    let llm_client = LlmClient::new(config);
 
-    // Send the modified request directly to the LLM endpoint
-    let llm_response = llm_client.send_request(modified_request_str).await?;
+    // Send the modified request directly to the LLM endpoint, bounded by the LLM concurrency limit
+    let llm_response_body = {
+        let _permit = state.rate_limiter.acquire_llm_permit().await?;
+        let llm_response = llm_client.send_request(modified_request_str).await?;
 
-    // Get the response body from the LLM
-    let llm_response_body = llm_response.text().await.map_err(|e| {
-        eprintln!("Error reading LLM response body: {}", e);
-        AppError::Reqwest(e)
-    })?;
+        // Get the response body from the LLM
+        llm_response.text().await.map_err(|e| {
+            eprintln!("Error reading LLM response body: {}", e);
+            AppError::Reqwest(e)
+        })?
+    };
 
-    // Try to parse the response as JSON
     // Try to parse the response as JSON
     match serde_json::from_str::<serde_json::Value>(&llm_response_body) {
         Ok(json_value) => {
+            // Debit the tokens reported by the LLM from the client's daily quota
+            state
+                .rate_limiter
+                .record_llm_usage(&client_key, usage_total_tokens(&json_value));
+
+            // Cache successful answers for similar future questions
+            if use_cache && json_value.get("error").is_none() {
+                state.semantic_cache.store(
+                    question_embedding,
+                    &parsed_request.model,
+                    &context_hash,
+                    &kb_version,
+                    json_value.clone(),
+                );
+            }
+
             // Create response with proper headers
             let mut response = axum::Json(json_value).into_response();
             response.headers_mut().insert(
                 "Content-Type",
                 axum::http::HeaderValue::from_static("application/json"),
             );
+            if use_cache {
+                response.headers_mut().insert(CACHE_STATUS_HEADER, axum::http::HeaderValue::from_static("miss"));
+            }
             Ok(response)
         }
         Err(_) => {
+            // Streamed responses report their usage in their last chunk
+            state
+                .rate_limiter
+                .record_llm_usage(&client_key, stream_usage_total_tokens(&llm_response_body));
+
             // If not valid JSON, return as text
             let mut response = llm_response_body.into_response();
             response.headers_mut().insert(
@@ -4,7 +4,14 @@
 //! It handles incoming HTTP requests, processes them through the RAG pipeline (retrieval + LLM calling),
 //! and returns responses in OpenAI API compatible format.
 
+pub mod diversity;
 pub mod handler;
+pub mod neighbors;
+pub mod parents;
 pub mod passthrough_handler;
+pub mod rate_limiter;
 pub mod retriever;
+pub mod semantic_cache;
 pub mod server;
+pub mod similarity;
+pub mod state;
@@ -0,0 +1,217 @@
+//! Expansion of the retrieved chunks with their neighbouring chunks.
+//!
+//! Chunks are often cut in the middle of an explanation. With
+//! `[retrieval] neighbor_chunks = N`, the chunks at most N positions before
+//! and after each retrieved chunk (`chunk_index` payload) are fetched from
+//! its source file with a filtered scroll, and the contiguous chunks of a
+//! file are merged into a single passage, so that overlapping neighbourhoods
+//! are not injected twice. Passages keep the rank of their best chunk.
+//! Parent sections (see the `parents` module) are never taken as neighbours.
+//!
+//! Chunks stored in a point owned by another file (see the `dedup` module)
+//! are not found among the neighbours of their file: the passage then stops
+//! before them.
+
+use std::collections::{BTreeMap, HashMap};
+use tracing::warn;
+
+use crate::AppError;
+use crate::qdrant_custom_client::{QdrantClient, SearchPointsPayload};
+
+/// Payload fields needed to rebuild the passages
+const NEIGHBOR_PAYLOAD_KEYS: [&str; 2] = ["text", "chunk_index"];
+
+/// Retrieved chunks of one source file, by position in the file, with their rank and text
+type SourceHits = BTreeMap<u64, (usize, String)>;
+
+/// Expands the retrieved chunks into passages made of their contiguous neighbours
+///
+/// Chunks without source position (indexed before `chunk_index` was stored)
+/// are kept as they are. If the neighbours of a file cannot be fetched, its
+/// chunks are kept alone.
+///
+/// # Arguments
+/// * `qdrant_client` - Qdrant client
+/// * `collection_name` - Name of the collection
+/// * `hits` - Rank and payload of the retrieved chunks
+/// * `radius` - Number of chunks fetched before and after each retrieved chunk
+///
+/// # Returns
+/// * `Vec<(usize, String)>` - Texts of the passages, with the rank of their best chunk
+pub async fn expand_with_neighbors(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    hits: Vec<(usize, SearchPointsPayload)>,
+    radius: usize,
+) -> Vec<(usize, String)> {
+    let mut passages: Vec<(usize, String)> = Vec::new();
+    let mut sources: HashMap<String, SourceHits> = HashMap::new();
+    for (rank, payload) in hits {
+        match payload.chunk_index {
+            Some(index) => {
+                let hits = sources.entry(payload.source).or_default();
+                hits.entry(index).or_insert((rank, payload.text));
+            }
+            None => passages.push((rank, payload.text)),
+        }
+    }
+
+    let radius = radius as u64;
+    for (source, hits) in sources {
+        let neighbors = fetch_neighbors(qdrant_client, collection_name, &source, &hits, radius).await;
+        let mut texts = match neighbors {
+            Ok(texts) => texts,
+            Err(e) => {
+                warn!("Failed to fetch the neighbouring chunks of {}: {}", source, e);
+                BTreeMap::new()
+            }
+        };
+        for (index, (_, text)) in &hits {
+            texts.entry(*index).or_insert_with(|| text.clone());
+        }
+        passages.extend(merge_contiguous(&hits, texts));
+    }
+    passages
+}
+
+/// Fetches the chunks of a file around its retrieved chunks
+///
+/// # Arguments
+/// * `qdrant_client` - Qdrant client
+/// * `collection_name` - Name of the collection
+/// * `source` - Source file of the chunks
+/// * `hits` - Retrieved chunks of the file
+/// * `radius` - Number of chunks fetched before and after each retrieved chunk
+///
+/// # Returns
+/// * `Result<BTreeMap<u64, String>, AppError>` - Texts of the chunks, by position in the file
+async fn fetch_neighbors(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    source: &str,
+    hits: &SourceHits,
+    radius: u64,
+) -> Result<BTreeMap<u64, String>, AppError> {
+    let ranges: Vec<serde_json::Value> = hits
+        .keys()
+        .map(|index| {
+            serde_json::json!({
+                "key": "chunk_index",
+                "range": { "gte": index.saturating_sub(radius), "lte": index + radius }
+            })
+        })
+        .collect();
+    let filter = serde_json::json!({
+        "must": [{ "key": "source", "match": { "value": source } }],
+        "must_not": [{ "key": "kind", "match": { "value": "parent" } }],
+        "should": ranges
+    });
+
+    let mut texts = BTreeMap::new();
+    let mut offset = None;
+    loop {
+        let page = qdrant_client
+            .scroll_points(
+                collection_name,
+                Some(filter.clone()),
+                &NEIGHBOR_PAYLOAD_KEYS,
+                hits.len() as u64 * (2 * radius + 1),
+                offset,
+            )
+            .await?;
+        for record in &page.points {
+            if let (Some(index), Some(text)) = (record.payload_u64("chunk_index"), record.payload_str("text")) {
+                texts.insert(index, text.to_string());
+            }
+        }
+        match page.next_page_offset {
+            Some(next) if !next.is_null() => offset = Some(next),
+            _ => break,
+        }
+    }
+    Ok(texts)
+}
+
+/// Merges the contiguous chunks of a file into passages
+///
+/// Runs of neighbours without any retrieved chunk, separated from them by a
+/// missing chunk, are dropped.
+///
+/// # Arguments
+/// * `hits` - Retrieved chunks of the file
+/// * `texts` - Texts of the retrieved chunks and of their neighbours, by position in the file
+///
+/// # Returns
+/// * `Vec<(usize, String)>` - The passages, with the rank of their best retrieved chunk
+fn merge_contiguous(hits: &SourceHits, texts: BTreeMap<u64, String>) -> Vec<(usize, String)> {
+    let mut passages = Vec::new();
+    let mut run: Vec<String> = Vec::new();
+    let mut run_rank: Option<usize> = None;
+    let mut previous: Option<u64> = None;
+
+    for (index, text) in texts {
+        if previous.is_some_and(|previous| previous + 1 != index) {
+            if let Some(rank) = run_rank.take() {
+                passages.push((rank, run.join("\n")));
+            }
+            run.clear();
+        }
+        if let Some((rank, _)) = hits.get(&index) {
+            run_rank = Some(run_rank.map_or(*rank, |best| best.min(*rank)));
+        }
+        run.push(text);
+        previous = Some(index);
+    }
+    if let Some(rank) = run_rank {
+        passages.push((rank, run.join("\n")));
+    }
+    passages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hits(hits: &[(u64, usize)]) -> SourceHits {
+        hits.iter()
+            .map(|(index, rank)| (*index, (*rank, format!("hit {}", index))))
+            .collect()
+    }
+
+    fn texts(indexes: &[u64]) -> BTreeMap<u64, String> {
+        indexes.iter().map(|index| (*index, format!("chunk {}", index))).collect()
+    }
+
+    #[test]
+    fn contiguous_chunks_are_merged_in_file_order() {
+        let passages = merge_contiguous(&hits(&[(3, 1)]), texts(&[2, 3, 4]));
+        assert_eq!(passages, [(1, "chunk 2\nchunk 3\nchunk 4".to_string())]);
+    }
+
+    #[test]
+    fn overlapping_neighbourhoods_keep_the_best_rank() {
+        // Chunks 2 and 4 retrieved with a radius of 1: their neighbourhoods meet at 3
+        let passages = merge_contiguous(&hits(&[(4, 0), (2, 5)]), texts(&[1, 2, 3, 4, 5]));
+        assert_eq!(passages, [(0, "chunk 1\nchunk 2\nchunk 3\nchunk 4\nchunk 5".to_string())]);
+    }
+
+    #[test]
+    fn a_missing_chunk_splits_the_passages() {
+        // Chunk 5 is shared with another file and not found among the neighbours
+        let passages = merge_contiguous(&hits(&[(3, 2), (7, 0)]), texts(&[2, 3, 4, 6, 7, 8]));
+        assert_eq!(
+            passages,
+            [
+                (2, "chunk 2\nchunk 3\nchunk 4".to_string()),
+                (0, "chunk 6\nchunk 7\nchunk 8".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_without_retrieved_chunk_are_dropped() {
+        let passages = merge_contiguous(&hits(&[(0, 0)]), texts(&[0, 1, 3, 4]));
+        assert_eq!(passages, [(0, "chunk 0\nchunk 1".to_string())]);
+        assert!(merge_contiguous(&hits(&[]), texts(&[0, 1])).is_empty());
+    }
+}
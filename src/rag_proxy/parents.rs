@@ -0,0 +1,68 @@
+//! Injection of the parent sections of the retrieved chunks.
+//!
+//! With `[indexing.parents] enabled = true`, the indexer stores, besides the
+//! chunks, the parent sections grouping consecutive chunks, and links each
+//! chunk to its section with the `parent_id` payload. The question is only
+//! matched with the chunks, which are precise, and the LLM gets the text of
+//! their parent sections, which give it the surrounding context. A section
+//! is injected once, at the rank of its best chunk. Sections are stored with
+//! an all-zero vector, without embedding: the searches exclude them with a
+//! `must_not` filter on `kind`, and they are only read by ID.
+
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+
+use crate::qdrant_custom_client::{QdrantClient, SearchPointsPayload};
+
+/// Payload fields of the parent sections needed for the context
+const PARENT_PAYLOAD_KEYS: [&str; 1] = ["text"];
+
+/// Replaces the retrieved chunks by the text of their parent sections
+///
+/// A chunk whose parent section cannot be found (deleted since, or not
+/// readable) is kept as it is.
+///
+/// # Arguments
+/// * `qdrant_client` - Qdrant client
+/// * `collection_name` - Name of the collection
+/// * `hits` - Rank and payload of the retrieved chunks that have a parent section
+///
+/// # Returns
+/// * `Vec<(usize, String)>` - Texts of the parent sections, with the rank of their best chunk
+pub async fn inject_parents(
+    qdrant_client: &QdrantClient,
+    collection_name: &str,
+    hits: Vec<(usize, SearchPointsPayload)>,
+) -> Vec<(usize, String)> {
+    let mut parent_ids: Vec<String> = hits.iter().filter_map(|(_, payload)| payload.parent_id.clone()).collect();
+    parent_ids.sort();
+    parent_ids.dedup();
+
+    let parents: HashMap<String, String> = match qdrant_client
+        .get_points(collection_name, &parent_ids, &PARENT_PAYLOAD_KEYS)
+        .await
+    {
+        Ok(records) => records
+            .iter()
+            .filter_map(|record| Some((record.id_string(), record.payload_str("text")?.to_string())))
+            .collect(),
+        Err(e) => {
+            warn!("Failed to fetch the parent sections of the retrieved chunks: {}", e);
+            HashMap::new()
+        }
+    };
+
+    let mut injected: HashSet<&str> = HashSet::new();
+    let mut passages = Vec::new();
+    for (rank, payload) in hits {
+        match payload.parent_id.as_deref().and_then(|id| parents.get_key_value(id)) {
+            Some((id, text)) => {
+                if injected.insert(id.as_str()) {
+                    passages.push((rank, text.clone()));
+                }
+            }
+            None => passages.push((rank, payload.text)),
+        }
+    }
+    passages
+}
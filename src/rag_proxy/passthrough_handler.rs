@@ -4,13 +4,15 @@
 //! in passthrough mode, where requests are forwarded directly to the LLM
 //! without any RAG processing.
 
-use axum::{Json, body::Bytes, http::HeaderValue, response::IntoResponse, extract::State};
+use axum::{Json, body::Bytes, http::{HeaderMap, HeaderValue}, response::IntoResponse, extract::{ConnectInfo, State}};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
-use crate::Config;
 use crate::AppError;
 use crate::clients::llm::LlmClient;
+use crate::rag_proxy::rate_limiter::{stream_usage_total_tokens, usage_total_tokens};
+use crate::rag_proxy::state::AppState;
 
 /// Chat completion request structure
 /// This matches the OpenAI API format for chat completions
@@ -56,39 +58,60 @@ pub struct ChatMessage {
 ///
 /// This function processes an incoming request by forwarding it directly
 /// to the configured LLM endpoint without any RAG processing.
+/// Rate limits and the LLM concurrency limit still apply.
 ///
 /// # Arguments
+/// * `state` - Shared application state (configuration and rate limiter)
+/// * `peer` - Address of the remote client, used when no configured API key is sent
+/// * `headers` - Headers of the incoming request
 /// * `request` - The incoming request as raw bytes
 ///
 /// # Returns
 /// * `Result<impl IntoResponse, AppError>` - The response from the LLM service
 pub async fn handle_passthrough_request(
-    State(config): State<Arc<Config>>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     request: Bytes
 ) -> Result<impl IntoResponse, AppError> {
-    // Configuration is now injected via State
+    // Reject the request early if the client exceeded its limits
+    let client_key = state.rate_limiter.client_key(&headers, peer.ip());
+    state.rate_limiter.check_request(&client_key)?;
 
     // Create LLM client
-    let llm_client = LlmClient::new(&config);
+    let llm_client = LlmClient::new(&state.config);
 
-    // Forward the request to the LLM endpoint
-    let response = llm_client.forward_request(request).await?;
+    // Forward the request to the LLM endpoint, bounded by the LLM concurrency limit
+    let body = {
+        let _permit = state.rate_limiter.acquire_llm_permit().await?;
+        let response = llm_client.forward_request(request).await?;
 
-    // Get the response body
-    let body = response.text().await.map_err(|e| {
-        eprintln!("Error reading LLM response body: {}", e);
-        AppError::Reqwest(e)
-    })?;
+        // Get the response body
+        response.text().await.map_err(|e| {
+            eprintln!("Error reading LLM response body: {}", e);
+            AppError::Reqwest(e)
+        })?
+    };
 
     // Try to parse as JSON to check if it's a valid OpenAI response
     match serde_json::from_str::<serde_json::Value>(&body) {
         Ok(json_value) => {
+            // Debit the tokens reported by the LLM from the client's daily quota
+            state
+                .rate_limiter
+                .record_llm_usage(&client_key, usage_total_tokens(&json_value));
+
             // Create response with proper headers
             let mut response = Json(json_value).into_response();
             response.headers_mut().insert("Content-Type", HeaderValue::from_static("application/json"));
             Ok(response)
         }
         Err(_) => {
+            // Streamed responses report their usage in their last chunk
+            state
+                .rate_limiter
+                .record_llm_usage(&client_key, stream_usage_total_tokens(&body));
+
             // If not valid JSON, return as text
             let mut response = body.into_response();
             response.headers_mut().insert("Content-Type", HeaderValue::from_static("text/plain"));
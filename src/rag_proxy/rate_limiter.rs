@@ -0,0 +1,499 @@
+//! RAG Proxy Rate Limiter Module
+//!
+//! This module protects the shared upstream quota from runaway clients.
+//! Each inbound client (identified by its bearer key when it is one of the
+//! configured `api_keys`, or by its IP address otherwise) gets two token
+//! buckets:
+//! - a request bucket refilled at `requests_per_minute`
+//! - an LLM token bucket refilled at `llm_tokens_per_day`, debited from the
+//!   `usage.total_tokens` field of the LLM responses (of the last chunk
+//!   carrying it for streamed responses, which only report their usage when
+//!   the request sets `stream_options.include_usage`)
+//!
+//! At most `MAX_TRACKED_CLIENTS` clients are tracked: idle clients (full
+//! buckets) are pruned first, then the least recently seen client.
+//!
+//! A limit of 0 means "unlimited", like for the concurrency limits.
+//!
+//! It also holds the global semaphores bounding the number of concurrent
+//! calls toward the Ollama embeddings service and the remote LLM.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::http::HeaderMap;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::AppError;
+use crate::RateLimitConfig;
+
+/// Number of seconds in a day, used to compute the LLM token refill rate
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Maximum number of tracked clients, above which idle then least recently seen clients are evicted
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// A simple token bucket refilled continuously over time
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket
+    ///
+    /// # Arguments
+    /// * `capacity` - Maximum number of tokens held by the bucket
+    /// * `refill_per_second` - Number of tokens added back every second
+    fn new(capacity: f64, refill_per_second: f64) -> Self {
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            refill_per_second,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Adds the tokens accumulated since the last refill, up to the capacity
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Takes `amount` tokens if they are available
+    ///
+    /// # Returns
+    /// * `bool` - True if the tokens were taken, false if the bucket is too low
+    fn try_take(&mut self, amount: f64, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= amount {
+            self.tokens -= amount;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Checks whether the bucket still holds at least one token
+    fn has_tokens(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    /// Debits `amount` tokens, allowing the bucket to go into debt.
+    /// Used for usage only known after the fact (LLM tokens).
+    fn consume(&mut self, amount: f64, now: Instant) {
+        self.refill(now);
+        self.tokens -= amount;
+    }
+
+    /// Returns the number of seconds until at least `amount` tokens are available
+    fn seconds_until(&self, amount: f64) -> u64 {
+        if self.refill_per_second <= 0.0 {
+            return u64::MAX;
+        }
+        let missing = (amount - self.tokens).max(0.0);
+        (missing / self.refill_per_second).ceil() as u64
+    }
+
+    /// Checks whether the bucket is full, i.e. the client has been idle long enough
+    fn is_full(&self) -> bool {
+        self.tokens >= self.capacity
+    }
+}
+
+/// Buckets tracked for a single client
+#[derive(Debug)]
+struct ClientBuckets {
+    requests: TokenBucket,
+    llm_tokens: TokenBucket,
+    last_seen: Instant,
+}
+
+/// Per-client rate limiter and global concurrency limiter
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    api_keys: HashSet<String>,
+    clients: Mutex<HashMap<String, ClientBuckets>>,
+    embedding_permits: Option<Semaphore>,
+    llm_permits: Option<Semaphore>,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter from configuration
+    ///
+    /// A rate or concurrency limit of 0 means "unlimited".
+    ///
+    /// # Arguments
+    /// * `config` - Rate limiting configuration
+    ///
+    /// # Returns
+    /// * `RateLimiter` - A new rate limiter
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let semaphore = |limit: usize| (limit > 0).then(|| Semaphore::new(limit));
+        RateLimiter {
+            config: config.clone(),
+            api_keys: config.api_keys.iter().cloned().collect(),
+            clients: Mutex::new(HashMap::new()),
+            embedding_permits: semaphore(config.max_concurrent_embeddings),
+            llm_permits: semaphore(config.max_concurrent_llm),
+        }
+    }
+
+    /// Identifies the client of a request
+    ///
+    /// The bearer key from the `Authorization` header is used when it is one
+    /// of the configured API keys; the client IP address is used otherwise,
+    /// so that a client cannot escape its limits by sending a new arbitrary
+    /// key with each request.
+    ///
+    /// # Arguments
+    /// * `headers` - Headers of the inbound request
+    /// * `client_ip` - IP address of the remote peer
+    ///
+    /// # Returns
+    /// * `String` - The key used to track the client
+    pub fn client_key(&self, headers: &HeaderMap, client_ip: std::net::IpAddr) -> String {
+        headers
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|key| key.trim())
+            .filter(|key| self.api_keys.contains(*key))
+            .map(|key| format!("key:{}", key))
+            .unwrap_or_else(|| format!("ip:{}", client_ip))
+    }
+
+    /// Checks whether a client may send a new request and debits its request bucket
+    ///
+    /// # Arguments
+    /// * `client_key` - Key identifying the client (see `client_key`)
+    ///
+    /// # Returns
+    /// * `Result<(), AppError>` - Ok if the request is allowed, `AppError::RateLimited` with the seconds to wait otherwise
+    pub fn check_request(&self, client_key: &str) -> Result<(), AppError> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut clients = self
+            .clients
+            .lock()
+            .map_err(|_| AppError::Unknown("Rate limiter lock poisoned".to_string()))?;
+
+        let buckets = self.client_buckets(&mut clients, client_key, now);
+
+        if self.config.llm_tokens_per_day > 0 && !buckets.llm_tokens.has_tokens(now) {
+            let retry_after = buckets.llm_tokens.seconds_until(1.0);
+            return Err(AppError::RateLimited {
+                message: format!("Daily LLM token quota exceeded, retry in {} seconds", retry_after),
+                retry_after,
+            });
+        }
+
+        if self.config.requests_per_minute > 0 && !buckets.requests.try_take(1.0, now) {
+            let retry_after = buckets.requests.seconds_until(1.0);
+            return Err(AppError::RateLimited {
+                message: format!("Too many requests, retry in {} seconds", retry_after),
+                retry_after,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Debits the LLM tokens consumed by a client
+    ///
+    /// # Arguments
+    /// * `client_key` - Key identifying the client
+    /// * `tokens` - Number of tokens reported in the LLM response `usage` field
+    pub fn record_llm_usage(&self, client_key: &str, tokens: u64) {
+        if !self.config.enabled || self.config.llm_tokens_per_day == 0 || tokens == 0 {
+            return;
+        }
+
+        let Ok(mut clients) = self.clients.lock() else {
+            tracing::error!("Rate limiter lock poisoned, LLM usage not recorded");
+            return;
+        };
+        let now = Instant::now();
+        let buckets = self.client_buckets(&mut clients, client_key, now);
+        buckets.llm_tokens.consume(tokens as f64, now);
+    }
+
+    /// Gets the buckets of a client, tracking it if it is new
+    ///
+    /// When `MAX_TRACKED_CLIENTS` clients are already tracked, idle clients
+    /// are pruned, then the least recently seen one is evicted if none is idle.
+    ///
+    /// # Arguments
+    /// * `clients` - Tracked clients, locked
+    /// * `client_key` - Key identifying the client
+    /// * `now` - Current time, recorded as the last time the client was seen
+    ///
+    /// # Returns
+    /// * `&mut ClientBuckets` - The buckets of the client
+    fn client_buckets<'a>(
+        &self,
+        clients: &'a mut HashMap<String, ClientBuckets>,
+        client_key: &str,
+        now: Instant,
+    ) -> &'a mut ClientBuckets {
+        if !clients.contains_key(client_key) && clients.len() >= MAX_TRACKED_CLIENTS {
+            clients.retain(|_, buckets| {
+                buckets.requests.refill(now);
+                buckets.llm_tokens.refill(now);
+                !(buckets.requests.is_full() && buckets.llm_tokens.is_full())
+            });
+            if clients.len() >= MAX_TRACKED_CLIENTS {
+                let least_recent = clients
+                    .iter()
+                    .min_by_key(|(_, buckets)| buckets.last_seen)
+                    .map(|(key, _)| key.clone());
+                if let Some(key) = least_recent {
+                    clients.remove(&key);
+                }
+            }
+        }
+
+        let buckets = clients
+            .entry(client_key.to_string())
+            .or_insert_with(|| self.new_client_buckets());
+        buckets.last_seen = now;
+        buckets
+    }
+
+    /// Waits for a free slot toward the Ollama embeddings service
+    ///
+    /// # Returns
+    /// * `Result<Option<SemaphorePermit>, AppError>` - A permit to hold during the call, None if unlimited
+    pub async fn acquire_embedding_permit(&self) -> Result<Option<SemaphorePermit<'_>>, AppError> {
+        Self::acquire(&self.embedding_permits).await
+    }
+
+    /// Waits for a free slot toward the remote LLM
+    ///
+    /// # Returns
+    /// * `Result<Option<SemaphorePermit>, AppError>` - A permit to hold during the call, None if unlimited
+    pub async fn acquire_llm_permit(&self) -> Result<Option<SemaphorePermit<'_>>, AppError> {
+        Self::acquire(&self.llm_permits).await
+    }
+
+    async fn acquire(semaphore: &Option<Semaphore>) -> Result<Option<SemaphorePermit<'_>>, AppError> {
+        match semaphore {
+            Some(semaphore) => semaphore
+                .acquire()
+                .await
+                .map(Some)
+                .map_err(|e| AppError::Unknown(format!("Concurrency limiter closed: {}", e))),
+            None => Ok(None),
+        }
+    }
+
+    fn new_client_buckets(&self) -> ClientBuckets {
+        let requests = self.config.requests_per_minute as f64;
+        let llm_tokens = self.config.llm_tokens_per_day as f64;
+        ClientBuckets {
+            requests: TokenBucket::new(requests, requests / 60.0),
+            llm_tokens: TokenBucket::new(llm_tokens, llm_tokens / SECONDS_PER_DAY),
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+/// Extracts the total number of tokens from an OpenAI-style response body
+///
+/// # Arguments
+/// * `response` - The LLM response parsed as JSON
+///
+/// # Returns
+/// * `u64` - `usage.total_tokens`, or the sum of prompt and completion tokens, or 0
+pub fn usage_total_tokens(response: &serde_json::Value) -> u64 {
+    let Some(usage) = response.get("usage") else {
+        return 0;
+    };
+    usage
+        .get("total_tokens")
+        .and_then(|v| v.as_u64())
+        .unwrap_or_else(|| {
+            let prompt = usage.get("prompt_tokens").and_then(|v| v.as_u64()).unwrap_or(0);
+            let completion = usage
+                .get("completion_tokens")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0);
+            prompt + completion
+        })
+}
+
+/// Extracts the total number of tokens from an OpenAI-style streamed (SSE) response body
+///
+/// The usage is reported by the last chunk, when the request sets
+/// `stream_options.include_usage`.
+///
+/// # Arguments
+/// * `body` - The streamed response, as `data: <chunk>` lines
+///
+/// # Returns
+/// * `u64` - The tokens of the last chunk reporting a usage, or 0
+pub fn stream_usage_total_tokens(body: &str) -> u64 {
+    body.lines()
+        .rev()
+        .filter_map(|line| line.trim().strip_prefix("data:"))
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data.trim()).ok())
+        .find(|chunk| chunk.get("usage").is_some_and(|usage| !usage.is_null()))
+        .map(|chunk| usage_total_tokens(&chunk))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use std::time::Duration;
+
+    fn limiter(requests_per_minute: u32, llm_tokens_per_day: u64) -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig {
+            enabled: true,
+            requests_per_minute,
+            llm_tokens_per_day,
+            ..RateLimitConfig::default()
+        })
+    }
+
+    fn retry_after(result: Result<(), AppError>) -> u64 {
+        match result {
+            Err(AppError::RateLimited { retry_after, .. }) => retry_after,
+            other => panic!("expected a rate limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bucket_refills_over_time_up_to_its_capacity() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 1.0);
+        bucket.last_refill = start;
+        assert!(bucket.try_take(2.0, start));
+        assert!(!bucket.try_take(1.0, start));
+        assert_eq!(bucket.seconds_until(1.0), 1);
+
+        assert!(bucket.try_take(1.0, start + Duration::from_secs(1)));
+        assert!(!bucket.has_tokens(start + Duration::from_secs(1)));
+
+        bucket.refill(start + Duration::from_secs(60));
+        assert!(bucket.is_full());
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn consumed_tokens_can_put_the_bucket_in_debt() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 1.0);
+        bucket.last_refill = start;
+        bucket.consume(15.0, start);
+        assert!(!bucket.has_tokens(start));
+        assert_eq!(bucket.seconds_until(1.0), 6);
+        assert!(bucket.has_tokens(start + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn requests_over_the_limit_are_rejected_per_client() {
+        let limiter = limiter(2, 0);
+        assert!(limiter.check_request("ip:10.0.0.1").is_ok());
+        assert!(limiter.check_request("ip:10.0.0.1").is_ok());
+        let wait = retry_after(limiter.check_request("ip:10.0.0.1"));
+        assert!((1..=30).contains(&wait));
+        assert!(limiter.check_request("ip:10.0.0.2").is_ok());
+    }
+
+    #[test]
+    fn exhausted_token_quota_rejects_requests() {
+        let limiter = limiter(0, 100);
+        assert!(limiter.check_request("key:a").is_ok());
+        limiter.record_llm_usage("key:a", 150);
+        let wait = retry_after(limiter.check_request("key:a"));
+        // 51 tokens are missing at a refill of 100 tokens per day
+        assert!(wait > 40_000 && wait <= 44_064);
+        assert!(limiter.check_request("key:b").is_ok());
+    }
+
+    #[test]
+    fn streamed_usage_is_read_from_the_last_chunk() {
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}],\"usage\":null}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":3,\"total_tokens\":15}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        assert_eq!(stream_usage_total_tokens(body), 15);
+        assert_eq!(stream_usage_total_tokens("data: {\"choices\":[]}\n\ndata: [DONE]\n"), 0);
+    }
+
+    #[test]
+    fn least_recently_seen_client_is_evicted_at_capacity() {
+        let limiter = limiter(10, 0);
+        {
+            let mut clients = limiter.clients.lock().unwrap();
+            let start = Instant::now();
+            for index in 0..MAX_TRACKED_CLIENTS {
+                let mut buckets = limiter.new_client_buckets();
+                // Busy clients, none of them idle
+                buckets.requests.tokens = 0.0;
+                buckets.requests.last_refill = start;
+                buckets.last_seen = start + Duration::from_millis(index as u64 + 1);
+                clients.insert(format!("ip:{}", index), buckets);
+            }
+            clients.get_mut("ip:0").unwrap().last_seen = start + Duration::from_secs(3600);
+        }
+
+        assert!(limiter.check_request("ip:new").is_ok());
+        let clients = limiter.clients.lock().unwrap();
+        assert_eq!(clients.len(), MAX_TRACKED_CLIENTS);
+        assert!(clients.contains_key("ip:new") && clients.contains_key("ip:0"));
+        assert!(!clients.contains_key("ip:1"));
+    }
+
+    #[test]
+    fn zero_limits_and_disabled_limiter_allow_every_request() {
+        let unlimited = limiter(0, 0);
+        unlimited.record_llm_usage("ip:10.0.0.1", 1_000);
+        let disabled = RateLimiter::new(&RateLimitConfig {
+            requests_per_minute: 1,
+            ..RateLimitConfig::default()
+        });
+        for _ in 0..100 {
+            assert!(unlimited.check_request("ip:10.0.0.1").is_ok());
+            assert!(disabled.check_request("ip:10.0.0.1").is_ok());
+        }
+    }
+
+    #[test]
+    fn rate_limited_response_has_retry_after_header() {
+        let limiter = limiter(1, 0);
+        limiter.check_request("ip:10.0.0.1").unwrap();
+        let response = limiter.check_request("ip:10.0.0.1").unwrap_err().into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[axum::http::header::RETRY_AFTER], "60");
+    }
+
+    #[test]
+    fn only_configured_api_keys_identify_clients() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            api_keys: vec!["secret".to_string()],
+            ..RateLimitConfig::default()
+        });
+        let ip: std::net::IpAddr = "10.0.0.1".parse().unwrap();
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(axum::http::header::AUTHORIZATION, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(limiter.client_key(&headers("Bearer secret"), ip), "key:secret");
+        assert_eq!(limiter.client_key(&headers("Bearer other"), ip), "ip:10.0.0.1");
+        assert_eq!(limiter.client_key(&HeaderMap::new(), ip), "ip:10.0.0.1");
+    }
+}
@@ -3,11 +3,32 @@
 //! This module handles the retrieval of relevant context from Qdrant based on
 //! the user's question. It creates embeddings for the question and searches
 //! Qdrant for similar documents to provide context for the LLM.
+//!
+//! The language of the question can be matched with the language detected on
+//! the chunks (`[retrieval] language_mode`): the chunks in the language of the
+//! question are either ranked higher or the only ones retrieved. With
+//! `[qdrant] sparse_vectors`, the sparse (BM25) vector of the question terms
+//! is searched along with its embedding, and both results fused (hybrid
+//! search), so that rare exact terms (names, codes) are found. The chunks
+//! can then be diversified (see the `diversity` module), replaced by their
+//! parent section (see the `parents` module) or expanded with their
+//! neighbouring chunks (see the `neighbors` module).
+
+use serde_json::json;
 
-use crate::Config;
 use crate::AppError;
-use crate::qdrant_custom_client::QdrantClient;
+use crate::qdrant_custom_client::{QdrantClient, ScoredPoint, SearchPointsPayload};
 use crate::clients::ollama::OllamaClient;
+use crate::indexing::language::LanguageDetector;
+use crate::indexing::sparse;
+use crate::rag_proxy::diversity::diversify;
+use crate::rag_proxy::neighbors::expand_with_neighbors;
+use crate::rag_proxy::parents::inject_parents;
+use crate::rag_proxy::similarity::scores_are_similarities;
+use crate::{Config, LanguageMode};
+
+/// Number of candidates searched per returned chunk when boosting a language
+const LANGUAGE_BOOST_CANDIDATES: u64 = 2;
 
 /// Retrieves relevant context from Qdrant based on the user's question
 ///
@@ -20,23 +41,49 @@ use crate::clients::ollama::OllamaClient;
 ///
 /// # Arguments
 /// * `question` - The user's question as a string slice
+/// * `ollama_client` - Shared Ollama client (see `AppState`)
 /// * `config` - The application configuration
 ///
 /// # Returns
 /// * `Result<String, AppError>` - The retrieved context or an error
 pub async fn retrieve_context(
     question: &str,
+    ollama_client: &OllamaClient,
     config: &Config,
 ) -> Result<String, AppError> {
-    // Create Ollama client
-    let ollama_client = OllamaClient::new(config);
+    let question_embedding = embed_question(question, ollama_client).await?;
+    retrieve_context_for_embedding(question, question_embedding, config).await
+}
 
+/// Generates the embedding of the user's question using Ollama
+///
+/// # Arguments
+/// * `question` - The user's question as a string slice
+/// * `ollama_client` - Shared Ollama client, holding the embedding cache (see `AppState`)
+///
+/// # Returns
+/// * `Result<Vec<f32>, AppError>` - The question embedding or an error
+pub async fn embed_question(question: &str, ollama_client: &OllamaClient) -> Result<Vec<f32>, AppError> {
     // Generate embedding for the question using OllamaClient
-    let embedding = ollama_client.generate_embedding(question).await?;
-
-    // Extract embedding from response
-    let question_embedding = embedding;
+    ollama_client.generate_embedding(question).await
+}
 
+/// Retrieves relevant context from Qdrant for an already computed question embedding
+///
+/// This allows callers to reuse the question embedding (e.g. for the semantic cache).
+///
+/// # Arguments
+/// * `question` - The user's question, whose language is matched with the chunks
+/// * `question_embedding` - Embedding of the user's question
+/// * `config` - The application configuration
+///
+/// # Returns
+/// * `Result<String, AppError>` - The retrieved context or an error
+pub async fn retrieve_context_for_embedding(
+    question: &str,
+    question_embedding: Vec<f32>,
+    config: &Config,
+) -> Result<String, AppError> {
     // Create a Qdrant client
     let qdrant_client = QdrantClient::new(
         config.qdrant.host.clone(),
@@ -44,18 +91,43 @@ pub async fn retrieve_context(
         config.qdrant.api_key.clone(),
         config.qdrant.vector_size as u64,
         config.qdrant.distance.clone(),
-        config.qdrant.limit as u64,
+        config.qdrant.limit,
         config.qdrant.score_threshold,
+        config.qdrant.sparse_vectors,
     );
 
+    let hybrid = config.qdrant.sparse_vectors;
+    let detector = (config.retrieval.language_mode != LanguageMode::Off || hybrid)
+        .then(|| LanguageDetector::new(&config.indexing.languages));
+    let language = detector.as_ref().and_then(|detector| detector.detect(question));
+    // The question terms, without the stop words of its language, are matched with the chunk terms
+    let sparse_query = detector
+        .as_ref()
+        .filter(|_| hybrid)
+        .map(|detector| sparse::query_vector(detector, question, language))
+        .filter(|vector| !vector.indices.is_empty());
+    let language = language.filter(|_| config.retrieval.language_mode != LanguageMode::Off);
+    let diversified = config.retrieval.mmr || config.retrieval.max_chunks_per_source > 0;
+    // More candidates than returned chunks are searched when some of them are set aside
+    let mut candidates_per_chunk = if diversified { config.retrieval.diversity_candidates.max(1) } else { 1 };
+    // Parent sections are stored as context for their chunks, only the chunks are matched
+    let mut filter = json!({ "must_not": [{ "key": "kind", "match": { "value": "parent" } }] });
+    match (config.retrieval.language_mode, language) {
+        (LanguageMode::Filter, Some(language)) => filter["should"] = language_conditions(language),
+        (LanguageMode::Boost, Some(_)) => candidates_per_chunk = candidates_per_chunk.max(LANGUAGE_BOOST_CANDIDATES),
+        _ => {}
+    }
+
     // Search Qdrant for similar documents using the question embedding
-    let search_results = qdrant_client
+    let mut search_results = qdrant_client
         .search_points(
             &config.qdrant.collection,
             question_embedding,
-            config.qdrant.limit,
+            config.qdrant.limit * candidates_per_chunk,
             config.qdrant.score_threshold,
-            None,
+            Some(filter),
+            config.retrieval.mmr,
+            sparse_query.as_ref(),
         )
         .await
         .map_err(|e| {
@@ -63,12 +135,124 @@ pub async fn retrieve_context(
             e
         })?;
 
-    // Extract the text content from the search results
-    let context = search_results
+    if config.retrieval.language_mode == LanguageMode::Boost
+        && let Some(language) = language
+    {
+        boost_language(
+            &mut search_results,
+            language,
+            config.retrieval.language_boost,
+            hybrid || scores_are_similarities(&config.qdrant.distance),
+        );
+    }
+    if diversified {
+        // Fused ranks are not similarities, MMR cannot mix them with the cosine similarity of the chunks
+        let scores = if hybrid { "RRF fusion" } else { config.qdrant.distance.as_str() };
+        search_results = diversify(search_results, config.qdrant.limit as usize, &config.retrieval, scores);
+    } else {
+        search_results.truncate(config.qdrant.limit as usize);
+    }
+
+    // Extract the text content from the search results: the parent sections of the chunks
+    // that have one, the other chunks with their neighbouring chunks if configured
+    let hits: Vec<(usize, SearchPointsPayload)> =
+        search_results.into_iter().filter_map(|point| point.payload).enumerate().collect();
+    let (with_parent, hits): (Vec<_>, Vec<_>) = hits.into_iter().partition(|(_, payload)| payload.parent_id.is_some());
+    let mut passages = inject_parents(&qdrant_client, &config.qdrant.collection, with_parent).await;
+    if config.retrieval.neighbor_chunks > 0 {
+        passages.extend(
+            expand_with_neighbors(&qdrant_client, &config.qdrant.collection, hits, config.retrieval.neighbor_chunks).await,
+        );
+    } else {
+        passages.extend(hits.into_iter().map(|(rank, payload)| (rank, payload.text)));
+    }
+    passages.sort_by_key(|(rank, _)| *rank);
+
+    let context = passages
         .into_iter()
-        .filter_map(|point| point.payload)
-        .map(|payload| payload.text)
+        .map(|(_, text)| text)
         .collect::<Vec<String>>()
         .join("\n\n");
     Ok(context)
 }
+
+/// Builds the filter conditions on the chunks in a language, or without detected language
+///
+/// # Arguments
+/// * `language` - ISO 639-1 code of the language
+///
+/// # Returns
+/// * `serde_json::Value` - The `should` conditions of the Qdrant filter
+fn language_conditions(language: &str) -> serde_json::Value {
+    json!([
+        { "key": "lang", "match": { "value": language } },
+        { "is_empty": { "key": "lang" } }
+    ])
+}
+
+/// Favours the chunks in a language and sorts the points by score again
+///
+/// Similarity scores (higher is better) are multiplied by the boost, and
+/// distance scores (lower is better) divided by it.
+///
+/// # Arguments
+/// * `points` - Points returned by the search
+/// * `language` - ISO 639-1 code of the language of the question
+/// * `boost` - Factor applied to the score of the chunks in the language
+/// * `similarities` - True if the scores are similarities, false if they are distances
+fn boost_language(points: &mut [ScoredPoint], language: &str, boost: f32, similarities: bool) {
+    for point in points.iter_mut() {
+        if point.payload.as_ref().and_then(|payload| payload.lang.as_deref()) == Some(language) {
+            if similarities {
+                point.score *= boost;
+            } else if boost > 0.0 {
+                point.score /= boost;
+            }
+        }
+    }
+    if similarities {
+        points.sort_by(|a, b| b.score.total_cmp(&a.score));
+    } else {
+        points.sort_by(|a, b| a.score.total_cmp(&b.score));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(id: &str, score: f32, lang: &str) -> ScoredPoint {
+        ScoredPoint {
+            id: id.into(),
+            vector: None,
+            payload: Some(SearchPointsPayload {
+                source: "a.md".to_string(),
+                text: id.to_string(),
+                lang: Some(lang.to_string()),
+                chunk_index: None,
+                parent_id: None,
+            }),
+            score,
+            version: 0,
+        }
+    }
+
+    fn ids(points: &[ScoredPoint]) -> Vec<&str> {
+        points.iter().map(|point| point.id.as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn boost_raises_similarity_scores_of_the_language() {
+        let mut points = vec![point("en", 0.8, "en"), point("fr", 0.7, "fr")];
+        boost_language(&mut points, "fr", 1.2, true);
+        assert_eq!(ids(&points), ["fr", "en"]);
+    }
+
+    #[test]
+    fn boost_lowers_distance_scores_of_the_language() {
+        let mut points = vec![point("en", 1.0, "en"), point("fr", 1.1, "fr")];
+        boost_language(&mut points, "fr", 1.2, false);
+        assert_eq!(ids(&points), ["fr", "en"]);
+        assert!((points[0].score - 1.1 / 1.2).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,273 @@
+//! RAG Proxy Semantic Cache Module
+//!
+//! This module implements an optional in-memory semantic cache of LLM answers.
+//! Each entry is keyed by the embedding of the user's question: a new question
+//! whose embedding is similar enough (cosine similarity above the configured
+//! threshold) to a cached one, for the same model and the same knowledge base
+//! version, is answered directly without calling Qdrant nor the LLM. The rest
+//! of the request (system prompt, earlier turns of the conversation and
+//! generation parameters such as `temperature` or `max_tokens`) must be
+//! exactly the same: it is hashed into the entry key (see `request_context_hash`).
+//!
+//! Entries expire after a configurable TTL, the number of entries is bounded
+//! (oldest entries are evicted first) and the whole cache is dropped when the
+//! knowledge base version written by `index_documents` changes.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::SemanticCacheConfig;
+use crate::rag_proxy::similarity::cosine_similarity;
+
+/// A cached answer
+struct CacheEntry {
+    embedding: Vec<f32>,
+    model: String,
+    context_hash: String,
+    answer: serde_json::Value,
+    created_at: Instant,
+}
+
+/// Mutable content of the cache, protected by a mutex
+struct CacheContent {
+    kb_version: String,
+    entries: VecDeque<CacheEntry>,
+}
+
+/// In-memory semantic cache of LLM answers
+pub struct SemanticCache {
+    config: SemanticCacheConfig,
+    content: Mutex<CacheContent>,
+}
+
+impl SemanticCache {
+    /// Creates an empty semantic cache
+    ///
+    /// # Arguments
+    /// * `config` - Semantic cache configuration
+    ///
+    /// # Returns
+    /// * `SemanticCache` - A new, empty cache
+    pub fn new(config: &SemanticCacheConfig) -> Self {
+        SemanticCache {
+            config: config.clone(),
+            content: Mutex::new(CacheContent {
+                kb_version: String::new(),
+                entries: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Checks whether the cache is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Name of the request header used to bypass the cache lookup
+    pub fn bypass_header(&self) -> &str {
+        &self.config.bypass_header
+    }
+
+    /// Looks up a cached answer for a question
+    ///
+    /// # Arguments
+    /// * `embedding` - Embedding of the user's question
+    /// * `model` - Model requested by the client
+    /// * `context_hash` - Hash of the rest of the request (see `request_context_hash`)
+    /// * `kb_version` - Current knowledge base version
+    ///
+    /// # Returns
+    /// * `Option<serde_json::Value>` - The best cached answer above the similarity threshold, if any
+    pub fn lookup(
+        &self,
+        embedding: &[f32],
+        model: &str,
+        context_hash: &str,
+        kb_version: &str,
+    ) -> Option<serde_json::Value> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let mut content = self.content.lock().ok()?;
+        self.invalidate_if_stale(&mut content, kb_version);
+        self.evict_expired(&mut content);
+
+        content
+            .entries
+            .iter()
+            .filter(|entry| entry.model == model && entry.context_hash == context_hash)
+            .map(|entry| (cosine_similarity(&entry.embedding, embedding), entry))
+            .filter(|(score, _)| *score >= self.config.similarity_threshold)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, entry)| entry.answer.clone())
+    }
+
+    /// Stores an answer in the cache
+    ///
+    /// # Arguments
+    /// * `embedding` - Embedding of the user's question
+    /// * `model` - Model requested by the client
+    /// * `context_hash` - Hash of the rest of the request (see `request_context_hash`)
+    /// * `kb_version` - Knowledge base version the answer was computed against
+    /// * `answer` - The LLM response to cache
+    pub fn store(
+        &self,
+        embedding: Vec<f32>,
+        model: &str,
+        context_hash: &str,
+        kb_version: &str,
+        answer: serde_json::Value,
+    ) {
+        if !self.config.enabled || self.config.max_entries == 0 {
+            return;
+        }
+
+        let Ok(mut content) = self.content.lock() else {
+            tracing::error!("Semantic cache lock poisoned, answer not cached");
+            return;
+        };
+        self.invalidate_if_stale(&mut content, kb_version);
+        self.evict_expired(&mut content);
+
+        while content.entries.len() >= self.config.max_entries {
+            content.entries.pop_front();
+        }
+        content.entries.push_back(CacheEntry {
+            embedding,
+            model: model.to_string(),
+            context_hash: context_hash.to_string(),
+            answer,
+            created_at: Instant::now(),
+        });
+    }
+
+    /// Drops every entry if the knowledge base changed since they were cached
+    fn invalidate_if_stale(&self, content: &mut CacheContent, kb_version: &str) {
+        if content.kb_version != kb_version {
+            if !content.entries.is_empty() {
+                info!(
+                    "Knowledge base version changed, dropping {} cached answers",
+                    content.entries.len()
+                );
+            }
+            content.entries.clear();
+            content.kb_version = kb_version.to_string();
+        }
+    }
+
+    /// Removes the entries older than the TTL (entries are ordered by age)
+    fn evict_expired(&self, content: &mut CacheContent) {
+        let ttl = Duration::from_secs(self.config.ttl_seconds);
+        while content
+            .entries
+            .front()
+            .is_some_and(|entry| entry.created_at.elapsed() > ttl)
+        {
+            content.entries.pop_front();
+        }
+    }
+}
+
+/// Hashes everything in a chat completion request but the question
+///
+/// The last message, whose embedding keys the cache, is removed; the other
+/// messages and every other field (model, `temperature`, `max_tokens`,
+/// tools...) are hashed, so that a cached answer is only reused for the same
+/// conversation and the same generation parameters.
+///
+/// # Arguments
+/// * `request` - The chat completion request parsed as JSON
+///
+/// # Returns
+/// * `String` - Hex SHA-256 of the request without its last message
+pub fn request_context_hash(request: &serde_json::Value) -> String {
+    let mut context = request.clone();
+    if let Some(messages) = context.get_mut("messages").and_then(|m| m.as_array_mut()) {
+        messages.pop();
+    }
+    format!("{:x}", Sha256::digest(context.to_string().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn cache(ttl_seconds: u64, max_entries: usize) -> SemanticCache {
+        SemanticCache::new(&SemanticCacheConfig {
+            enabled: true,
+            similarity_threshold: 0.9,
+            ttl_seconds,
+            max_entries,
+            ..SemanticCacheConfig::default()
+        })
+    }
+
+    #[test]
+    fn similar_questions_hit_and_different_ones_miss() {
+        let cache = cache(3600, 10);
+        cache.store(vec![1.0, 0.0], "m", "ctx", "v1", json!("answer"));
+
+        assert_eq!(cache.lookup(&[0.99, 0.05], "m", "ctx", "v1"), Some(json!("answer")));
+        // Cosine similarity of about 0.7, below the threshold
+        assert_eq!(cache.lookup(&[1.0, 1.0], "m", "ctx", "v1"), None);
+        assert_eq!(cache.lookup(&[1.0, 0.0], "other", "ctx", "v1"), None);
+        assert_eq!(cache.lookup(&[1.0, 0.0], "m", "other", "v1"), None);
+    }
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let cache = cache(0, 10);
+        cache.store(vec![1.0, 0.0], "m", "ctx", "v1", json!("answer"));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.lookup(&[1.0, 0.0], "m", "ctx", "v1"), None);
+    }
+
+    #[test]
+    fn oldest_entries_are_evicted_beyond_max_entries() {
+        let cache = cache(3600, 2);
+        cache.store(vec![1.0, 0.0, 0.0], "m", "ctx", "v1", json!("first"));
+        cache.store(vec![0.0, 1.0, 0.0], "m", "ctx", "v1", json!("second"));
+        cache.store(vec![0.0, 0.0, 1.0], "m", "ctx", "v1", json!("third"));
+
+        assert_eq!(cache.lookup(&[1.0, 0.0, 0.0], "m", "ctx", "v1"), None);
+        assert_eq!(cache.lookup(&[0.0, 1.0, 0.0], "m", "ctx", "v1"), Some(json!("second")));
+        assert_eq!(cache.lookup(&[0.0, 0.0, 1.0], "m", "ctx", "v1"), Some(json!("third")));
+    }
+
+    #[test]
+    fn a_new_knowledge_base_version_drops_the_entries() {
+        let cache = cache(3600, 10);
+        cache.store(vec![1.0, 0.0], "m", "ctx", "v1", json!("answer"));
+        assert_eq!(cache.lookup(&[1.0, 0.0], "m", "ctx", "v2"), None);
+        // The entries computed against the previous version are gone for good
+        assert_eq!(cache.lookup(&[1.0, 0.0], "m", "ctx", "v1"), None);
+    }
+
+    #[test]
+    fn context_hash_separates_system_prompts_and_models() {
+        let request = |model: &str, system: &str, question: &str| {
+            json!({
+                "model": model,
+                "messages": [
+                    { "role": "system", "content": system },
+                    { "role": "user", "content": question }
+                ]
+            })
+        };
+        let base = request_context_hash(&request("m", "Be brief", "What is RAG?"));
+
+        // The question itself is keyed by its embedding, not by the hash
+        assert_eq!(base, request_context_hash(&request("m", "Be brief", "Define RAG")));
+        assert_ne!(base, request_context_hash(&request("m", "Be detailed", "What is RAG?")));
+        assert_ne!(base, request_context_hash(&request("other", "Be brief", "What is RAG?")));
+        let mut with_temperature = request("m", "Be brief", "What is RAG?");
+        with_temperature["temperature"] = json!(0.2);
+        assert_ne!(base, request_context_hash(&with_temperature));
+    }
+}
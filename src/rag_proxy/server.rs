@@ -17,6 +17,7 @@ use crate::load_config;
 use crate::AppError;
 use crate::rag_proxy::handler::handle_rag_request;
 use crate::rag_proxy::passthrough_handler::handle_passthrough_request;
+use crate::rag_proxy::state::AppState;
 use tokio::net::TcpListener;
 
 /// Starts the RAG proxy server
@@ -51,7 +52,7 @@ pub async fn start_server() -> Result<(), AppError> {
         )
     };
     
-    let app = app.with_state(config.clone());
+    let app = app.with_state(Arc::new(AppState::new(config.clone())));
 
     // Add health check endpoint to the app
     let app = app.route("/health", get(health_check));
@@ -66,7 +67,10 @@ pub async fn start_server() -> Result<(), AppError> {
 
     // Start the server with the configured address
     let listener = TcpListener::bind(addr).await.map_err(AppError::Io)?;
-    axum::serve(listener, app).await.map_err(AppError::Io)?;
+    // Expose the peer address to the handlers, used to identify clients without API key
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .map_err(AppError::Io)?;
 
     Ok(())
 }
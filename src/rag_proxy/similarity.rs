@@ -0,0 +1,36 @@
+//! Similarity between embedding vectors.
+//!
+//! Shared by the semantic cache, which compares the embeddings of questions,
+//! and by the diversification of the retrieved chunks, which compares the
+//! vectors of the candidates and relies on the meaning of the search scores.
+
+/// Computes the cosine similarity between two vectors
+///
+/// # Returns
+/// * `f32` - Similarity in [-1, 1], or 0 for empty or mismatched vectors
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// Tells whether the search scores of a Qdrant distance grow with similarity
+///
+/// `Cosine` and `Dot` scores are similarities (higher is better), while
+/// `Euclid` and `Manhattan` scores are distances (lower is better).
+///
+/// # Arguments
+/// * `distance` - Distance of the collection (`qdrant.distance`)
+///
+/// # Returns
+/// * `bool` - True if a higher score means a more similar point
+pub fn scores_are_similarities(distance: &str) -> bool {
+    distance.eq_ignore_ascii_case("cosine") || distance.eq_ignore_ascii_case("dot")
+}
@@ -0,0 +1,44 @@
+//! RAG Proxy State Module
+//!
+//! This module defines the shared state injected into the Axum handlers.
+//! It is created once at server startup and shared via `State<Arc<AppState>>`.
+
+use std::sync::Arc;
+
+use crate::Config;
+use crate::clients::ollama::OllamaClient;
+use crate::rag_proxy::rate_limiter::RateLimiter;
+use crate::rag_proxy::semantic_cache::SemanticCache;
+
+/// State shared by all the request handlers
+pub struct AppState {
+    /// Application configuration loaded from config.toml
+    pub config: Arc<Config>,
+    /// Per-client rate limiter and global concurrency limiter
+    pub rate_limiter: RateLimiter,
+    /// Semantic cache of LLM answers
+    pub semantic_cache: SemanticCache,
+    /// Ollama client embedding the questions, with the persistent embedding cache opened once
+    pub ollama_client: OllamaClient,
+}
+
+impl AppState {
+    /// Creates the shared state from the loaded configuration
+    ///
+    /// # Arguments
+    /// * `config` - Application configuration
+    ///
+    /// # Returns
+    /// * `AppState` - The state to share between handlers
+    pub fn new(config: Arc<Config>) -> Self {
+        let rate_limiter = RateLimiter::new(&config.rate_limit);
+        let semantic_cache = SemanticCache::new(&config.semantic_cache);
+        let ollama_client = OllamaClient::new(&config);
+        AppState {
+            config,
+            rate_limiter,
+            semantic_cache,
+            ollama_client,
+        }
+    }
+}
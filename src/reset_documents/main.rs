@@ -2,8 +2,9 @@ use std::fs;
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
-use toml;
 
+use rag_rust::indexing::file_tracker::TrackerLock;
+use rag_rust::indexing::kb_version;
 use rag_rust::qdrant_custom_client::QdrantClient;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -26,6 +27,12 @@ struct QdrantConfig {
 #[derive(Debug, Deserialize, Serialize)]
 struct IndexingConfig {
     file_tracker_path: String,
+    #[serde(default = "default_kb_version_path")]
+    kb_version_path: String,
+}
+
+fn default_kb_version_path() -> String {
+    "kb_version.txt".to_string()
 }
 
 use rag_rust::init_logging;
@@ -40,6 +47,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config_content = fs::read_to_string("config.toml")?;
     let config: Config = toml::from_str(&config_content)?;
 
+    // Make sure no indexing process is running on the tracker
+    let _tracker_lock = TrackerLock::acquire(&config.indexing_config.file_tracker_path)?;
+
     // Create Qdrant client from config
     let qdrant_client = QdrantClient::new(
         config.qdrant_config.host,
@@ -49,6 +59,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         String::new(), // distance not needed for deletion
         0,             // limit not needed for deletion
         0.0,           // score_threshold not needed for deletion
+        false,         // sparse_vectors not needed for deletion
     );
 
     // Delete the Qdrant collection
@@ -96,5 +107,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         );
     }
 
+    // Publish a new knowledge base version so the proxy drops its cached answers
+    let version = kb_version::bump_version(&config.indexing_config.kb_version_path)?;
+    info!("Knowledge base version updated to {}", version);
+
     Ok(())
 }